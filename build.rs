@@ -0,0 +1,52 @@
+// Copyright (c) 2025, Decisym, LLC
+// Licensed under the BSD 3-Clause License (see LICENSE file in the project root).
+
+//! Records the versions of the RDF dependencies resolved in Cargo.lock for `de --version-info`
+
+use std::{env, fs, path::Path};
+
+const DEPENDENCIES: &[&str] = &[
+    "hdt",
+    "oxrdf",
+    "oxrdfio",
+    "sparesults",
+    "spareval",
+    "spargebra",
+    "oxhttp",
+];
+
+fn main() {
+    let lock = Path::new(&env::var("CARGO_MANIFEST_DIR").unwrap()).join("Cargo.lock");
+    println!("cargo:rerun-if-changed={}", lock.display());
+    let lock = fs::read_to_string(lock).unwrap_or_default();
+
+    let mut versions = Vec::new();
+    // Cargo.lock lists each package as a [[package]] table with name, version and source keys
+    for package in lock.split("[[package]]").skip(1) {
+        let value = |key: &str| {
+            package.lines().find_map(|l| {
+                l.strip_prefix(key)?
+                    .trim_start()
+                    .strip_prefix('=')
+                    .map(|v| v.trim().trim_matches('"').to_string())
+            })
+        };
+        let (Some(name), Some(mut version)) = (value("name"), value("version")) else {
+            continue;
+        };
+        if !DEPENDENCIES.contains(&name.as_str()) {
+            continue;
+        }
+        if let Some(commit) = value("source")
+            .filter(|s| s.starts_with("git+"))
+            .and_then(|s| s.rsplit_once('#').map(|(_, c)| c.to_string()))
+        {
+            version = format!("{version} (git {})", &commit[..commit.len().min(7)]);
+        }
+        versions.push(format!("{name}={version}"));
+    }
+    println!(
+        "cargo:rustc-env=DE_DEPENDENCY_VERSIONS={}",
+        versions.join(";")
+    );
+}
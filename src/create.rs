@@ -1,30 +1,295 @@
 // Copyright (c) 2025, Decisym, LLC
 // Licensed under the BSD 3-Clause License (see LICENSE file in the project root).
 
+use crate::manifest::Manifest;
+use crate::rdf2nt;
 use crate::rdf2nt::ConvertResult;
-use crate::rdf2nt::OxRdfConvert;
+use crate::rdf2nt::InputFormat;
+use crate::rdf2nt::IriValidation;
 use crate::rdf2nt::Rdf2Nt;
+use crate::rdf2nt::{Converter, OxRdfConvert};
 use log::*;
 use std::fs::{self, File, OpenOptions};
-use std::io::{copy, BufReader, BufWriter, Write};
-use std::path::Path;
+use std::io::{copy, BufRead, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use tempfile::{Builder, NamedTempFile};
 
-/// Creates a HDT file from RDF source
+/// Environment variable that, when set, has the same effect as `--keep-intermediate`
+pub const KEEP_INTERMEDIATE_ENV: &str = "DE_KEEP_INTERMEDIATE";
+
+/// Environment variable used as `--temp-prefix` when the option isn't given
+pub const TEMP_PREFIX_ENV: &str = "DE_TEMP_PREFIX";
+
+/// Options controlling how a HDT file is created
+#[derive(Clone, Default, Debug)]
+pub struct CreateOptions {
+    /// Keep the combined NTriples file used to build the HDT instead of deleting it
+    pub keep_intermediate: bool,
+    /// Parse all non NTriple inputs with this format instead of inferring it from the file extension
+    pub input_format: Option<InputFormat>,
+    /// Manifest recording already converted inputs, unchanged inputs are not converted again
+    pub manifest: Option<String>,
+    /// Skip generating the index cache file next to the created HDT
+    pub no_cache: bool,
+    /// Include the offending source line in RDF syntax error messages
+    pub explain_errors: bool,
+    /// Check the IRIs of all triples before building the HDT, not checked when not set
+    pub validate_iris: Option<IriValidation>,
+    /// Directory whose RDF files are added to the data files
+    pub input_dir: Option<String>,
+    /// Also add the RDF files in subdirectories of [CreateOptions::input_dir]
+    pub recursive: bool,
+    /// Overwrite the output file if it already exists instead of failing
+    pub force: bool,
+    /// Fail once the combined NTriples exceed this many triples, unlimited when not set
+    pub max_triples: Option<u64>,
+    /// Compress the written HDT, the output name has to end with the extension of the compression
+    pub compress_output: Option<HdtCompression>,
+    /// Store typed literals of numeric, boolean and date/time datatypes in their canonical form,
+    /// e.g. `"1"` for `"01"^^xsd:integer`. N-Triples inputs are then converted rather than copied
+    pub normalize_literals: bool,
+    /// Name the combined NTriples file `<temp_prefix>.nt` instead of giving it a random name in the
+    /// system temporary directory, e.g. `build/data` for `build/data.nt`
+    pub temp_prefix: Option<String>,
+    /// Library converting the non NTriple inputs
+    pub converter: Converter,
+    /// Build the HDT from the data files that could be converted, only warning about the others,
+    /// instead of failing when any of them is missing or of an unsupported format
+    pub skip_unhandled: bool,
+}
+
+/// Compression of a created HDT file, see [CreateOptions::compress_output]
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum HdtCompression {
+    Gzip,
+    Zstd,
+}
+
+impl HdtCompression {
+    /// Extension of HDT files compressed this way, following `.hdt`
+    pub fn extension(self) -> &'static str {
+        match self {
+            HdtCompression::Gzip => "gz",
+            HdtCompression::Zstd => "zst",
+        }
+    }
+
+    /// Compression of a HDT file detected from its extension, e.g. `data.hdt.gz`
+    pub fn from_file_name(file: &str) -> Option<Self> {
+        [HdtCompression::Gzip, HdtCompression::Zstd]
+            .into_iter()
+            .find(|c| file.ends_with(&format!(".hdt.{}", c.extension())))
+    }
+}
+
+/// What a creation would do with its data files, see [plan_create]
+#[derive(Clone, Default, Debug, PartialEq)]
+pub struct CreatePlan {
+    /// RDF files converted to NTriples
+    pub convert: Vec<String>,
+    /// A NTriples file used as is, when it is the only data file
+    pub copy: Vec<String>,
+    /// Missing files and files without a RDF extension, which fail the creation
+    pub unrecognized: Vec<String>,
+}
+
+/// Result of a HDT creation
+pub struct CreateSummary {
+    pub hdt: hdt::Hdt,
+    /// Path to the combined NTriples file, only set when it was kept
+    pub intermediate_nt: Option<String>,
+    /// Stats of the data files converted to NTriples. NTriples inputs and inputs reused from a
+    /// manifest are not converted and not counted.
+    pub conversion: ConvertResult,
+    /// Number of triples in the created HDT
+    pub triples: usize,
+}
+
+/// Writes a one line summary of a successful creation: the HDT path, its size in bytes and its
+/// number of triples, tab separated so scripts can split it
+pub fn write_summary<W: Write>(
+    hdt_name: &str,
+    summary: &CreateSummary,
+    writer: &mut W,
+) -> anyhow::Result<()> {
+    let size = fs::metadata(hdt_name)
+        .map_err(|e| anyhow::anyhow!("Error reading {hdt_name}: {e}"))?
+        .len();
+    writeln!(
+        writer,
+        "{hdt_name}\t{size} bytes\t{} triples",
+        summary.triples
+    )?;
+    Ok(())
+}
+
+/// Creates a HDT file from RDF source, overwriting `hdt_name` if it exists
 pub fn do_create(hdt_name: &str, data: &[String]) -> anyhow::Result<hdt::Hdt, anyhow::Error> {
+    let options = CreateOptions {
+        force: true,
+        ..Default::default()
+    };
+    do_create_with_options(hdt_name, data, &options).map(|s| s.hdt)
+}
+
+/// Same as [do_create], with additional control over the creation through [CreateOptions]
+pub fn do_create_with_options(
+    hdt_name: &str,
+    data: &[String],
+    options: &CreateOptions,
+) -> anyhow::Result<CreateSummary, anyhow::Error> {
     debug!("Creating HDT...");
+    if !options.force && Path::new(hdt_name).exists() {
+        return Err(anyhow::anyhow!(
+            "output file {hdt_name} already exists, use --force to overwrite it"
+        ));
+    }
+    if let Some(compression) = options.compress_output {
+        // compressed HDTs are recognized by their extension when they are queried
+        if HdtCompression::from_file_name(hdt_name) != Some(compression) {
+            return Err(anyhow::anyhow!(
+                "compressed output file {hdt_name} should end with .hdt.{}",
+                compression.extension()
+            ));
+        }
+    }
+    let (new_hdt, intermediate_nt, conversion) = build_hdt(data, options, hdt_name)?;
+
+    let out_file = OpenOptions::new()
+        .create(true)
+        .write(true)
+        .truncate(true)
+        .open(hdt_name)?;
+    let writer = BufWriter::new(out_file);
+    match options.compress_output {
+        None => {
+            let mut writer = writer;
+            new_hdt.write(&mut writer)?;
+            writer.flush()?;
+        }
+        Some(HdtCompression::Gzip) => {
+            let mut encoder = flate2::write::GzEncoder::new(writer, flate2::Compression::default());
+            new_hdt.write(&mut encoder)?;
+            encoder.finish()?.flush()?;
+        }
+        Some(HdtCompression::Zstd) => {
+            let mut encoder = zstd::stream::write::Encoder::new(writer, 0)?;
+            new_hdt.write(&mut encoder)?;
+            encoder.finish()?.flush()?;
+        }
+    }
+
+    if !Path::new(hdt_name).exists() {
+        return Err(anyhow::anyhow!(
+            "failed to create HDT in requested location {hdt_name}"
+        ));
+    }
+    // Prints location of HDT assuming HDT is generated
+    debug!("HDT file created at {hdt_name}");
+    if options.compress_output.is_some() {
+        // the cache is written next to the HDT that is loaded, which is the decompressed copy
+        debug!("skipping the index cache of compressed {hdt_name}");
+    } else if !options.no_cache {
+        // loading with the cache enabled writes the index cache file used by later queries
+        hdt::Hdt::new_hybrid_cache(Path::new(hdt_name), true)
+            .map_err(|e| anyhow::anyhow!("Error generating index cache for {hdt_name}: {e}"))?;
+        debug!("HDT index cache created for {hdt_name}");
+    }
+    // the header records the count, no need to iterate all the triples
+    let triples = usize::try_from(crate::view::header_triples(hdt_name)?)?;
+    Ok(CreateSummary {
+        hdt: new_hdt,
+        intermediate_nt,
+        conversion,
+        triples,
+    })
+}
+
+/// Classifies the data files, including the ones found in [CreateOptions::input_dir], the way a
+/// creation with `options` would, without converting anything or building the HDT
+pub fn plan_create(data: &[String], options: &CreateOptions) -> anyhow::Result<CreatePlan> {
+    let data = data_files(data, options)?;
+    let (copy, convert, mut unrecognized) = classify_data_files(&data, options.normalize_literals);
+    let (convert, unsupported): (Vec<_>, Vec<_>) = convert
+        .into_iter()
+        .partition(|f| options.input_format.is_some() || rdf2nt::is_rdf_file(f));
+    unrecognized.extend(unsupported);
+    Ok(CreatePlan {
+        convert,
+        copy,
+        unrecognized,
+    })
+}
+
+/// Writes the plan of a dry run, one tab separated line per data file: what is done with it and its path
+pub fn write_plan<W: Write>(plan: &CreatePlan, writer: &mut W) -> anyhow::Result<()> {
+    for (action, files) in [
+        ("convert", &plan.convert),
+        ("copy", &plan.copy),
+        ("unrecognized", &plan.unrecognized),
+    ] {
+        for file in files {
+            writeln!(writer, "{action}\t{file}")?;
+        }
+    }
+    Ok(())
+}
+
+/// Converts RDF files to a HDT held in memory, for embedders that want the HDT without writing it
+/// to a file. The data files are still combined in a temporary NTriples file, removed before returning.
+pub fn rdf_to_hdt_bytes(data: &[String]) -> anyhow::Result<Vec<u8>> {
+    let (hdt, _, _) = build_hdt(data, &CreateOptions::default(), "the HDT")?;
+    let mut bytes = Vec::new();
+    hdt.write(&mut bytes)?;
+    Ok(bytes)
+}
+
+/// Combines the data files into NTriples and builds the HDT from them, `target` names the HDT in
+/// messages. Returns the HDT, the kept intermediate NTriples file and the conversion stats.
+fn build_hdt(
+    data: &[String],
+    options: &CreateOptions,
+    target: &str,
+) -> anyhow::Result<(hdt::Hdt, Option<String>, ConvertResult)> {
+    let data = data_files(data, options)?;
+    let data = data.as_slice();
     // creating a tempfile to hold all the contents of the rdf input files
-    let mut tmp_file = Builder::new()
-        .suffix(".nt")
-        .append(true)
-        .tempfile()
-        .map_err(|e| anyhow::anyhow!("Error creating temporary file: {:?}", e))?;
-
-    let (combined_rdf_path, unknown_files) =
-        files_to_rdf(data, &mut tmp_file, Arc::new(OxRdfConvert {}))?;
-    if !unknown_files.is_empty() {
-        for f in &unknown_files {
+    let mut tmp_file = match &options.temp_prefix {
+        Some(temp_prefix) => {
+            let (dir, name) = split_temp_prefix(temp_prefix)?;
+            temp_builder(Some(name), ".nt")
+                .tempfile_in(dir)
+                .map_err(|e| temp_prefix_error(temp_prefix, ".nt", e))?
+        }
+        None => temp_builder(None, ".nt")
+            .tempfile()
+            .map_err(|e| anyhow::anyhow!("Error creating temporary file: {:?}", e))?,
+    };
+
+    let converter = options.converter.rdf2nt(OxRdfConvert {
+        input_format: options.input_format,
+        explain_errors: options.explain_errors,
+        max_triples: options.max_triples,
+        normalize_literals: options.normalize_literals,
+    });
+    let (combined_rdf_path, conversion) = match &options.manifest {
+        Some(manifest) => {
+            files_to_rdf_with_manifest(data, &mut tmp_file, converter, Path::new(manifest))?
+        }
+        None => files_to_rdf_with_stats(data, &mut tmp_file, converter)?,
+    };
+    let unknown_files = &conversion.unhandled;
+    if !unknown_files.is_empty() && options.skip_unhandled {
+        for f in unknown_files {
+            if Path::new(f).exists() {
+                warn!("skipping {f:?}, its format is not supported");
+            } else {
+                warn!("skipping {f:?}, it could not be found on local machine");
+            }
+        }
+    } else if !unknown_files.is_empty() {
+        for f in unknown_files {
             if !Path::new(f).exists() {
                 error!("file {f:?} could not be found on local machine");
             }
@@ -37,28 +302,190 @@ pub fn do_create(hdt_name: &str, data: &[String]) -> anyhow::Result<hdt::Hdt, an
         ));
     }
 
-    let new_hdt = hdt::Hdt::read_nt(Path::new(&combined_rdf_path))
-        .map_err(|e| anyhow::anyhow!("Error converting combined RDF to HDT: {e}"))?;
+    // counting stops right past what the checks below need, a single NTriples input can be large
+    let limit = options.max_triples.map_or(1, |max| max.saturating_add(1));
+    let triples = count_nt_triples(Path::new(&combined_rdf_path), limit)
+        .map_err(|e| anyhow::anyhow!("Error reading {combined_rdf_path}: {e}"))?;
+    if triples == 0 {
+        return Err(anyhow::anyhow!(
+            "no triples to build HDT from, the data files {data:?} hold none"
+        ));
+    }
+    // conversions and copies stop at the limit, a single NTriples input is used as is
+    if let Some(max_triples) = options.max_triples {
+        if triples > max_triples {
+            return Err(anyhow::anyhow!(
+                "data files hold more than the maximum of {max_triples} triples"
+            ));
+        }
+    }
 
-    let out_file = OpenOptions::new()
-        .create(true)
-        .write(true)
-        .truncate(true)
-        .open(hdt_name)?;
-    let mut writer = BufWriter::new(out_file);
-    new_hdt.write(&mut writer)?;
+    if let Some(level) = options.validate_iris {
+        let invalid = rdf2nt::validate_iris(&combined_rdf_path, level)?;
+        if invalid > 0 {
+            warn!("{invalid} triple(s) with invalid IRIs added to {target}");
+        }
+    }
+
+    let new_hdt = hdt::Hdt::read_nt(Path::new(&combined_rdf_path)).map_err(|e| {
+        // the combined NTriples doesn't tell which input a bad triple came from
+        error!("HDT creation failed, the combined RDF was built from: {data:?}");
+        anyhow::anyhow!("Error converting combined RDF of {data:?} to HDT: {e}")
+    })?;
+
+    // a single NTriple input is used as is, there is no intermediate file to keep
+    let intermediate_nt =
+        if options.keep_intermediate && Path::new(&combined_rdf_path) == tmp_file.path() {
+            tmp_file
+                .keep()
+                .map_err(|e| anyhow::anyhow!("Error keeping intermediate file: {e}"))?;
+            info!("intermediate NTriples file: {combined_rdf_path}");
+            Some(combined_rdf_path)
+        } else {
+            let _ = fs::remove_file(tmp_file.path());
+            None
+        };
+
+    Ok((new_hdt, intermediate_nt, conversion))
+}
+
+/// Builder of temporary files and directories, named `name` followed by `suffix` when a name is
+/// given instead of random characters. Creating a named one fails when it already exists.
+pub(crate) fn temp_builder<'a>(name: Option<&'a str>, suffix: &'a str) -> Builder<'a, 'a> {
+    let mut builder = Builder::new();
+    builder.suffix(suffix).append(true);
+    if let Some(name) = name {
+        builder.prefix(name).rand_bytes(0);
+    }
+    builder
+}
+
+/// Directory and file name of a `--temp-prefix`, the current directory when it has none
+pub(crate) fn split_temp_prefix(temp_prefix: &str) -> anyhow::Result<(&Path, &str)> {
+    let path = Path::new(temp_prefix);
+    let name = path
+        .file_name()
+        .and_then(|n| n.to_str())
+        .ok_or_else(|| anyhow::anyhow!("temporary file prefix {temp_prefix} has no file name"))?;
+    let dir = path
+        .parent()
+        .filter(|d| !d.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+    Ok((dir, name))
+}
+
+pub(crate) fn temp_prefix_error(
+    temp_prefix: &str,
+    suffix: &str,
+    e: std::io::Error,
+) -> anyhow::Error {
+    if e.kind() == std::io::ErrorKind::AlreadyExists {
+        anyhow::anyhow!(
+            "{temp_prefix}{suffix} already exists, remove it or pick another temporary file prefix"
+        )
+    } else {
+        anyhow::anyhow!("Error creating {temp_prefix}{suffix}: {e}")
+    }
+}
+
+/// The data files along with the RDF files of [CreateOptions::input_dir]
+fn data_files(data: &[String], options: &CreateOptions) -> anyhow::Result<Vec<String>> {
+    let mut data = data.to_vec();
+    if let Some(dir) = &options.input_dir {
+        let found = find_rdf_files(Path::new(dir), options.recursive)
+            .map_err(|e| anyhow::anyhow!("Error reading input directory {dir}: {e}"))?;
+        if found.is_empty() {
+            return Err(anyhow::anyhow!("no RDF files found in {dir}"));
+        }
+        debug!("found {} RDF files in {dir}", found.len());
+        for f in found {
+            if !data.contains(&f) {
+                data.push(f);
+            }
+        }
+    }
+    Ok(data)
+}
+
+/// Decompress a HDT written with [CreateOptions::compress_output] into `dir`, returning the path of
+/// the plain HDT. It keeps the file name without the compression extension, e.g. `data.hdt` for
+/// `data.hdt.gz`, so its graph is named as the uncompressed file would be.
+pub fn decompress_hdt(file: &str, dir: &Path) -> anyhow::Result<PathBuf> {
+    let compression = HdtCompression::from_file_name(file)
+        .ok_or_else(|| anyhow::anyhow!("{file} is not a compressed HDT"))?;
+    let name = Path::new(file)
+        .file_stem()
+        .ok_or_else(|| anyhow::anyhow!("Invalid file path: {file}"))?;
+    let target = dir.join(name);
+    let source = File::open(file).map_err(|e| anyhow::anyhow!("Error opening {file}: {e}"))?;
+    let mut reader: Box<dyn Read> = match compression {
+        HdtCompression::Gzip => Box::new(flate2::read::GzDecoder::new(source)),
+        HdtCompression::Zstd => Box::new(zstd::stream::read::Decoder::new(source)?),
+    };
+    let mut writer = BufWriter::new(File::create(&target)?);
+    copy(&mut reader, &mut writer)
+        .map_err(|e| anyhow::anyhow!("Error decompressing {file}: {e}"))?;
     writer.flush()?;
+    debug!("decompressed {file} to {target:?}");
+    Ok(target)
+}
 
-    let _ = fs::remove_file(tmp_file.path());
+/// Extract the `.hdt` entries of a zip archive into `dir`, returning the paths of the extracted
+/// HDTs. Entries keep their path within the archive, so their graphs are named after the entry
+/// names. Other entries are skipped.
+pub fn extract_hdt_zip(file: &str, dir: &Path) -> anyhow::Result<Vec<PathBuf>> {
+    let source = File::open(file).map_err(|e| anyhow::anyhow!("Error opening {file}: {e}"))?;
+    let mut archive = zip::ZipArchive::new(BufReader::new(source))
+        .map_err(|e| anyhow::anyhow!("Error reading zip archive {file}: {e}"))?;
+    let mut extracted = vec![];
+    for i in 0..archive.len() {
+        let mut entry = archive.by_index(i)?;
+        if entry.is_dir() || !entry.name().ends_with(".hdt") {
+            continue;
+        }
+        // rejects entries escaping the target directory, e.g. `../data.hdt`
+        let Some(name) = entry.enclosed_name() else {
+            warn!(
+                "{file}: skipping zip entry with unsafe path {}",
+                entry.name()
+            );
+            continue;
+        };
+        let target = dir.join(name);
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut writer = BufWriter::new(File::create(&target)?);
+        copy(&mut entry, &mut writer)
+            .map_err(|e| anyhow::anyhow!("Error extracting {} from {file}: {e}", entry.name()))?;
+        writer.flush()?;
+        extracted.push(target);
+    }
+    if extracted.is_empty() {
+        return Err(anyhow::anyhow!("zip archive {file} contains no HDT files"));
+    }
+    debug!("extracted {} HDT files from {file}", extracted.len());
+    Ok(extracted)
+}
 
-    if !Path::new(hdt_name).exists() {
-        return Err(anyhow::anyhow!(
-            "failed to create HDT in requested location {hdt_name}"
-        ));
+/// List the files with a RDF extension in `dir`, sorted, descending into subdirectories when `recursive` is set.
+/// Other files are skipped.
+pub fn find_rdf_files(dir: &Path, recursive: bool) -> std::io::Result<Vec<String>> {
+    let mut found = vec![];
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            if recursive {
+                found.extend(find_rdf_files(&path, recursive)?);
+            }
+        } else if let Some(file) = path.to_str().filter(|f| rdf2nt::is_rdf_file(f)) {
+            found.push(file.to_string());
+        } else {
+            debug!("skipping {path:?}, not a RDF file");
+        }
     }
-    // Prints location of HDT assuming HDT is generated
-    debug!("HDT file created at {hdt_name}");
-    Ok(new_hdt)
+    found.sort();
+    Ok(found)
 }
 
 /// Converts a list of RDF files to NTriple RDF
@@ -68,49 +495,188 @@ pub fn files_to_rdf(
     out_file: &mut NamedTempFile,
     converter: Arc<dyn Rdf2Nt>,
 ) -> anyhow::Result<(String, Vec<String>), anyhow::Error> {
+    files_to_rdf_with_stats(data, out_file, converter).map(|(path, res)| (path, res.unhandled))
+}
+
+/// Splits data files into NTriples files, files to convert and missing files
+fn classify_data_files(
+    data: &[String],
+    convert_ntriples: bool,
+) -> (Vec<String>, Vec<String>, Vec<String>) {
+    let convert_ntriples = convert_ntriples || is_combined(data);
     let mut nt_files = vec![];
     let mut files_to_convert = vec![];
-    let mut unrecognized_files = vec![];
+    let mut missing_files = vec![];
 
     for file in data.iter() {
         let path = Path::new(&file);
         if !path.exists() {
-            unrecognized_files.push(file.clone());
+            missing_files.push(file.clone());
             continue;
         }
 
         // Check for triples, this is the preferred RDF format and no additional conversion is required
-        if file.ends_with(".nt") {
+        if file.ends_with(".nt") && !convert_ntriples {
             debug!("Adding RDF triples to graph");
             nt_files.push(file.clone());
         } else {
             files_to_convert.push(file.clone());
         }
     }
+    (nt_files, files_to_convert, missing_files)
+}
 
-    let conv_res = if !files_to_convert.is_empty() {
-        let r = converter
+/// Whether more than one data file is combined. NTriples inputs are then converted rather than
+/// copied as is, the conversion scopes their blank nodes to their file.
+fn is_combined(data: &[String]) -> bool {
+    data.iter().filter(|f| Path::new(f).exists()).count() > 1
+}
+
+/// Same as [files_to_rdf], returning the conversion stats with the names of all unhandled files
+pub fn files_to_rdf_with_stats(
+    data: &[String],
+    out_file: &mut NamedTempFile,
+    converter: Arc<dyn Rdf2Nt>,
+) -> anyhow::Result<(String, ConvertResult), anyhow::Error> {
+    let (nt_files, files_to_convert, mut unrecognized_files) =
+        classify_data_files(data, converter.converts_ntriples());
+
+    let mut conv_res = if !files_to_convert.is_empty() {
+        converter
             .convert_to_nt(files_to_convert, out_file.as_file())
-            .map_err(|e| anyhow::anyhow!("Error converting file(s) to NT: {e}"))?;
-        unrecognized_files.extend(r.unhandled.clone());
-        r
+            .map_err(|e| anyhow::anyhow!("Error converting file(s) to NT: {e}"))?
     } else {
         ConvertResult::default()
     };
+    unrecognized_files.append(&mut conv_res.unhandled);
+    conv_res.unhandled = unrecognized_files;
 
     // optimization attempt. If only one NTriple file provided don't do an additional file copy otherwise
-    // inefficient when creating an HDT file from one large file
-    if nt_files.len() > 1 || conv_res.converted != 0 {
-        for nt_file in nt_files {
-            let source = File::open(&nt_file)
-                .map_err(|e| anyhow::anyhow!("Error opening file {:?}: {:?}", nt_file, e))?;
-            let mut source_reader = BufReader::new(source);
+    // inefficient when creating an HDT file from one large file. Combined with other files it is converted.
+    if let [nt_file] = nt_files.as_slice() {
+        return Ok((nt_file.clone(), conv_res));
+    }
+
+    Ok((
+        out_file
+            .path()
+            .to_str()
+            .ok_or_else(|| anyhow::anyhow!("Invalid UTF-8 in temp file path"))?
+            .to_string(),
+        conv_res,
+    ))
+}
+
+/// Copy the NTriples file `nt_file` to `out`, adding its triples to `triples`. Fails as soon as
+/// `triples` exceeds `max_triples`, before the rest of the file is copied. Returns the number of
+/// triples copied.
+fn append_nt(
+    nt_file: &str,
+    out: &mut impl Write,
+    triples: &mut u64,
+    max_triples: Option<u64>,
+) -> anyhow::Result<u64> {
+    let source = File::open(nt_file)
+        .map_err(|e| anyhow::anyhow!("Error opening file {:?}: {:?}", nt_file, e))?;
+    let mut source_reader = BufReader::new(source);
+    let mut out = BufWriter::new(out);
+    let mut line = Vec::new();
+    let mut copied = 0;
+    loop {
+        line.clear();
+        let read = source_reader
+            .read_until(b'\n', &mut line)
+            .and_then(|read| out.write_all(&line).map(|()| read))
+            .map_err(|e| anyhow::anyhow!("Error copying file {:?}: {:?}", nt_file, e))?;
+        if read == 0 {
+            out.flush()?;
+            return Ok(copied);
+        }
+        let statement = line.trim_ascii_start();
+        if !statement.is_empty() && !statement.starts_with(b"#") {
+            copied += 1;
+            *triples += 1;
+            if let Some(max) = max_triples.filter(|max| *triples > *max) {
+                return Err(anyhow::anyhow!(
+                    "more than the maximum of {max} triples, stopped in {nt_file}"
+                ));
+            }
+        }
+    }
+}
+
+/// Number of triples in a NTriples file, one per line besides blank and comment lines. Counting
+/// stops once `limit` triples are found.
+fn count_nt_triples(nt_file: &Path, limit: u64) -> std::io::Result<u64> {
+    let mut count = 0;
+    for line in BufReader::new(File::open(nt_file)?).lines() {
+        if count >= limit {
+            break;
+        }
+        let line = line?;
+        let line = line.trim_start();
+        if !line.is_empty() && !line.starts_with('#') {
+            count += 1;
+        }
+    }
+    Ok(count)
+}
 
-            copy(&mut source_reader, out_file)
-                .map_err(|e| anyhow::anyhow!("Error copying file {:?}: {:?}", &nt_file, e))?;
+/// Same as [files_to_rdf], but each input's NTriples are cached and recorded in the manifest at
+/// `manifest_path`. Inputs whose size and modification time match the manifest are not converted again.
+pub fn files_to_rdf_with_manifest(
+    data: &[String],
+    out_file: &mut NamedTempFile,
+    converter: Arc<dyn Rdf2Nt>,
+    manifest_path: &Path,
+) -> anyhow::Result<(String, ConvertResult), anyhow::Error> {
+    let mut manifest = Manifest::load(manifest_path)?;
+    let mut nt_files = vec![];
+    let mut res = ConvertResult::default();
+
+    for file in data.iter() {
+        if !Path::new(&file).exists() {
+            res.unhandled.push(file.clone());
+            continue;
+        }
+        if file.ends_with(".nt") && !converter.converts_ntriples() && !is_combined(data) {
+            debug!("Adding RDF triples to graph");
+            nt_files.push(file.clone());
+            continue;
         }
-    } else if nt_files.len() == 1 && conv_res.converted == 0 {
-        return Ok((nt_files[0].clone(), unrecognized_files));
+        if let Some(nt) = manifest.cached_nt(file) {
+            debug!("{file} unchanged since last conversion, reusing {nt:?}");
+            nt_files.push(nt.to_string_lossy().into_owned());
+            continue;
+        }
+
+        let nt = manifest.nt_path(file)?;
+        let nt_file = File::create(&nt)
+            .map_err(|e| anyhow::anyhow!("Error creating file {:?}: {:?}", nt, e))?;
+        let r = converter
+            .convert_to_nt(vec![file.clone()], &nt_file)
+            .map_err(|e| anyhow::anyhow!("Error converting file(s) to NT: {e}"))?;
+        if r.converted == 0 {
+            res.unhandled.extend(r.unhandled);
+            manifest.remove(file);
+            let _ = fs::remove_file(&nt);
+            continue;
+        }
+        res.converted += r.converted;
+        res.triples.extend(r.triples);
+        manifest.record(file, nt.clone())?;
+        // saved after every conversion so an interrupted run can resume from here
+        manifest.save()?;
+        nt_files.push(nt.to_string_lossy().into_owned());
+    }
+    manifest.save()?;
+
+    if nt_files.len() == 1 {
+        return Ok((nt_files[0].clone(), res));
+    }
+    let mut triples = 0;
+    for nt_file in nt_files {
+        append_nt(&nt_file, out_file, &mut triples, converter.max_triples())?;
     }
 
     Ok((
@@ -119,7 +685,7 @@ pub fn files_to_rdf(
             .to_str()
             .ok_or_else(|| anyhow::anyhow!("Invalid UTF-8 in temp file path"))?
             .to_string(),
-        unrecognized_files,
+        res,
     ))
 }
 
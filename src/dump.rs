@@ -0,0 +1,99 @@
+// Copyright (c) 2025, Decisym, LLC
+// Licensed under the BSD 3-Clause License (see LICENSE file in the project root).
+
+use crate::query::DeOutput;
+use crate::sparql::hdt_triple_to_triple;
+use anyhow::anyhow;
+use log::*;
+use oxrdfio::{JsonLdProfileSet, RdfFormat, RdfSerializer};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::sync::Arc;
+
+/// Options controlling how HDT files are exported as RDF
+#[derive(Clone, Default, Debug)]
+pub struct DumpOptions {
+    /// RDF format to write, NTriples when not set
+    pub output: Option<DeOutput>,
+    /// Sort triples by subject, predicate and object so output is stable across runs and files.
+    /// All triples are buffered in memory first.
+    pub sort: bool,
+    /// Only write this many randomly selected triples of all files. Only the selected triples are
+    /// held in memory, however large the files are.
+    pub sample: Option<usize>,
+    /// Seed of the random selection of [DumpOptions::sample], making it reproducible
+    pub seed: Option<u64>,
+}
+
+/// Export the triples of HDT files as RDF
+pub fn do_dump<W: Write>(
+    hdt_files: &[String],
+    options: &DumpOptions,
+    writer: &mut BufWriter<W>,
+) -> anyhow::Result<()> {
+    let format = match &options.output {
+        None | Some(DeOutput::NTRIPLE) => RdfFormat::NTriples,
+        Some(DeOutput::N3) => RdfFormat::N3,
+        Some(DeOutput::NQUADS) => RdfFormat::NQuads,
+        Some(DeOutput::RDFXML) => RdfFormat::RdfXml,
+        Some(DeOutput::TRIG) => RdfFormat::TriG,
+        Some(DeOutput::TURTLE) => RdfFormat::Turtle,
+        Some(DeOutput::JSONLD) => RdfFormat::JsonLd {
+            profile: JsonLdProfileSet::empty(),
+        },
+        Some(o) => {
+            return Err(anyhow!(
+                "{o:?} is not an RDF format, dump supports only N3, NQUADS, RDFXML, NTRIPLE, TRIG, TURTLE, or JSONLD"
+            ))
+        }
+    };
+
+    let mut serializer = RdfSerializer::from_format(format).for_writer(&mut *writer);
+    // triples written once all files are read, when sorting or sampling
+    let mut buffered: Vec<[Arc<str>; 3]> = vec![];
+    let mut rng = match options.seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_os_rng(),
+    };
+    let mut seen = 0_usize;
+    for f in hdt_files {
+        if !Path::new(f).exists() {
+            error!("file {f:?} could not be found on local machine");
+            return Err(anyhow!("file {f:?} could not be found on local machine"));
+        }
+        let hdt = hdt::Hdt::new_hybrid_cache(Path::new(f), true)
+            .map_err(|e| anyhow!("error loading HDT file {f}: {e}"))?;
+        debug!("dumping {f}");
+        if let Some(sample) = options.sample {
+            // reservoir sampling, every triple has the same chance to be selected
+            for t in hdt.triples_all() {
+                if buffered.len() < sample {
+                    buffered.push(t);
+                } else if let Some(slot) = buffered.get_mut(rng.random_range(0..=seen)) {
+                    *slot = t;
+                }
+                seen += 1;
+            }
+        } else if options.sort {
+            buffered.extend(hdt.triples_all());
+        } else {
+            for t in hdt.triples_all() {
+                serializer.serialize_triple(&hdt_triple_to_triple(&t)?)?;
+            }
+        }
+    }
+    if options.sort {
+        buffered.sort_unstable();
+    }
+    for t in &buffered {
+        serializer.serialize_triple(&hdt_triple_to_triple(t)?)?;
+    }
+    if let Some(sample) = options.sample {
+        debug!("sampled {} of {seen} triples", sample.min(seen));
+    }
+    serializer.finish()?;
+    writer.flush()?;
+    Ok(())
+}
@@ -2,11 +2,16 @@
 // Licensed under the BSD 3-Clause License (see LICENSE file in the project root).
 
 pub mod create;
+pub mod dump;
+pub mod manifest;
 pub mod query;
 pub mod rdf2nt;
+pub mod reindex;
 #[cfg(feature = "server")]
 pub mod serve;
 #[cfg(feature = "server")]
 pub mod service_description;
 pub mod sparql;
+pub mod stats;
+pub mod version;
 pub mod view;
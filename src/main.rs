@@ -1,17 +1,20 @@
 // Copyright (c) 2025, Decisym, LLC
 // Licensed under the BSD 3-Clause License (see LICENSE file in the project root).
 
-use clap::{Parser, Subcommand};
+use clap::{CommandFactory, Parser, Subcommand};
 use de::*;
-use log::error;
+use log::{error, info};
 use std::io::{stdout, BufWriter, Write};
 
 #[derive(Parser)]
 #[command(author, version, about="CLI tool for creating and querying HDT files", long_about = None)]
-#[command(propagate_version = true)]
+#[command(propagate_version = true, arg_required_else_help = true)]
 struct Cli {
     #[command(subcommand)]
-    command: Commands,
+    command: Option<Commands>,
+    /// Print the version of de and of the RDF libraries it was built with
+    #[arg(long, exclusive = true)]
+    version_info: bool,
     #[command(flatten)]
     verbose: clap_verbosity_flag::Verbosity,
 }
@@ -26,6 +29,54 @@ enum Commands {
         #[clap(short, long, num_args = 1..)]
         /// Path to data files to be added to Graph (Acceptable inputs are as follows: RDF)
         data: Vec<String>,
+        #[clap(long)]
+        /// Keep the combined NTriples file used to build the HDT and log its path. Can also be enabled with DE_KEEP_INTERMEDIATE
+        keep_intermediate: bool,
+        #[clap(long)]
+        /// Name the combined NTriples file <TEMP_PREFIX>.nt instead of a random name, failing if it exists. Defaults to DE_TEMP_PREFIX
+        temp_prefix: Option<String>,
+        #[clap(long, value_enum)]
+        /// Parse all non NTriple data files with this format instead of inferring it from the file extension
+        input_format: Option<rdf2nt::InputFormat>,
+        #[clap(long)]
+        /// JSON manifest recording converted data files, re-runs only convert files changed since the last run
+        manifest: Option<String>,
+        #[clap(long)]
+        /// Don't generate the index cache file next to the output HDT, useful when the HDT is only used once
+        no_cache: bool,
+        #[clap(long)]
+        /// Show the offending source line when a data file fails to parse
+        explain_errors: bool,
+        #[clap(long, value_enum, num_args = 0..=1, default_missing_value = "strict")]
+        /// Check that all IRIs are valid, failing on (strict, the default) or logging (warn) triples with invalid ones
+        validate_iris: Option<rdf2nt::IriValidation>,
+        #[clap(long)]
+        /// Directory whose RDF files are added to the data files
+        input_dir: Option<String>,
+        #[clap(long, requires = "input_dir")]
+        /// Also add the RDF files in subdirectories of --input-dir
+        recursive: bool,
+        #[clap(short, long)]
+        /// Overwrite the output file if it already exists
+        force: bool,
+        #[clap(long)]
+        /// Fail once the data files add up to more than this many triples, a safety valve for untrusted inputs
+        max_triples: Option<u64>,
+        #[clap(long, value_enum)]
+        /// Compress the HDT, the output name has to end with .hdt.gz or .hdt.zst accordingly
+        compress_output: Option<create::HdtCompression>,
+        #[clap(long)]
+        /// Print which data files would be converted, copied as NTriples or rejected, without creating the HDT
+        dry_run: bool,
+        #[clap(long)]
+        /// Store numeric, boolean and date/time literals in their canonical form, e.g. "1" for "01"^^xsd:integer
+        normalize_literals: bool,
+        #[clap(long, value_enum, default_value_t, hide = true)]
+        /// Library converting the data files to NTriples, to compare converters
+        converter: rdf2nt::Converter,
+        #[clap(long, default_value_t = true, action = clap::ArgAction::Set)]
+        /// Fail when a data file is missing or of an unsupported format. With --fail-on-unhandled=false the HDT is built from the other files, the skipped ones are logged
+        fail_on_unhandled: bool,
     },
     /// Query HDT and RDF files using SPARQL query format
     Query {
@@ -36,8 +87,80 @@ enum Commands {
         /// Path to SPARQL query file. (should end in .rq)
         sparql: Vec<String>,
         /// Output to return the query results as using https://docs.rs/oxigraph/0.4.3/oxigraph/sparql/results/enum.QueryResultsFormat.html and https://crates.io/crates/oxrdfio
-        #[clap(short, long, default_value_t, value_enum)]
-        output: query::DeOutput,
+        /// [default: csv for SELECT/ASK, ntriple for CONSTRUCT, turtle for DESCRIBE, auto with --out-file]. auto picks the
+        /// format from the --out-file extension, or csv for SELECT, json for ASK and turtle for CONSTRUCT/DESCRIBE
+        #[clap(short, long, value_enum)]
+        output: Option<query::DeOutput>,
+        #[clap(long)]
+        /// Indent JSON query results
+        pretty: bool,
+        #[clap(long)]
+        /// Keep the combined NTriples file built from RDF data files and log its path. Can also be enabled with DE_KEEP_INTERMEDIATE
+        keep_intermediate: bool,
+        #[clap(long)]
+        /// Convert RDF data files in the directory <TEMP_PREFIX> instead of a random one, failing if it exists. Defaults to DE_TEMP_PREFIX
+        temp_prefix: Option<String>,
+        #[clap(long, value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+        /// Maximum number of HDT files to load in parallel. Lower values reduce peak memory usage
+        load_concurrency: Option<usize>,
+        #[clap(long, value_name = "SECS", value_parser = clap::value_parser!(u64).range(1..))]
+        /// Fail when loading the HDT files takes longer than this many seconds, naming the files still loading
+        timeout_load: Option<u64>,
+        #[clap(long, value_enum)]
+        /// Parse all non-HDT data files with this format instead of inferring it from the file extension
+        input_format: Option<rdf2nt::InputFormat>,
+        #[clap(long)]
+        /// Abbreviate IRIs in CSV and TSV results using the query's PREFIX declarations
+        prefixes: bool,
+        #[clap(long)]
+        /// Show the offending source line when a RDF data file fails to parse
+        explain_errors: bool,
+        #[clap(long)]
+        /// Only query the graph with this name, e.g. file:///data.hdt. Can be repeated
+        graph: Vec<String>,
+        #[clap(long)]
+        /// Field delimiter of CSV results, e.g. ';' or '|'
+        delimiter: Option<char>,
+        #[clap(long)]
+        /// Name graphs <GRAPH_BASE><file stem> instead of file:///<file name>, e.g. http://my.org/graphs/
+        graph_base: Option<String>,
+        #[clap(long, conflicts_with = "graph_base")]
+        /// Name graphs by their file stem, e.g. data for data.hdt, instead of file:///<file name>
+        short_graph_names: bool,
+        #[clap(long)]
+        /// Write IRIs in TSV results without angle brackets, as in CSV results
+        raw_iris: bool,
+        #[clap(long, value_parser = parse_binding)]
+        /// Bind a query variable before evaluation, e.g. t=http://example.org/Fruit or name='"Apple"'. Can be repeated
+        bind: Vec<(String, String)>,
+        #[clap(long, value_hint = clap::ValueHint::FilePath)]
+        /// Write the results to this file instead of stdout
+        out_file: Option<String>,
+        #[clap(long, value_name = "IRI")]
+        /// Place all CONSTRUCT and DESCRIBE triples in this named graph with nquads and trig output
+        output_graph: Option<String>,
+        #[clap(long)]
+        /// Print the triple patterns of the query with the number of triples each matches instead of the results
+        explain_cost: bool,
+        #[clap(long)]
+        /// Print the PREFIX declarations each query doesn't use instead of the results, one <QUERY FILE>\t<PREFIX>: line each
+        explain_unused_prefixes: bool,
+        #[clap(long, value_enum, num_args = 0..=1, default_missing_value = "text")]
+        /// Print the algebra of each query as evaluated, as SPARQL S-Expressions (text, the default) or JSON, instead of the results
+        explain: Option<query::ExplainFormat>,
+        #[clap(long)]
+        /// Print a SHA-256 hash of the results, independent of their order, instead of the results
+        hash: bool,
+        #[clap(long, value_hint = clap::ValueHint::FilePath)]
+        /// File of PREFIX or @prefix declarations available to every query, the query's own declarations take precedence
+        prefix_file: Option<String>,
+        #[clap(long, value_enum, default_value_t, hide = true)]
+        /// Library converting the RDF data files to NTriples, to compare converters
+        converter: rdf2nt::Converter,
+        #[cfg(feature = "profiling")]
+        #[clap(long, value_hint = clap::ValueHint::FilePath)]
+        /// Write a pprof CPU profile of the query execution to this file, e.g. out.pb
+        profile: Option<String>,
     },
     /// Start a server to listen for /sparql, /update and /store API's. HDT's are read-only
     /// per spec, so new graphs (i.e. files) can be uploaded, but existing HDT triples can NOT
@@ -49,16 +172,130 @@ enum Commands {
         /// If not present, an in-memory storage will be used.
         #[arg(short, long, value_hint = clap::ValueHint::DirPath)]
         location: String,
-        /// Host and port to listen to
+        /// Host and port to listen to, a comma separated list of them, e.g. 127.0.0.1:8080,[::1]:8080, or `unix:<path>` to listen on a Unix domain socket, answering one request per connection
         #[arg(short, long, default_value = "localhost:7878", value_hint = clap::ValueHint::Hostname)]
         bind: String,
+        /// Maximum number of HDT files to load in parallel. Lower values reduce peak memory usage
+        #[arg(long, value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+        load_concurrency: Option<usize>,
+        /// Allow SPARQL updates to modify existing named graphs (DELETE DATA, DELETE/INSERT, INSERT DATA into existing graphs, CLEAR and DROP) and Graph Store POSTs to add triples to them
+        #[arg(long)]
+        allow_mutations: bool,
+        /// Don't write index cache files next to the HDT files when loading them
+        #[arg(long)]
+        no_cache: bool,
+        /// Use the union of all graphs as the default graph. With `--union-default-graph=false` the
+        /// default graph is empty and only patterns in a GRAPH clause match
+        #[arg(long, default_value_t = true, action = clap::ArgAction::Set)]
+        union_default_graph: bool,
+        /// Use this graph, e.g. file:///data.hdt, as the default graph matched by patterns outside a
+        /// GRAPH clause, instead of the union of all graphs. The other graphs stay available by name
+        #[arg(long)]
+        default_graph: Option<String>,
+        /// Name graphs <GRAPH_BASE><file stem> instead of file:///<file name>, e.g. http://my.org/graphs/
+        #[arg(long)]
+        graph_base: Option<String>,
+        /// Name graphs by their file stem, e.g. data for data.hdt, instead of file:///<file name>
+        #[arg(long, conflicts_with = "graph_base")]
+        short_graph_names: bool,
+        /// Maximum number of SPARQL and graph store requests per minute from a client, told apart by the last X-Forwarded-For address
+        #[arg(long, value_parser = clap::value_parser!(u32).range(1..), requires = "trusted_proxy")]
+        rate_limit: Option<u32>,
+        /// The server is only reachable through a reverse proxy appending the client address to X-Forwarded-For
+        #[arg(long)]
+        trusted_proxy: bool,
+        /// Number of query results kept in memory to answer repeated SPARQL queries until a HDT file changes, 0 disables the cache
+        #[arg(long, default_value_t = 0)]
+        query_cache_size: usize,
+        /// Memory in bytes used by the query cache, results larger than a quarter of it are not cached
+        #[arg(long, default_value_t = 64 * 1024 * 1024)]
+        query_cache_max_bytes: usize,
+        /// Require `Authorization: Bearer <TOKEN>` for SPARQL updates and graph store writes
+        #[arg(long, conflicts_with = "auth_user")]
+        auth_token: Option<String>,
+        /// Require HTTP basic authentication as this user for SPARQL updates and graph store writes
+        #[arg(long, requires = "auth_pass")]
+        auth_user: Option<String>,
+        /// Password of --auth-user
+        #[arg(long, requires = "auth_user")]
+        auth_pass: Option<String>,
+        /// Also require the credentials for queries and graph store reads
+        #[arg(long)]
+        auth_reads: bool,
+        /// Maximum number of connections handled at once [default: 128 per CPU]
+        #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+        max_connections: Option<u32>,
+        /// Seconds a connection may wait on reading a request or writing a response before it is closed,
+        /// this also closes connections kept alive and idle between requests for longer
+        #[arg(long, value_name = "SECS", default_value_t = 60, value_parser = clap::value_parser!(u64).range(1..))]
+        timeout: u64,
+        /// Pick up HDT files added to, changed in or removed from the location as soon as it happens,
+        /// instead of scanning the location on every request
+        #[arg(long)]
+        watch: bool,
+    },
+    /// Export the triples of HDT files as RDF
+    Dump {
+        #[clap(short, long, num_args = 1.., required = true)]
+        /// Path to HDT files
+        data: Vec<String>,
+        /// RDF format to write the triples in [default: ntriple]
+        #[clap(short, long, value_enum)]
+        output: Option<query::DeOutput>,
+        #[clap(long)]
+        /// Sort triples by subject, predicate and object for deterministic output. Buffers all triples in memory
+        sort: bool,
+        #[clap(long)]
+        /// Only write N randomly selected triples
+        sample: Option<usize>,
+        #[clap(long, requires = "sample")]
+        /// Seed of the random selection of --sample, for reproducible samples
+        seed: Option<u64>,
     },
     /// Use to view info about an HDT file
     View {
         #[clap(short, long, num_args = 1.., required = true)]
         /// Path to HDT files
         data: Vec<String>,
+        #[clap(long)]
+        /// Also print every raw header triple
+        raw: bool,
+        #[clap(long)]
+        /// Also list the distinct predicates used in each file
+        predicates: bool,
     },
+    /// Regenerate the index cache files of HDT files, e.g. when they are stale or corrupt
+    Reindex {
+        #[clap(short, long, num_args = 1.., required = true)]
+        /// Path to HDT files
+        data: Vec<String>,
+    },
+    /// Print the triple count of each HDT file and their total, read from the file headers
+    Stats {
+        #[clap(short, long, num_args = 1.., required = true)]
+        /// Path to HDT files
+        data: Vec<String>,
+        #[clap(long)]
+        /// Print the counts as JSON
+        json: bool,
+    },
+}
+
+/// Split a `--bind` argument into the variable name and its value
+fn parse_binding(arg: &str) -> Result<(String, String), String> {
+    arg.split_once('=')
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .ok_or_else(|| format!("expected <VARIABLE>=<VALUE>, got {arg}"))
+}
+
+fn temp_prefix_or_env(temp_prefix: &Option<String>) -> Option<String> {
+    temp_prefix
+        .clone()
+        .or_else(|| std::env::var(create::TEMP_PREFIX_ENV).ok())
+}
+
+fn keep_intermediate_from_env() -> bool {
+    std::env::var_os(create::KEEP_INTERMEDIATE_ENV).is_some()
 }
 
 #[tokio::main]
@@ -68,21 +305,239 @@ async fn main() {
     env_logger::Builder::new()
         .filter_level(cli.verbose.log_level_filter())
         .init();
+    if cli.version_info {
+        print!("{}", version::version_info());
+        std::process::exit(exitcode::OK);
+    }
+    let Some(command) = &cli.command else {
+        Cli::command()
+            .error(
+                clap::error::ErrorKind::MissingSubcommand,
+                "a subcommand is required",
+            )
+            .exit()
+    };
     let mut stdout_writer = BufWriter::new(stdout());
     // Matching CLI input to commands
-    let result = match &cli.command {
+    let result = match command {
         Commands::Query {
             data,
             sparql,
             output,
-        } => query::do_query(data, sparql, output, &mut stdout_writer).await,
-        Commands::Create { output_name, data } => match create::do_create(output_name, data) {
-            Ok(_) => Ok(()),
-            Err(e) => Err(e),
-        },
-        Commands::View { data } => view::view_hdt(data, &mut stdout_writer),
+            pretty,
+            keep_intermediate,
+            temp_prefix,
+            load_concurrency,
+            timeout_load,
+            input_format,
+            prefixes,
+            explain_errors,
+            graph,
+            delimiter,
+            graph_base,
+            short_graph_names,
+            raw_iris,
+            bind,
+            out_file,
+            output_graph,
+            explain_cost,
+            explain_unused_prefixes,
+            explain,
+            hash,
+            prefix_file,
+            converter,
+            #[cfg(feature = "profiling")]
+            profile,
+        } => {
+            let options = query::QueryOptions {
+                output: output.clone(),
+                pretty: *pretty,
+                keep_intermediate: *keep_intermediate || keep_intermediate_from_env(),
+                temp_prefix: temp_prefix_or_env(temp_prefix),
+                load_concurrency: *load_concurrency,
+                load_timeout: timeout_load.map(std::time::Duration::from_secs),
+                input_format: *input_format,
+                prefixes: *prefixes,
+                explain_errors: *explain_errors,
+                graphs: graph.clone(),
+                delimiter: *delimiter,
+                graph_base: graph_base.clone(),
+                short_graph_names: *short_graph_names,
+                raw_iris: *raw_iris,
+                bindings: bind.clone(),
+                out_file: out_file.clone(),
+                output_graph: output_graph.clone(),
+                explain_cost: *explain_cost,
+                explain_unused_prefixes: *explain_unused_prefixes,
+                explain: *explain,
+                hash: *hash,
+                prefix_file: prefix_file.clone(),
+                converter: *converter,
+                #[cfg(feature = "profiling")]
+                profile: profile.clone(),
+            };
+            // an interrupted or terminated query doesn't get to drop its working directories
+            tokio::spawn(async {
+                #[cfg(unix)]
+                let exit_code = {
+                    use tokio::signal::unix::{signal, SignalKind};
+                    let Ok(mut terminate) = signal(SignalKind::terminate()) else {
+                        return;
+                    };
+                    tokio::select! {
+                        res = tokio::signal::ctrl_c() => res.map(|()| 130),
+                        _ = terminate.recv() => Ok(143),
+                    }
+                };
+                #[cfg(not(unix))]
+                let exit_code = tokio::signal::ctrl_c().await.map(|()| 130);
+                if let Ok(exit_code) = exit_code {
+                    query::remove_temp_dirs();
+                    std::process::exit(exit_code);
+                }
+            });
+            query::do_query_with_options(data, sparql, &options, &mut stdout_writer).await
+        }
+        Commands::Create {
+            output_name,
+            data,
+            keep_intermediate,
+            temp_prefix,
+            input_format,
+            manifest,
+            no_cache,
+            explain_errors,
+            validate_iris,
+            input_dir,
+            recursive,
+            force,
+            max_triples,
+            compress_output,
+            dry_run,
+            normalize_literals,
+            converter,
+            fail_on_unhandled,
+        } => {
+            let options = create::CreateOptions {
+                keep_intermediate: *keep_intermediate || keep_intermediate_from_env(),
+                temp_prefix: temp_prefix_or_env(temp_prefix),
+                input_format: *input_format,
+                manifest: manifest.clone(),
+                no_cache: *no_cache,
+                explain_errors: *explain_errors,
+                validate_iris: *validate_iris,
+                input_dir: input_dir.clone(),
+                recursive: *recursive,
+                force: *force,
+                max_triples: *max_triples,
+                compress_output: *compress_output,
+                normalize_literals: *normalize_literals,
+                converter: *converter,
+                skip_unhandled: !*fail_on_unhandled,
+            };
+            if *dry_run {
+                create::plan_create(data, &options).and_then(|plan| {
+                    create::write_plan(&plan, &mut stdout_writer)?;
+                    if plan.unrecognized.is_empty() {
+                        Ok(())
+                    } else {
+                        Err(anyhow::anyhow!(
+                            "unsupported files detected: {:?}",
+                            plan.unrecognized
+                        ))
+                    }
+                })
+            } else {
+                match create::do_create_with_options(output_name, data, &options) {
+                    Ok(summary) => {
+                        info!(
+                            "{} data file(s) converted to NTriples",
+                            summary.conversion.converted
+                        );
+                        create::write_summary(output_name, &summary, &mut stdout_writer)
+                    }
+                    Err(e) => Err(e),
+                }
+            }
+        }
+        Commands::Dump {
+            data,
+            output,
+            sort,
+            sample,
+            seed,
+        } => {
+            let options = dump::DumpOptions {
+                output: output.clone(),
+                sort: *sort,
+                sample: *sample,
+                seed: *seed,
+            };
+            dump::do_dump(data, &options, &mut stdout_writer)
+        }
+        Commands::View {
+            data,
+            raw,
+            predicates,
+        } => {
+            let options = view::ViewOptions {
+                raw: *raw,
+                predicates: *predicates,
+            };
+            view::view_hdt_with_options(data, &options, &mut stdout_writer)
+        }
+        Commands::Reindex { data } => reindex::do_reindex(data, &mut stdout_writer),
+        Commands::Stats { data, json } => stats::do_stats(data, *json, &mut stdout_writer),
         #[cfg(feature = "server")]
-        Commands::Serve { location, bind } => de::serve::serve(location.to_owned(), bind),
+        Commands::Serve {
+            location,
+            bind,
+            load_concurrency,
+            allow_mutations,
+            no_cache,
+            union_default_graph,
+            default_graph,
+            graph_base,
+            short_graph_names,
+            rate_limit,
+            trusted_proxy,
+            query_cache_size,
+            query_cache_max_bytes,
+            auth_token,
+            auth_user,
+            auth_pass,
+            auth_reads,
+            max_connections,
+            timeout,
+            watch,
+        } => de::serve::serve(de::serve::ServeConfig {
+            bind: bind.clone(),
+            locations: location.clone(),
+            load_concurrency: *load_concurrency,
+            allow_mutations: *allow_mutations,
+            no_cache: *no_cache,
+            union_default_graph: *union_default_graph,
+            default_graph: default_graph.clone(),
+            graph_base: graph_base.clone(),
+            short_graph_names: *short_graph_names,
+            rate_limit: *rate_limit,
+            trusted_proxy: *trusted_proxy,
+            query_cache_size: *query_cache_size,
+            query_cache_max_bytes: *query_cache_max_bytes,
+            auth: match (auth_token, auth_user, auth_pass) {
+                (Some(token), _, _) => Some(de::serve::ServeAuth::Bearer(token.clone())),
+                (None, Some(user), Some(password)) => Some(de::serve::ServeAuth::Basic {
+                    user: user.clone(),
+                    password: password.clone(),
+                }),
+                _ => None,
+            },
+            auth_reads: *auth_reads,
+            max_connections: max_connections.map(|n| n as usize),
+            timeout: std::time::Duration::from_secs(*timeout),
+            watch: *watch,
+            ..Default::default()
+        }),
     };
     stdout_writer.flush().unwrap();
     match result {
@@ -0,0 +1,152 @@
+// Copyright (c) 2025, Decisym, LLC
+// Licensed under the BSD 3-Clause License (see LICENSE file in the project root).
+
+//! Record of the inputs already converted to NTriples during `de create --manifest`, so re-runs
+//! only convert the inputs that changed since the last run.
+
+use log::*;
+use serde_json::{json, Value};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::fs::{self, File, Metadata};
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+const MANIFEST_VERSION: u64 = 1;
+
+/// Converted NTriples for an input file along with the input's size and modification time at conversion
+#[derive(Clone, Debug, PartialEq)]
+struct ManifestEntry {
+    size: u64,
+    modified_ns: u64,
+    nt: PathBuf,
+}
+
+/// Manifest stored as JSON, converted NTriples are cached in a `<manifest>.d` directory next to it
+#[derive(Debug)]
+pub struct Manifest {
+    path: PathBuf,
+    cache_dir: PathBuf,
+    entries: HashMap<String, ManifestEntry>,
+}
+
+impl Manifest {
+    /// Load the manifest at `path`, starting from an empty one if the file doesn't exist yet
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let mut cache_dir = path.as_os_str().to_owned();
+        cache_dir.push(".d");
+        let mut manifest = Self {
+            path: path.to_path_buf(),
+            cache_dir: PathBuf::from(cache_dir),
+            entries: HashMap::new(),
+        };
+        if !path.exists() {
+            return Ok(manifest);
+        }
+
+        let content: Value = serde_json::from_reader(File::open(path)?)
+            .map_err(|e| anyhow::anyhow!("Error reading manifest {path:?}: {e}"))?;
+        if content["version"].as_u64() != Some(MANIFEST_VERSION) {
+            warn!("ignoring manifest {path:?} written by an unsupported version");
+            return Ok(manifest);
+        }
+        if let Some(files) = content["files"].as_object() {
+            for (file, entry) in files {
+                let (Some(size), Some(modified_ns), Some(nt)) = (
+                    entry["size"].as_u64(),
+                    entry["modified_ns"].as_u64(),
+                    entry["nt"].as_str(),
+                ) else {
+                    warn!("ignoring invalid manifest entry for {file}");
+                    continue;
+                };
+                manifest.entries.insert(
+                    file.clone(),
+                    ManifestEntry {
+                        size,
+                        modified_ns,
+                        nt: PathBuf::from(nt),
+                    },
+                );
+            }
+        }
+        Ok(manifest)
+    }
+
+    /// Write the manifest back to disk
+    pub fn save(&self) -> anyhow::Result<()> {
+        let mut files = serde_json::Map::new();
+        for (file, entry) in &self.entries {
+            files.insert(
+                file.clone(),
+                json!({
+                    "size": entry.size,
+                    "modified_ns": entry.modified_ns,
+                    "nt": entry.nt.to_string_lossy(),
+                }),
+            );
+        }
+        let content = json!({ "version": MANIFEST_VERSION, "files": files });
+        fs::write(&self.path, serde_json::to_string_pretty(&content)?)
+            .map_err(|e| anyhow::anyhow!("Error writing manifest {:?}: {e}", self.path))
+    }
+
+    /// Cached NTriples for `file`, only if the input is unchanged since it was converted
+    pub fn cached_nt(&self, file: &str) -> Option<PathBuf> {
+        let entry = self.entries.get(&key(file))?;
+        let meta = fs::metadata(file).ok()?;
+        if entry.size == meta.len()
+            && Some(entry.modified_ns) == modified_ns(&meta)
+            && entry.nt.exists()
+        {
+            Some(entry.nt.clone())
+        } else {
+            None
+        }
+    }
+
+    /// Location where the converted NTriples for `file` are cached
+    pub fn nt_path(&self, file: &str) -> anyhow::Result<PathBuf> {
+        fs::create_dir_all(&self.cache_dir)
+            .map_err(|e| anyhow::anyhow!("Error creating {:?}: {e}", self.cache_dir))?;
+        let mut hasher = DefaultHasher::new();
+        key(file).hash(&mut hasher);
+        Ok(self.cache_dir.join(format!("{:016x}.nt", hasher.finish())))
+    }
+
+    /// Record that `file` in its current state was converted to `nt`
+    pub fn record(&mut self, file: &str, nt: PathBuf) -> anyhow::Result<()> {
+        let meta = fs::metadata(file)?;
+        let modified_ns = modified_ns(&meta)
+            .ok_or_else(|| anyhow::anyhow!("modification time unavailable for {file}"))?;
+        self.entries.insert(
+            key(file),
+            ManifestEntry {
+                size: meta.len(),
+                modified_ns,
+                nt,
+            },
+        );
+        Ok(())
+    }
+
+    /// Forget `file`, deleting its cached NTriples
+    pub fn remove(&mut self, file: &str) {
+        if let Some(entry) = self.entries.remove(&key(file)) {
+            let _ = fs::remove_file(entry.nt);
+        }
+    }
+}
+
+/// Inputs are keyed on their canonical path so the manifest survives running from another directory
+fn key(file: &str) -> String {
+    fs::canonicalize(file)
+        .map(|p| p.to_string_lossy().into_owned())
+        .unwrap_or_else(|_| file.to_string())
+}
+
+fn modified_ns(meta: &Metadata) -> Option<u64> {
+    let modified = meta.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+    u64::try_from(modified.as_nanos()).ok()
+}
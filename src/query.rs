@@ -2,20 +2,24 @@
 // Licensed under the BSD 3-Clause License (see LICENSE file in the project root).
 
 use crate::create;
-use crate::rdf2nt::OxRdfConvert;
+use crate::rdf2nt::{self, Converter, InputFormat, OxRdfConvert};
 use crate::sparql;
 use anyhow::Error;
 use log::*;
-use oxrdfio::RdfFormat;
 use oxrdfio::RdfSerializer;
+use oxrdfio::{JsonLdProfileSet, RdfFormat};
+use sha2::{Digest, Sha256};
 use sparesults::QueryResultsFormat;
 use sparesults::QueryResultsSerializer;
 use spareval::QueryResults;
+use spargebra::term::{GroundTerm, NamedNode, Term, Variable};
+use spargebra::Query;
 use std::fs;
 use std::fs::File;
 use std::io::{BufWriter, Read, Write};
-use std::path::Path;
-use std::sync::Arc;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
 use tempfile::{tempdir, Builder, NamedTempFile};
 
 #[derive(clap::ValueEnum, Clone, Default, Debug, PartialEq)]
@@ -50,15 +54,257 @@ pub enum DeOutput {
 
     /// <https://www.w3.org/TR/turtle/>
     TURTLE,
+
+    /// <https://www.w3.org/TR/json-ld/>
+    #[value(alias = "json-ld")]
+    JSONLD,
+
+    /// Only the number of solutions (SELECT), triples (CONSTRUCT/DESCRIBE) or 1/0 (ASK)
+    COUNT,
+
+    /// A new HDT built from the triples of CONSTRUCT/DESCRIBE queries, written to the output file
+    HDT,
+
+    /// Picked from the extension of the output file, or else from the query form: CSV for SELECT,
+    /// JSON for ASK and Turtle for CONSTRUCT/DESCRIBE
+    AUTO,
+}
+
+/// How `--explain` writes the algebra of the queries
+#[derive(clap::ValueEnum, Clone, Copy, Default, Debug, PartialEq)]
+pub enum ExplainFormat {
+    /// The SPARQL S-Expressions of the algebra, one `<query file>\t<algebra>` line per query
+    #[default]
+    Text,
+
+    /// A JSON object per line and query, see [sparql::query_plan_json]
+    Json,
+}
+
+/// Options controlling how queries are executed and how their results are written
+#[derive(Clone, Default, Debug)]
+pub struct QueryOptions {
+    /// Format to write results in, chosen from the query form when not set
+    pub output: Option<DeOutput>,
+    /// Indent JSON query results
+    pub pretty: bool,
+    /// Keep the combined NTriples file built from non-HDT data files instead of deleting it
+    pub keep_intermediate: bool,
+    /// Maximum number of HDT files loaded in parallel, unbounded when not set
+    pub load_concurrency: Option<usize>,
+    /// Maximum time loading the HDT files may take, unbounded when not set
+    pub load_timeout: Option<std::time::Duration>,
+    /// Parse all non-HDT data files with this format instead of inferring it from the file extension
+    pub input_format: Option<InputFormat>,
+    /// Abbreviate IRIs in CSV and TSV results using the query's PREFIX declarations
+    pub prefixes: bool,
+    /// Include the offending source line in RDF syntax error messages
+    pub explain_errors: bool,
+    /// Only load and query the graphs with these names, e.g. `file:///data.hdt`. All graphs when empty
+    pub graphs: Vec<String>,
+    /// Field delimiter of CSV results, a comma when not set
+    pub delimiter: Option<char>,
+    /// Name graphs `<graph_base><file stem>` instead of `file:///<file name>`
+    pub graph_base: Option<String>,
+    /// Name graphs by their bare file stem, e.g. `data`, instead of `file:///<file name>`
+    pub short_graph_names: bool,
+    /// Write IRIs in TSV results without the angle brackets required by the spec, as in CSV results
+    pub raw_iris: bool,
+    /// Values bound to query variables before evaluation, as (variable name, IRI or quoted literal)
+    pub bindings: Vec<(String, String)>,
+    /// Write results to this file instead of the writer, its extension guides [DeOutput::AUTO]
+    pub out_file: Option<String>,
+    /// Place every CONSTRUCT and DESCRIBE triple in the graph with this IRI in N-Quads and TriG
+    /// results, instead of the graphs of the data files containing it
+    pub output_graph: Option<String>,
+    /// Instead of evaluating the queries, write their triple patterns with the number of triples each matches
+    pub explain_cost: bool,
+    /// Instead of evaluating the queries, write the prefixes each declares without using them
+    pub explain_unused_prefixes: bool,
+    /// Instead of evaluating the queries, write their algebra as evaluated in this format
+    pub explain: Option<ExplainFormat>,
+    /// Write a SHA-256 hash of the results of each query instead of the results, see [results_hash]
+    pub hash: bool,
+    /// File of `PREFIX` or Turtle `@prefix` declarations added to every query that doesn't declare the same prefix name
+    pub prefix_file: Option<String>,
+    /// Create the working directory of converted data files at `<temp_prefix>` instead of a random
+    /// directory in the system temporary directory. The combined NTriples file is then `<temp_prefix>/combined.nt`
+    pub temp_prefix: Option<String>,
+    /// Library converting the non-HDT data files
+    pub converter: Converter,
+    /// Write a pprof CPU profile of loading, evaluating and writing the results to this file
+    #[cfg(feature = "profiling")]
+    pub profile: Option<String>,
 }
-/// Execute a list of sparql queries over a list of RDF files. Non-HDT data files are converted to temporary HDT files before query execution
+
+/// Execute a list of sparql queries over a list of RDF files. Non-HDT data files are converted to temporary HDT files before query execution.
+/// When no output format is given, SELECT and ASK results are written as CSV, CONSTRUCT results as NTriples and DESCRIBE results as Turtle.
+/// The default graph is the union of all data files, HDT and converted RDF alike, with triples found in several files returned once.
+/// Results are buffered before reaching `writer`, so it doesn't need to be buffered itself.
 pub async fn do_query<W: Write>(
     data_files: &[String],
     query_files: &[String],
-    out: &DeOutput,
+    out: Option<&DeOutput>,
+    writer: &mut W,
+) -> anyhow::Result<()> {
+    let options = QueryOptions {
+        output: out.cloned(),
+        ..Default::default()
+    };
+    do_query_with_options(data_files, query_files, &options, writer).await
+}
+
+/// Same as [do_query], with additional control over execution and output through [QueryOptions]
+pub async fn do_query_with_options<W: Write>(
+    data_files: &[String],
+    query_files: &[String],
+    options: &QueryOptions,
+    writer: &mut W,
+) -> anyhow::Result<()> {
+    #[cfg(feature = "profiling")]
+    let profiler = match &options.profile {
+        Some(_) => Some(
+            pprof::ProfilerGuardBuilder::default()
+                .frequency(1000)
+                .blocklist(&["libc", "libgcc", "pthread", "vdso"])
+                .build()
+                .map_err(|e| anyhow::anyhow!("Error starting the profiler: {e}"))?,
+        ),
+        None => None,
+    };
+    let result = match &options.out_file {
+        Some(out_file) if options.output == Some(DeOutput::HDT) => {
+            write_query_results_to_hdt(data_files, query_files, options, out_file).await
+        }
+        Some(out_file) => {
+            write_query_results_to_file(data_files, query_files, options, out_file).await
+        }
+        None if options.output == Some(DeOutput::HDT) => Err(anyhow::anyhow!(
+            "HDT output can't be written to stdout, set an output file"
+        )),
+        None => {
+            let mut buffered = BufWriter::new(writer);
+            write_query_results(data_files, query_files, options, &mut buffered).await
+        }
+    };
+    #[cfg(feature = "profiling")]
+    if let (Some(profiler), Some(profile)) = (profiler, &options.profile) {
+        write_profile(&profiler, profile)?;
+    }
+    result
+}
+
+async fn write_query_results_to_file(
+    data_files: &[String],
+    query_files: &[String],
+    options: &QueryOptions,
+    out_file: &str,
+) -> anyhow::Result<()> {
+    let file = File::create(out_file)
+        .map_err(|e| anyhow::anyhow!("Error creating output file {out_file}: {e}"))?;
+    let mut file_writer = BufWriter::new(file);
+    write_query_results(data_files, query_files, options, &mut file_writer).await?;
+    file_writer.flush()?;
+    Ok(())
+}
+
+/// Builds the HDT `out_file` from the triples of all the queries, which are first written to a
+/// temporary NTriples file
+async fn write_query_results_to_hdt(
+    data_files: &[String],
+    query_files: &[String],
+    options: &QueryOptions,
+    out_file: &str,
+) -> anyhow::Result<()> {
+    if options.hash || options.explain_cost || options.explain.is_some() {
+        return Err(anyhow::anyhow!(
+            "HDT output can't be combined with hashing or explaining queries"
+        ));
+    }
+    let mut nt_file = Builder::new()
+        .suffix(".nt")
+        .tempfile()
+        .map_err(|e| anyhow::anyhow!("Error creating temporary file: {e}"))?;
+    let mut nt_writer = BufWriter::new(nt_file.as_file_mut());
+    write_query_results(data_files, query_files, options, &mut nt_writer).await?;
+    nt_writer.flush()?;
+    drop(nt_writer);
+    let nt_path = nt_file.path().to_string_lossy().to_string();
+    create::do_create(out_file, &[nt_path])
+        .map_err(|e| anyhow::anyhow!("Error creating HDT {out_file} from query results: {e}"))?;
+    info!("wrote query results to HDT {out_file}");
+    Ok(())
+}
+
+#[cfg(feature = "profiling")]
+fn write_profile(guard: &pprof::ProfilerGuard, path: &str) -> anyhow::Result<()> {
+    use pprof::protos::Message;
+
+    let profile = guard
+        .report()
+        .build()
+        .and_then(|report| report.pprof())
+        .map_err(|e| anyhow::anyhow!("Error building the profile: {e}"))?;
+    let mut content = Vec::new();
+    profile.write_to_vec(&mut content)?;
+    std::fs::write(path, content)
+        .map_err(|e| anyhow::anyhow!("Error writing profile {path}: {e}"))?;
+    info!("wrote CPU profile to {path}");
+    Ok(())
+}
+
+async fn write_query_results<W: Write>(
+    data_files: &[String],
+    query_files: &[String],
+    options: &QueryOptions,
     writer: &mut BufWriter<W>,
 ) -> anyhow::Result<()> {
     debug!("Executing querying ...");
+    // results written to a file are formatted after its extension unless a format is given
+    let out = match (&options.output, &options.out_file) {
+        (None, Some(_)) => Some(&DeOutput::AUTO),
+        (out, _) => out.as_ref(),
+    };
+    if options.pretty && !matches!(out, Some(DeOutput::JSON) | Some(DeOutput::AUTO)) {
+        warn!("pretty printing only applies to JSON output");
+    }
+    if options.prefixes
+        && !matches!(
+            out,
+            None | Some(DeOutput::CSV) | Some(DeOutput::TSV) | Some(DeOutput::AUTO)
+        )
+    {
+        warn!("prefix abbreviation only applies to CSV and TSV output");
+    }
+    if let Some(delimiter) = options.delimiter {
+        if matches!(delimiter, '"' | '\r' | '\n') {
+            return Err(anyhow::anyhow!(
+                "{delimiter:?} can't be used as CSV delimiter"
+            ));
+        }
+        if !matches!(out, None | Some(DeOutput::CSV) | Some(DeOutput::AUTO)) {
+            warn!("delimiter only applies to CSV output");
+        }
+    }
+    if options.raw_iris && !matches!(out, Some(DeOutput::TSV) | Some(DeOutput::AUTO)) {
+        warn!("raw IRIs only apply to TSV output");
+    }
+    let output_graph = options
+        .output_graph
+        .as_deref()
+        .map(|iri| {
+            NamedNode::new(iri).map_err(|e| anyhow::anyhow!("invalid output graph {iri}: {e}"))
+        })
+        .transpose()?;
+    if output_graph.is_some()
+        && !matches!(
+            out,
+            Some(DeOutput::NQUADS) | Some(DeOutput::TRIG) | Some(DeOutput::AUTO)
+        )
+    {
+        warn!("output graph only applies to NQUADS and TRIG output");
+    }
+    let bindings = parse_bindings(&options.bindings)?;
 
     // fail fast on input validation
     for rq in query_files {
@@ -72,40 +318,211 @@ pub async fn do_query<W: Write>(
         }
     }
 
-    let (dir_path_vec, hdt_path_vec, e) = handle_files(data_files.to_owned()).await;
+    let mut named_graphs = vec![];
+    let (dir_path_vec, hdt_path_vec, e) =
+        handle_files(data_files.to_owned(), options, &mut named_graphs).await;
+    // the working directories are removed whichever way this function returns
+    let _temp_dirs = TempDirGuard::new(dir_path_vec);
 
     if let Some(e) = e {
-        file_cleanup(dir_path_vec.clone()).await;
         return Err(anyhow::anyhow!("Error reading data files: {e}",));
     }
 
-    let dataset = sparql::AggregateHdt::new(&hdt_path_vec)
-        .map_err(|e| anyhow::anyhow!("error initializting HDT files: {e}"))?;
-    let snapshot = dataset
-        .get_snapshot(None)
-        .map_err(|e| anyhow::anyhow!("{e}"))?;
+    let dataset = if hdt_path_vec.is_empty() && !named_graphs.is_empty() {
+        // dataset files with only named graphs
+        sparql::AggregateHdt::empty()
+    } else {
+        sparql::AggregateHdt::new(&hdt_path_vec)
+            .map_err(|e| anyhow::anyhow!("error initializting HDT files: {e}"))?
+    };
+    let dataset = match options.load_concurrency {
+        Some(n) => dataset.with_load_concurrency(n)?,
+        None => dataset,
+    };
+    let dataset = match options.load_timeout {
+        Some(timeout) => dataset.with_load_timeout(timeout),
+        None => dataset,
+    };
+    let dataset = match &options.graph_base {
+        Some(graph_base) => dataset.with_graph_base(graph_base)?,
+        None if options.short_graph_names => dataset.with_short_graph_names()?,
+        None => dataset,
+    };
+    let mut dataset = dataset;
+    for (graph, path) in &named_graphs {
+        dataset = dataset.with_named_graph(graph, Path::new(path))?;
+    }
+    for graph in &options.graphs {
+        if !dataset.contains_graph_name(graph)? {
+            error!("graph {graph} is not one of the HDT data files");
+            let naming = match &options.graph_base {
+                Some(graph_base) => format!("{graph_base}<HDT file stem>"),
+                None if options.short_graph_names => "<HDT file stem>".to_string(),
+                None => "file:///<HDT file name>".to_string(),
+            };
+            return Err(anyhow::anyhow!(
+                "unknown graph {graph}, graphs are named {naming}"
+            ));
+        }
+    }
 
+    let shared_prefixes = match &options.prefix_file {
+        Some(prefix_file) => {
+            let declarations = fs::read_to_string(prefix_file)
+                .map_err(|e| anyhow::anyhow!("Error reading prefix file {prefix_file}: {e}"))?;
+            let prefixes = sparql::query_prefixes(&declarations);
+            if prefixes.is_empty() {
+                warn!("no prefix declarations found in {prefix_file}");
+            }
+            prefixes
+        }
+        None => vec![],
+    };
+
+    // queries are parsed before loading so only the graphs they can match are loaded
+    let mut queries = vec![];
     for rq in query_files {
         let mut f = File::open(rq)?;
         let mut buffer = String::new();
 
         f.read_to_string(&mut buffer)?;
-        let qr = match sparql::query(&buffer, &snapshot, None) {
+        if options.explain_unused_prefixes {
+            // checked before the shared prefixes are added, they aren't the query's own
+            let with_shared = sparql::prepend_prefixes(&buffer, &shared_prefixes);
+            if let Err(e) = sparql::parse_query(&with_shared, None) {
+                error!("problem parsing the query {rq}: {e}");
+                return Err(anyhow::anyhow!("{e}"));
+            }
+            for prefix in sparql::unused_prefixes(&buffer, &shared_prefixes) {
+                writeln!(writer, "{rq}\t{prefix}:")?;
+            }
+            continue;
+        }
+        if !shared_prefixes.is_empty() {
+            buffer = sparql::prepend_prefixes(&buffer, &shared_prefixes);
+        }
+        let mut parsed = match sparql::parse_query(&buffer, None) {
+            Ok(q) => q,
+            Err(e) => {
+                error!("problem parsing the query {rq}: {e}");
+                return Err(anyhow::anyhow!("{e}"));
+            }
+        };
+        if !bindings.is_empty() {
+            let (bound, unused) = sparql::bind_variables(&parsed, &bindings);
+            for variable in unused {
+                warn!(
+                    "{rq}: variable {variable} is not used by the query, binding it has no effect"
+                );
+            }
+            parsed = bound;
+        }
+        match options.explain {
+            Some(ExplainFormat::Text) => {
+                writeln!(writer, "{rq}\t{}", sparql::inline_values(&parsed).to_sse())?;
+                continue;
+            }
+            Some(ExplainFormat::Json) => {
+                let mut plan = sparql::query_plan_json(&sparql::inline_values(&parsed));
+                plan["file"] = rq.as_str().into();
+                writeln!(writer, "{plan}")?;
+                continue;
+            }
+            None => {}
+        }
+        queries.push((rq, buffer, parsed));
+    }
+    if options.explain_unused_prefixes || options.explain.is_some() {
+        writer.flush()?;
+        return Ok(());
+    }
+    let query_graphs = queries
+        .iter()
+        .map(|(_, _, parsed)| sparql::query_graphs(parsed))
+        .collect::<Option<Vec<_>>>()
+        .map(|graphs| graphs.concat());
+    let named_graphs = match (options.graphs.is_empty(), query_graphs) {
+        (true, query_graphs) => query_graphs,
+        (false, None) => Some(options.graphs.clone()),
+        (false, Some(query_graphs)) => Some(
+            options
+                .graphs
+                .iter()
+                .filter(|g| query_graphs.contains(g))
+                .cloned()
+                .collect(),
+        ),
+    };
+    if let Some(graphs) = &named_graphs {
+        debug!("loading only the graphs the queries can match: {graphs:?}");
+    }
+    let snapshot = dataset
+        .get_snapshot(named_graphs)
+        .map_err(|e| anyhow::anyhow!("{e}"))?;
+
+    for (rq, buffer, parsed) in queries {
+        if options.explain_cost {
+            for (pattern, estimate) in sparql::pattern_estimates(&parsed, &snapshot) {
+                writeln!(writer, "{estimate}\t{pattern}")?;
+            }
+            continue;
+        }
+        let auto_output;
+        let out = match out {
+            Some(DeOutput::AUTO) => {
+                auto_output = auto_output_format(&parsed, options.out_file.as_deref());
+                debug!("{rq}: writing results as {auto_output:?}");
+                Some(&auto_output)
+            }
+            out => out,
+        };
+        let is_describe = matches!(parsed, Query::Describe { .. });
+        // results are evaluated lazily, so timings include writing them out
+        let start = Instant::now();
+        let qr = match sparql::evaluate(&parsed, &snapshot) {
             Ok(r) => r,
             Err(e) => {
                 error!("problem executing the hdt query: {e}");
-                file_cleanup(dir_path_vec.clone()).await;
                 return Err(anyhow::anyhow!("{e}"));
             }
         };
 
+        if options.hash {
+            // same layout as sha256sum
+            writeln!(writer, "{}  {rq}", results_hash(qr)?)?;
+            info!("{rq}: hashed results in {:?}", start.elapsed());
+            continue;
+        }
+
+        if out == Some(&DeOutput::COUNT) {
+            // results are consumed without being serialized or buffered
+            let count = match qr {
+                QueryResults::Solutions(mut solutions) => {
+                    solutions.try_fold(0_usize, |n, s| s.map(|_| n + 1))?
+                }
+                QueryResults::Boolean(result) => usize::from(result),
+                QueryResults::Graph(mut triples) => {
+                    triples.try_fold(0_usize, |n, t| t.map(|_| n + 1))?
+                }
+            };
+            writeln!(writer, "{count}")?;
+            info!("{rq}: counted {count} results in {:?}", start.elapsed());
+            continue;
+        }
+
         match qr {
             QueryResults::Solutions(query_solution_iter) => {
                 let result_format = match out {
-                    DeOutput::CSV => QueryResultsFormat::Csv,
-                    DeOutput::TSV => QueryResultsFormat::Tsv,
-                    DeOutput::JSON => QueryResultsFormat::Json,
-                    DeOutput::XML => QueryResultsFormat::Xml,
+                    None | Some(DeOutput::CSV) => QueryResultsFormat::Csv,
+                    Some(DeOutput::TSV) => QueryResultsFormat::Tsv,
+                    Some(DeOutput::JSON) => QueryResultsFormat::Json,
+                    Some(DeOutput::XML) => QueryResultsFormat::Xml,
+                    Some(DeOutput::HDT) => {
+                        error!("HDT output is only supported for CONSTRUCT and DESCRIBE queries");
+                        return Err(anyhow::anyhow!(
+                            "HDT output is only supported for CONSTRUCT and DESCRIBE queries"
+                        ));
+                    }
                     _ => {
                         error!("ASK queries support only CSV, TSV, JSON, or XML");
                         return Err(anyhow::anyhow!(
@@ -113,28 +530,86 @@ pub async fn do_query<W: Write>(
                         ));
                     }
                 };
+                if (options.prefixes || options.raw_iris)
+                    && result_format == QueryResultsFormat::Tsv
+                {
+                    let prefixes = if options.prefixes {
+                        sparql::query_prefixes(&buffer)
+                    } else {
+                        vec![]
+                    };
+                    let count =
+                        write_custom_tsv(query_solution_iter, &prefixes, options.raw_iris, writer)?;
+                    info!("{rq}: {count} solutions in {:?}", start.elapsed());
+                    continue;
+                }
+                let prefixes = if options.prefixes && result_format == QueryResultsFormat::Csv {
+                    sparql::query_prefixes(&buffer)
+                } else {
+                    vec![]
+                };
+                if let Some(delimiter) = options.delimiter.filter(|d| *d != ',') {
+                    if result_format == QueryResultsFormat::Csv {
+                        let count =
+                            write_delimited_csv(query_solution_iter, delimiter, &prefixes, writer)?;
+                        info!("{rq}: {count} solutions in {:?}", start.elapsed());
+                        continue;
+                    }
+                }
+                let pretty_json = options.pretty && result_format == QueryResultsFormat::Json;
+                let mut json_buffer = Vec::new();
+                let target: &mut dyn Write = if pretty_json {
+                    &mut json_buffer
+                } else {
+                    &mut *writer
+                };
                 let results_writer = QueryResultsSerializer::from_format(result_format);
                 let mut serializer = results_writer.serialize_solutions_to_writer(
-                    &mut *writer,
+                    target,
                     query_solution_iter.variables().into(),
                 )?;
+                let mut count = 0;
                 for s in query_solution_iter {
                     let s = s?;
-                    serializer.serialize(&s).map_err(|e| {
+                    let serialized = if prefixes.is_empty() {
+                        serializer.serialize(&s)
+                    } else {
+                        // CSV writes IRIs without brackets, so a prefixed name can stand in for the IRI
+                        let abbreviated: Vec<_> = s
+                            .iter()
+                            .map(|(v, t)| match abbreviate_iri(t, &prefixes) {
+                                Some(name) => (v, Term::NamedNode(NamedNode::new_unchecked(name))),
+                                None => (v, t.clone()),
+                            })
+                            .collect();
+                        serializer.serialize(abbreviated.iter().map(|(v, t)| (*v, t)))
+                    };
+                    serialized.map_err(|e| {
                         error!("error serializing query solutions to desired output format: {e}");
                         anyhow::anyhow!(
                             "error serializing query solutions to desired output format: {e}"
                         )
                     })?;
+                    count += 1;
                 }
                 serializer.finish()?;
+                if pretty_json {
+                    write_pretty_json(&json_buffer, writer)?;
+                }
+                info!("{rq}: {count} solutions in {:?}", start.elapsed());
             }
             QueryResults::Boolean(result) => {
                 let result_format = match out {
-                    DeOutput::CSV => QueryResultsFormat::Csv,
-                    DeOutput::TSV => QueryResultsFormat::Tsv,
-                    DeOutput::JSON => QueryResultsFormat::Json,
-                    DeOutput::XML => QueryResultsFormat::Xml,
+                    None | Some(DeOutput::CSV) => QueryResultsFormat::Csv,
+                    Some(DeOutput::TSV) => QueryResultsFormat::Tsv,
+                    Some(DeOutput::JSON) => QueryResultsFormat::Json,
+                    Some(DeOutput::XML) => QueryResultsFormat::Xml,
+                    Some(DeOutput::HDT) => {
+                        error!("HDT output is only supported for CONSTRUCT and DESCRIBE queries");
+                        return Err(anyhow::anyhow!(
+                            "HDT output is only supported for CONSTRUCT and DESCRIBE queries"
+                        ));
+                    }
                     _ => {
                         warn!(
                             "ASK queries support only CSV, TSV, JSON, or XML. Defaulting to CSV format"
@@ -142,76 +617,368 @@ pub async fn do_query<W: Write>(
                         QueryResultsFormat::Csv
                     }
                 };
+                let pretty_json = options.pretty && result_format == QueryResultsFormat::Json;
+                let mut json_buffer = Vec::new();
+                let target: &mut dyn Write = if pretty_json {
+                    &mut json_buffer
+                } else {
+                    &mut *writer
+                };
                 let results_writer = QueryResultsSerializer::from_format(result_format);
                 results_writer
-                    .serialize_boolean_to_writer(&mut *writer, result)
+                    .serialize_boolean_to_writer(target, result)
                     .map_err(|e| {
                         error!("error serializing query solutions to desired output format: {e}");
                         anyhow::anyhow!(
                             "error serializing query solutions to desired output format: {e}"
                         )
                     })?;
+                if pretty_json {
+                    write_pretty_json(&json_buffer, writer)?;
+                }
+                info!("{rq}: {result} in {:?}", start.elapsed());
             }
             QueryResults::Graph(query_triple_iter) => {
                 let result_format = match out {
-                    DeOutput::N3 => RdfFormat::N3,
-                    DeOutput::NQUADS => RdfFormat::NQuads,
-                    DeOutput::NTRIPLE => RdfFormat::NTriples,
-                    DeOutput::RDFXML => RdfFormat::RdfXml,
-                    DeOutput::TRIG => RdfFormat::TriG,
-                    DeOutput::TURTLE => RdfFormat::Turtle,
+                    // DESCRIBE returns resource descriptions, which read best grouped by subject
+                    None if is_describe => RdfFormat::Turtle,
+                    None => RdfFormat::NTriples,
+                    Some(DeOutput::N3) => RdfFormat::N3,
+                    Some(DeOutput::NQUADS) => RdfFormat::NQuads,
+                    // the HDT is built from NTriples, see write_query_results_to_hdt
+                    Some(DeOutput::NTRIPLE) | Some(DeOutput::HDT) => RdfFormat::NTriples,
+                    Some(DeOutput::RDFXML) => RdfFormat::RdfXml,
+                    Some(DeOutput::TRIG) => RdfFormat::TriG,
+                    Some(DeOutput::TURTLE) => RdfFormat::Turtle,
+                    Some(DeOutput::JSONLD) => RdfFormat::JsonLd {
+                        profile: JsonLdProfileSet::empty(),
+                    },
                     _ => {
-                        warn!("CONSTRUCT and DESCRIBE queries only support NQ, NT, RDFXML, TRIG, and TTL formats. Defaulting to NTriple format");
-                        RdfFormat::NTriples
+                        error!("CONSTRUCT and DESCRIBE queries support only N3, NQUADS, RDFXML, NTRIPLE, TRIG, TURTLE, or JSONLD");
+                        return Err(anyhow::anyhow!(
+                            "CONSTRUCT and DESCRIBE queries support only N3, NQUADS, RDFXML, NTRIPLE, TRIG, TURTLE, or JSONLD"
+                        ));
                     }
                 };
+                // dataset formats place each triple in the graphs it is found in
+                let with_graphs = matches!(result_format, RdfFormat::NQuads | RdfFormat::TriG);
                 let mut serializer =
                     RdfSerializer::from_format(result_format).for_writer(&mut *writer);
+                let mut count = 0;
                 for triple in query_triple_iter {
                     let triple = triple?;
-                    serializer.serialize_triple(&triple)?
+                    if let (true, Some(output_graph)) = (with_graphs, &output_graph) {
+                        serializer
+                            .serialize_quad(triple.as_ref().in_graph(output_graph.as_ref()))?;
+                        count += 1;
+                        continue;
+                    }
+                    let graphs = if with_graphs {
+                        snapshot.graphs_containing(&triple)
+                    } else {
+                        vec![]
+                    };
+                    if graphs.is_empty() {
+                        serializer.serialize_triple(&triple)?;
+                    }
+                    for graph in graphs {
+                        // graph names aren't always absolute IRIs, see --short-graph-names
+                        let graph_name = NamedNode::new_unchecked(graph);
+                        serializer.serialize_quad(triple.as_ref().in_graph(graph_name.as_ref()))?;
+                    }
+                    count += 1;
                 }
                 serializer.finish()?;
+                info!("{rq}: {count} triples in {:?}", start.elapsed());
+                if is_describe && count == 0 {
+                    warn!("DESCRIBE query {rq} did not match any resources");
+                }
             }
         };
     }
     writer.flush()?;
 
-    // TODO this needs to be run on success and before any return Err()
-    file_cleanup(dir_path_vec.clone()).await;
+    Ok(())
+}
+
+/// Hex encoded SHA-256 hash of query results that doesn't depend on the order of solutions or
+/// triples, so the same results always hash the same. SELECT solutions are hashed as sorted rows
+/// of N-Triples terms, CONSTRUCT and DESCRIBE results as sorted N-Triples lines. Blank nodes are
+/// hashed by label, so results with blank nodes created by the query differ from run to run.
+pub fn results_hash(results: QueryResults<'_>) -> anyhow::Result<String> {
+    let lines = match results {
+        QueryResults::Solutions(solutions) => {
+            let variables = solutions.variables().to_vec();
+            let mut rows = solutions
+                .map(|s| {
+                    let s = s?;
+                    Ok(variables
+                        .iter()
+                        .map(|v| s.get(v).map(|t| t.to_string()).unwrap_or_default())
+                        .collect::<Vec<_>>()
+                        .join("\t"))
+                })
+                .collect::<anyhow::Result<Vec<_>>>()?;
+            rows.sort();
+            let header = variables
+                .iter()
+                .map(|v| v.to_string())
+                .collect::<Vec<_>>()
+                .join("\t");
+            std::iter::once(header).chain(rows).collect()
+        }
+        QueryResults::Boolean(result) => vec![result.to_string()],
+        QueryResults::Graph(triples) => {
+            let mut lines = triples
+                .map(|t| Ok(format!("{} .", t?)))
+                .collect::<anyhow::Result<Vec<_>>>()?;
+            lines.sort();
+            lines
+        }
+    };
+    let mut hasher = Sha256::new();
+    for line in lines {
+        hasher.update(line.as_bytes());
+        hasher.update(b"\n");
+    }
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect())
+}
+
+/// Format picked by [DeOutput::AUTO]: the one matching the extension of `out_file` when it
+/// suits the query form, otherwise the default of the query form
+fn auto_output_format(query: &Query, out_file: Option<&str>) -> DeOutput {
+    let graph_results = matches!(query, Query::Construct { .. } | Query::Describe { .. });
+    let extension = out_file
+        .and_then(|f| Path::new(f).extension())
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_ascii_lowercase());
+    let from_extension = match (extension.as_deref(), graph_results) {
+        (Some("csv"), false) => Some(DeOutput::CSV),
+        (Some("tsv"), false) => Some(DeOutput::TSV),
+        (Some("json" | "srj"), false) => Some(DeOutput::JSON),
+        (Some("xml" | "srx"), false) => Some(DeOutput::XML),
+        (Some("ttl"), true) => Some(DeOutput::TURTLE),
+        (Some("nt"), true) => Some(DeOutput::NTRIPLE),
+        (Some("nq"), true) => Some(DeOutput::NQUADS),
+        (Some("rdf" | "owl" | "xml"), true) => Some(DeOutput::RDFXML),
+        (Some("trig"), true) => Some(DeOutput::TRIG),
+        (Some("n3"), true) => Some(DeOutput::N3),
+        (Some("jsonld" | "json"), true) => Some(DeOutput::JSONLD),
+        _ => None,
+    };
+    from_extension.unwrap_or(match query {
+        Query::Select { .. } => DeOutput::CSV,
+        Query::Ask { .. } => DeOutput::JSON,
+        _ => DeOutput::TURTLE,
+    })
+}
+
+/// Parse `--bind` values into the terms bound to each variable, a variable may be bound once
+fn parse_bindings(bindings: &[(String, String)]) -> anyhow::Result<Vec<(Variable, GroundTerm)>> {
+    let mut parsed: Vec<(Variable, GroundTerm)> = vec![];
+    for (name, value) in bindings {
+        let variable = Variable::new(name.trim_start_matches(['?', '$']))
+            .map_err(|e| anyhow::anyhow!("invalid variable name {name}: {e}"))?;
+        if parsed.iter().any(|(v, _)| *v == variable) {
+            return Err(anyhow::anyhow!(
+                "variable {variable} is bound more than once"
+            ));
+        }
+        let term = match sparql::parse_object(value)
+            .map_err(|e| anyhow::anyhow!("invalid value for {variable}: {e}"))?
+        {
+            Term::NamedNode(iri) => GroundTerm::NamedNode(iri),
+            Term::Literal(literal) => GroundTerm::Literal(literal),
+            _ => {
+                return Err(anyhow::anyhow!(
+                    "{variable} can only be bound to an IRI or a literal, not {value}"
+                ))
+            }
+        };
+        parsed.push((variable, term));
+    }
+    Ok(parsed)
+}
+
+/// Prefixed name for an IRI term under the longest matching namespace, if any
+fn abbreviate_iri(term: &Term, prefixes: &[(String, String)]) -> Option<String> {
+    let Term::NamedNode(iri) = term else {
+        return None;
+    };
+    prefixes
+        .iter()
+        .filter_map(|(name, namespace)| {
+            let local = iri.as_str().strip_prefix(namespace.as_str())?;
+            local
+                .chars()
+                .all(|c| c.is_alphanumeric() || c == '_' || c == '-')
+                .then_some((namespace.len(), format!("{name}:{local}")))
+        })
+        .max_by_key(|(len, _)| *len)
+        .map(|(_, name)| name)
+}
+
+/// Writes SPARQL TSV results with IRIs abbreviated to prefixed names, or with `raw_iris` as bare
+/// IRIs, which the TSV serializer can't express since it writes every IRI between angle brackets
+fn write_custom_tsv<W: Write>(
+    solutions: spareval::QuerySolutionIter<'_>,
+    prefixes: &[(String, String)],
+    raw_iris: bool,
+    writer: &mut W,
+) -> anyhow::Result<usize> {
+    let variables = solutions.variables().to_vec();
+    let header: Vec<_> = variables.iter().map(|v| v.to_string()).collect();
+    writeln!(writer, "{}", header.join("\t"))?;
+    let mut count = 0;
+    for s in solutions {
+        let s = s?;
+        let row: Vec<_> = variables
+            .iter()
+            .map(|v| match s.get(v) {
+                Some(t) => match (abbreviate_iri(t, prefixes), t) {
+                    (Some(name), _) => name,
+                    (None, Term::NamedNode(iri)) if raw_iris => iri.as_str().to_string(),
+                    (None, t) => t.to_string(),
+                },
+                None => String::new(),
+            })
+            .collect();
+        writeln!(writer, "{}", row.join("\t"))?;
+        count += 1;
+    }
+    Ok(count)
+}
+
+/// Writes SPARQL CSV results separating fields with `delimiter`, which the CSV serializer
+/// always separates with a comma. Fields containing the delimiter, a quote or a line break are quoted.
+fn write_delimited_csv<W: Write>(
+    solutions: spareval::QuerySolutionIter<'_>,
+    delimiter: char,
+    prefixes: &[(String, String)],
+    writer: &mut W,
+) -> anyhow::Result<usize> {
+    let separator = delimiter.to_string();
+    let field = |value: &str| {
+        if value.contains(|c| c == delimiter || matches!(c, '"' | '\r' | '\n')) {
+            format!("\"{}\"", value.replace('"', "\"\""))
+        } else {
+            value.to_string()
+        }
+    };
+    let variables = solutions.variables().to_vec();
+    let header: Vec<_> = variables.iter().map(|v| field(v.as_str())).collect();
+    write!(writer, "{}\r\n", header.join(&separator))?;
+    let mut count = 0;
+    for s in solutions {
+        let s = s?;
+        let row: Vec<_> = variables
+            .iter()
+            .map(|v| match s.get(v) {
+                Some(t) => field(&match abbreviate_iri(t, prefixes) {
+                    Some(name) => name,
+                    None => csv_value(t),
+                }),
+                None => String::new(),
+            })
+            .collect();
+        write!(writer, "{}\r\n", row.join(&separator))?;
+        count += 1;
+    }
+    Ok(count)
+}
+
+/// Value of a term in SPARQL CSV results, without the syntax distinguishing IRIs from literals
+fn csv_value(term: &Term) -> String {
+    match term {
+        Term::NamedNode(iri) => iri.as_str().to_string(),
+        Term::Literal(literal) => literal.value().to_string(),
+        t => t.to_string(),
+    }
+}
 
+/// Re-serializes compact SPARQL JSON results with indentation
+fn write_pretty_json<W: Write>(json: &[u8], writer: &mut W) -> anyhow::Result<()> {
+    let value: serde_json::Value = serde_json::from_slice(json)
+        .map_err(|e| anyhow::anyhow!("error reading JSON query results: {e}"))?;
+    serde_json::to_writer_pretty(&mut *writer, &value)?;
+    writeln!(writer)?;
     Ok(())
 }
 
-async fn handle_files(files: Vec<String>) -> (Vec<String>, Vec<String>, Option<anyhow::Error>) {
+/// Converts the data files to HDT. The named graphs of dataset files, e.g. TriG, are converted to
+/// a HDT each, added to `named_graphs` along with their graph name, and the rest merged.
+async fn handle_files(
+    files: Vec<String>,
+    options: &QueryOptions,
+    named_graphs: &mut Vec<(String, String)>,
+) -> (Vec<String>, Vec<String>, Option<anyhow::Error>) {
     let mut dir_path_vec: Vec<String> = vec![]; // This is holding the path to the tempfiles that havent been removed from disk
     let mut hdt_path_vec: Vec<String> = vec![]; // This is holding all the paths to the hdt files. this needs to stay
-    let tmp_dir = match tempdir() {
+    let tmp_dir = match &options.temp_prefix {
+        Some(temp_prefix) => create::split_temp_prefix(temp_prefix).and_then(|(dir, name)| {
+            create::temp_builder(Some(name), "")
+                .tempdir_in(dir)
+                .map_err(|e| create::temp_prefix_error(temp_prefix, "", e))
+        }),
+        None => tempdir().map_err(|e| anyhow::anyhow!("{e:?}")),
+    };
+    let tmp_dir = match tmp_dir {
         Ok(d) => d,
         Err(e) => {
             return (
                 dir_path_vec,
                 hdt_path_vec,
-                Some(anyhow::anyhow!(
-                    "Error creating temporary working dir: {:?}",
-                    e
-                )),
+                Some(anyhow::anyhow!("Error creating temporary working dir: {e}")),
             )
         }
     };
     let t_path = tmp_dir.path(); // Getting the tempdir path.
 
+    // files in a working dir of a known name get known names too
+    let temp_name = options.temp_prefix.as_ref().map(|_| "combined");
+
     // Creating TempFile to hold the hdt contents
-    let mut rdf_tempfile: NamedTempFile = Builder::new()
-        .suffix(".nt")
-        .append(true)
+    let mut rdf_tempfile: NamedTempFile = create::temp_builder(temp_name, ".nt")
         .tempfile_in(t_path)
         .unwrap();
 
     let mut files_to_convert = vec![];
+    let mut decompressed = false;
     for f in &files {
         if f.ends_with(".hdt") {
             hdt_path_vec.push(f.to_string())
+        } else if create::HdtCompression::from_file_name(f).is_some() {
+            match create::decompress_hdt(f, t_path) {
+                Ok(p) => hdt_path_vec.push(p.to_string_lossy().into_owned()),
+                Err(e) => return (dir_path_vec, hdt_path_vec, Some(e)),
+            }
+            decompressed = true;
+        } else if f.ends_with(".zip") {
+            match create::extract_hdt_zip(f, t_path) {
+                Ok(paths) => {
+                    hdt_path_vec.extend(paths.into_iter().map(|p| p.to_string_lossy().into_owned()))
+                }
+                Err(e) => return (dir_path_vec, hdt_path_vec, Some(e)),
+            }
+            decompressed = true;
+        } else if let Some(format) = rdf2nt::dataset_format(f, options.input_format) {
+            let split = rdf2nt::split_dataset(f, format, t_path).and_then(|(default, named)| {
+                for (graph, nt) in named {
+                    named_graphs.push((graph, nt_to_hdt(&nt)?.to_string_lossy().into_owned()));
+                }
+                Ok(default)
+            });
+            match split {
+                Ok(default) => {
+                    files_to_convert.extend(default.map(|p| p.to_string_lossy().into_owned()))
+                }
+                Err(e) => return (dir_path_vec, hdt_path_vec, Some(e)),
+            }
+            decompressed = true;
         } else {
             files_to_convert.push(f.to_string());
         }
@@ -220,7 +987,12 @@ async fn handle_files(files: Vec<String>) -> (Vec<String>, Vec<String>, Option<a
     let (combined_rdf_path, unknown_files) = match create::files_to_rdf(
         &files_to_convert,
         &mut rdf_tempfile,
-        Arc::new(OxRdfConvert {}),
+        options.converter.rdf2nt(OxRdfConvert {
+            input_format: options.input_format,
+            explain_errors: options.explain_errors,
+            max_triples: None,
+            normalize_literals: false,
+        }),
     ) {
         Ok((p, u)) => (p, u),
         Err(e) => {
@@ -261,11 +1033,10 @@ async fn handle_files(files: Vec<String>) -> (Vec<String>, Vec<String>, Option<a
         rdf_tempfile.path()
     };
 
-    if meta.len() != 0 || rdf_tempfile.path() != Path::new(&combined_rdf_path) {
+    let converted = meta.len() != 0 || rdf_tempfile.path() != Path::new(&combined_rdf_path);
+    if converted {
         // Creating TempFile to hold the hdt contents
-        let named_tempfile: NamedTempFile = Builder::new()
-            .suffix(".hdt")
-            .append(true)
+        let named_tempfile: NamedTempFile = create::temp_builder(temp_name, ".hdt")
             .tempfile_in(t_path)
             .unwrap();
 
@@ -292,20 +1063,79 @@ async fn handle_files(files: Vec<String>) -> (Vec<String>, Vec<String>, Option<a
         };
         hdt_path_vec.push(named_tempfile.path().to_str().unwrap().to_string());
         let _ = named_tempfile.keep();
+        if options.keep_intermediate && meta.len() != 0 {
+            // leave the working dir in place so the combined NTriples file survives the query
+            info!(
+                "intermediate NTriples file: {}",
+                rdf_tempfile.path().to_str().unwrap()
+            );
+            let _ = rdf_tempfile.keep();
+        } else {
+            dir_path_vec.push(t_path.to_str().unwrap().to_string());
+        }
+        let _ = tmp_dir.keep();
+    } else if decompressed {
         dir_path_vec.push(t_path.to_str().unwrap().to_string());
         let _ = tmp_dir.keep();
     }
 
-    if hdt_path_vec.is_empty() {
+    if hdt_path_vec.is_empty() && named_graphs.is_empty() {
         error!("no files to query")
     }
     (dir_path_vec, hdt_path_vec, None)
 }
 
+/// Builds the HDT of a NTriples file next to it, with the same file stem
+fn nt_to_hdt(nt: &Path) -> anyhow::Result<PathBuf> {
+    let hdt = hdt::Hdt::read_nt(nt)
+        .map_err(|e| anyhow::anyhow!("error converting {} to HDT: {e}", nt.display()))?;
+    let path = nt.with_extension("hdt");
+    let mut writer = BufWriter::new(File::create(&path)?);
+    hdt.write(&mut writer)?;
+    writer.flush()?;
+    Ok(path)
+}
+
 // performs directory removal for a list of directories
 pub async fn file_cleanup(dirs: Vec<String>) {
+    remove_dirs(&dirs);
+}
+
+/// Working directories of the queries being run, see [remove_temp_dirs]
+static TEMP_DIRS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+/// Removes the working directories of a query when dropped, on success, error and panic alike
+struct TempDirGuard(Vec<String>);
+
+impl TempDirGuard {
+    fn new(dirs: Vec<String>) -> Self {
+        lock_temp_dirs().extend(dirs.iter().cloned());
+        Self(dirs)
+    }
+}
+
+impl Drop for TempDirGuard {
+    fn drop(&mut self) {
+        lock_temp_dirs().retain(|d| !self.0.contains(d));
+        remove_dirs(&self.0);
+    }
+}
+
+fn lock_temp_dirs() -> std::sync::MutexGuard<'static, Vec<String>> {
+    // a panic while holding the lock leaves a usable list behind
+    TEMP_DIRS.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Removes the working directories of all queries being run, for when the process is interrupted
+/// and their cleanup won't get to run
+pub fn remove_temp_dirs() {
+    let dirs = std::mem::take(&mut *lock_temp_dirs());
+    remove_dirs(&dirs);
+}
+
+fn remove_dirs(dirs: &[String]) {
     debug!("Cleaning up environment");
-    for dir in dirs.iter() {
+    for dir in dirs {
         if let Err(e) = fs::remove_dir_all(dir) {
             error!("Failed to remove directory {dir:?}: {e:?}")
         };
@@ -2,13 +2,21 @@
 // Licensed under the BSD 3-Clause License (see LICENSE file in the project root).
 
 use log::{debug, error, warn};
+use oxrdf::vocab::xsd;
 use oxrdf::GraphName::DefaultGraph;
-use oxrdf::TripleRef;
+use oxrdf::{BlankNode, Literal, NamedOrBlankNode, Term, Triple};
 use oxrdfio::RdfFormat::{self, NTriples};
 use oxrdfio::RdfSerializer;
-use oxrdfio::{RdfParseError, RdfParser};
-use std::io::{BufReader, BufWriter, Write};
+use oxrdfio::{RdfParseError, RdfParser, RdfSyntaxError};
+use oxsdatatypes::{Boolean, Date, DateTime, Decimal, Double, Float, Integer, Time};
+use std::collections::hash_map::DefaultHasher;
+use std::fmt::Display;
+use std::fs::File;
+use std::hash::{Hash, Hasher};
+use std::io::{BufRead, BufReader, BufWriter, Cursor, Read, Write};
 use std::path::Path;
+use std::str::FromStr;
+use std::sync::Arc;
 
 /// Trait for different RDF libraries to implement for converting a list of files into NTriple RDF
 /// returns stats on converted data via ConvertResult
@@ -18,6 +26,16 @@ pub trait Rdf2Nt {
         file_paths: Vec<String>,
         output_file: &std::fs::File,
     ) -> anyhow::Result<ConvertResult>;
+
+    /// Whether N-Triples inputs have to go through [Rdf2Nt::convert_to_nt] too, rather than being copied as is
+    fn converts_ntriples(&self) -> bool {
+        false
+    }
+
+    /// Number of triples past which creating a HDT fails, N-Triples inputs copied as is included
+    fn max_triples(&self) -> Option<u64> {
+        None
+    }
 }
 
 #[derive(Debug, Default)]
@@ -25,10 +43,90 @@ pub trait Rdf2Nt {
 pub struct ConvertResult {
     pub converted: i32,
     pub unhandled: Vec<String>,
+    /// Number of triples written for each converted file, in conversion order
+    pub triples: Vec<(String, u64)>,
+}
+
+/// RDF serialization formats accepted as input, used to override detection from file extensions
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum InputFormat {
+    /// <https://www.w3.org/TR/turtle/>
+    Ttl,
+
+    /// <https://www.w3.org/TR/n-triples/>
+    Nt,
+
+    /// <https://www.w3.org/TR/n-quads/>
+    Nq,
+
+    /// <https://www.w3.org/TR/rdf-syntax-grammar/>
+    Rdfxml,
+
+    /// <https://www.w3.org/TR/json-ld/>
+    Jsonld,
+
+    /// <https://www.w3.org/TR/trig/>
+    Trig,
+
+    /// <https://w3c.github.io/N3/spec/>
+    N3,
+}
+
+impl InputFormat {
+    /// Parser format for this input format
+    pub fn rdf_format(self) -> anyhow::Result<RdfFormat> {
+        let extension = match self {
+            InputFormat::Ttl => "ttl",
+            InputFormat::Nt => "nt",
+            InputFormat::Nq => "nq",
+            InputFormat::Rdfxml => "rdf",
+            InputFormat::Jsonld => "jsonld",
+            InputFormat::Trig => "trig",
+            InputFormat::N3 => "n3",
+        };
+        RdfFormat::from_extension(extension)
+            .ok_or_else(|| anyhow::anyhow!("input format {self:?} is not supported"))
+    }
+}
+
+/// [Rdf2Nt] implementations data files can be converted with, e.g. to compare them
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq)]
+pub enum Converter {
+    /// [OxRdfConvert]
+    #[default]
+    Oxrdf,
+}
+
+impl Converter {
+    /// Converter of this kind, configured as `settings` as far as it supports
+    pub fn rdf2nt(self, settings: OxRdfConvert) -> Arc<dyn Rdf2Nt> {
+        match self {
+            Converter::Oxrdf => Arc::new(settings),
+        }
+    }
+}
+
+/// How strictly IRIs of converted data are checked against RFC 3987
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum IriValidation {
+    /// Log each triple with an invalid IRI
+    Warn,
+    /// Fail on the first triple with an invalid IRI
+    Strict,
 }
 
 /// Rdf2Nt implementation using oxrdf and oxrdfio crates
-pub struct OxRdfConvert {}
+#[derive(Default)]
+pub struct OxRdfConvert {
+    /// Parse every file with this format instead of inferring it from the file extension
+    pub input_format: Option<InputFormat>,
+    /// Include the offending source line in syntax error messages
+    pub explain_errors: bool,
+    /// Fail once more than this many triples were written, unlimited when not set
+    pub max_triples: Option<u64>,
+    /// Write typed literals of numeric, boolean and date/time datatypes in their canonical form
+    pub normalize_literals: bool,
+}
 
 impl Rdf2Nt for OxRdfConvert {
     fn convert_to_nt(
@@ -38,33 +136,43 @@ impl Rdf2Nt for OxRdfConvert {
     ) -> anyhow::Result<ConvertResult> {
         let mut res = ConvertResult::default();
         let mut dest_writer = BufWriter::new(output_file);
+        let mut written = 0_u64;
         for file in &file_paths {
             let source = std::fs::File::open(file)
                 .map_err(|e| anyhow::anyhow!("Error opening file {:?}: {:?}", file, e))?;
-            let source_reader = BufReader::new(source);
+            // the RDF format of compressed files is given by the extension before the compression one
+            let (name, compression) = Compression::from_file_name(file);
+            let source_reader = utf8_reader(file, compression.decoder(source))?;
 
             debug!("converting {} to nt format", &file);
 
             let mut serializer =
                 RdfSerializer::from_format(NTriples).for_writer(dest_writer.by_ref());
             let v = std::time::Instant::now();
-            let rdf_format = match Path::new(&file)
-                .extension()
-                .and_then(|ext| ext.to_str())
-                .and_then(RdfFormat::from_extension)
-            {
-                Some(format) => format,
-                None if file.ends_with(".owl") => {
-                    // OWL files should be in XML format: https://www.w3.org/TR/owl-xmlsyntax/
-                    RdfFormat::RdfXml
-                }
-                None => {
-                    res.unhandled.push(file.to_string());
-                    continue;
+            let rdf_format = if let Some(input_format) = self.input_format {
+                input_format.rdf_format()?
+            } else {
+                match Path::new(name)
+                    .extension()
+                    .and_then(|ext| ext.to_str())
+                    .and_then(RdfFormat::from_extension)
+                {
+                    Some(format) => format,
+                    None if name.ends_with(".owl") => {
+                        // OWL files should be in XML format: https://www.w3.org/TR/owl-xmlsyntax/
+                        RdfFormat::RdfXml
+                    }
+                    None => {
+                        res.unhandled.push(file.to_string());
+                        continue;
+                    }
                 }
             };
+            let blank_node_prefix = blank_node_prefix(file);
             // TODO oxrdfio does offer split_file_for_parallel_parsing() which greatly improves performance, but only available for NT or NQ formats
             let quads = RdfParser::from_format(rdf_format).for_reader(source_reader);
+            let mut triple_count = 0;
+            let mut skipped_n3 = 0;
             for q in quads {
                 let q = match q {
                     Ok(v) => v,
@@ -72,35 +180,433 @@ impl Rdf2Nt for OxRdfConvert {
                         // I/O error while reading file
                         return Err(anyhow::anyhow!("Error reading file {file}: {v}"));
                     }
+                    Err(RdfParseError::Syntax(syn_err))
+                        if rdf_format == RdfFormat::N3 && is_n3_only_statement(&syn_err) =>
+                    {
+                        debug!("skipping N3 statement of {file}: {syn_err}");
+                        skipped_n3 += 1;
+                        continue;
+                    }
                     Err(RdfParseError::Syntax(syn_err)) => {
                         if rdf_format == RdfFormat::RdfXml {
                             // XML file extensions are not guaranteed to be RdfXML
                             res.unhandled.push(file.to_string());
                             break;
+                        } else if is_quoted_triple_error(file, compression, &syn_err) {
+                            // quoted triples parse only with RDF 1.2 support, which HDT can't store anyway
+                            error!("RDF-star quoted triples found in {file}: {syn_err}");
+                            return Err(anyhow::anyhow!(
+                                "RDF-star quoted triples are not supported, HDT can only store plain triples. Syntax error for RDF file {file}: {syn_err}"
+                            ));
                         } else {
                             // based on file extension, should have been able to parse
-                            error!("syntax error for RDF file {file}: {syn_err}");
+                            let context = if self.explain_errors {
+                                explain_syntax_error(file, compression, &syn_err)
+                                    .unwrap_or_default()
+                            } else {
+                                String::new()
+                            };
+                            error!("syntax error for RDF file {file}: {syn_err}{context}");
                             return Err(anyhow::anyhow!(
-                                "syntax error for RDF file {file}: {syn_err}"
+                                "syntax error for RDF file {file}: {syn_err}{context}"
                             ));
                         }
                     }
                 };
+                if q.graph_name != DefaultGraph && rdf_format == RdfFormat::N3 {
+                    // statements of N3 formulas, e.g. rule bodies, are quoted rather than asserted
+                    skipped_n3 += 1;
+                    continue;
+                }
                 if q.graph_name != DefaultGraph {
                     warn!("HDT does not support named graphs, merging triples for {file}");
                 }
-                serializer.serialize_triple(TripleRef::new(
-                    q.subject.as_ref(),
-                    q.predicate.as_ref(),
-                    q.object.as_ref(),
-                ))?
+                // blank nodes are scoped to their file, labels are made unique so files don't share nodes once merged
+                let subject = match q.subject {
+                    NamedOrBlankNode::BlankNode(b) => {
+                        rename_blank_node(&blank_node_prefix, &b).into()
+                    }
+                    s => s,
+                };
+                let object = match q.object {
+                    Term::BlankNode(b) => rename_blank_node(&blank_node_prefix, &b).into(),
+                    Term::Literal(l) if self.normalize_literals => normalize_literal(l).into(),
+                    o => o,
+                };
+                serializer.serialize_triple(&Triple::new(subject, q.predicate, object))?;
+                triple_count += 1;
+                written += 1;
+                if let Some(max) = self.max_triples.filter(|max| written > *max) {
+                    return Err(anyhow::anyhow!(
+                        "more than the maximum of {max} triples converted, stopped in {file}"
+                    ));
+                }
             }
 
+            if skipped_n3 > 0 {
+                warn!("skipped {skipped_n3} N3 statements of {file} that are not plain RDF triples, e.g. in formulas or with variables");
+            }
             serializer.finish()?;
             res.converted += 1;
-            debug!("Convert time: {:?}", v.elapsed());
+            res.triples.push((file.to_string(), triple_count));
+            debug!(
+                "converted {file}: {triple_count} triples in {:?}",
+                v.elapsed()
+            );
         }
         dest_writer.flush()?;
         Ok(res)
     }
+
+    fn converts_ntriples(&self) -> bool {
+        self.normalize_literals
+    }
+
+    fn max_triples(&self) -> Option<u64> {
+        self.max_triples
+    }
+}
+
+/// Canonical form of a typed literal with a numeric, boolean or date/time datatype, e.g. `"1"` for
+/// `"01"^^xsd:integer` or `"true"` for `"1"^^xsd:boolean`. Other literals, and values that are
+/// invalid for their datatype, are kept as is.
+fn normalize_literal(literal: Literal) -> Literal {
+    fn canonical<T: FromStr + Display>(value: &str) -> Option<String> {
+        value.parse::<T>().ok().map(|v| v.to_string())
+    }
+
+    let datatype = literal.datatype();
+    let value = literal.value();
+    let integer_types = [
+        xsd::INTEGER,
+        xsd::LONG,
+        xsd::INT,
+        xsd::SHORT,
+        xsd::BYTE,
+        xsd::NON_NEGATIVE_INTEGER,
+        xsd::POSITIVE_INTEGER,
+        xsd::NON_POSITIVE_INTEGER,
+        xsd::NEGATIVE_INTEGER,
+        xsd::UNSIGNED_LONG,
+        xsd::UNSIGNED_INT,
+        xsd::UNSIGNED_SHORT,
+        xsd::UNSIGNED_BYTE,
+    ];
+    let normalized = if integer_types.contains(&datatype) {
+        canonical::<Integer>(value)
+    } else if datatype == xsd::DECIMAL {
+        canonical::<Decimal>(value)
+    } else if datatype == xsd::DOUBLE {
+        canonical::<Double>(value)
+    } else if datatype == xsd::FLOAT {
+        canonical::<Float>(value)
+    } else if datatype == xsd::BOOLEAN {
+        canonical::<Boolean>(value)
+    } else if datatype == xsd::DATE_TIME {
+        canonical::<DateTime>(value)
+    } else if datatype == xsd::DATE {
+        canonical::<Date>(value)
+    } else if datatype == xsd::TIME {
+        canonical::<Time>(value)
+    } else {
+        None
+    };
+    match normalized {
+        Some(normalized) if normalized != value => {
+            Literal::new_typed_literal(normalized, datatype.into_owned())
+        }
+        _ => literal,
+    }
+}
+
+/// Whether `file` has the extension of a RDF format, possibly followed by a compression one
+pub fn is_rdf_file(file: &str) -> bool {
+    let (name, _) = Compression::from_file_name(file);
+    name.ends_with(".owl")
+        || Path::new(name)
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .and_then(RdfFormat::from_extension)
+            .is_some()
+}
+
+/// Format of `file` when it's a RDF dataset format that can hold named graphs, i.e. TriG,
+/// N-Quads or JSON-LD, given by `input_format` or else by the file extension
+pub fn dataset_format(file: &str, input_format: Option<InputFormat>) -> Option<RdfFormat> {
+    let format = match input_format {
+        Some(input_format) => input_format.rdf_format().ok()?,
+        None => {
+            let (name, _) = Compression::from_file_name(file);
+            RdfFormat::from_extension(Path::new(name).extension()?.to_str()?)?
+        }
+    };
+    // the graphs of N3 are formulas, quoted rather than asserted
+    (format.supports_datasets() && format != RdfFormat::N3).then_some(format)
+}
+
+/// Splits a RDF dataset file into a NTriples file in `dir` per graph. Returns the file of the
+/// default graph, if it has triples, and the graph name and file of each named graph in order of
+/// appearance. Triples of graphs named by a blank node are added to the default graph.
+pub fn split_dataset(
+    file: &str,
+    format: RdfFormat,
+    dir: &Path,
+) -> anyhow::Result<(
+    Option<std::path::PathBuf>,
+    Vec<(String, std::path::PathBuf)>,
+)> {
+    let source =
+        File::open(file).map_err(|e| anyhow::anyhow!("Error opening file {file:?}: {e}"))?;
+    let (_, compression) = Compression::from_file_name(file);
+    let source_reader = utf8_reader(file, compression.decoder(source))?;
+    let blank_node_prefix = blank_node_prefix(file);
+
+    let mut default_graph = None;
+    let mut named_graphs: Vec<(String, std::path::PathBuf)> = vec![];
+    let mut serializers = std::collections::HashMap::new();
+    for q in RdfParser::from_format(format).for_reader(source_reader) {
+        let q = q.map_err(|e| anyhow::anyhow!("syntax error for RDF file {file}: {e}"))?;
+        let graph = match q.graph_name {
+            oxrdf::GraphName::NamedNode(n) => Some(n.into_string()),
+            oxrdf::GraphName::BlankNode(_) => {
+                warn!("HDT does not support blank node graph names, merging their triples into the default graph of {file}");
+                None
+            }
+            DefaultGraph => None,
+        };
+        let serializer = match serializers.entry(graph.clone()) {
+            std::collections::hash_map::Entry::Occupied(e) => e.into_mut(),
+            std::collections::hash_map::Entry::Vacant(e) => {
+                let path = match &graph {
+                    Some(graph) => {
+                        let path =
+                            dir.join(format!("{blank_node_prefix}graph{}.nt", named_graphs.len()));
+                        named_graphs.push((graph.clone(), path.clone()));
+                        path
+                    }
+                    None => {
+                        let path = dir.join(format!("{blank_node_prefix}default.nt"));
+                        default_graph = Some(path.clone());
+                        path
+                    }
+                };
+                let out = File::create(&path)
+                    .map_err(|e| anyhow::anyhow!("Error creating {path:?}: {e}"))?;
+                e.insert(RdfSerializer::from_format(NTriples).for_writer(BufWriter::new(out)))
+            }
+        };
+        // blank nodes are scoped to their file, as in OxRdfConvert
+        let subject = match q.subject {
+            NamedOrBlankNode::BlankNode(b) => rename_blank_node(&blank_node_prefix, &b).into(),
+            s => s,
+        };
+        let object = match q.object {
+            Term::BlankNode(b) => rename_blank_node(&blank_node_prefix, &b).into(),
+            o => o,
+        };
+        serializer.serialize_triple(&Triple::new(subject, q.predicate, object))?;
+    }
+    for (_, serializer) in serializers {
+        serializer.finish()?.flush()?;
+    }
+    Ok((default_graph, named_graphs))
+}
+
+/// Prefix for the blank node labels of `file`. It's derived from the file path rather than its position
+/// in the conversion so files converted in separate calls, e.g. with a manifest, get distinct prefixes too.
+fn blank_node_prefix(file: &str) -> String {
+    let mut hasher = DefaultHasher::new();
+    file.hash(&mut hasher);
+    format!("f{:016x}_", hasher.finish())
+}
+
+fn rename_blank_node(prefix: &str, node: &BlankNode) -> BlankNode {
+    BlankNode::new_unchecked(format!("{prefix}{}", node.as_str()))
+}
+
+/// Checks every IRI of a NTriples file, including the ones of NTriples inputs that are copied without
+/// being parsed. Returns the number of triples with an invalid IRI, only ever 0 with [IriValidation::Strict]
+/// as the first one is an error.
+pub fn validate_iris(nt_file: &str, level: IriValidation) -> anyhow::Result<usize> {
+    let source =
+        File::open(nt_file).map_err(|e| anyhow::anyhow!("Error opening file {nt_file:?}: {e}"))?;
+    // the parser would reject invalid IRIs with a syntax error not telling which triple they're in
+    let triples = RdfParser::from_format(NTriples)
+        .lenient()
+        .for_reader(BufReader::new(source));
+    let mut invalid = 0;
+    for t in triples {
+        let t = t.map_err(|e| anyhow::anyhow!("Error reading {nt_file}: {e}"))?;
+        let mut iris = vec![t.predicate.as_str()];
+        if let NamedOrBlankNode::NamedNode(s) = &t.subject {
+            iris.push(s.as_str());
+        }
+        match &t.object {
+            Term::NamedNode(o) => iris.push(o.as_str()),
+            Term::Literal(l) => iris.push(l.datatype().as_str()),
+            _ => {}
+        }
+        let Some(Err(e)) = iris
+            .into_iter()
+            .map(oxiri::Iri::parse)
+            .find(|iri| iri.is_err())
+        else {
+            continue;
+        };
+        let triple = Triple::new(t.subject, t.predicate, t.object);
+        match level {
+            IriValidation::Strict => {
+                error!("invalid IRI in triple {triple}: {e}");
+                return Err(anyhow::anyhow!("invalid IRI in triple {triple}: {e}"));
+            }
+            IriValidation::Warn => warn!("invalid IRI in triple {triple}: {e}"),
+        }
+        invalid += 1;
+    }
+    Ok(invalid)
+}
+
+/// Compression of an input file, detected from its last extension
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Compression {
+    None,
+    Bzip2,
+    Xz,
+}
+
+impl Compression {
+    /// Splits the compression extension off a file name, e.g. `data.ttl.xz` gives `data.ttl` and [Compression::Xz]
+    pub(crate) fn from_file_name(file: &str) -> (&str, Self) {
+        if let Some(name) = file.strip_suffix(".bz2") {
+            (name, Compression::Bzip2)
+        } else if let Some(name) = file.strip_suffix(".xz") {
+            (name, Compression::Xz)
+        } else {
+            (file, Compression::None)
+        }
+    }
+
+    /// Wraps `source` in a reader of its decompressed content
+    fn decoder(self, source: File) -> Box<dyn BufRead> {
+        match self {
+            Compression::None => Box::new(BufReader::new(source)),
+            Compression::Bzip2 => Box::new(BufReader::new(bzip2::read::BzDecoder::new(source))),
+            Compression::Xz => Box::new(BufReader::new(xz2::read::XzDecoder::new(source))),
+        }
+    }
+}
+
+/// Byte order mark of UTF-8 encoded text
+const UTF8_BOM: &[u8] = &[0xEF, 0xBB, 0xBF];
+
+/// Detects the encoding of `source` from its byte order mark and returns a reader of its UTF-8 content.
+/// A UTF-8 BOM is skipped and UTF-16 text is transcoded, text without a BOM is assumed to be UTF-8.
+fn utf8_reader(file: &str, mut source: Box<dyn BufRead>) -> anyhow::Result<Box<dyn BufRead>> {
+    let start = source
+        .fill_buf()
+        .map_err(|e| anyhow::anyhow!("Error reading file {file}: {e}"))?;
+    // UTF-32 BOMs are checked first, the UTF-32 LE BOM starts with the UTF-16 LE one
+    if start.starts_with(&[0xFF, 0xFE, 0x00, 0x00]) || start.starts_with(&[0x00, 0x00, 0xFE, 0xFF])
+    {
+        error!("{file} is UTF-32 encoded");
+        return Err(anyhow::anyhow!(
+            "unsupported encoding UTF-32 for RDF file {file}, convert it to UTF-8 first"
+        ));
+    }
+    let big_endian = if start.starts_with(UTF8_BOM) {
+        debug!("skipping UTF-8 byte order mark of {file}");
+        source.consume(UTF8_BOM.len());
+        return Ok(source);
+    } else if start.starts_with(&[0xFF, 0xFE]) {
+        false
+    } else if start.starts_with(&[0xFE, 0xFF]) {
+        true
+    } else {
+        return Ok(source);
+    };
+
+    debug!("transcoding UTF-16 {file} to UTF-8");
+    source.consume(2);
+    let mut bytes = vec![];
+    source
+        .read_to_end(&mut bytes)
+        .map_err(|e| anyhow::anyhow!("Error reading file {file}: {e}"))?;
+    if bytes.len() % 2 != 0 {
+        return Err(anyhow::anyhow!(
+            "invalid UTF-16 content in RDF file {file}: odd number of bytes"
+        ));
+    }
+    let units = bytes.chunks_exact(2).map(|b| match big_endian {
+        true => u16::from_be_bytes([b[0], b[1]]),
+        false => u16::from_le_bytes([b[0], b[1]]),
+    });
+    let text = char::decode_utf16(units)
+        .collect::<Result<String, _>>()
+        .map_err(|e| anyhow::anyhow!("invalid UTF-16 content in RDF file {file}: {e}"))?;
+    Ok(Box::new(Cursor::new(text.into_bytes())))
+}
+
+/// Shows the source line a syntax error points to, with a marker under the error's column
+fn explain_syntax_error(
+    file: &str,
+    compression: Compression,
+    err: &RdfSyntaxError,
+) -> Option<String> {
+    let start = err.location()?.start;
+    let line = source_line(file, compression, start.line)?;
+    let marker = " ".repeat(usize::try_from(start.column).ok()?);
+    Some(format!(
+        "\n  --> {file}:{}:{}\n   | {line}\n   | {marker}^",
+        start.line + 1,
+        start.column + 1
+    ))
+}
+
+/// Whether a N3 syntax error is a statement that parsed fine but has no RDF equivalent, e.g. with
+/// the variables of rules, `@forAll` and `@forSome`, a literal subject or a literal or blank node
+/// predicate. These are reported without a location, any other error is a real syntax error.
+fn is_n3_only_statement(err: &RdfSyntaxError) -> bool {
+    if err.location().is_some() {
+        return false;
+    }
+    let message = err.to_string().to_ascii_lowercase();
+    ["variables", "literals", "blank nodes"]
+        .iter()
+        .any(|term| message.contains(&format!("{term} are not allowed")))
+}
+
+/// Whether a syntax error is on, or right after, a `<<` opening a quoted triple, which the parser
+/// rejects without RDF 1.2 support. `<<` elsewhere on the line, e.g. in a literal, doesn't count.
+fn is_quoted_triple_error(file: &str, compression: Compression, err: &RdfSyntaxError) -> bool {
+    let Some(location) = err.location() else {
+        return false;
+    };
+    let Some(line) = source_line(file, compression, location.start.line) else {
+        return false;
+    };
+    let (Ok(start), Ok(end)) = (
+        usize::try_from(location.start.column),
+        usize::try_from(location.end.column),
+    ) else {
+        return false;
+    };
+    let len = if location.end.line == location.start.line {
+        end.saturating_sub(start).max(2)
+    } else {
+        2
+    };
+    let before: String = line.chars().take(start).collect();
+    let at: String = line.chars().skip(start).take(len).collect();
+    at.contains("<<") || before.trim_end().ends_with("<<")
+}
+
+/// Reads the given (0 based) line of a file, used to give context on syntax errors
+fn source_line(file: &str, compression: Compression, line: u64) -> Option<String> {
+    let source = std::fs::File::open(file).ok()?;
+    // read the way the parser did, the line numbers are the ones of the decompressed UTF-8 text
+    utf8_reader(file, compression.decoder(source))
+        .ok()?
+        .lines()
+        .nth(usize::try_from(line).ok()?)?
+        .ok()
 }
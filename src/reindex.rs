@@ -0,0 +1,39 @@
+// Copyright (c) 2025, Decisym, LLC
+// Licensed under the BSD 3-Clause License (see LICENSE file in the project root).
+
+use crate::sparql::{cache_files, remove_cache_files};
+use anyhow::anyhow;
+use log::*;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+/// Regenerate the index cache files of HDT files, e.g. when they are stale or corrupt, without
+/// rebuilding the HDTs. Existing cache files are deleted first. Writes each HDT path along with
+/// the cache files created for it, tab separated.
+pub fn do_reindex<W: Write>(
+    hdt_files: &[String],
+    writer: &mut BufWriter<W>,
+) -> anyhow::Result<(), anyhow::Error> {
+    for f in hdt_files {
+        let path = Path::new(f);
+        if !path.exists() {
+            error!("file {path:?} could not be found on local machine");
+            return Err(anyhow!("file {path:?} could not be found on local machine"));
+        }
+        if path.extension().is_none_or(|ext| ext != "hdt") {
+            return Err(anyhow!("{f} is not a HDT file"));
+        }
+        remove_cache_files(path);
+        // loading with the cache enabled writes the cache files
+        hdt::Hdt::new_hybrid_cache(path, true)
+            .map_err(|e| anyhow!("Error generating index cache for {f}: {e}"))?;
+        debug!("index cache regenerated for {f}");
+        let created = cache_files(path)
+            .iter()
+            .map(|p| p.to_string_lossy().into_owned())
+            .collect::<Vec<_>>();
+        writeln!(writer, "{f}\t{}", created.join("\t"))?;
+    }
+    writer.flush()?;
+    Ok(())
+}
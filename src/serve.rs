@@ -2,7 +2,8 @@ use http::{
     header::{
         ACCEPT, ACCESS_CONTROL_ALLOW_HEADERS, ACCESS_CONTROL_ALLOW_METHODS,
         ACCESS_CONTROL_ALLOW_ORIGIN, ACCESS_CONTROL_REQUEST_HEADERS, ACCESS_CONTROL_REQUEST_METHOD,
-        CONTENT_TYPE, LOCATION, ORIGIN,
+        AUTHORIZATION, CONTENT_TYPE, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED, LINK,
+        LOCATION, ORIGIN, RETRY_AFTER, WWW_AUTHENTICATE,
     },
     uri::PathAndQuery,
     HeaderValue, Method, Request, Response, StatusCode,
@@ -15,63 +16,310 @@ use oxrdfio::{RdfFormat, RdfParser, RdfSerializer};
 use rand::random;
 use sparesults::{QueryResultsFormat, QueryResultsSerializer};
 use spareval::{QueryEvaluator, QueryResults, QueryableDataset};
-use spargebra::SparqlParser;
+use spargebra::{
+    algebra::{GraphPattern, QueryDataset},
+    Query, SparqlParser,
+};
+use std::str::FromStr;
 use std::{
     borrow::Cow,
     cell::RefCell,
     cmp::min,
+    collections::{HashMap, HashSet},
     fmt,
     io::{self, BufWriter, Read, Write},
-    net::ToSocketAddrs,
-    path::Path,
+    net::{SocketAddr, TcpListener, ToSocketAddrs},
+    path::{Path, PathBuf},
     rc::Rc,
-    sync::Arc,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        mpsc, Arc, Mutex,
+    },
     thread::available_parallelism,
-    time::Duration,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
-use std::{collections::HashMap, str::FromStr, sync::RwLock};
 use url::form_urlencoded;
 
 use crate::{
-    service_description::{generate_service_description, EndpointKind},
-    sparql::{hdt_bgp_str_to_term, AggregateHdt},
+    service_description::{generate_service_description, EndpointKind, ServiceFeatures},
+    sparql::{
+        self, find_hdt_files, parse_object, parse_predicate, parse_subject, AggregateHdt,
+        AggregateHdtSnapshot,
+    },
 };
 
 type HttpError = (StatusCode, String);
 
 const MAX_SPARQL_BODY_SIZE: u64 = 1024 * 1024 * 128; // 128MB
 const HTTP_TIMEOUT: Duration = Duration::from_secs(60);
+const SHUTDOWN_GRACE_PERIOD: Duration = Duration::from_secs(30);
+const PREPARED_QUERY_CACHE_SIZE: usize = 256;
+// Longer queries are parsed every time rather than kept by the prepared query cache
+const PREPARED_QUERY_MAX_SIZE: usize = 64 * 1024;
+// Chunks of query results written ahead of the client, and their size
+const STREAMED_CHUNKS: usize = 4;
+const STREAMED_CHUNK_SIZE: usize = 64 * 1024;
+const RATE_LIMIT_MAX_CLIENTS: usize = 10_000;
+const QUERY_CACHE_MAX_BYTES: usize = 64 * 1024 * 1024; // 64MB
+/// Largest page of results the `limit` parameter of a query can ask for
+const MAX_PAGE_SIZE: usize = 10_000;
+/// Bind strings starting with this prefix are Unix domain socket paths, e.g. `unix:/run/de.sock`
+const UNIX_SOCKET_PREFIX: &str = "unix:";
+/// Header carrying the token [UnixSocketProxy] adds to the requests it forwards
+const UNIX_SOCKET_TOKEN_HEADER: &str = "x-de-unix-socket-token";
+// Longest request line the Unix socket proxy looks for before adding its token
+const MAX_REQUEST_LINE_SIZE: u64 = 64 * 1024;
+// Times binding the server is tried when another process takes a free port picked for it
+const BIND_ATTEMPTS: usize = 3;
 const HTML_ROOT_PAGE: &str = include_str!("../templates/query.html");
 #[expect(clippy::large_include_file)]
 const YASGUI_JS: &str = include_str!("../templates/yasgui/yasgui.min.js");
 const YASGUI_CSS: &str = include_str!("../templates/yasgui/yasgui.min.css");
 const LOGO: &str = include_str!("../templates/logo.svg");
 
-pub fn serve(
-    locations: String,
-    bind: &str,
-    // read_only: bool,
-    // cors: bool,
-    // union_default_graph: bool,
-    // timeout_s: Option<u64>,
-) -> anyhow::Result<()> {
-    let union_default_graph = true;
-    let cors = false;
-
-    // Find all *.hdt files in the locations directory
-    let hdt_paths: Vec<String> = std::fs::read_dir(&locations)?
-        .filter_map(|entry| {
-            let entry = entry.ok()?;
-            let path = entry.path();
-            if path.extension()? == "hdt" {
-                Some(path.to_string_lossy().into_owned())
+/// Configuration of the SPARQL server started by [serve_with_config]
+#[derive(Clone, Debug)]
+pub struct ServeConfig {
+    /// Host and port to listen to, or a comma separated list of them to listen to each, e.g.
+    /// `127.0.0.1:8080,[::1]:8080`. Port 0 picks a free port. `unix:<path>` listens on a Unix
+    /// domain socket instead. Its connections are forwarded to a loopback port that only answers
+    /// requests coming through the socket, each socket connection carries a single request.
+    pub bind: String,
+    /// Directory containing the HDT files to serve
+    pub locations: String,
+    /// Allow cross-origin requests from any origin
+    pub cors: bool,
+    /// Reject SPARQL updates and Graph Store writes
+    pub read_only: bool,
+    /// Use the union of all graphs as the default graph
+    pub union_default_graph: bool,
+    /// Name of the graph used as the default graph, instead of the union of all graphs or an
+    /// empty graph. Queries selecting their dataset with request parameters still get the union
+    pub default_graph: Option<String>,
+    /// Maximum time a connection waits on reading or writing. This also closes kept alive
+    /// connections that stay idle for longer between requests
+    pub timeout: Duration,
+    /// Maximum number of connections handled at once, 128 per available CPU when not set
+    pub max_connections: Option<usize>,
+    /// Maximum size in bytes of SPARQL query and update request bodies
+    pub max_body_size: u64,
+    /// Also serve HDT files in subdirectories of `locations`
+    pub recursive: bool,
+    /// Keep the graphs in sync with `locations` through file system notifications rather than
+    /// scanning it on every request
+    pub watch: bool,
+    /// Maximum number of HDT files loaded in parallel, unbounded when not set
+    pub load_concurrency: Option<usize>,
+    /// Allow SPARQL updates to modify existing named graphs
+    pub allow_mutations: bool,
+    /// Don't write index cache files next to the HDT files when loading them
+    pub no_cache: bool,
+    /// Name graphs `<graph_base><file stem>` instead of `file:///<file name>`
+    pub graph_base: Option<String>,
+    /// Name graphs by their bare file stem, e.g. `data`, instead of `file:///<file name>`
+    pub short_graph_names: bool,
+    /// Maximum number of requests per minute from a client, unlimited when not set. Requires
+    /// `trusted_proxy`, clients are told apart by the address the proxy adds to `X-Forwarded-For`
+    pub rate_limit: Option<u32>,
+    /// The server is only reachable through a reverse proxy appending the client address to
+    /// `X-Forwarded-For`, so the last address of the header can be trusted
+    pub trusted_proxy: bool,
+    /// Number of SPARQL query results kept in memory to answer repeated queries, 0 disables the cache
+    pub query_cache_size: usize,
+    /// Memory used by the cached query results, a result larger than a quarter of it is streamed
+    /// to the client without being cached
+    pub query_cache_max_bytes: usize,
+    /// Credentials required for SPARQL updates and Graph Store writes, no authentication when not set
+    pub auth: Option<ServeAuth>,
+    /// Also require the credentials of `auth` for queries and Graph Store reads
+    pub auth_reads: bool,
+    /// Parsed SPARQL queries reused by repeated requests, shared by the clones of this config
+    pub prepared_queries: Arc<PreparedQueryCache>,
+    /// Counters served at `/metrics`, shared by the clones of this config
+    pub metrics: Arc<ServerMetrics>,
+}
+
+/// Credentials a client has to send in the `Authorization` header, see [ServeConfig::auth]
+#[derive(Clone)]
+pub enum ServeAuth {
+    /// `Authorization: Bearer <token>`
+    Bearer(String),
+    /// `Authorization: Basic <base64 of user:password>`
+    Basic { user: String, password: String },
+}
+
+impl fmt::Debug for ServeAuth {
+    // the secrets are left out, configurations end up in logs
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Bearer(_) => f.write_str("Bearer(..)"),
+            Self::Basic { user, .. } => write!(f, "Basic {{ user: {user:?}, .. }}"),
+        }
+    }
+}
+
+impl ServeAuth {
+    fn scheme(&self) -> &'static str {
+        match self {
+            Self::Bearer(_) => "Bearer",
+            Self::Basic { .. } => "Basic",
+        }
+    }
+
+    /// Whether the value of an `Authorization` header carries these credentials
+    fn accepts(&self, authorization: &[u8]) -> bool {
+        let Some(separator) = authorization.iter().position(|b| *b == b' ') else {
+            return false;
+        };
+        let (scheme, credentials) = authorization.split_at(separator);
+        if !scheme.eq_ignore_ascii_case(self.scheme().as_bytes()) {
+            return false;
+        }
+        let credentials = credentials.trim_ascii();
+        match self {
+            Self::Bearer(token) => constant_time_eq(credentials, token.as_bytes()),
+            Self::Basic { user, password } => constant_time_eq(
+                credentials,
+                base64_encode(format!("{user}:{password}").as_bytes()).as_bytes(),
+            ),
+        }
+    }
+}
+
+/// Compare secrets in a time independent of the position of the first difference
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+fn base64_encode(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, b)| n | (u32::from(*b) << (16 - 8 * i)));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[((n >> (18 - 6 * i)) & 0x3f) as usize] as char);
             } else {
-                None
+                encoded.push('=');
             }
-        })
+        }
+    }
+    encoded
+}
+
+impl Default for ServeConfig {
+    fn default() -> Self {
+        Self {
+            bind: "localhost:7878".to_string(),
+            locations: ".".to_string(),
+            cors: false,
+            read_only: false,
+            union_default_graph: true,
+            timeout: HTTP_TIMEOUT,
+            max_connections: None,
+            max_body_size: MAX_SPARQL_BODY_SIZE,
+            recursive: false,
+            watch: false,
+            default_graph: None,
+            load_concurrency: None,
+            allow_mutations: false,
+            no_cache: false,
+            graph_base: None,
+            short_graph_names: false,
+            rate_limit: None,
+            trusted_proxy: false,
+            query_cache_size: 0,
+            query_cache_max_bytes: QUERY_CACHE_MAX_BYTES,
+            auth: None,
+            auth_reads: false,
+            prepared_queries: Arc::new(PreparedQueryCache::new(PREPARED_QUERY_CACHE_SIZE)),
+            metrics: Arc::new(ServerMetrics::default()),
+        }
+    }
+}
+
+/// A server started by [serve_with_config], requests are handled on background threads until
+/// [ServerHandle::shutdown] is called. Dropping the handle removes the Unix domain socket, if any.
+pub struct ServerHandle {
+    addresses: Vec<SocketAddr>,
+    store: Arc<AggregateHdt>,
+    shutdown: Arc<ShutdownState>,
+    _server: oxhttp::ListeningServer,
+    _watcher: Option<notify::RecommendedWatcher>,
+    #[cfg(unix)]
+    _unix_proxy: Option<UnixSocketProxy>,
+}
+
+impl ServerHandle {
+    /// TCP addresses the server listens on. For a Unix domain socket this is the loopback
+    /// address the socket forwards to, it rejects requests made to it directly.
+    pub fn local_addrs(&self) -> &[SocketAddr] {
+        &self.addresses
+    }
+
+    /// The store queried by the server
+    pub fn store(&self) -> &AggregateHdt {
+        &self.store
+    }
+
+    /// Stop handling requests, waiting up to `grace_period` for in-flight ones to finish, and
+    /// remove the store's temp files. Requests arriving afterwards are answered with 503.
+    /// Returns false if requests were still running after the grace period.
+    pub fn shutdown(&self, grace_period: Duration) -> bool {
+        self.shutdown.shutdown(&self.store, grace_period)
+    }
+}
+
+/// Serve the HDT files in `config.locations` over HTTP until SIGINT or SIGTERM is received
+pub fn serve(config: ServeConfig) -> anyhow::Result<()> {
+    let handle = serve_with_config(config.clone())?;
+    #[cfg(target_os = "linux")]
+    systemd_notify(b"READY=1")?;
+    match config.bind.strip_prefix(UNIX_SOCKET_PREFIX) {
+        Some(path) => eprintln!("Listening for requests on Unix socket {path}"),
+        None => eprintln!("Listening for requests at http://{}", config.bind),
+    }
+
+    wait_for_shutdown_signal()?;
+    eprintln!(
+        "Shutting down, waiting up to {}s for in-flight requests",
+        SHUTDOWN_GRACE_PERIOD.as_secs()
+    );
+    #[cfg(target_os = "linux")]
+    systemd_notify(b"STOPPING=1")?;
+    handle.shutdown(SHUTDOWN_GRACE_PERIOD);
+    Ok(())
+}
+
+/// Start serving the HDT files in `config.locations` in the background
+pub fn serve_with_config(config: ServeConfig) -> anyhow::Result<ServerHandle> {
+    if config.timeout.is_zero() {
+        return Err(anyhow::anyhow!("the connection timeout must be positive"));
+    }
+    let max_connections = match config.max_connections {
+        Some(0) => return Err(anyhow::anyhow!("at least one connection must be allowed")),
+        Some(n) => n,
+        None => available_parallelism()?.get() * 128,
+    };
+    if config.rate_limit.is_some() && !config.trusted_proxy {
+        // any client could pick its own X-Forwarded-For and so its own bucket
+        return Err(anyhow::anyhow!(
+            "rate limiting tells clients apart by X-Forwarded-For, it requires a trusted reverse proxy setting that header"
+        ));
+    }
+    let hdt_paths: Vec<String> = find_hdt_files(Path::new(&config.locations), config.recursive)?
+        .into_iter()
+        .map(|p| p.to_string_lossy().into_owned())
         .collect();
 
-    eprintln!("Found {} HDT files in {}", hdt_paths.len(), locations);
+    eprintln!(
+        "Found {} HDT files in {}",
+        hdt_paths.len(),
+        config.locations
+    );
     for path in &hdt_paths {
         eprintln!("  - {}", path);
     }
@@ -80,41 +328,885 @@ pub fn serve(
     let store = if hdt_paths.is_empty() {
         warn!(
             "Warning: No HDT files found in the specified locations: {}",
-            locations
+            config.locations
         );
-        AggregateHdt {
-            file_paths: Arc::new(RwLock::new(HashMap::new())),
-        }
+        AggregateHdt::empty()
     } else {
         AggregateHdt::new(&hdt_paths)?
     };
-
-    // let timeout = timeout_s.map(Duration::from_secs);
-    let mut server = if cors {
-        Server::new(cors_middleware(move |request| {
-            handle_request(request, &store, union_default_graph, locations.to_owned())
-                .unwrap_or_else(|(status, message)| error(status, message))
-        }))
+    let store = match &config.graph_base {
+        Some(graph_base) => store.with_graph_base(graph_base)?,
+        None if config.short_graph_names => store.with_short_graph_names()?,
+        None => store,
+    };
+    let store = Arc::new(
+        match config.load_concurrency {
+            Some(n) => store.with_load_concurrency(n)?,
+            None => store,
+        }
+        .with_mutations(config.allow_mutations)
+        .with_cache(!config.no_cache),
+    );
+    let shutdown = Arc::new(ShutdownState::default());
+    let watcher = if config.watch {
+        Some(watch_locations(Arc::clone(&store), &config)?)
     } else {
-        Server::new(move |request| {
-            handle_request(request, &store, union_default_graph, locations.to_owned())
+        None
+    };
+
+    let (handler_store, handler_shutdown, handler_config) = (
+        Arc::clone(&store),
+        Arc::clone(&shutdown),
+        Arc::new(config.clone()),
+    );
+    let mut respond: Box<Handler> = {
+        let (store, config) = (Arc::clone(&handler_store), Arc::clone(&handler_config));
+        Box::new(move |request: &mut Request<Body>| {
+            handle_request_with_config(request, &store, &config)
                 .unwrap_or_else(|(status, message)| error(status, message))
         })
+    };
+    if config.query_cache_size > 0 {
+        respond = Box::new(query_cache_middleware(
+            Arc::new(QueryCache::new(
+                config.query_cache_size,
+                config.query_cache_max_bytes,
+            )),
+            handler_store,
+            handler_config,
+            respond,
+        ));
+    }
+    let mut handler: Box<Handler> = Box::new(move |request: &mut Request<Body>| {
+        let Some(_in_flight) = handler_shutdown.begin_request() else {
+            return error(
+                StatusCode::SERVICE_UNAVAILABLE,
+                "The server is shutting down",
+            );
+        };
+        respond(request)
+    });
+    if let Some(requests_per_minute) = config.rate_limit {
+        handler = Box::new(rate_limit_middleware(
+            RateLimiter::new(requests_per_minute),
+            handler,
+        ));
     }
-    .with_global_timeout(HTTP_TIMEOUT)
-    .with_server_name(concat!("Oxigraph/", env!("CARGO_PKG_VERSION")))?
-    .with_max_concurrent_connections(available_parallelism()?.get() * 128);
-    for socket in bind.to_socket_addrs()? {
-        server = server.bind(socket);
+    // outside the rate limit so scrapes are always answered
+    handler = Box::new(metrics_middleware(
+        Arc::clone(&config.metrics),
+        Arc::clone(&store),
+        handler,
+    ));
+    if config.cors {
+        handler = Box::new(cors_middleware(handler));
     }
-    let server = server.spawn()?;
-    #[cfg(target_os = "linux")]
-    systemd_notify_ready()?;
-    eprintln!("Listening for requests at http://{bind}");
-    server.join()?;
+    // oxhttp only listens on TCP, Unix socket connections are forwarded to a loopback port. Any
+    // local user can connect to that port, so it only answers requests carrying the token the
+    // forwarding adds.
+    let unix_socket = config.bind.strip_prefix(UNIX_SOCKET_PREFIX).map(Path::new);
+    #[cfg(not(unix))]
+    if unix_socket.is_some() {
+        return Err(anyhow::anyhow!(
+            "Unix domain sockets are not supported on this platform"
+        ));
+    }
+    let unix_socket_token = unix_socket.map(|_| format!("{:032x}", random::<u128>()));
+    if let Some(token) = &unix_socket_token {
+        handler = Box::new(unix_socket_token_middleware(token.clone(), handler));
+    }
+    let handler: Arc<Handler> = Arc::from(handler);
+    // free ports are picked before oxhttp binds them, another process may take one in between
+    let mut attempts = 1;
+    let (addresses, server) = loop {
+        let addresses: Vec<SocketAddr> = if unix_socket.is_some() {
+            vec![TcpListener::bind("127.0.0.1:0")?.local_addr()?]
+        } else {
+            bind_addresses(&config.bind)?
+        };
+        let handler = Arc::clone(&handler);
+        let mut server = Server::new(move |request: &mut Request<Body>| handler(request))
+            .with_global_timeout(config.timeout)
+            .with_server_name(concat!("Oxigraph/", env!("CARGO_PKG_VERSION")))?
+            .with_max_concurrent_connections(max_connections);
+        for address in &addresses {
+            server = server.bind(*address);
+        }
+        match server.spawn() {
+            Err(e) if e.kind() == io::ErrorKind::AddrInUse && attempts < BIND_ATTEMPTS => {
+                debug!("error binding {addresses:?}, trying again: {e}");
+                attempts += 1;
+            }
+            server => break (addresses, server?),
+        }
+    };
+    #[cfg(unix)]
+    let unix_proxy = unix_socket
+        .zip(unix_socket_token)
+        .map(|(path, token)| UnixSocketProxy::start(path, addresses[0], &token))
+        .transpose()?;
+
+    Ok(ServerHandle {
+        addresses,
+        store,
+        shutdown,
+        _server: server,
+        _watcher: watcher,
+        #[cfg(unix)]
+        _unix_proxy: unix_proxy,
+    })
+}
+
+/// Socket addresses of a comma separated list of `host:port` addresses. oxhttp doesn't report the
+/// port it bound, so free ports are picked up front for port 0.
+fn bind_addresses(bind: &str) -> anyhow::Result<Vec<SocketAddr>> {
+    let mut addresses = vec![];
+    for entry in bind.split(',').map(str::trim) {
+        let resolved = entry
+            .to_socket_addrs()
+            .map_err(|e| anyhow::anyhow!("invalid bind address {entry:?}: {e}"))?;
+        for address in resolved {
+            let address = match address.port() {
+                0 => TcpListener::bind(address)?.local_addr()?,
+                _ => address,
+            };
+            if !addresses.contains(&address) {
+                addresses.push(address);
+            }
+        }
+    }
+    if addresses.is_empty() {
+        return Err(anyhow::anyhow!("no address to listen to in {bind:?}"));
+    }
+    Ok(addresses)
+}
+
+/// Coordinates a graceful stop of the server: once shutdown begins new requests are rejected,
+/// in-flight requests get a grace period to finish and the store's temp files are removed.
+#[derive(Default)]
+pub struct ShutdownState {
+    shutting_down: AtomicBool,
+    in_flight: AtomicUsize,
+}
+
+/// Marks a request as in-flight until dropped
+pub struct InFlightRequest<'a>(&'a ShutdownState);
+
+impl Drop for InFlightRequest<'_> {
+    fn drop(&mut self) {
+        self.0.in_flight.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl ShutdownState {
+    /// Register a new request. Returns `None` once shutdown has begun.
+    pub fn begin_request(&self) -> Option<InFlightRequest<'_>> {
+        self.in_flight.fetch_add(1, Ordering::SeqCst);
+        let request = InFlightRequest(self);
+        if self.shutting_down.load(Ordering::SeqCst) {
+            return None;
+        }
+        Some(request)
+    }
+
+    /// Stop accepting requests, wait up to `grace_period` for in-flight ones to finish and
+    /// delete the temp files created by the store. Returns false if requests were still running.
+    pub fn shutdown(&self, store: &AggregateHdt, grace_period: Duration) -> bool {
+        self.shutting_down.store(true, Ordering::SeqCst);
+        let deadline = Instant::now() + grace_period;
+        while self.in_flight.load(Ordering::SeqCst) > 0 && Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        let drained = self.in_flight.load(Ordering::SeqCst) == 0;
+        if !drained {
+            warn!(
+                "{} request(s) still running after the shutdown grace period",
+                self.in_flight.load(Ordering::SeqCst)
+            );
+        }
+        let removed = store.remove_temp_files();
+        debug!("removed {removed} temp file(s)");
+        drained
+    }
+}
+
+/// Forwards connections accepted on a Unix domain socket to the HTTP server listening on `target`.
+/// The first request of a connection gets the header `x-de-unix-socket-token: <token>` and
+/// `Connection: close`, so each connection carries a single request the server can tell came
+/// through the socket. The socket file is removed when dropped.
+#[cfg(unix)]
+pub struct UnixSocketProxy {
+    path: std::path::PathBuf,
+}
+
+#[cfg(unix)]
+impl UnixSocketProxy {
+    pub fn start(path: &Path, target: SocketAddr, token: &str) -> io::Result<Self> {
+        use std::os::unix::fs::FileTypeExt;
+        use std::os::unix::net::UnixListener;
+
+        // a socket left behind by a previous run would make bind fail
+        if std::fs::symlink_metadata(path).is_ok_and(|m| m.file_type().is_socket()) {
+            std::fs::remove_file(path)?;
+        }
+        let listener = UnixListener::bind(path)?;
+        let token: Arc<str> = token.into();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                match stream {
+                    Ok(stream) => {
+                        let token = Arc::clone(&token);
+                        std::thread::spawn(move || {
+                            if let Err(e) = forward_unix_connection(stream, target, &token) {
+                                debug!("error forwarding Unix socket connection: {e}");
+                            }
+                        });
+                    }
+                    Err(e) => warn!("error accepting Unix socket connection: {e}"),
+                }
+            }
+        });
+        Ok(Self {
+            path: path.to_path_buf(),
+        })
+    }
+}
+
+#[cfg(unix)]
+impl Drop for UnixSocketProxy {
+    fn drop(&mut self) {
+        if let Err(e) = std::fs::remove_file(&self.path) {
+            warn!("failed to remove Unix socket {:?}: {e}", self.path);
+        }
+    }
+}
+
+#[cfg(unix)]
+fn forward_unix_connection(
+    client: std::os::unix::net::UnixStream,
+    target: SocketAddr,
+    token: &str,
+) -> io::Result<()> {
+    use std::io::BufRead;
+    use std::net::{Shutdown, TcpStream};
+
+    let server = TcpStream::connect(target)?;
+    let (client_read, mut server_write) = (client.try_clone()?, server.try_clone()?);
+    let headers = format!("{UNIX_SOCKET_TOKEN_HEADER}: {token}\r\nConnection: close\r\n");
+    let upstream = std::thread::spawn(move || {
+        let mut client_read = io::BufReader::new(client_read);
+        let mut forward = || -> io::Result<u64> {
+            // the headers go right after the request line, before those of the client
+            let mut request_line = Vec::new();
+            (&mut client_read)
+                .take(MAX_REQUEST_LINE_SIZE)
+                .read_until(b'\n', &mut request_line)?;
+            server_write.write_all(&request_line)?;
+            if request_line.ends_with(b"\n") {
+                server_write.write_all(headers.as_bytes())?;
+            }
+            io::copy(&mut client_read, &mut server_write)
+        };
+        let copied = forward();
+        let _ = server_write.shutdown(Shutdown::Write);
+        copied
+    });
+    let (mut server_read, mut client_write) = (server, client);
+    io::copy(&mut server_read, &mut client_write)?;
+    let _ = client_write.shutdown(Shutdown::Write);
+    upstream
+        .join()
+        .map_err(|_| io::Error::other("Unix socket forwarding thread panicked"))??;
     Ok(())
 }
 
+/// Block until SIGINT or SIGTERM is received
+fn wait_for_shutdown_signal() -> io::Result<()> {
+    // serve runs inside the CLI's tokio runtime, which can't be blocked on, so signals are
+    // awaited from a dedicated thread with its own runtime
+    std::thread::spawn(|| {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?
+            .block_on(async {
+                #[cfg(unix)]
+                {
+                    use tokio::signal::unix::{signal, SignalKind};
+                    let mut terminate = signal(SignalKind::terminate())?;
+                    tokio::select! {
+                        res = tokio::signal::ctrl_c() => res,
+                        _ = terminate.recv() => Ok(()),
+                    }
+                }
+                #[cfg(not(unix))]
+                tokio::signal::ctrl_c().await
+            })
+    })
+    .join()
+    .map_err(|_| io::Error::other("signal handler thread panicked"))?
+}
+
+type Handler = dyn Fn(&mut Request<Body>) -> Response<Body> + Send + Sync + 'static;
+
+/// Only answers requests forwarded by [UnixSocketProxy], the loopback port it forwards to is
+/// reachable by any local user
+fn unix_socket_token_middleware(
+    token: String,
+    on_request: impl Fn(&mut Request<Body>) -> Response<Body> + Send + Sync + 'static,
+) -> impl Fn(&mut Request<Body>) -> Response<Body> + Send + Sync + 'static {
+    move |request| {
+        if request
+            .headers()
+            .get(UNIX_SOCKET_TOKEN_HEADER)
+            .is_none_or(|t| t.as_bytes() != token.as_bytes())
+        {
+            return error(
+                StatusCode::FORBIDDEN,
+                "Requests are only accepted on the Unix socket",
+            );
+        }
+        on_request(request)
+    }
+}
+
+/// Token buckets limiting the request rate of each client. A bucket holds up to a minute of
+/// requests and refills continuously.
+pub struct RateLimiter {
+    requests_per_minute: u32,
+    buckets: Mutex<RateLimitBuckets>,
+}
+
+/// Buckets of the recently seen clients in two generations. Once the current generation is full
+/// the previous one is dropped, so memory stays bounded and eviction is amortized O(1).
+#[derive(Default)]
+struct RateLimitBuckets {
+    current: HashMap<String, (f64, Instant)>,
+    previous: HashMap<String, (f64, Instant)>,
+}
+
+impl RateLimiter {
+    /// Limiter allowing `requests_per_minute` requests per client, at least 1
+    pub fn new(requests_per_minute: u32) -> Self {
+        Self {
+            requests_per_minute: requests_per_minute.max(1),
+            buckets: Mutex::new(RateLimitBuckets::default()),
+        }
+    }
+
+    /// Take a token from the client's bucket, or tell how long until one is available
+    pub fn check(&self, client: &str) -> Result<(), Duration> {
+        let capacity = f64::from(self.requests_per_minute);
+        let per_second = capacity / 60.;
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap();
+        let buckets = &mut *buckets;
+        if !buckets.current.contains_key(client) {
+            if buckets.current.len() >= RATE_LIMIT_MAX_CLIENTS / 2 {
+                // clients not seen for a whole generation start over with a full bucket
+                buckets.previous = std::mem::take(&mut buckets.current);
+            }
+            let bucket = buckets.previous.remove(client).unwrap_or((capacity, now));
+            buckets.current.insert(client.to_string(), bucket);
+        }
+        let (tokens, last) = buckets.current.get_mut(client).unwrap();
+        *tokens = (*tokens + now.duration_since(*last).as_secs_f64() * per_second).min(capacity);
+        *last = now;
+        if *tokens >= 1. {
+            *tokens -= 1.;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1. - *tokens) / per_second))
+        }
+    }
+}
+
+/// Client a request is counted against. oxhttp doesn't expose the peer address, so clients
+/// are told apart by the address the trusted reverse proxy appends to `X-Forwarded-For`. The
+/// entries before it come from the client and could be anything. Requests without the header
+/// didn't go through the proxy and share a single bucket.
+fn rate_limit_client(request: &Request<Body>) -> String {
+    request
+        .headers()
+        .get_all("X-Forwarded-For")
+        .iter()
+        .next_back()
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.rsplit(',').next())
+        .map(|client| client.trim().to_string())
+        .unwrap_or_default()
+}
+
+fn rate_limit_middleware(
+    limiter: RateLimiter,
+    on_request: impl Fn(&mut Request<Body>) -> Response<Body> + Send + Sync + 'static,
+) -> impl Fn(&mut Request<Body>) -> Response<Body> + Send + Sync + 'static {
+    move |request| {
+        // the UI pages are cheap and serve as liveness checks, only SPARQL and store requests count
+        let exempt = matches!(
+            request.uri().path(),
+            "/" | "/yasgui.min.css" | "/yasgui.min.js" | "/logo.svg"
+        );
+        if !exempt {
+            if let Err(retry_after) = limiter.check(&rate_limit_client(request)) {
+                let mut response = error(StatusCode::TOO_MANY_REQUESTS, "Too many requests");
+                response.headers_mut().insert(
+                    RETRY_AFTER,
+                    HeaderValue::from(
+                        retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0),
+                    ),
+                );
+                return response;
+            }
+        }
+        on_request(request)
+    }
+}
+
+/// Serialized result of a SPARQL query along with the dataset version it was computed from
+struct CachedResult {
+    version: u64,
+    content_type: Option<HeaderValue>,
+    body: Arc<[u8]>,
+    last_used: u64,
+}
+
+/// Least recently used SPARQL query results, see [ServeConfig::query_cache_size] and
+/// [ServeConfig::query_cache_max_bytes]
+struct QueryCache {
+    capacity: usize,
+    max_bytes: usize,
+    clock: AtomicU64,
+    entries: Mutex<CachedResults>,
+}
+
+#[derive(Default)]
+struct CachedResults {
+    results: HashMap<Vec<u8>, CachedResult>,
+    // size of the keys and bodies of `results`
+    bytes: usize,
+}
+
+impl QueryCache {
+    fn new(capacity: usize, max_bytes: usize) -> Self {
+        Self {
+            capacity,
+            max_bytes,
+            clock: AtomicU64::new(0),
+            entries: Mutex::new(CachedResults::default()),
+        }
+    }
+
+    /// Largest result worth caching, bigger ones would evict most of the cache
+    fn max_result_size(&self) -> usize {
+        self.max_bytes / 4
+    }
+
+    /// Result cached for `key`, only if it was computed from the dataset at `version`
+    fn get(&self, key: &[u8], version: u64) -> Option<(Option<HeaderValue>, Arc<[u8]>)> {
+        let mut entries = self.entries.lock().unwrap();
+        let entry = entries
+            .results
+            .get_mut(key)
+            .filter(|e| e.version == version)?;
+        entry.last_used = self.clock.fetch_add(1, Ordering::Relaxed);
+        Some((entry.content_type.clone(), Arc::clone(&entry.body)))
+    }
+
+    fn insert(
+        &self,
+        key: Vec<u8>,
+        version: u64,
+        content_type: Option<HeaderValue>,
+        body: Arc<[u8]>,
+    ) {
+        let size = key.len() + body.len();
+        if size > self.max_result_size() {
+            return;
+        }
+        let mut entries = self.entries.lock().unwrap();
+        if let Some(replaced) = entries.results.remove(&key) {
+            entries.bytes -= key.len() + replaced.body.len();
+        }
+        while !entries.results.is_empty()
+            && (entries.results.len() >= self.capacity || entries.bytes + size > self.max_bytes)
+        {
+            let evicted = entries
+                .results
+                .iter()
+                .min_by_key(|(_, e)| e.last_used)
+                .map(|(k, _)| k.clone())
+                .unwrap();
+            let evicted_result = entries.results.remove(&evicted).unwrap();
+            entries.bytes -= evicted.len() + evicted_result.body.len();
+        }
+        entries.bytes += size;
+        entries.results.insert(
+            key,
+            CachedResult {
+                version,
+                content_type,
+                body,
+                last_used: self.clock.fetch_add(1, Ordering::Relaxed),
+            },
+        );
+    }
+}
+
+/// Response body copied into the query cache as the client reads it. The copy is dropped once it
+/// grows past `max_size`, and only a body read to the end is cached.
+struct CachingBody {
+    body: Body,
+    content: Option<Vec<u8>>,
+    max_size: usize,
+    on_end: Option<Box<dyn FnOnce(Vec<u8>) + Send>>,
+}
+
+impl Read for CachingBody {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = self.body.read(buf)?;
+        if len == 0 && !buf.is_empty() {
+            if let (Some(content), Some(on_end)) = (self.content.take(), self.on_end.take()) {
+                on_end(content);
+            }
+        } else if let Some(content) = &mut self.content {
+            if content.len() + len > self.max_size {
+                self.content = None;
+            } else {
+                content.extend_from_slice(&buf[..len]);
+            }
+        }
+        Ok(len)
+    }
+}
+
+/// Marks a request whose store was already synced by [query_cache_middleware]
+#[derive(Clone, Copy)]
+struct LocationsSynced;
+
+/// Least recently used parsed SPARQL queries, keyed by the base IRI and the query string.
+/// Parsing does not depend on the data so entries never have to be invalidated. Queries longer
+/// than 64KB aren't kept, so the cache holds at most `capacity` times that.
+#[derive(Debug)]
+pub struct PreparedQueryCache {
+    capacity: usize,
+    clock: AtomicU64,
+    parses: AtomicU64,
+    entries: Mutex<HashMap<(String, String), (Query, u64)>>,
+}
+
+impl PreparedQueryCache {
+    /// Cache keeping at most `capacity` queries, 0 parses every query
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            clock: AtomicU64::new(0),
+            parses: AtomicU64::new(0),
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Number of queries actually parsed, requests answered from the cache are not counted
+    pub fn parses(&self) -> u64 {
+        self.parses.load(Ordering::Relaxed)
+    }
+
+    fn get_or_parse(&self, base_iri: &str, query: &str) -> Result<Query, HttpError> {
+        let key = (base_iri.to_string(), query.to_string());
+        if let Some((parsed, last_used)) = self.entries.lock().unwrap().get_mut(&key) {
+            *last_used = self.clock.fetch_add(1, Ordering::Relaxed);
+            return Ok(parsed.clone());
+        }
+        self.parses.fetch_add(1, Ordering::Relaxed);
+        let parsed = SparqlParser::new()
+            .with_base_iri(base_iri)
+            .map_err(bad_request)?
+            .parse_query(query)
+            .map_err(bad_request)?;
+        if self.capacity == 0 || base_iri.len() + query.len() > PREPARED_QUERY_MAX_SIZE {
+            return Ok(parsed);
+        }
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() >= self.capacity && !entries.contains_key(&key) {
+            let evicted = entries
+                .iter()
+                .min_by_key(|(_, (_, last_used))| *last_used)
+                .map(|(k, _)| k.clone());
+            if let Some(evicted) = evicted {
+                entries.remove(&evicted);
+            }
+        }
+        entries.insert(
+            key,
+            (parsed.clone(), self.clock.fetch_add(1, Ordering::Relaxed)),
+        );
+        Ok(parsed)
+    }
+}
+
+/// Answers repeated SPARQL queries from `cache` while the dataset is unchanged. Only successful
+/// results of GET and POST queries are cached, the response tells if it was a hit with `X-Cache`.
+/// Results are streamed to the client as they are cached, those too large to be cached are only
+/// streamed.
+fn query_cache_middleware(
+    cache: Arc<QueryCache>,
+    store: Arc<AggregateHdt>,
+    config: Arc<ServeConfig>,
+    on_request: impl Fn(&mut Request<Body>) -> Response<Body> + Send + Sync + 'static,
+) -> impl Fn(&mut Request<Body>) -> Response<Body> + Send + Sync + 'static {
+    move |request| {
+        if !is_sparql_query(request) {
+            return on_request(request);
+        }
+        let body = match limited_body(request, config.max_body_size) {
+            Ok(body) => body,
+            Err((status, message)) => return error(status, message),
+        };
+        // the store is synced first so files added or removed since the last request change the version
+        if let Err(e) = sync_locations(&store, &config) {
+            return error(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("error loading data files: {e}"),
+            );
+        }
+        let version = store.version();
+        // the negotiated format is part of the key, the same query may be asked as JSON and CSV
+        let mut key = format!("{} {}\n", request.method(), request.uri()).into_bytes();
+        // results are only shared between requests carrying the same credentials
+        for header in [CONTENT_TYPE, ACCEPT, AUTHORIZATION] {
+            if let Some(value) = request.headers().get(header) {
+                key.extend_from_slice(value.as_bytes());
+            }
+            key.push(b'\n');
+        }
+        key.extend_from_slice(&body);
+
+        if let Some((content_type, cached)) = cache.get(&key, version) {
+            debug!("query result served from cache");
+            let mut response = Response::builder().header("X-Cache", "HIT");
+            if let Some(content_type) = content_type {
+                response = response.header(CONTENT_TYPE, content_type);
+            }
+            return response.body(cached.to_vec().into()).unwrap();
+        }
+
+        *request.body_mut() = body.into();
+        request.extensions_mut().insert(LocationsSynced);
+        let response = on_request(request);
+        if response.status() != StatusCode::OK {
+            return response;
+        }
+        let (mut parts, body) = response.into_parts();
+        parts
+            .headers
+            .insert("X-Cache", HeaderValue::from_static("MISS"));
+        let content_type = parts.headers.get(CONTENT_TYPE).cloned();
+        let cache = Arc::clone(&cache);
+        let body = Body::from_read(CachingBody {
+            body,
+            content: Some(Vec::new()),
+            max_size: cache.max_result_size(),
+            on_end: Some(Box::new(move |content| {
+                cache.insert(key, version, content_type, content.into())
+            })),
+        });
+        Response::from_parts(parts, body)
+    }
+}
+
+/// Whether the request asks the query endpoint to evaluate a SPARQL query, as opposed to
+/// describing the service
+fn is_sparql_query(request: &Request<Body>) -> bool {
+    request.uri().path() == "/query"
+        && match *request.method() {
+            Method::GET => !url_query(request).is_empty(),
+            Method::POST => true,
+            _ => false,
+        }
+}
+
+/// Upper bounds in seconds of the query duration histogram buckets
+const QUERY_DURATION_BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// Counters exposed in the Prometheus text format at `/metrics`
+#[derive(Debug, Default)]
+pub struct ServerMetrics {
+    queries: AtomicU64,
+    query_errors: AtomicU64,
+    // cumulative count of queries per bucket of QUERY_DURATION_BUCKETS
+    query_duration_buckets: [AtomicU64; QUERY_DURATION_BUCKETS.len()],
+    query_duration_micros: AtomicU64,
+    bytes_served: Arc<AtomicU64>,
+    responses_abandoned: Arc<AtomicU64>,
+    query_evaluations_running: AtomicUsize,
+}
+
+impl ServerMetrics {
+    /// Record a query that took `duration` to answer
+    pub fn observe_query(&self, duration: Duration, failed: bool) {
+        self.queries.fetch_add(1, Ordering::Relaxed);
+        if failed {
+            self.query_errors.fetch_add(1, Ordering::Relaxed);
+        }
+        let seconds = duration.as_secs_f64();
+        for (bound, bucket) in QUERY_DURATION_BUCKETS
+            .iter()
+            .zip(&self.query_duration_buckets)
+        {
+            if seconds <= *bound {
+                bucket.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        self.query_duration_micros.fetch_add(
+            u64::try_from(duration.as_micros()).unwrap_or(u64::MAX),
+            Ordering::Relaxed,
+        );
+    }
+
+    /// Render the metrics in the Prometheus text exposition format
+    pub fn render(&self, store: &AggregateHdt) -> String {
+        let queries = self.queries.load(Ordering::Relaxed);
+        let mut out = String::new();
+        out.push_str("# HELP de_queries_total SPARQL queries received.\n");
+        out.push_str("# TYPE de_queries_total counter\n");
+        out.push_str(&format!("de_queries_total {queries}\n"));
+        out.push_str("# HELP de_query_errors_total SPARQL queries answered with an error.\n");
+        out.push_str("# TYPE de_query_errors_total counter\n");
+        out.push_str(&format!(
+            "de_query_errors_total {}\n",
+            self.query_errors.load(Ordering::Relaxed)
+        ));
+        out.push_str("# HELP de_query_duration_seconds Time taken to answer SPARQL queries.\n");
+        out.push_str("# TYPE de_query_duration_seconds histogram\n");
+        for (bound, bucket) in QUERY_DURATION_BUCKETS
+            .iter()
+            .zip(&self.query_duration_buckets)
+        {
+            out.push_str(&format!(
+                "de_query_duration_seconds_bucket{{le=\"{bound}\"}} {}\n",
+                bucket.load(Ordering::Relaxed)
+            ));
+        }
+        out.push_str(&format!(
+            "de_query_duration_seconds_bucket{{le=\"+Inf\"}} {queries}\n"
+        ));
+        out.push_str(&format!(
+            "de_query_duration_seconds_sum {}\n",
+            self.query_duration_micros.load(Ordering::Relaxed) as f64 / 1e6
+        ));
+        out.push_str(&format!("de_query_duration_seconds_count {queries}\n"));
+        out.push_str("# HELP de_graphs_loaded Graphs served from HDT files.\n");
+        out.push_str("# TYPE de_graphs_loaded gauge\n");
+        out.push_str(&format!("de_graphs_loaded {}\n", store.graph_count()));
+        out.push_str("# HELP de_served_bytes_total Bytes of response bodies sent.\n");
+        out.push_str("# TYPE de_served_bytes_total counter\n");
+        out.push_str(&format!(
+            "de_served_bytes_total {}\n",
+            self.bytes_served.load(Ordering::Relaxed)
+        ));
+        out.push_str(
+            "# HELP de_abandoned_responses_total Response bodies dropped before their end, e.g. when the client disconnected.\n",
+        );
+        out.push_str("# TYPE de_abandoned_responses_total counter\n");
+        out.push_str(&format!(
+            "de_abandoned_responses_total {}\n",
+            self.responses_abandoned.load(Ordering::Relaxed)
+        ));
+        out.push_str(
+            "# HELP de_query_evaluations_running Queries whose results are still being evaluated.\n",
+        );
+        out.push_str("# TYPE de_query_evaluations_running gauge\n");
+        out.push_str(&format!(
+            "de_query_evaluations_running {}\n",
+            self.query_evaluations_running.load(Ordering::Relaxed)
+        ));
+        out
+    }
+}
+
+/// Counts a query evaluation as running until dropped
+struct RunningEvaluation(Arc<ServerMetrics>);
+
+impl RunningEvaluation {
+    fn new(metrics: Arc<ServerMetrics>) -> Self {
+        metrics
+            .query_evaluations_running
+            .fetch_add(1, Ordering::Relaxed);
+        Self(metrics)
+    }
+}
+
+impl Drop for RunningEvaluation {
+    fn drop(&mut self) {
+        self.0
+            .query_evaluations_running
+            .fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Response body counting the bytes read from it, used for bodies streamed without a known length
+struct CountingBody {
+    body: Body,
+    counter: Arc<AtomicU64>,
+    abandoned: Arc<AtomicU64>,
+    finished: bool,
+}
+
+impl Read for CountingBody {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = self.body.read(buf)?;
+        self.counter.fetch_add(len as u64, Ordering::Relaxed);
+        if len == 0 && !buf.is_empty() {
+            self.finished = true;
+        }
+        Ok(len)
+    }
+}
+
+impl Drop for CountingBody {
+    fn drop(&mut self) {
+        if !self.finished {
+            self.abandoned.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+fn metrics_middleware(
+    metrics: Arc<ServerMetrics>,
+    store: Arc<AggregateHdt>,
+    on_request: impl Fn(&mut Request<Body>) -> Response<Body> + Send + Sync + 'static,
+) -> impl Fn(&mut Request<Body>) -> Response<Body> + Send + Sync + 'static {
+    move |request| {
+        if request.uri().path() == "/metrics" && *request.method() == Method::GET {
+            return Response::builder()
+                .header(CONTENT_TYPE, "text/plain; version=0.0.4")
+                .body(metrics.render(&store).into())
+                .unwrap();
+        }
+        let start = is_sparql_query(request).then(Instant::now);
+        let response = on_request(request);
+        if let Some(start) = start {
+            // results are streamed, so this is the time to the first byte
+            metrics.observe_query(start.elapsed(), !response.status().is_success());
+        }
+        let (parts, body) = response.into_parts();
+        let body = match body.len() {
+            Some(len) => {
+                metrics.bytes_served.fetch_add(len, Ordering::Relaxed);
+                body
+            }
+            None => Body::from_read(CountingBody {
+                body,
+                counter: Arc::clone(&metrics.bytes_served),
+                abandoned: Arc::clone(&metrics.responses_abandoned),
+                finished: false,
+            }),
+        };
+        Response::from_parts(parts, body)
+    }
+}
+
 fn cors_middleware(
     on_request: impl Fn(&mut Request<Body>) -> Response<Body> + Send + Sync + 'static,
 ) -> impl Fn(&mut Request<Body>) -> Response<Body> + Send + Sync + 'static {
@@ -147,18 +1239,111 @@ fn cors_middleware(
     }
 }
 
+/// Update the graphs of `store` with the HDT files added to or removed from `config.locations`,
+/// unless a watcher started by [serve_with_config] does it
+fn sync_locations(store: &AggregateHdt, config: &ServeConfig) -> anyhow::Result<()> {
+    if !config.watch {
+        store.sync_recursive(Path::new(&config.locations).to_path_buf(), config.recursive)?;
+    }
+    Ok(())
+}
+
+/// Sync `store` with `config.locations` whenever a HDT file in it is created, changed or removed
+fn watch_locations(
+    store: Arc<AggregateHdt>,
+    config: &ServeConfig,
+) -> anyhow::Result<notify::RecommendedWatcher> {
+    use notify::{event::ModifyKind, EventKind, RecursiveMode, Watcher};
+
+    let location = PathBuf::from(&config.locations);
+    let recursive = config.recursive;
+    let watched = location.clone();
+    let mut watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+        let event = match event {
+            Ok(event) => event,
+            Err(e) => {
+                warn!("error watching {}: {e}", watched.display());
+                return;
+            }
+        };
+        // index cache files written next to the HDTs as they load don't change the graphs
+        let hdt_files: Vec<_> = event
+            .paths
+            .iter()
+            .filter(|p| p.extension().is_some_and(|e| e == "hdt"))
+            .collect();
+        if hdt_files.is_empty()
+            || matches!(
+                event.kind,
+                EventKind::Access(_) | EventKind::Modify(ModifyKind::Metadata(_))
+            )
+        {
+            return;
+        }
+        if matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_)) {
+            // indexes built for a previous version of the file are stale
+            for path in hdt_files {
+                sparql::remove_cache_files(path);
+            }
+        }
+        match store.sync_recursive(watched.clone(), recursive) {
+            Ok((0, 0)) => {}
+            Ok((added, removed)) => eprintln!(
+                "{}: {added} graph(s) added, {removed} removed",
+                watched.display()
+            ),
+            Err(e) => warn!("error syncing {}: {e}", watched.display()),
+        }
+    })?;
+    let mode = if recursive {
+        RecursiveMode::Recursive
+    } else {
+        RecursiveMode::NonRecursive
+    };
+    watcher.watch(&location, mode)?;
+    Ok(watcher)
+}
+
 pub fn handle_request(
     request: &mut Request<Body>,
     store: &AggregateHdt,
-    // read_only: bool,
     union_default_graph: bool,
-    // timeout: Option<Duration>,
     locations: String,
 ) -> Result<Response<Body>, HttpError> {
+    let config = ServeConfig {
+        union_default_graph,
+        locations,
+        ..Default::default()
+    };
+    handle_request_with_config(request, store, &config)
+}
+
+/// Same as [handle_request], with the behavior of the server controlled by [ServeConfig]
+pub fn handle_request_with_config(
+    request: &mut Request<Body>,
+    store: &AggregateHdt,
+    config: &ServeConfig,
+) -> Result<Response<Body>, HttpError> {
+    let union_default_graph = config.union_default_graph;
     println!("{}  {}", request.uri().path(), request.method().as_ref());
-    let _ = store
-        .sync(Path::new(&locations).to_path_buf())
-        .map_err(|e| internal_server_error(format!("error loading data files: {}", e)))?;
+    if let Some(auth) = &config.auth {
+        if requires_auth(request, config)
+            && !request
+                .headers()
+                .get(AUTHORIZATION)
+                .is_some_and(|h| auth.accepts(h.as_bytes()))
+        {
+            return Ok(Response::builder()
+                .status(StatusCode::UNAUTHORIZED)
+                .header(WWW_AUTHENTICATE, format!("{} realm=\"de\"", auth.scheme()))
+                .body("Missing or invalid credentials".into())
+                .unwrap());
+        }
+    }
+    if request.extensions().get::<LocationsSynced>().is_none() {
+        sync_locations(store, config)
+            .map_err(|e| internal_server_error(format!("error loading data files: {}", e)))?;
+    }
     match (request.uri().path(), request.method().as_ref()) {
         ("/", "HEAD") => Ok(Response::builder()
             .header(CONTENT_TYPE, "text/html")
@@ -192,12 +1377,22 @@ pub fn handle_request(
             .header(CONTENT_TYPE, "image/svg+xml")
             .body(LOGO.into())
             .unwrap()),
+        ("/query", "HEAD") => {
+            let format = rdf_content_negotiation(request)?;
+            Ok(Response::builder()
+                .header(CONTENT_TYPE, format.media_type())
+                .body(Body::empty())
+                .unwrap())
+        }
         ("/query", "GET") => {
             let query = url_query(request);
             if query.is_empty() {
                 let format = rdf_content_negotiation(request)?;
-                let description =
-                    generate_service_description(format, EndpointKind::Query, union_default_graph);
+                let description = generate_service_description(
+                    format,
+                    EndpointKind::Query,
+                    service_features(store, config),
+                );
                 Ok(Response::builder()
                     .header(CONTENT_TYPE, format.media_type())
                     .body(description.into())
@@ -205,10 +1400,10 @@ pub fn handle_request(
             } else {
                 configure_and_evaluate_sparql_query(
                     store,
+                    config,
                     &[url_query(request)],
                     None,
                     request,
-                    union_default_graph,
                     // timeout,
                 )
             }
@@ -217,49 +1412,62 @@ pub fn handle_request(
             let content_type =
                 content_type(request).ok_or_else(|| bad_request("No Content-Type given"))?;
             if content_type == "application/sparql-query" {
-                let query = limited_string_body(request)?;
+                let query = limited_string_body(request, config.max_body_size)?;
                 configure_and_evaluate_sparql_query(
                     store,
+                    config,
                     &[url_query(request)],
                     Some(query),
                     request,
-                    union_default_graph,
                     // timeout,
                 )
             } else if content_type == "application/x-www-form-urlencoded" {
-                let buffer = limited_body(request)?;
+                let buffer = limited_body(request, config.max_body_size)?;
                 configure_and_evaluate_sparql_query(
                     store,
+                    config,
                     &[url_query(request), &buffer],
                     None,
                     request,
-                    union_default_graph,
                     // timeout,
                 )
             } else {
                 Err(unsupported_media_type(&content_type))
             }
         }
+        ("/update", "HEAD") => {
+            if config.read_only {
+                return Err(the_server_is_read_only());
+            }
+            let format = rdf_content_negotiation(request)?;
+            Ok(Response::builder()
+                .header(CONTENT_TYPE, format.media_type())
+                .body(Body::empty())
+                .unwrap())
+        }
         ("/update", "GET") => {
-            // if read_only {
-            //     return Err(the_server_is_read_only());
-            // }
+            if config.read_only {
+                return Err(the_server_is_read_only());
+            }
             let format = rdf_content_negotiation(request)?;
-            let description =
-                generate_service_description(format, EndpointKind::Update, union_default_graph);
+            let description = generate_service_description(
+                format,
+                EndpointKind::Update,
+                service_features(store, config),
+            );
             Ok(Response::builder()
                 .header(CONTENT_TYPE, format.media_type())
                 .body(description.into())
                 .unwrap())
         }
         ("/update", "POST") => {
-            // if read_only {
-            //     return Err(the_server_is_read_only());
-            // }
+            if config.read_only {
+                return Err(the_server_is_read_only());
+            }
             let content_type =
                 content_type(request).ok_or_else(|| bad_request("No Content-Type given"))?;
             if content_type == "application/sparql-update" {
-                let update = limited_string_body(request)?;
+                let update = limited_string_body(request, config.max_body_size)?;
                 configure_and_evaluate_sparql_update(
                     store,
                     &[url_query(request)],
@@ -268,7 +1476,7 @@ pub fn handle_request(
                     union_default_graph,
                 )
             } else if content_type == "application/x-www-form-urlencoded" {
-                let buffer = limited_body(request)?;
+                let buffer = limited_body(request, config.max_body_size)?;
                 configure_and_evaluate_sparql_update(
                     store,
                     &[url_query(request), &buffer],
@@ -284,6 +1492,17 @@ pub fn handle_request(
             if let Some(target) = store_target(request)? {
                 assert_that_graph_exists(store, &target)?;
                 let format = rdf_content_negotiation(request)?;
+                let validators = graph_validators(store, &target);
+                if let Some((etag, modified)) = &validators {
+                    if is_not_modified(request, etag, *modified) {
+                        return Ok(Response::builder()
+                            .status(StatusCode::NOT_MODIFIED)
+                            .header(ETAG, etag)
+                            .header(LAST_MODIFIED, httpdate::fmt_http_date(*modified))
+                            .body(Body::empty())
+                            .unwrap());
+                    }
+                }
                 let s = &store
                     .get_snapshot(None)
                     .map_err(|_| internal_server_error("data temporarily unavailable"))?;
@@ -292,7 +1511,7 @@ pub fn handle_request(
                 let triples: Vec<_> = s
                     .internal_quads_for_pattern(None, None, None, Some(Some(&graph_arc)))
                     .collect();
-                ReadForWrite::build_response(
+                let mut response = ReadForWrite::build_response(
                     move |w| {
                         Ok((
                             RdfSerializer::from_format(format).for_writer(w),
@@ -303,26 +1522,10 @@ pub fn handle_request(
                         Ok(if let Some(triple) = triples_iter.next() {
                             let triple = triple?;
                             // Parse the triple parts into an RDF triple
-                            let subject = NamedOrBlankNode::try_from(
-                                hdt_bgp_str_to_term(&triple.subject)
-                                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?,
-                            )
-                            .map_err(|e| {
-                                io::Error::new(io::ErrorKind::InvalidData, format!("{:?}", e))
-                            })?;
-                            let predicate = NamedNode::try_from(
-                                hdt_bgp_str_to_term(&triple.predicate)
-                                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?,
-                            )
-                            .map_err(|e| {
-                                io::Error::new(io::ErrorKind::InvalidData, format!("{:?}", e))
-                            })?;
-                            let object = hdt_bgp_str_to_term(&triple.object)
-                                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
                             let triple = oxrdf::Triple {
-                                subject,
-                                predicate,
-                                object,
+                                subject: parse_subject(&triple.subject)?,
+                                predicate: parse_predicate(&triple.predicate)?,
+                                object: parse_object(&triple.object)?,
                             };
                             serializer.serialize_triple(&triple)?;
                             Some((serializer, triples_iter))
@@ -332,7 +1535,20 @@ pub fn handle_request(
                         })
                     },
                     format.media_type(),
-                )
+                )?;
+                if let Some((etag, modified)) = validators {
+                    let headers = response.headers_mut();
+                    headers.insert(
+                        ETAG,
+                        HeaderValue::from_str(&etag).map_err(internal_server_error)?,
+                    );
+                    headers.insert(
+                        LAST_MODIFIED,
+                        HeaderValue::from_str(&httpdate::fmt_http_date(modified))
+                            .map_err(internal_server_error)?,
+                    );
+                }
+                Ok(response)
             } else {
                 let format = rdf_content_negotiation(request)?;
                 if !format.supports_datasets() {
@@ -340,40 +1556,17 @@ pub fn handle_request(
                         "It is not possible to serialize the full RDF dataset using {format} that does not support named graphs"
                     )));
                 }
-                let triples = store.collect_all_triples();
+                let triples = store.iter_all_triples();
                 ReadForWrite::build_response(
-                    move |w| {
-                        Ok((
-                            RdfSerializer::from_format(format).for_writer(w),
-                            triples.into_iter(),
-                        ))
-                    },
+                    move |w| Ok((RdfSerializer::from_format(format).for_writer(w), triples)),
                     |(mut serializer, mut triples_iter)| {
                         Ok(
                             if let Some((_graph_name, triple_parts)) = triples_iter.next() {
                                 // Parse the triple parts into an RDF triple
-                                let subject = NamedOrBlankNode::try_from(
-                                    hdt_bgp_str_to_term(&triple_parts[0]).map_err(|e| {
-                                        io::Error::new(io::ErrorKind::InvalidData, e)
-                                    })?,
-                                )
-                                .map_err(|e| {
-                                    io::Error::new(io::ErrorKind::InvalidData, format!("{:?}", e))
-                                })?;
-                                let predicate = NamedNode::try_from(
-                                    hdt_bgp_str_to_term(&triple_parts[1]).map_err(|e| {
-                                        io::Error::new(io::ErrorKind::InvalidData, e)
-                                    })?,
-                                )
-                                .map_err(|e| {
-                                    io::Error::new(io::ErrorKind::InvalidData, format!("{:?}", e))
-                                })?;
-                                let object = hdt_bgp_str_to_term(&triple_parts[2])
-                                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
                                 let triple = oxrdf::Triple {
-                                    subject,
-                                    predicate,
-                                    object,
+                                    subject: parse_subject(&triple_parts[0])?,
+                                    predicate: parse_predicate(&triple_parts[1])?,
+                                    object: parse_object(&triple_parts[2])?,
                                 };
                                 serializer.serialize_triple(&triple)?;
                                 Some((serializer, triples_iter))
@@ -388,33 +1581,34 @@ pub fn handle_request(
             }
         }
         (path, "PUT") if path.starts_with("/store") => {
-            // if read_only {
-            //     return Err(the_server_is_read_only());
-            // }
+            if config.read_only {
+                return Err(the_server_is_read_only());
+            }
             let content_type =
                 content_type(request).ok_or_else(|| bad_request("No Content-Type given"))?;
             if let Some(target) = store_target(request)? {
+                // the default graph is the union of all the data files
+                let NamedGraphName::NamedNode(target) = target else {
+                    return Err(bad_request(
+                        "PUT to the default graph is not supported. Only named graphs can be replaced.",
+                    ));
+                };
                 let format = RdfFormat::from_media_type(&content_type)
                     .ok_or_else(|| unsupported_media_type(&content_type))?;
-                let p = web_load_graph(store, request, format, &GraphName::from(target.clone()))?;
-                let new = !match &target {
-                    NamedGraphName::NamedNode(target) => {
-                        if store
-                            .contains_graph_name(&target.clone().into_string())
-                            .map_err(internal_server_error)?
-                        {
-                            store
-                                .remove_named_graph(target)
-                                .map_err(internal_server_error)?;
-                            true
-                        } else {
-                            store
-                                .insert_named_graph(target, Path::new(&p))
-                                .map_err(internal_server_error)?;
-                            false
-                        }
-                    }
-                    NamedGraphName::DefaultGraph => return Err(internal_server_error("")),
+                let p = web_load_graph(store, request, format, &target.clone().into())?;
+                let new = !if store
+                    .contains_graph_name(&target.clone().into_string())
+                    .map_err(internal_server_error)?
+                {
+                    store
+                        .remove_named_graph(&target)
+                        .map_err(internal_server_error)?;
+                    true
+                } else {
+                    store
+                        .insert_named_graph(&target, Path::new(&p))
+                        .map_err(internal_server_error)?;
+                    false
                 };
 
                 Ok(Response::builder()
@@ -437,12 +1631,17 @@ pub fn handle_request(
             }
         }
         (path, "DELETE") if path.starts_with("/store") => {
-            // if read_only {
-            //     return Err(the_server_is_read_only());
-            // }
+            if config.read_only {
+                return Err(the_server_is_read_only());
+            }
             if let Some(target) = store_target(request)? {
                 match target {
-                    NamedGraphName::DefaultGraph => todo!(),
+                    // the default graph is the union of all the data files
+                    NamedGraphName::DefaultGraph => {
+                        return Err(bad_request(
+                            "DELETE of the default graph is not supported. Only named graphs can be deleted.",
+                        ));
+                    }
                     NamedGraphName::NamedNode(target) => {
                         if store
                             .contains_graph_name(&target.clone().into_string())
@@ -468,22 +1667,38 @@ pub fn handle_request(
                 .unwrap())
         }
         (path, "POST") if path.starts_with("/store") => {
-            // if read_only {
-            //     return Err(the_server_is_read_only());
-            // }
+            if config.read_only {
+                return Err(the_server_is_read_only());
+            }
             let content_type =
                 content_type(request).ok_or_else(|| bad_request("No Content-Type given"))?;
             if let Some(target) = store_target(request)? {
                 let format = RdfFormat::from_media_type(&content_type)
                     .ok_or_else(|| unsupported_media_type(&content_type))?;
-                let new = assert_that_graph_exists(store, &target).is_ok();
+                if let NamedGraphName::NamedNode(graph) = &target {
+                    if store
+                        .contains_graph_name(&graph.clone().into_string())
+                        .map_err(internal_server_error)?
+                    {
+                        // POST adds to an existing graph instead of replacing it
+                        if !store.allows_mutations() {
+                            return Err(content_is_read_only(format!(
+                                "graph {graph} already exists and mutations are not allowed"
+                            )));
+                        }
+                        let p = web_parse_graph(store, request, format, &graph.clone().into())?;
+                        store.append_to_named_graph(graph, &p).map_err(|e| {
+                            internal_server_error(format!("Failed to update graph {graph}: {e}"))
+                        })?;
+                        return Ok(Response::builder()
+                            .status(StatusCode::NO_CONTENT)
+                            .body(Body::empty())
+                            .unwrap());
+                    }
+                }
                 web_load_graph(store, request, format, &GraphName::from(target))?;
                 Ok(Response::builder()
-                    .status(if new {
-                        StatusCode::CREATED
-                    } else {
-                        StatusCode::NO_CONTENT
-                    })
+                    .status(StatusCode::CREATED)
                     .body(Body::empty())
                     .unwrap())
             } else {
@@ -524,6 +1739,22 @@ pub fn handle_request(
     }
 }
 
+/// Whether the request has to carry the credentials of [ServeConfig::auth]: writes always do,
+/// reads only with [ServeConfig::auth_reads]. The UI pages hold no data and stay open.
+fn requires_auth(request: &Request<Body>, config: &ServeConfig) -> bool {
+    let path = request.uri().path();
+    let is_write = match *request.method() {
+        Method::POST => path == "/update" || path.starts_with("/store"),
+        Method::PUT | Method::DELETE => path.starts_with("/store"),
+        _ => false,
+    };
+    let is_ui = matches!(
+        path,
+        "/" | "/yasgui.min.css" | "/yasgui.min.js" | "/logo.svg"
+    );
+    is_write || (config.auth_reads && !is_ui)
+}
+
 fn base_url(request: &Request<Body>) -> String {
     let uri = request.uri();
     if uri.query().is_some() {
@@ -558,18 +1789,18 @@ fn url_query_parameter<'a>(request: &'a Request<Body>, param: &str) -> Option<Co
         .map(|(_, v)| v)
 }
 
-fn limited_string_body(request: &mut Request<Body>) -> Result<String, HttpError> {
-    String::from_utf8(limited_body(request)?)
+fn limited_string_body(request: &mut Request<Body>, max_size: u64) -> Result<String, HttpError> {
+    String::from_utf8(limited_body(request, max_size)?)
         .map_err(|e| bad_request(format!("Invalid UTF-8 body: {e}")))
 }
 
-fn limited_body(request: &mut Request<Body>) -> Result<Vec<u8>, HttpError> {
+fn limited_body(request: &mut Request<Body>, max_size: u64) -> Result<Vec<u8>, HttpError> {
     let body = request.body_mut();
     if let Some(body_len) = body.len() {
-        if body_len > MAX_SPARQL_BODY_SIZE {
+        if body_len > max_size {
             // it's too big
             return Err(bad_request(format!(
-                "SPARQL body payloads are limited to {MAX_SPARQL_BODY_SIZE} bytes, found {body_len} bytes"
+                "SPARQL body payloads are limited to {max_size} bytes, found {body_len} bytes"
             )));
         }
         let mut payload = Vec::with_capacity(
@@ -582,12 +1813,12 @@ fn limited_body(request: &mut Request<Body>) -> Result<Vec<u8>, HttpError> {
         Ok(payload)
     } else {
         let mut payload = Vec::new();
-        body.take(MAX_SPARQL_BODY_SIZE + 1)
+        body.take(max_size + 1)
             .read_to_end(&mut payload)
             .map_err(internal_server_error)?;
-        if payload.len() > MAX_SPARQL_BODY_SIZE.try_into().unwrap() {
+        if payload.len() > max_size.try_into().unwrap() {
             return Err(bad_request(format!(
-                "SPARQL body payloads are limited to {MAX_SPARQL_BODY_SIZE} bytes"
+                "SPARQL body payloads are limited to {max_size} bytes"
             )));
         }
         Ok(payload)
@@ -596,17 +1827,21 @@ fn limited_body(request: &mut Request<Body>) -> Result<Vec<u8>, HttpError> {
 
 fn configure_and_evaluate_sparql_query(
     store: &AggregateHdt,
+    config: &ServeConfig,
     encoded: &[&[u8]],
     mut query: Option<String>,
     request: &Request<Body>,
-    default_use_default_graph_as_union: bool,
     // timeout: Option<Duration>,
 ) -> Result<Response<Body>, HttpError> {
     let mut default_graph_uris = Vec::new();
     let mut named_graph_uris = Vec::new();
     let mut use_default_graph_as_union = false;
+    let mut page = Page::default();
     for encoded in encoded {
         for (k, v) in form_urlencoded::parse(encoded) {
+            if !matches!(k.as_ref(), "query" | "limit" | "offset") {
+                page.params.push((k.to_string(), v.to_string()));
+            }
             match k.as_ref() {
                 "query" => {
                     if query.is_some() {
@@ -614,126 +1849,367 @@ fn configure_and_evaluate_sparql_query(
                     }
                     query = Some(v.into_owned())
                 }
+                "limit" => {
+                    let limit = v.parse::<usize>().ok().filter(|l| *l > 0).ok_or_else(|| {
+                        bad_request(format!("limit should be a positive integer, found {v}"))
+                    })?;
+                    page.limit = Some(limit.min(MAX_PAGE_SIZE))
+                }
+                "offset" => {
+                    page.offset = v.parse().map_err(|_| {
+                        bad_request(format!("offset should be a positive integer, found {v}"))
+                    })?
+                }
                 "default-graph-uri" => default_graph_uris.push(v.into_owned()),
                 "union-default-graph" => use_default_graph_as_union = true,
                 "named-graph-uri" => named_graph_uris.push(v.into_owned()),
                 _ => (),
-            }
-        }
-    }
-    if default_graph_uris.is_empty() && named_graph_uris.is_empty() {
-        use_default_graph_as_union |= default_use_default_graph_as_union;
+            }
+        }
     }
+    // graphs listed by the request make up the dataset of the query, in place of its FROM and
+    // FROM NAMED clauses. Without named-graph-uri there are no named graphs, and without
+    // default-graph-uri the default graph is empty
+    let dataset = (!default_graph_uris.is_empty() || !named_graph_uris.is_empty()).then(|| {
+        // graph names aren't always absolute IRIs, see --short-graph-names
+        QueryDataset {
+            default: default_graph_uris
+                .into_iter()
+                .map(NamedNode::new_unchecked)
+                .collect(),
+            named: Some(
+                named_graph_uris
+                    .into_iter()
+                    .map(NamedNode::new_unchecked)
+                    .collect(),
+            ),
+        }
+    });
     let query = query.ok_or_else(|| bad_request("You should set the 'query' parameter"))?;
     evaluate_sparql_query(
         store,
+        config,
         &query,
         use_default_graph_as_union,
-        default_graph_uris,
-        named_graph_uris,
+        dataset,
         request,
+        page,
         // timeout,
     )
 }
 
+/// Page of SELECT results asked with the `limit` and `offset` parameters of a query request,
+/// pages hold at most [MAX_PAGE_SIZE] results. Without them all results are returned.
+#[derive(Clone, Default)]
+struct Page {
+    limit: Option<usize>,
+    offset: usize,
+    // parameters of the request other than the query and the page, carried to the next page
+    params: Vec<(String, String)>,
+}
+
+impl Page {
+    /// Restrict a SELECT query to the solutions up to the end of the page, plus one telling whether
+    /// a next page exists. The solutions before the page are skipped as they stream by, see
+    /// [sparql::skip_solutions]. Other query forms are returned as is.
+    fn apply(&self, query: Query) -> Query {
+        let Some(limit) = self.limit else {
+            return query;
+        };
+        match query {
+            Query::Select {
+                dataset,
+                pattern,
+                base_iri,
+            } => Query::Select {
+                dataset,
+                pattern: GraphPattern::Slice {
+                    inner: Box::new(pattern),
+                    start: 0,
+                    length: Some(self.offset.saturating_add(limit).saturating_add(1)),
+                },
+                base_iri,
+            },
+            query => query,
+        }
+    }
+
+    /// `Link` header value of the following page, a GET request carrying the query and the other
+    /// parameters of the request, whether they were sent in the URL or a form body
+    fn next_link(&self, request: &Request<Body>, query: &str, limit: usize) -> String {
+        let mut params = form_urlencoded::Serializer::new(String::new());
+        for (k, v) in &self.params {
+            params.append_pair(k, v);
+        }
+        params
+            .append_pair("query", query)
+            .append_pair("limit", &limit.to_string())
+            .append_pair("offset", &(self.offset + limit).to_string());
+        format!("<{}?{}>; rel=\"next\"", base_url(request), params.finish())
+    }
+}
+
 fn evaluate_sparql_query(
     store: &AggregateHdt,
+    config: &ServeConfig,
     query: &str,
-    _use_default_graph_as_union: bool,
-    _default_graph_uris: Vec<String>,
-    named_graph_uris: Vec<String>,
+    use_default_graph_as_union: bool,
+    dataset: Option<QueryDataset>,
     request: &Request<Body>,
+    page: Page,
     // timeout: Option<Duration>,
 ) -> Result<Response<Body>, HttpError> {
     debug!("query: {query}");
-    let stuff = SparqlParser::new()
-        .with_base_iri(base_url(request))
-        .map_err(bad_request)?
-        .parse_query(query)
-        .map_err(bad_request)?;
+    // the default graph is the designated default graph or the union of all graphs, as
+    // configured, unless the request asks for the union or the query has a dataset of its own
+    let default_graph = config
+        .default_graph
+        .clone()
+        .filter(|_| !use_default_graph_as_union);
+    let union_default_graph = use_default_graph_as_union || config.union_default_graph;
+    let mut stuff = config
+        .prepared_queries
+        .get_or_parse(&base_url(request), query)?;
+    if let Some(dataset) = dataset {
+        match &mut stuff {
+            Query::Select { dataset: d, .. }
+            | Query::Construct { dataset: d, .. }
+            | Query::Describe { dataset: d, .. }
+            | Query::Ask { dataset: d, .. } => *d = Some(dataset),
+        }
+    }
+    let stuff = page.apply(stuff);
 
     // Get snapshot with optional graph filtering
-    // Optimization: Filter graphs BEFORE loading into memory by passing named_graph_uris
-    // to get_snapshot(). This significantly reduces memory usage and load time when
+    // Optimization: Filter graphs BEFORE loading into memory, e.g. to the graphs of the
+    // dataset of the query. This significantly reduces memory usage and load time when
     // only a subset of graphs are needed for the query.
-    // Note: union_default_graph is always true - default graph is union of all loaded graphs
-    let graph_filter = if !named_graph_uris.is_empty() {
-        Some(named_graph_uris)
-    } else {
-        None
-    };
+    let graph_filter = sparql::query_graphs(&stuff);
     let s = store
         .get_snapshot(graph_filter)
-        .map_err(|_| internal_server_error("data temporarily unavailable"))?;
+        .map_err(|_| internal_server_error("data temporarily unavailable"))?
+        .with_union_default_graph(union_default_graph)
+        .with_default_graph(default_graph);
+
+    let format = match &stuff {
+        Query::Construct { .. } | Query::Describe { .. } => {
+            ResultsFormat::Graph(rdf_content_negotiation(request)?)
+        }
+        Query::Select { .. } | Query::Ask { .. } => {
+            ResultsFormat::Solutions(query_results_content_negotiation(request)?)
+        }
+    };
+    let (has_next, body) =
+        stream_query_results(s, stuff, format, page.clone(), Arc::clone(&config.metrics))?;
+    let mut response = Response::builder().header(CONTENT_TYPE, format.media_type());
+    if let (true, Some(limit)) = (has_next, page.limit) {
+        response = response.header(LINK, page.next_link(request, query, limit));
+    }
+    Ok(response.body(body).unwrap())
+}
+
+/// Format of the results of a query, depending on its form
+#[derive(Clone, Copy)]
+enum ResultsFormat {
+    Solutions(QueryResultsFormat),
+    Graph(RdfFormat),
+}
 
-    let results = QueryEvaluator::new()
-        .prepare(&stuff)
-        .execute(&s)
+impl ResultsFormat {
+    fn media_type(self) -> &'static str {
+        match self {
+            Self::Solutions(format) => format.media_type(),
+            Self::Graph(format) => format.media_type(),
+        }
+    }
+}
+
+/// Evaluate `query` over `snapshot` and write its results on a thread of its own. The thread owns
+/// the snapshot, so the results can borrow it and are only evaluated as fast as the client reads
+/// them. Once the client disconnects writing fails, which stops the evaluation. Solutions that
+/// have to be buffered before the first byte is written, e.g. a page of them, are evaluated in
+/// full first since a disconnect is only noticed when writing. Returns whether a page of
+/// solutions has a next page, known once the page is evaluated, along with the body.
+fn stream_query_results(
+    snapshot: AggregateHdtSnapshot,
+    query: Query,
+    format: ResultsFormat,
+    page: Page,
+    metrics: Arc<ServerMetrics>,
+) -> Result<(bool, Body), HttpError> {
+    let (head_sender, head_receiver) = mpsc::sync_channel(1);
+    let (chunk_sender, chunk_receiver) = mpsc::sync_channel(STREAMED_CHUNKS);
+    let running = RunningEvaluation::new(metrics);
+    std::thread::Builder::new()
+        .name("query evaluation".to_string())
+        .spawn(move || {
+            let _running = running;
+            let mut writer = ChannelWriter {
+                sender: chunk_sender,
+                buffer: Vec::new(),
+            };
+            let written =
+                write_query_results(&snapshot, &query, format, page, &head_sender, &mut writer)
+                    .and_then(|()| writer.flush());
+            match written {
+                Ok(()) => (),
+                Err(e) if e.kind() == io::ErrorKind::BrokenPipe => {
+                    debug!("client disconnected, query evaluation stopped")
+                }
+                Err(e) => {
+                    eprintln!("Internal server error while streaming results: {e}");
+                    let _ = writer
+                        .write_all(e.to_string().as_bytes())
+                        .and_then(|()| writer.flush());
+                }
+            }
+        })
         .map_err(internal_server_error)?;
-    match results {
-        QueryResults::Solutions(solutions) => {
-            let format = query_results_content_negotiation(request)?;
-            // Collect variable names and solutions to avoid lifetime issues
+    let has_next = head_receiver
+        .recv()
+        .map_err(|_| internal_server_error("query evaluation failed"))??;
+    Ok((
+        has_next,
+        Body::from_read(ChannelReader {
+            receiver: chunk_receiver,
+            chunk: Vec::new(),
+            position: 0,
+        }),
+    ))
+}
+
+/// Write the results of `query` to `writer`. Before the first byte is written `head` is told
+/// whether a next page exists, or why the evaluation failed.
+fn write_query_results(
+    snapshot: &AggregateHdtSnapshot,
+    query: &Query,
+    format: ResultsFormat,
+    page: Page,
+    head: &mpsc::SyncSender<Result<bool, HttpError>>,
+    writer: &mut ChannelWriter,
+) -> io::Result<()> {
+    let results = match evaluate_page(snapshot, query, &page) {
+        Ok(results) => results,
+        Err(e) => {
+            let _ = head.send(Err(e));
+            return Ok(());
+        }
+    };
+    match (results, format) {
+        (QueryResults::Solutions(solutions), ResultsFormat::Solutions(format)) => {
             let variables = solutions.variables().to_vec();
-            let solutions_vec: Vec<_> = solutions
-                .collect::<Result<_, _>>()
-                .map_err(internal_server_error)?;
-            ReadForWrite::build_response(
-                move |w| {
-                    Ok((
-                        QueryResultsSerializer::from_format(format)
-                            .serialize_solutions_to_writer(w, variables)?,
-                        solutions_vec.into_iter(),
-                    ))
-                },
-                |(mut serializer, mut solutions_iter)| {
-                    Ok(if let Some(solution) = solutions_iter.next() {
-                        serializer.serialize(&solution)?;
-                        Some((serializer, solutions_iter))
-                    } else {
-                        serializer.finish()?;
-                        None
-                    })
-                },
-                format.media_type(),
-            )
+            let (has_next, solutions): (bool, Box<dyn Iterator<Item = _> + '_>) = match page.limit {
+                Some(limit) => {
+                    // the page is small enough to be buffered, which tells whether a next page
+                    // exists before the headers are sent
+                    let mut page_solutions = match solutions.collect::<Result<Vec<_>, _>>() {
+                        Ok(page_solutions) => page_solutions,
+                        Err(e) => {
+                            let _ = head.send(Err(internal_server_error(e)));
+                            return Ok(());
+                        }
+                    };
+                    let has_next = page_solutions.len() > limit;
+                    page_solutions.truncate(limit);
+                    (has_next, Box::new(page_solutions.into_iter().map(Ok)))
+                }
+                None => (false, Box::new(solutions)),
+            };
+            let _ = head.send(Ok(has_next));
+            let mut serializer = QueryResultsSerializer::from_format(format)
+                .serialize_solutions_to_writer(&mut *writer, variables)?;
+            for solution in solutions {
+                serializer.serialize(&solution.map_err(io::Error::other)?)?;
+            }
+            serializer.finish()?;
         }
-        QueryResults::Boolean(result) => {
-            let format = query_results_content_negotiation(request)?;
-            let mut body = Vec::new();
+        (QueryResults::Boolean(result), ResultsFormat::Solutions(format)) => {
+            let _ = head.send(Ok(false));
             QueryResultsSerializer::from_format(format)
-                .serialize_boolean_to_writer(&mut body, result)
-                .map_err(internal_server_error)?;
-            Ok(Response::builder()
-                .header(CONTENT_TYPE, format.media_type())
-                .body(body.into())
-                .unwrap())
+                .serialize_boolean_to_writer(&mut *writer, result)?;
         }
-        QueryResults::Graph(triples) => {
-            let format = rdf_content_negotiation(request)?;
-            // Collect triples to avoid lifetime issues
-            let triples: Vec<_> = triples
-                .collect::<Result<_, _>>()
-                .map_err(internal_server_error)?;
-            ReadForWrite::build_response(
-                move |w| {
-                    Ok((
-                        RdfSerializer::from_format(format).for_writer(w),
-                        triples.into_iter(),
-                    ))
-                },
-                |(mut serializer, mut triples_iter)| {
-                    Ok(if let Some(t) = triples_iter.next() {
-                        serializer.serialize_triple(&t)?;
-                        Some((serializer, triples_iter))
-                    } else {
-                        serializer.finish()?;
-                        None
-                    })
-                },
-                format.media_type(),
-            )
+        (QueryResults::Graph(triples), ResultsFormat::Graph(format)) => {
+            let _ = head.send(Ok(false));
+            let mut serializer = RdfSerializer::from_format(format).for_writer(&mut *writer);
+            for triple in triples {
+                serializer.serialize_triple(&triple.map_err(io::Error::other)?)?;
+            }
+            serializer.finish()?;
         }
+        _ => {
+            let _ = head.send(Err(internal_server_error(
+                "the query returned results of another form",
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Evaluate `query`, skipping the solutions before the page
+fn evaluate_page<'a>(
+    snapshot: &'a AggregateHdtSnapshot,
+    query: &Query,
+    page: &Page,
+) -> Result<QueryResults<'a>, HttpError> {
+    // the FROM and FROM NAMED clauses of the query scope the graphs it sees, as in the CLI
+    let mut results = sparql::evaluate(query, snapshot).map_err(internal_server_error)?;
+    if let QueryResults::Solutions(solutions) = &mut results {
+        sparql::skip_solutions(solutions, page.offset).map_err(internal_server_error)?;
+    }
+    Ok(results)
+}
+
+/// Writes to a [ChannelReader] in chunks. Writing fails once the reader is dropped, e.g. because
+/// the client disconnected.
+struct ChannelWriter {
+    sender: mpsc::SyncSender<Vec<u8>>,
+    buffer: Vec<u8>,
+}
+
+impl Write for ChannelWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+        if self.buffer.len() >= STREAMED_CHUNK_SIZE {
+            self.flush()?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if !self.buffer.is_empty() {
+            self.sender
+                .send(std::mem::take(&mut self.buffer))
+                .map_err(|_| {
+                    io::Error::new(io::ErrorKind::BrokenPipe, "the response body was dropped")
+                })?;
+        }
+        Ok(())
+    }
+}
+
+/// Response body read from the chunks sent by a [ChannelWriter]
+struct ChannelReader {
+    receiver: mpsc::Receiver<Vec<u8>>,
+    chunk: Vec<u8>,
+    position: usize,
+}
+
+impl Read for ChannelReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.position == self.chunk.len() {
+            match self.receiver.recv() {
+                Ok(chunk) => {
+                    self.chunk = chunk;
+                    self.position = 0;
+                }
+                // the writer is done
+                Err(_) => return Ok(0),
+            }
+        }
+        let len = min(self.chunk.len() - self.position, buf.len());
+        buf[..len].copy_from_slice(&self.chunk[self.position..self.position + len]);
+        self.position += len;
+        Ok(len)
     }
 }
 
@@ -783,7 +2259,7 @@ fn configure_and_evaluate_sparql_update(
 fn evaluate_sparql_update(
     store: &AggregateHdt,
     update: &str,
-    _use_default_graph_as_union: bool,
+    use_default_graph_as_union: bool,
     _default_graph_uris: Vec<String>,
     _named_graph_uris: Vec<String>,
     request: &Request<Body>,
@@ -817,7 +2293,6 @@ fn evaluate_sparql_update(
             // Allow INSERT DATA - but only to new graphs
             GraphUpdateOperation::InsertData { data } => {
                 use spargebra::term::GraphName as SparqlGraphName;
-                use std::collections::HashSet;
 
                 // Extract all graph names from the quads
                 let mut graphs_used = HashSet::new();
@@ -834,11 +2309,12 @@ fn evaluate_sparql_update(
                     }
                 }
 
-                // Check that all target graphs don't already exist
+                // Check that all target graphs don't already exist, unless they may be modified
                 for graph in graphs_used {
-                    if store
-                        .contains_graph_name(&graph.clone().into_string())
-                        .map_err(internal_server_error)?
+                    if !store.allows_mutations()
+                        && store
+                            .contains_graph_name(&graph.clone().into_string())
+                            .map_err(internal_server_error)?
                     {
                         return Err(content_is_read_only(format!(
                             "Graph {} already exists. INSERT DATA is only allowed to new graphs.",
@@ -876,17 +2352,43 @@ fn evaluate_sparql_update(
                 }
             }
 
-            // Reject all operations that modify existing data
-            GraphUpdateOperation::DeleteData { .. } => {
-                return Err(content_is_read_only(
-                    "DELETE DATA is not allowed. Only INSERT DATA to new graphs is permitted.",
-                ));
+            // Operations modifying existing data are only allowed when the server permits mutations
+            GraphUpdateOperation::DeleteData { data } => {
+                use spargebra::term::GraphName as SparqlGraphName;
+
+                if !store.allows_mutations() {
+                    return Err(content_is_read_only(
+                        "DELETE DATA is not allowed. Only INSERT DATA to new graphs is permitted.",
+                    ));
+                }
+                if data
+                    .iter()
+                    .any(|quad| quad.graph_name == SparqlGraphName::DefaultGraph)
+                {
+                    return Err(content_is_read_only(
+                        "DELETE DATA from the default graph is not allowed. Only named graphs are supported.",
+                    ));
+                }
             }
 
-            GraphUpdateOperation::DeleteInsert { .. } => {
-                return Err(content_is_read_only(
-                    "DELETE/INSERT operations are not allowed. Only INSERT DATA to new graphs is permitted."
-                ));
+            GraphUpdateOperation::DeleteInsert { delete, insert, .. } => {
+                use spargebra::term::GraphNamePattern;
+
+                if !store.allows_mutations() {
+                    return Err(content_is_read_only(
+                        "DELETE/INSERT operations are not allowed. Only INSERT DATA to new graphs is permitted."
+                    ));
+                }
+                if delete
+                    .iter()
+                    .map(|q| &q.graph_name)
+                    .chain(insert.iter().map(|q| &q.graph_name))
+                    .any(|g| !matches!(g, GraphNamePattern::NamedNode(_)))
+                {
+                    return Err(content_is_read_only(
+                        "DELETE/INSERT templates must target an explicit named graph with GRAPH <iri> { ... }",
+                    ));
+                }
             }
 
             GraphUpdateOperation::Clear { graph, silent } => {
@@ -895,10 +2397,16 @@ fn evaluate_sparql_update(
                 match graph {
                     GraphTarget::NamedNode(graph_name) => {
                         // Allow CLEAR for named graphs (will remove the graph)
+                        if !store.allows_mutations() {
+                            return Err(content_is_read_only(
+                                "CLEAR GRAPH is not allowed. Existing graphs can only be cleared when mutations are allowed.",
+                            ));
+                        }
                         let exists = store
                             .contains_graph_name(&graph_name.clone().into_string())
                             .map_err(internal_server_error)?;
 
+                        // SILENT only skips a graph that doesn't exist instead of failing the request
                         if !exists && !silent {
                             return Err(bad_request(format!(
                                 "Graph {} does not exist.",
@@ -930,10 +2438,16 @@ fn evaluate_sparql_update(
                 match graph {
                     GraphTarget::NamedNode(graph_name) => {
                         // Allow DROP for named graphs (will remove the graph)
+                        if !store.allows_mutations() {
+                            return Err(content_is_read_only(
+                                "DROP GRAPH is not allowed. Existing graphs can only be dropped when mutations are allowed.",
+                            ));
+                        }
                         let exists = store
                             .contains_graph_name(&graph_name.clone().into_string())
                             .map_err(internal_server_error)?;
 
+                        // SILENT only skips a graph that doesn't exist instead of failing the request
                         if !exists && !silent {
                             return Err(bad_request(format!(
                                 "Graph {} does not exist.",
@@ -984,7 +2498,6 @@ fn evaluate_sparql_update(
 
             GraphUpdateOperation::InsertData { data } => {
                 use spargebra::term::GraphName as SparqlGraphName;
-                use std::collections::HashMap;
 
                 // Group quads by graph name
                 let mut quads_by_graph: HashMap<&NamedNode, Vec<&spargebra::term::Quad>> =
@@ -999,6 +2512,28 @@ fn evaluate_sparql_update(
                 for (graph, quads) in quads_by_graph {
                     let quad_count = quads.len();
 
+                    if store
+                        .contains_graph_name(&graph.clone().into_string())
+                        .map_err(internal_server_error)?
+                    {
+                        // only reachable when mutations are allowed, checked during validation
+                        let triples: Vec<_> = quads
+                            .into_iter()
+                            .map(|q| {
+                                oxrdf::Triple::new(
+                                    q.subject.clone(),
+                                    q.predicate.clone(),
+                                    q.object.clone(),
+                                )
+                            })
+                            .collect();
+                        store
+                            .update_named_graph(graph, &Default::default(), &triples)
+                            .map_err(internal_server_error)?;
+                        eprintln!("Inserted {} triples into graph {}", quad_count, graph);
+                        continue;
+                    }
+
                     // Create temporary NT file
                     let tmp_nt = tempfile::Builder::new()
                         .suffix(".nt")
@@ -1006,6 +2541,7 @@ fn evaluate_sparql_update(
                         .map_err(internal_server_error)?;
 
                     let (nt_file, nt_path) = tmp_nt.keep().map_err(internal_server_error)?;
+                    store.track_temp_file(&nt_path);
                     let mut nt_writer = BufWriter::new(&nt_file);
 
                     // Write quads as triples to NT file
@@ -1042,6 +2578,75 @@ fn evaluate_sparql_update(
                 }
             }
 
+            GraphUpdateOperation::DeleteData { data } => {
+                use spargebra::term::GraphName as SparqlGraphName;
+
+                let mut changes: HashMap<NamedNode, GraphChanges> = HashMap::new();
+                for quad in data {
+                    if let SparqlGraphName::NamedNode(graph) = &quad.graph_name {
+                        let Some(object) = ground_term(&quad.object) else {
+                            continue;
+                        };
+                        changes.entry(graph.clone()).or_default().delete.insert(
+                            oxrdf::Triple::new(
+                                quad.subject.clone(),
+                                quad.predicate.clone(),
+                                object,
+                            ),
+                        );
+                    }
+                }
+                apply_graph_changes(store, changes)?;
+            }
+
+            GraphUpdateOperation::DeleteInsert {
+                delete,
+                insert,
+                using,
+                pattern,
+            } => {
+                let query = spargebra::Query::Select {
+                    dataset: using.clone(),
+                    pattern: (**pattern).clone(),
+                    base_iri: None,
+                };
+                let snapshot = store
+                    .get_snapshot(None)
+                    .map_err(|_| internal_server_error("data temporarily unavailable"))?
+                    .with_union_default_graph(use_default_graph_as_union || using.is_some());
+                let QueryResults::Solutions(solutions) = QueryEvaluator::new()
+                    .prepare(&sparql::inline_values(&query))
+                    .execute(&snapshot)
+                    .map_err(internal_server_error)?
+                else {
+                    return Err(internal_server_error(
+                        "WHERE clause did not return solutions",
+                    ));
+                };
+
+                let mut changes: HashMap<NamedNode, GraphChanges> = HashMap::new();
+                for solution in solutions {
+                    let solution = solution.map_err(internal_server_error)?;
+                    for q in delete {
+                        if let Some((graph, triple)) = instantiate_ground_quad(q, &solution) {
+                            changes.entry(graph).or_default().delete.insert(triple);
+                        }
+                    }
+                    // blank nodes in the INSERT template are fresh for every solution
+                    let mut blank_nodes = HashMap::new();
+                    for q in insert {
+                        if let Some((graph, triple)) =
+                            instantiate_quad(q, &solution, &mut blank_nodes)
+                        {
+                            changes.entry(graph).or_default().insert.push(triple);
+                        }
+                    }
+                }
+                // the snapshot holds the HDT files open, release it before they are rewritten
+                drop(snapshot);
+                apply_graph_changes(store, changes)?;
+            }
+
             GraphUpdateOperation::Load {
                 destination,
                 source: _,
@@ -1069,8 +2674,8 @@ fn evaluate_sparql_update(
                         .contains_graph_name(&graph_name.clone().into_string())
                         .map_err(internal_server_error)?;
 
-                    if exists {
-                        // Remove the graph
+                    if exists && store.allows_mutations() {
+                        // HDT can't store an empty graph, a cleared graph is removed
                         store
                             .remove_named_graph(graph_name)
                             .map_err(internal_server_error)?;
@@ -1090,7 +2695,7 @@ fn evaluate_sparql_update(
                         .contains_graph_name(&graph_name.clone().into_string())
                         .map_err(internal_server_error)?;
 
-                    if exists {
+                    if exists && store.allows_mutations() {
                         // Remove the graph
                         let removed = store
                             .remove_named_graph(graph_name)
@@ -1104,13 +2709,6 @@ fn evaluate_sparql_update(
                     }
                 }
             }
-
-            _ => {
-                // Should never reach here due to validation above
-                return Err(internal_server_error(
-                    "Unexpected operation passed validation",
-                ));
-            }
         }
     }
 
@@ -1120,6 +2718,123 @@ fn evaluate_sparql_update(
         .unwrap())
 }
 
+/// Triples removed from and added to a named graph by an update
+#[derive(Default)]
+struct GraphChanges {
+    delete: HashSet<oxrdf::Triple>,
+    insert: Vec<oxrdf::Triple>,
+}
+
+fn apply_graph_changes(
+    store: &AggregateHdt,
+    changes: HashMap<NamedNode, GraphChanges>,
+) -> Result<(), HttpError> {
+    for (graph, changes) in changes {
+        store
+            .update_named_graph(&graph, &changes.delete, &changes.insert)
+            .map_err(|e| internal_server_error(format!("Failed to update graph {graph}: {e}")))?;
+        eprintln!(
+            "Updated graph {graph}: {} triple(s) deleted, {} inserted",
+            changes.delete.len(),
+            changes.insert.len()
+        );
+    }
+    Ok(())
+}
+
+fn ground_term(term: &spargebra::term::GroundTerm) -> Option<oxrdf::Term> {
+    use spargebra::term::GroundTerm;
+
+    match term {
+        GroundTerm::NamedNode(n) => Some(n.clone().into()),
+        GroundTerm::Literal(l) => Some(l.clone().into()),
+        // quoted triples can't be stored in HDT
+        #[allow(unreachable_patterns)]
+        _ => None,
+    }
+}
+
+fn named_node_pattern(
+    pattern: &spargebra::term::NamedNodePattern,
+    solution: &sparesults::QuerySolution,
+) -> Option<NamedNode> {
+    use spargebra::term::NamedNodePattern;
+
+    match pattern {
+        NamedNodePattern::NamedNode(n) => Some(n.clone()),
+        NamedNodePattern::Variable(v) => match solution.get(v)? {
+            oxrdf::Term::NamedNode(n) => Some(n.clone()),
+            _ => None,
+        },
+    }
+}
+
+fn graph_name_pattern(
+    pattern: &spargebra::term::GraphNamePattern,
+    solution: &sparesults::QuerySolution,
+) -> Option<NamedNode> {
+    use spargebra::term::GraphNamePattern;
+
+    match pattern {
+        GraphNamePattern::NamedNode(n) => Some(n.clone()),
+        GraphNamePattern::Variable(v) => match solution.get(v)? {
+            oxrdf::Term::NamedNode(n) => Some(n.clone()),
+            _ => None,
+        },
+        GraphNamePattern::DefaultGraph => None,
+    }
+}
+
+/// Bind a DELETE template against a solution, templates with unbound variables produce nothing
+fn instantiate_ground_quad(
+    quad: &spargebra::term::GroundQuadPattern,
+    solution: &sparesults::QuerySolution,
+) -> Option<(NamedNode, oxrdf::Triple)> {
+    use spargebra::term::GroundTermPattern;
+
+    let term = |pattern: &GroundTermPattern| match pattern {
+        GroundTermPattern::NamedNode(n) => Some(oxrdf::Term::from(n.clone())),
+        GroundTermPattern::Literal(l) => Some(l.clone().into()),
+        GroundTermPattern::Variable(v) => solution.get(v).cloned(),
+        #[allow(unreachable_patterns)]
+        _ => None,
+    };
+    let subject = NamedOrBlankNode::try_from(term(&quad.subject)?).ok()?;
+    let predicate = named_node_pattern(&quad.predicate, solution)?;
+    let object = term(&quad.object)?;
+    let graph = graph_name_pattern(&quad.graph_name, solution)?;
+    Some((graph, oxrdf::Triple::new(subject, predicate, object)))
+}
+
+/// Bind an INSERT template against a solution, templates with unbound variables produce nothing
+fn instantiate_quad(
+    quad: &spargebra::term::QuadPattern,
+    solution: &sparesults::QuerySolution,
+    blank_nodes: &mut HashMap<String, oxrdf::BlankNode>,
+) -> Option<(NamedNode, oxrdf::Triple)> {
+    use spargebra::term::TermPattern;
+
+    let mut term = |pattern: &TermPattern| match pattern {
+        TermPattern::NamedNode(n) => Some(oxrdf::Term::from(n.clone())),
+        TermPattern::BlankNode(b) => Some(
+            blank_nodes
+                .entry(b.as_str().to_string())
+                .or_default()
+                .clone()
+                .into(),
+        ),
+        TermPattern::Literal(l) => Some(l.clone().into()),
+        TermPattern::Variable(v) => solution.get(v).cloned(),
+        #[allow(unreachable_patterns)]
+        _ => None,
+    };
+    let subject = NamedOrBlankNode::try_from(term(&quad.subject)?).ok()?;
+    let object = term(&quad.object)?;
+    let predicate = named_node_pattern(&quad.predicate, solution)?;
+    let graph = graph_name_pattern(&quad.graph_name, solution)?;
+    Some((graph, oxrdf::Triple::new(subject, predicate, object)))
+}
+
 fn store_target(request: &Request<Body>) -> Result<Option<NamedGraphName>, HttpError> {
     if request.uri().path() == "/store" {
         if let Some(graph) = url_query_parameter(request, "graph") {
@@ -1166,6 +2881,38 @@ fn assert_that_graph_exists(
     }
 }
 
+/// ETag and modification time of the HDT file backing a named graph, derived from its size and mtime
+fn graph_validators(store: &AggregateHdt, target: &NamedGraphName) -> Option<(String, SystemTime)> {
+    let NamedGraphName::NamedNode(graph) = target else {
+        return None;
+    };
+    let meta = std::fs::metadata(store.graph_path(graph.as_str())?).ok()?;
+    let modified = meta.modified().ok()?;
+    let since_epoch = modified.duration_since(UNIX_EPOCH).ok()?;
+    Some((
+        format!("\"{:x}-{:x}\"", meta.len(), since_epoch.as_nanos()),
+        modified,
+    ))
+}
+
+/// Evaluates `If-None-Match` and, when it is absent, `If-Modified-Since` against a graph's validators
+fn is_not_modified(request: &Request<Body>, etag: &str, modified: SystemTime) -> bool {
+    let header =
+        |name: http::header::HeaderName| request.headers().get(name).and_then(|h| h.to_str().ok());
+    if let Some(if_none_match) = header(IF_NONE_MATCH) {
+        return if_none_match.split(',').any(|tag| {
+            let tag = tag.trim();
+            tag == "*" || tag.trim_start_matches("W/") == etag
+        });
+    }
+    if let Some(since) = header(IF_MODIFIED_SINCE).and_then(|h| httpdate::parse_http_date(h).ok()) {
+        // HTTP dates only have a precision of one second
+        let secs = |t: SystemTime| t.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).ok();
+        return secs(modified) <= secs(since);
+    }
+    false
+}
+
 #[derive(Eq, PartialEq, Debug, Clone, Hash)]
 enum NamedGraphName {
     NamedNode(NamedNode),
@@ -1181,6 +2928,14 @@ impl From<NamedGraphName> for GraphName {
     }
 }
 
+fn service_features(store: &AggregateHdt, config: &ServeConfig) -> ServiceFeatures {
+    ServiceFeatures {
+        union_default_graph: config.union_default_graph && config.default_graph.is_none(),
+        read_only: config.read_only,
+        mutations: store.allows_mutations(),
+    }
+}
+
 fn rdf_content_negotiation(request: &Request<Body>) -> Result<RdfFormat, HttpError> {
     content_negotiation(
         request,
@@ -1231,25 +2986,30 @@ fn content_negotiation<F: Copy>(
     println!("{ACCEPT} {header}");
     let mut result = None;
     let mut result_score = 0_f32;
-    for mut possible in header.split(',') {
+    for possible in header.split(',') {
+        // Only the q parameter is used for ranking, others like charset are ignored
+        let mut parameters = possible.split(';');
+        let possible = parameters.next().unwrap_or_default().trim();
         let mut score = 1.;
-        if let Some((possible_type, last_parameter)) = possible.rsplit_once(';') {
-            if let Some((name, value)) = last_parameter.split_once('=') {
+        let mut valid_score = true;
+        for parameter in parameters {
+            if let Some((name, value)) = parameter.split_once('=') {
                 if name.trim().eq_ignore_ascii_case("q") {
-                    score = f32::from_str(value.trim()).map_err(|_| {
-                        bad_request(format!("Invalid Accept media type score: {value}"))
-                    })?;
-                    possible = possible_type;
+                    match f32::from_str(value.trim()) {
+                        Ok(q) if (0. ..=1.).contains(&q) => score = q,
+                        _ => valid_score = false,
+                    }
                 }
             }
         }
+        if !valid_score {
+            debug!("Ignoring Accept media type with invalid score: '{possible}'");
+            continue;
+        }
         if score <= result_score {
             continue;
         }
         let (possible_base, possible_sub) = possible
-            .split_once(';')
-            .unwrap_or((possible, ""))
-            .0
             .split_once('/')
             .ok_or_else(|| bad_request(format!("Invalid media type: '{possible}'")))?;
         let possible_base = possible_base.trim();
@@ -1301,6 +3061,40 @@ fn web_load_graph(
     format: RdfFormat,
     to_graph_name: &GraphName,
 ) -> Result<String, HttpError> {
+    let base_iri = if let GraphName::NamedNode(graph_name) = to_graph_name {
+        Some(graph_name.as_str())
+    } else {
+        None
+    };
+    let p = web_parse_graph(store, request, format, to_graph_name)?;
+
+    store
+        .insert_named_graph(
+            &NamedNode::from_str(
+                base_iri.unwrap_or(
+                    &store
+                        .graph_name_for(p.as_path())
+                        .map_err(|_| internal_server_error("error with propsed graph name"))?,
+                ),
+            )
+            .unwrap_or(
+                NamedNode::from_str(&format!("file:///{:x}", random::<u128>()))
+                    .map_err(|_| internal_server_error("error with propsed graph name"))?,
+            ),
+            p.as_path(),
+        )
+        .map_err(|_| internal_server_error("error persisting graph to store"))?;
+
+    Ok(p.as_path().to_string_lossy().to_string())
+}
+
+/// Writes the RDF of the request body to a NTriples file tracked by the store, returning its path
+fn web_parse_graph(
+    store: &AggregateHdt,
+    request: &mut Request<Body>,
+    format: RdfFormat,
+    to_graph_name: &GraphName,
+) -> Result<PathBuf, HttpError> {
     let base_iri = if let GraphName::NamedNode(graph_name) = to_graph_name {
         Some(graph_name.as_str())
     } else {
@@ -1320,6 +3114,7 @@ fn web_load_graph(
         .tempfile()
         .map_err(|_| internal_server_error("error during RDF to HDT conversion"))?;
     let (f, p) = tmp_file.keep().map_err(|_| internal_server_error(""))?;
+    store.track_temp_file(&p);
     let mut dest_writer = BufWriter::new(&f);
 
     let mut serializer =
@@ -1334,22 +3129,12 @@ fn web_load_graph(
             ))
             .map_err(|_| internal_server_error("error during RDF serialization"))?
     }
+    serializer
+        .finish()
+        .and_then(|w| w.flush())
+        .map_err(|_| internal_server_error("error during RDF serialization"))?;
 
-    store
-        .insert_named_graph(
-            &NamedNode::from_str(base_iri.unwrap_or(&format!(
-                "file:///{}",
-                p.as_path().file_name().unwrap().to_str().unwrap()
-            )))
-            .unwrap_or(
-                NamedNode::from_str(&format!("file:///{:x}", random::<u128>()))
-                    .map_err(|_| internal_server_error("error with propsed graph name"))?,
-            ),
-            p.as_path(),
-        )
-        .map_err(|_| internal_server_error("error persisting graph to store"))?;
-
-    Ok(p.as_path().to_str().unwrap().to_string())
+    Ok(p)
 }
 
 fn web_load_dataset(
@@ -1394,6 +3179,11 @@ fn bad_request(message: impl fmt::Display) -> HttpError {
     (StatusCode::BAD_REQUEST, message.to_string())
 }
 
+fn the_server_is_read_only() -> HttpError {
+    eprintln!("FORBIDDEN: the server is read-only");
+    (StatusCode::FORBIDDEN, "The server is read-only".into())
+}
+
 fn content_is_read_only(message: impl fmt::Display) -> HttpError {
     eprintln!("FORBIDDEN: readonly {message}");
     (
@@ -1502,13 +3292,96 @@ impl Write for ReadForWriteWriter {
 }
 
 #[cfg(target_os = "linux")]
-fn systemd_notify_ready() -> io::Result<()> {
+fn systemd_notify(state: &[u8]) -> io::Result<()> {
     use std::env;
 
     if let Some(path) = env::var_os("NOTIFY_SOCKET") {
         use std::os::unix::net::UnixDatagram;
 
-        UnixDatagram::unbound()?.send_to(b"READY=1", path)?;
+        UnixDatagram::unbound()?.send_to(state, path)?;
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request_accepting(accept: &str) -> Request<Body> {
+        Request::builder()
+            .uri("http://localhost/query")
+            .header(ACCEPT, accept)
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    #[test]
+    fn test_content_negotiation_charset_and_q() {
+        let request = request_accepting("text/turtle;charset=utf-8;q=0.9");
+        assert_eq!(
+            rdf_content_negotiation(&request).unwrap(),
+            RdfFormat::Turtle
+        );
+
+        // charset after q must not be mistaken for the score
+        let request = request_accepting(
+            "text/turtle;q=0.5;charset=utf-8, application/n-triples;charset=utf-8;q=0.8",
+        );
+        assert_eq!(
+            rdf_content_negotiation(&request).unwrap(),
+            RdfFormat::NTriples
+        );
+    }
+
+    #[test]
+    fn test_content_negotiation_mixed_weights() {
+        let request = request_accepting(
+            "text/csv;q=0.2, application/sparql-results+json;q=0.9, application/sparql-results+xml;q=0.5",
+        );
+        assert_eq!(
+            query_results_content_negotiation(&request).unwrap(),
+            QueryResultsFormat::Json
+        );
+
+        let request =
+            request_accepting("application/sparql-results+json;q=0.1, text/tab-separated-values");
+        assert_eq!(
+            query_results_content_negotiation(&request).unwrap(),
+            QueryResultsFormat::Tsv
+        );
+    }
+
+    #[test]
+    fn test_content_negotiation_skips_malformed_q() {
+        let request = request_accepting("text/csv;q=high, application/sparql-results+xml;q=0.4");
+        assert_eq!(
+            query_results_content_negotiation(&request).unwrap(),
+            QueryResultsFormat::Xml
+        );
+
+        let request = request_accepting("text/csv;q=2");
+        let (status, _) = query_results_content_negotiation(&request).unwrap_err();
+        assert_eq!(status, StatusCode::NOT_ACCEPTABLE);
+    }
+
+    #[test]
+    fn test_auth_accepts() {
+        assert_eq!(base64_encode(b"user:pass"), "dXNlcjpwYXNz");
+        assert_eq!(base64_encode(b"ab"), "YWI=");
+        assert_eq!(base64_encode(b"a"), "YQ==");
+
+        let basic = ServeAuth::Basic {
+            user: "user".to_string(),
+            password: "pass".to_string(),
+        };
+        assert!(basic.accepts(b"Basic dXNlcjpwYXNz"));
+        assert!(basic.accepts(b"basic dXNlcjpwYXNz"));
+        assert!(!basic.accepts(b"Basic dXNlcjpwYXNx"));
+        assert!(!basic.accepts(b"Bearer dXNlcjpwYXNz"));
+
+        let bearer = ServeAuth::Bearer("secret".to_string());
+        assert!(bearer.accepts(b"Bearer secret"));
+        assert!(!bearer.accepts(b"Bearer secret2"));
+        assert!(!bearer.accepts(b"secret"));
+    }
+}
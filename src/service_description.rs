@@ -1,5 +1,5 @@
 use oxrdf::{vocab::rdf, BlankNode, NamedNodeRef, TripleRef};
-use oxrdfio::{RdfFormat, RdfSerializer};
+use oxrdfio::{JsonLdProfileSet, RdfFormat, RdfSerializer};
 use sparesults::QueryResultsFormat;
 
 mod sd {
@@ -39,23 +39,50 @@ mod sd {
     );
 }
 
+/// Features of this server that SPARQL service description has no term for
+mod de {
+    use oxrdf::NamedNodeRef;
+
+    /// Updates and graph store writes are rejected
+    pub const READ_ONLY: NamedNodeRef<'_> =
+        NamedNodeRef::new_unchecked("https://github.com/DeciSym/de#ReadOnly");
+    /// Updates may delete and replace existing triples, not only add new ones
+    pub const MUTATIONS: NamedNodeRef<'_> =
+        NamedNodeRef::new_unchecked("https://github.com/DeciSym/de#Mutations");
+}
+
 #[derive(Eq, PartialEq, Clone, Copy)]
 pub enum EndpointKind {
     Query,
     Update,
 }
 
+/// Capabilities of the server advertised as features of its endpoints
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ServiceFeatures {
+    /// The default graph is the union of all graphs
+    pub union_default_graph: bool,
+    /// Updates and graph store writes are rejected
+    pub read_only: bool,
+    /// Updates may delete and replace existing triples
+    pub mutations: bool,
+}
+
 pub fn generate_service_description(
     format: RdfFormat,
     kind: EndpointKind,
-    union_default_graph: bool,
+    features: ServiceFeatures,
 ) -> Vec<u8> {
     let mut graph = Vec::new();
     let root = BlankNode::default();
     graph.push(TripleRef::new(&root, rdf::TYPE, sd::SERVICE));
     if matches!(
         format,
-        RdfFormat::Turtle | RdfFormat::TriG | RdfFormat::N3 | RdfFormat::RdfXml
+        RdfFormat::Turtle
+            | RdfFormat::TriG
+            | RdfFormat::N3
+            | RdfFormat::RdfXml
+            | RdfFormat::JsonLd { .. }
     ) {
         // Hack: we use the default base IRI ie. the IRI from which the file is served
         graph.push(TripleRef::new(
@@ -90,6 +117,9 @@ pub fn generate_service_description(
             RdfFormat::TriG,
             RdfFormat::N3,
             RdfFormat::RdfXml,
+            RdfFormat::JsonLd {
+                profile: JsonLdProfileSet::empty(),
+            },
         ] {
             graph.push(TripleRef::new(
                 &root,
@@ -101,9 +131,14 @@ pub fn generate_service_description(
     if kind == EndpointKind::Update {
         graph.push(TripleRef::new(&root, sd::FEATURE, sd::EMPTY_GRAPHS));
     }
-    if union_default_graph {
+    if features.union_default_graph {
         graph.push(TripleRef::new(&root, sd::FEATURE, sd::UNION_DEFAULT_GRAPH));
     }
+    if features.read_only {
+        graph.push(TripleRef::new(&root, sd::FEATURE, de::READ_ONLY));
+    } else if features.mutations {
+        graph.push(TripleRef::new(&root, sd::FEATURE, de::MUTATIONS));
+    }
     graph.push(TripleRef::new(
         &root,
         sd::DEFAULT_ENTAILMENT_REGIME,
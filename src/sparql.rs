@@ -1,24 +1,67 @@
+use oxrdf::{NamedOrBlankNode, Triple};
 use spareval::{InternalQuad, QueryEvaluationError, QueryEvaluator, QueryableDataset};
-use spargebra::term::{BlankNode, NamedNode, Term};
-use spargebra::SparqlParser;
+use spargebra::algebra::{Expression, GraphPattern, QueryDataset};
+use spargebra::term::{
+    BlankNode, GroundTerm, NamedNode, NamedNodePattern, Term, TermPattern, TriplePattern, Variable,
+};
+use spargebra::{Query, SparqlParser};
 use std::{
-    collections::HashMap,
-    io::{Error, ErrorKind},
+    collections::{BTreeSet, HashMap},
+    io::{BufReader, Error, ErrorKind, Read},
     path::Path,
     str::FromStr,
-    sync::{Arc, RwLock},
+    sync::{Arc, Mutex, RwLock},
 };
 
+/// A graph of an [AggregateHdt] as returned by [AggregateHdt::get_all_graphs]
+#[cfg(feature = "server")]
+pub struct GraphInfo {
+    /// Name of the graph, e.g. `file:///data.hdt`
+    pub name: String,
+    /// HDT file backing the graph
+    pub path: std::path::PathBuf,
+    /// Header of the HDT file
+    pub header: hdt::header::Header,
+    /// Last modification time of the HDT file
+    pub mtime: std::time::SystemTime,
+    /// Size of the HDT file in bytes
+    pub file_size: u64,
+}
+
 /// Boundry over a Header-Dictionary-Triplies (HDT) storage layer.
 /// Stores file paths only; HDT instances are created per-request for better concurrency.
 pub struct AggregateHdt {
     // Map graph names (URIs) to file paths on disk
     pub file_paths: Arc<RwLock<HashMap<String, std::path::PathBuf>>>,
+    // Dedicated pool bounding how many HDTs load at once, rayon's global pool is used when unset
+    load_pool: Option<Arc<rayon::ThreadPool>>,
+    // Maximum time loading the HDTs of a snapshot may take, unbounded when unset
+    load_timeout: Option<std::time::Duration>,
+    // Files created on behalf of this store (uploaded NT and the HDTs built from them), removed on shutdown
+    temp_files: Arc<Mutex<Vec<std::path::PathBuf>>>,
+    // Whether the content of existing graphs may be changed, graphs are read-only by default
+    allow_mutations: bool,
+    // Held by [AggregateHdt::update_named_graph] from reading a graph to replacing its file, so
+    // concurrent updates don't overwrite each other's changes
+    update_lock: Arc<Mutex<()>>,
+    // Whether index cache files are written next to the HDTs when they are loaded
+    write_cache: bool,
+    // Graphs of HDT files are named `<graph_base><file stem>`, or `file:///<file name>` when unset.
+    // An empty base names them by their bare file stem, see [AggregateHdt::with_short_graph_names]
+    graph_base: Option<String>,
+    // Working directory of the HDTs extracted by [AggregateHdt::from_zip], removed with the store
+    extracted: Option<Arc<tempfile::TempDir>>,
 }
 
 pub struct AggregateHdtSnapshot {
     // Map graph names (URIs) to HDT instances
     pub hdts: HashMap<String, hdt::hdt::HdtHybrid>,
+    // Graphs read from in-memory HDTs rather than files, see [AggregateHdtSnapshot::from_readers]
+    memory_hdts: HashMap<String, hdt::Hdt>,
+    // Whether the default graph is the union of all graphs, or empty
+    union_default_graph: bool,
+    // Graph used as the default graph instead, see [AggregateHdtSnapshot::with_default_graph]
+    default_graph: Option<String>,
 }
 
 impl AggregateHdt {
@@ -37,22 +80,142 @@ impl AggregateHdt {
             }
 
             // Create graph name from filename
-            let graph_name = format!(
-                "file:///{}",
-                path.file_name()
-                    .ok_or_else(|| anyhow::anyhow!("Invalid file path: {}", p))?
-                    .to_str()
-                    .ok_or_else(|| anyhow::anyhow!("Invalid filename encoding: {}", p))?
-            );
-
-            file_paths.insert(graph_name, path.to_path_buf());
+            insert_graph(&mut file_paths, file_graph_name(None, path)?, path)?;
         }
 
         Ok(Self {
             file_paths: Arc::new(RwLock::new(file_paths)),
+            load_pool: None,
+            load_timeout: None,
+            temp_files: Arc::new(Mutex::new(Vec::new())),
+            allow_mutations: false,
+            update_lock: Arc::new(Mutex::new(())),
+            write_cache: true,
+            graph_base: None,
+            extracted: None,
         })
     }
 
+    /// Create a store with a graph for each `.hdt` entry of a zip archive, named after the entry
+    /// as [AggregateHdt::new] names files. The entries are extracted to a temporary directory that
+    /// is removed when the store is dropped.
+    pub fn from_zip(path: &Path) -> anyhow::Result<Self> {
+        let dir = tempfile::tempdir()?;
+        let file = path.to_string_lossy();
+        let paths: Vec<String> = crate::create::extract_hdt_zip(&file, dir.path())?
+            .into_iter()
+            .map(|p| p.to_string_lossy().into_owned())
+            .collect();
+        let mut store = Self::new(&paths)?;
+        store.extracted = Some(Arc::new(dir));
+        Ok(store)
+    }
+
+    /// Create a store without any graphs
+    pub fn empty() -> Self {
+        Self {
+            file_paths: Arc::new(RwLock::new(HashMap::new())),
+            load_pool: None,
+            load_timeout: None,
+            temp_files: Arc::new(Mutex::new(Vec::new())),
+            allow_mutations: false,
+            update_lock: Arc::new(Mutex::new(())),
+            write_cache: true,
+            graph_base: None,
+            extracted: None,
+        }
+    }
+
+    /// Limit the number of HDT files loaded in parallel by [AggregateHdt::get_snapshot].
+    /// Useful on memory constrained machines where loading many large HDTs at once can run out of memory.
+    pub fn with_load_concurrency(mut self, max_parallel_loads: usize) -> anyhow::Result<Self> {
+        if max_parallel_loads == 0 {
+            return Err(anyhow::anyhow!(
+                "the load concurrency must be at least 1 HDT file at a time"
+            ));
+        }
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(max_parallel_loads)
+            .build()
+            .map_err(|e| anyhow::anyhow!("error creating HDT loading thread pool: {e}"))?;
+        self.load_pool = Some(Arc::new(pool));
+        Ok(self)
+    }
+
+    /// Fail [AggregateHdt::get_snapshot] when loading the HDT files takes longer than `timeout`,
+    /// e.g. because of a bad disk, naming the files still loading. Loads can't be interrupted, they
+    /// keep going in the background after the error.
+    pub fn with_load_timeout(mut self, timeout: std::time::Duration) -> Self {
+        self.load_timeout = Some(timeout);
+        self
+    }
+
+    /// Allow [AggregateHdt::update_named_graph] to change the triples of existing graphs
+    pub fn with_mutations(mut self, allow_mutations: bool) -> Self {
+        self.allow_mutations = allow_mutations;
+        self
+    }
+
+    /// Whether to write index cache files next to the HDTs when loading them. The cache speeds up
+    /// later loads, disable it when the HDTs are only used once.
+    pub fn with_cache(mut self, write_cache: bool) -> Self {
+        self.write_cache = write_cache;
+        self
+    }
+
+    /// Name the graphs of HDT files `<graph_base><file stem>`, e.g. `http://my.org/graphs/data` for
+    /// `data.hdt` with `http://my.org/graphs/`, instead of `file:///data.hdt` so local file names
+    /// don't show in query results. Graphs already in the store are renamed.
+    pub fn with_graph_base(mut self, graph_base: &str) -> anyhow::Result<Self> {
+        oxiri::Iri::parse(format!("{graph_base}graph"))
+            .map_err(|e| anyhow::anyhow!("invalid graph base IRI {graph_base}: {e}"))?;
+        self.graph_base = Some(graph_base.to_string());
+        self.rename_graphs()
+    }
+
+    /// Name the graphs of HDT files by their file stem, e.g. `data` for `data.hdt`, instead of
+    /// `file:///data.hdt`. The names are short opaque labels rather than absolute IRIs, so they
+    /// can't be written in a `GRAPH <...>` clause but are matched by `GRAPH ?g`. Graphs already in
+    /// the store are renamed.
+    pub fn with_short_graph_names(mut self) -> anyhow::Result<Self> {
+        self.graph_base = Some(String::new());
+        self.rename_graphs()
+    }
+
+    fn rename_graphs(self) -> anyhow::Result<Self> {
+        let file_paths = std::mem::take(&mut *self.file_paths.write().unwrap());
+        let mut paths: Vec<_> = file_paths.into_values().collect();
+        paths.sort();
+        let mut renamed = HashMap::new();
+        for path in paths {
+            insert_graph(&mut renamed, self.graph_name_for(&path)?, &path)?;
+        }
+        *self.file_paths.write().unwrap() = renamed;
+        Ok(self)
+    }
+
+    /// Add the HDT file at `path` as the graph `graph_name` rather than naming it after the file,
+    /// e.g. for a named graph of a dataset file. Graphs are named after their file again by
+    /// [AggregateHdt::with_graph_base] and [AggregateHdt::with_short_graph_names], so call it after them.
+    pub fn with_named_graph(self, graph_name: &str, path: &Path) -> anyhow::Result<Self> {
+        insert_graph(
+            &mut self.file_paths.write().unwrap(),
+            graph_name.to_string(),
+            path,
+        )?;
+        Ok(self)
+    }
+
+    /// Name of the graph of a HDT file
+    pub fn graph_name_for(&self, path: &Path) -> anyhow::Result<String> {
+        file_graph_name(self.graph_base.as_deref(), path)
+    }
+
+    /// Whether the triples of existing graphs may be changed
+    pub fn allows_mutations(&self) -> bool {
+        self.allow_mutations
+    }
+
     /// Create a snapshot of HDT instances for querying.
     ///
     /// # Arguments
@@ -99,36 +262,112 @@ impl AggregateHdt {
         drop(file_paths_guard);
 
         // Load filtered HDTs in parallel
-        let hdts: HashMap<String, hdt::hdt::HdtHybrid> = paths_vec
-            .par_iter()
-            .map(
-                |(graph_name, path)| -> anyhow::Result<(String, hdt::hdt::HdtHybrid)> {
-                    // let mut reader = BufReader::new(std::fs::File::open(path)?);
-                    let hdt = hdt::Hdt::new_hybrid_cache(path, true).map_err(|e| {
-                        anyhow::anyhow!("Failed to load HDT from {:?}: {}", path, e)
-                    })?;
-                    Ok((graph_name.clone(), hdt))
-                },
-            )
-            .collect::<anyhow::Result<Vec<_>>>()?
-            .into_iter()
-            .collect();
+        let write_cache = self.write_cache;
+        let pool = self.load_pool.clone();
+        // files are removed once loaded, those left when the timeout expires are reported
+        let pending: Arc<Mutex<BTreeSet<std::path::PathBuf>>> = Arc::new(Mutex::new(
+            paths_vec.iter().map(|(_, p)| p.clone()).collect(),
+        ));
+        let load = {
+            let pending = pending.clone();
+            move || {
+                let load_all = || {
+                    paths_vec
+                        .par_iter()
+                        .map(
+                            |(graph_name, path)| -> anyhow::Result<(String, hdt::hdt::HdtHybrid)> {
+                                check_hdt_file(path)?;
+                                let hdt =
+                                    hdt::Hdt::new_hybrid_cache(path, write_cache).map_err(|e| {
+                                        anyhow::anyhow!("Failed to load HDT from {:?}: {}", path, e)
+                                    })?;
+                                pending.lock().unwrap().remove(path);
+                                Ok((graph_name.clone(), hdt))
+                            },
+                        )
+                        .collect::<anyhow::Result<Vec<_>>>()
+                };
+                match &pool {
+                    Some(pool) => pool.install(load_all),
+                    None => load_all(),
+                }
+            }
+        };
+        let loaded = match self.load_timeout {
+            None => load()?,
+            Some(timeout) => {
+                // the HDT reader is blocking, the load runs on its own thread so it can be waited on with a timeout
+                let (sender, receiver) = std::sync::mpsc::channel();
+                std::thread::spawn(move || {
+                    let _ = sender.send(load());
+                });
+                match receiver.recv_timeout(timeout) {
+                    Ok(loaded) => loaded?,
+                    Err(_) => {
+                        let slow: Vec<_> = pending.lock().unwrap().iter().cloned().collect();
+                        return Err(anyhow::anyhow!(
+                            "loading HDT files timed out after {timeout:?}, still loading {slow:?}"
+                        )
+                        .into());
+                    }
+                }
+            }
+        };
+        let hdts: HashMap<String, hdt::hdt::HdtHybrid> = loaded.into_iter().collect();
 
-        Ok(AggregateHdtSnapshot { hdts })
+        Ok(AggregateHdtSnapshot {
+            hdts,
+            memory_hdts: HashMap::new(),
+            union_default_graph: true,
+            default_graph: None,
+        })
     }
 
-    #[cfg(feature = "server")]
-    pub fn contains_graph_name(&self, graph_name: &String) -> Result<bool, anyhow::Error> {
+    pub fn contains_graph_name(&self, graph_name: &str) -> Result<bool, anyhow::Error> {
         Ok(self.file_paths.read().unwrap().contains_key(graph_name))
     }
 
+    /// Build the HDT of the N-Triples file `nt_path` next to `file_path`, with the same file stem
+    #[cfg(feature = "server")]
+    fn nt_to_graph_hdt(
+        &self,
+        nt_path: &Path,
+        file_path: &Path,
+    ) -> Result<std::path::PathBuf, anyhow::Error> {
+        use std::io::Write;
+
+        // the stem of data.ttl.xz is data
+        let file_name = file_path.file_name().unwrap_or_default().to_string_lossy();
+        let (name, _) = crate::rdf2nt::Compression::from_file_name(&file_name);
+        let stem = Path::new(name).file_stem().unwrap_or_default();
+        let hdt_path = file_path
+            .parent()
+            .unwrap_or(Path::new(""))
+            .join(format!("{}.hdt", stem.to_string_lossy()));
+
+        let h = hdt::Hdt::read_nt(nt_path)?;
+        let mut hdt_writer = std::io::BufWriter::new(std::fs::File::create(&hdt_path)?);
+        h.write(&mut hdt_writer)?;
+        hdt_writer.flush()?;
+        self.track_temp_file(&hdt_path);
+        Ok(hdt_path)
+    }
+
+    /// Path of the HDT file backing a graph
+    #[cfg(feature = "server")]
+    pub fn graph_path(&self, graph_name: &str) -> Option<std::path::PathBuf> {
+        self.file_paths.read().unwrap().get(graph_name).cloned()
+    }
+
+    /// Add the graph `graph_name` backed by `file_path`, a HDT file used as is or a RDF file of any
+    /// format `create` accepts, converted to a HDT file of the same name next to it.
     #[cfg(feature = "server")]
     pub fn insert_named_graph(
         &self,
         graph_name: &NamedNode,
         file_path: &Path,
     ) -> Result<(), anyhow::Error> {
-        use std::io::Write;
+        use crate::rdf2nt::{self, Rdf2Nt as _};
 
         let extension = file_path
             .extension()
@@ -143,26 +382,25 @@ impl AggregateHdt {
                 }
                 file_path.to_path_buf()
             }
-            "nt" => {
-                // Convert NT to HDT
-                // create new HDT file in same dir as original file, with same name
-                let hdt_path = format!(
-                    "{}/{}.hdt",
-                    file_path.to_path_buf().parent().unwrap().to_str().unwrap(),
-                    file_path.file_stem().unwrap().to_str().unwrap()
-                );
-
-                // Read the NT file and convert to HDT
-                let h = hdt::Hdt::read_nt(file_path)?;
-                let mut hdt_writer = std::io::BufWriter::new(std::fs::File::create(&hdt_path)?);
-
-                h.write(&mut hdt_writer)?;
-                hdt_writer.flush()?;
-                std::path::PathBuf::from(&hdt_path)
+            "nt" => self.nt_to_graph_hdt(file_path, file_path)?,
+            _ if rdf2nt::is_rdf_file(&file_path.to_string_lossy()) => {
+                // Other RDF formats are converted to N-Triples first, as by do_create
+                let nt_file = tempfile::Builder::new().suffix(".nt").tempfile()?;
+                let conversion = rdf2nt::OxRdfConvert::default().convert_to_nt(
+                    vec![file_path.to_string_lossy().into_owned()],
+                    nt_file.as_file(),
+                )?;
+                if !conversion.unhandled.is_empty() {
+                    return Err(anyhow::anyhow!(
+                        "Failed to convert {:?} to N-Triples",
+                        file_path
+                    ));
+                }
+                self.nt_to_graph_hdt(nt_file.path(), file_path)?
             }
             _ => {
                 return Err(anyhow::anyhow!(
-                    "Unsupported file extension: {}. Only .hdt and .nt are supported.",
+                    "Unsupported file extension: {}. Only .hdt and RDF files are supported.",
                     extension
                 ));
             }
@@ -173,6 +411,111 @@ impl AggregateHdt {
         Ok(())
     }
 
+    /// Remove `delete` from and add `insert` to a named graph. The graph's HDT file is rebuilt
+    /// in place, or created when the graph doesn't exist yet. A graph left without triples is removed
+    /// since HDT can't store an empty graph. Updates are applied one at a time.
+    #[cfg(feature = "server")]
+    pub fn update_named_graph(
+        &self,
+        graph_name: &NamedNode,
+        delete: &std::collections::HashSet<Triple>,
+        insert: &[Triple],
+    ) -> Result<(), anyhow::Error> {
+        use std::collections::HashSet;
+        use std::io::Write;
+
+        if !self.allow_mutations {
+            return Err(anyhow::anyhow!(
+                "graph {graph_name} can not be modified, mutations are not allowed"
+            ));
+        }
+        let _update = self.update_lock.lock().unwrap();
+
+        let path = self.graph_path(graph_name.as_str());
+        let mut seen = HashSet::new();
+        let mut triples = vec![];
+        if let Some(path) = &path {
+            let hdt = hdt::Hdt::new_hybrid_cache(path, self.write_cache)
+                .map_err(|e| anyhow::anyhow!("Failed to load HDT from {:?}: {}", path, e))?;
+            for t in hdt.triples_all() {
+                let t = hdt_triple_to_triple(&t)?;
+                if !delete.contains(&t) && seen.insert(t.clone()) {
+                    triples.push(t);
+                }
+            }
+        }
+        for t in insert {
+            if seen.insert(t.clone()) {
+                triples.push(t.clone());
+            }
+        }
+
+        if triples.is_empty() {
+            if path.is_some() {
+                self.remove_named_graph(graph_name)?;
+            }
+            return Ok(());
+        }
+
+        let (nt_file, nt_path) = tempfile::Builder::new().suffix(".nt").tempfile()?.keep()?;
+        let mut serializer = oxrdfio::RdfSerializer::from_format(oxrdfio::RdfFormat::NTriples)
+            .for_writer(std::io::BufWriter::new(nt_file));
+        for t in &triples {
+            serializer.serialize_triple(t)?;
+        }
+        serializer.finish()?.flush()?;
+
+        let Some(path) = path else {
+            self.track_temp_file(&nt_path);
+            return self.insert_named_graph(graph_name, &nt_path);
+        };
+        let rebuilt = hdt::Hdt::read_nt(&nt_path);
+        let _ = std::fs::remove_file(&nt_path);
+        // written next to the original then renamed over it, readers never see a partial file
+        let parent = path.parent().unwrap_or(Path::new("."));
+        let mut hdt_file = tempfile::NamedTempFile::new_in(parent)?;
+        {
+            let mut hdt_writer = std::io::BufWriter::new(hdt_file.as_file_mut());
+            rebuilt?.write(&mut hdt_writer)?;
+            hdt_writer.flush()?;
+        }
+        hdt_file.persist(&path)?;
+        // indexes built for the previous content are stale
+        remove_cache_files(&path);
+        Ok(())
+    }
+
+    /// Add the triples of a HDT or RDF file to a named graph, rebuilding its HDT file with
+    /// [AggregateHdt::update_named_graph]. Triples the graph already holds are kept once.
+    #[cfg(feature = "server")]
+    pub fn append_to_named_graph(
+        &self,
+        graph_name: &NamedNode,
+        file_path: &Path,
+    ) -> Result<(), anyhow::Error> {
+        let extension = file_path
+            .extension()
+            .and_then(|s| s.to_str())
+            .ok_or_else(|| anyhow::anyhow!("File has no extension: {:?}", file_path))?;
+        let triples = if extension == "hdt" {
+            let hdt = hdt::Hdt::new_hybrid_cache(file_path, false)
+                .map_err(|e| anyhow::anyhow!("Failed to load HDT from {:?}: {}", file_path, e))?;
+            hdt.triples_all()
+                .map(|t| hdt_triple_to_triple(&t))
+                .collect::<Result<Vec<_>, _>>()?
+        } else {
+            let format = oxrdfio::RdfFormat::from_extension(extension)
+                .ok_or_else(|| anyhow::anyhow!("Unsupported file extension: {extension}"))?;
+            let file = std::fs::File::open(file_path)?;
+            oxrdfio::RdfParser::from_format(format)
+                .without_named_graphs()
+                .for_reader(BufReader::new(file))
+                .map(|q| q.map(Triple::from))
+                .collect::<Result<Vec<_>, _>>()?
+        };
+        self.update_named_graph(graph_name, &std::collections::HashSet::new(), &triples)
+    }
+
     #[cfg(feature = "server")]
     pub fn remove_named_graph(&self, graph_name: &NamedNode) -> Result<bool, anyhow::Error> {
         let mut file_paths = self.file_paths.write().unwrap();
@@ -183,36 +526,7 @@ impl AggregateHdt {
                 eprintln!("Deleted HDT file: {:?}", path);
             }
 
-            // Delete associated cache files
-            if let Some(parent) = path.parent() {
-                if let Some(filename) = path.file_name() {
-                    let filename_str = filename.to_string_lossy();
-
-                    if let Ok(entries) = std::fs::read_dir(parent) {
-                        for entry in entries.flatten() {
-                            let entry_path = entry.path();
-                            if let Some(entry_name) = entry_path.file_name() {
-                                let entry_name_str = entry_name.to_string_lossy();
-
-                                // Check if this is a cache file for our HDT
-                                if entry_name_str.starts_with(&*filename_str)
-                                    && (entry_name_str.contains(".index.")
-                                        || entry_name_str.ends_with(".cache"))
-                                {
-                                    if let Err(e) = std::fs::remove_file(&entry_path) {
-                                        eprintln!(
-                                            "Warning: Failed to delete cache file {:?}: {}",
-                                            entry_path, e
-                                        );
-                                    } else {
-                                        eprintln!("Deleted cache file: {:?}", entry_path);
-                                    }
-                                }
-                            }
-                        }
-                    }
-                }
-            }
+            remove_cache_files(&path);
 
             Ok(true)
         } else {
@@ -220,6 +534,35 @@ impl AggregateHdt {
         }
     }
 
+    /// Record a file created on behalf of this store so [AggregateHdt::remove_temp_files] can delete it
+    pub fn track_temp_file(&self, path: &Path) {
+        self.temp_files.lock().unwrap().push(path.to_path_buf());
+    }
+
+    /// Files created on behalf of this store that have not been removed yet
+    pub fn temp_files(&self) -> Vec<std::path::PathBuf> {
+        self.temp_files.lock().unwrap().clone()
+    }
+
+    /// Delete all files recorded with [AggregateHdt::track_temp_file], along with any HDT cache files
+    /// built next to them. Returns the number of files removed.
+    pub fn remove_temp_files(&self) -> usize {
+        let mut temp_files = self.temp_files.lock().unwrap();
+        let mut removed = 0;
+        for path in temp_files.drain(..) {
+            // graphs deleted through the store API have already been removed from disk
+            if !path.exists() {
+                continue;
+            }
+            match std::fs::remove_file(&path) {
+                Ok(()) => removed += 1,
+                Err(e) => eprintln!("Warning: Failed to delete temp file {:?}: {}", path, e),
+            }
+            remove_cache_files(&path);
+        }
+        removed
+    }
+
     #[cfg(feature = "server")]
     pub fn clear(&self) -> Result<(), anyhow::Error> {
         let mut file_paths = self.file_paths.write().unwrap();
@@ -233,28 +576,27 @@ impl AggregateHdt {
     /// Returns a tuple of (added_count, removed_count).
     #[cfg(feature = "server")]
     pub fn sync(&self, location: std::path::PathBuf) -> Result<(usize, usize), anyhow::Error> {
+        self.sync_recursive(location, false)
+    }
+
+    /// Same as [AggregateHdt::sync], also picking up HDT files in subdirectories when `recursive` is set
+    #[cfg(feature = "server")]
+    pub fn sync_recursive(
+        &self,
+        location: std::path::PathBuf,
+        recursive: bool,
+    ) -> Result<(usize, usize), anyhow::Error> {
         use std::collections::HashSet;
 
         // Scan the location for .hdt files
-        let mut current_files: HashSet<std::path::PathBuf> = HashSet::new();
-        if location.is_dir() {
-            for entry in std::fs::read_dir(&location)? {
-                let entry = entry?;
-                let path = entry.path();
-                if path.is_file() {
-                    if let Some(ext) = path.extension() {
-                        if ext == "hdt" {
-                            current_files.insert(path);
-                        }
-                    }
-                }
-            }
-        } else {
+        if !location.is_dir() {
             return Err(anyhow::anyhow!(
                 "Sync location is not a directory: {:?}",
                 location
             ));
         }
+        let current_files: HashSet<std::path::PathBuf> =
+            find_hdt_files(&location, recursive)?.into_iter().collect();
 
         let mut file_paths = self.file_paths.write().unwrap();
 
@@ -265,22 +607,20 @@ impl AggregateHdt {
         let mut added = 0;
         for path in &current_files {
             if !existing_paths.contains(path) {
-                let graph_name = format!(
-                    "file:///{}",
-                    path.file_name()
-                        .ok_or_else(|| anyhow::anyhow!("Invalid file path: {:?}", path))?
-                        .to_str()
-                        .ok_or_else(|| anyhow::anyhow!("Invalid filename encoding: {:?}", path))?
-                );
-                file_paths.insert(graph_name, path.clone());
+                // e.g. files with the same name in different subdirectories
+                if let Err(e) = insert_graph(&mut file_paths, self.graph_name_for(path)?, path) {
+                    log::warn!("skipping {path:?}: {e}");
+                    continue;
+                }
                 added += 1;
             }
         }
 
-        // Find deletions (files in our map not on disk)
+        // Find deletions (files in our map not on disk). Graphs created through the API live
+        // outside the location and are kept as long as their file exists.
         let mut to_remove: Vec<String> = Vec::new();
         for (graph_name, path) in file_paths.iter() {
-            if !current_files.contains(path) {
+            if !current_files.contains(path) && (path.starts_with(&location) || !path.exists()) {
                 to_remove.push(graph_name.clone());
             }
         }
@@ -293,20 +633,45 @@ impl AggregateHdt {
         Ok((added, removed))
     }
 
-    /// Get all graph names and their associated HDT header information.
-    /// Returns a Vec of tuples containing (graph_name, file_path, hdt::header::Header).
+    /// Number of graphs in the store
     #[cfg(feature = "server")]
-    pub fn get_all_graphs(
-        &self,
-    ) -> Result<Vec<(String, std::path::PathBuf, hdt::header::Header)>, anyhow::Error> {
+    pub fn graph_count(&self) -> usize {
+        self.file_paths.read().unwrap().len()
+    }
+
+    /// Token that changes whenever a graph is added or removed or the HDT file of one is
+    /// modified, derived from the graph names and the paths, sizes and modification times of the files
+    #[cfg(feature = "server")]
+    pub fn version(&self) -> u64 {
+        use std::hash::{DefaultHasher, Hash, Hasher};
+
+        let file_paths = self.file_paths.read().unwrap();
+        let mut graphs: Vec<_> = file_paths.iter().collect();
+        graphs.sort();
+        let mut hasher = DefaultHasher::new();
+        for (graph_name, path) in graphs {
+            graph_name.hash(&mut hasher);
+            path.hash(&mut hasher);
+            if let Ok(meta) = std::fs::metadata(path) {
+                meta.len().hash(&mut hasher);
+                meta.modified().ok().hash(&mut hasher);
+            }
+        }
+        hasher.finish()
+    }
+
+    /// Get all graph names along with their HDT header information and file metadata,
+    /// sorted by graph name.
+    #[cfg(feature = "server")]
+    pub fn get_all_graphs(&self) -> Result<Vec<GraphInfo>, anyhow::Error> {
         let file_paths = self.file_paths.read().unwrap();
         let mut result = Vec::new();
 
         for (graph_name, path) in file_paths.iter() {
+            let file = std::fs::File::open(path).map_err(|e| anyhow::anyhow!("{e}"))?;
+            let metadata = file.metadata().map_err(|e| anyhow::anyhow!("{e}"))?;
             // Read HDT header to get metadata
-            let mut reader = std::io::BufReader::new(
-                std::fs::File::open(path).map_err(|e| anyhow::anyhow!("{e}"))?,
-            );
+            let mut reader = std::io::BufReader::new(file);
 
             // Read control info first, then header
             let header = hdt::containers::ControlInfo::read(&mut reader)
@@ -317,37 +682,173 @@ impl AggregateHdt {
                 })
                 .map_err(|e| anyhow::anyhow!("{e}"))?;
 
-            result.push((graph_name.clone(), path.clone(), header));
+            result.push(GraphInfo {
+                name: graph_name.clone(),
+                path: path.clone(),
+                header,
+                mtime: metadata.modified().map_err(|e| anyhow::anyhow!("{e}"))?,
+                file_size: metadata.len(),
+            });
         }
 
         // Sort by graph name
-        result.sort_by(|a, b| a.0.cmp(&b.0));
+        result.sort_by(|a, b| a.name.cmp(&b.name));
         Ok(result)
     }
 
     /// Collect all triples from all HDTs and return them as a Vec with their graph names.
-    /// This is useful for scenarios where you need a consumable iterator.
-    /// NOTE: This creates HDT instances for all graphs, so it may be memory-intensive.
+    /// NOTE: This holds the triples of all graphs at once, see [AggregateHdt::iter_all_triples]
+    /// to go through them without doing so.
     #[cfg(feature = "server")]
     pub fn collect_all_triples(&self) -> Vec<(String, [Arc<str>; 3])> {
-        let file_paths = self.file_paths.read().unwrap();
-        let mut result = Vec::new();
-        for (graph_name, path) in file_paths.iter() {
-            // Create HDT instance for this file
-            if let Ok(hdt) = hdt::hdt::Hdt::new_hybrid_cache(path, true) {
-                for triple in hdt.triples_all() {
-                    result.push((graph_name.clone(), triple));
-                }
+        self.iter_all_triples().collect()
+    }
+
+    /// All triples of all HDTs with their graph names, graph by graph in graph name order. A
+    /// graph is only loaded once the triples of the previous one are consumed, and only its
+    /// triples are held in memory. Graphs added or removed after the call aren't seen, graphs
+    /// that fail to load are skipped.
+    #[cfg(feature = "server")]
+    pub fn iter_all_triples(&self) -> impl Iterator<Item = (String, [Arc<str>; 3])> + use<> {
+        let mut graphs: Vec<_> = self
+            .file_paths
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(name, path)| (name.clone(), path.clone()))
+            .collect();
+        graphs.sort();
+        let write_cache = self.write_cache;
+        graphs.into_iter().flat_map(move |(graph_name, path)| {
+            // the HDT can't outlive this closure, its triples are collected before it's dropped
+            let triples: Vec<[Arc<str>; 3]> =
+                match hdt::hdt::Hdt::new_hybrid_cache(&path, write_cache) {
+                    Ok(hdt) => hdt.triples_all().collect(),
+                    Err(e) => {
+                        log::warn!("skipping graph {graph_name}, failed to load {path:?}: {e}");
+                        vec![]
+                    }
+                };
+            triples.into_iter().map(move |t| (graph_name.clone(), t))
+        })
+    }
+}
+
+/// Checks that `path` starts with the `$HDT` cookie of the control information of HDT files, so
+/// e.g. a Turtle file renamed to `.hdt` is reported as such rather than failing to parse
+pub fn check_hdt_file(path: &Path) -> anyhow::Result<()> {
+    let mut cookie = [0_u8; 4];
+    let read = std::fs::File::open(path).and_then(|mut f| f.read_exact(&mut cookie));
+    match read {
+        Ok(()) if &cookie == b"$HDT" => Ok(()),
+        Ok(()) => Err(anyhow::anyhow!(
+            "file {} is not a valid HDT file",
+            path.display()
+        )),
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => Err(anyhow::anyhow!(
+            "file {} is not a valid HDT file, it is too short",
+            path.display()
+        )),
+        Err(e) => Err(anyhow::anyhow!("Failed to read {}: {e}", path.display())),
+    }
+}
+
+/// List the `.hdt` files in `dir`, descending into subdirectories when `recursive` is set
+#[cfg(feature = "server")]
+pub fn find_hdt_files(dir: &Path, recursive: bool) -> std::io::Result<Vec<std::path::PathBuf>> {
+    let mut found = vec![];
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            if recursive {
+                found.extend(find_hdt_files(&path, recursive)?);
             }
+        } else if path.extension().is_some_and(|ext| ext == "hdt") {
+            found.push(path);
+        }
+    }
+    Ok(found)
+}
+
+/// Index cache files built next to an HDT file when it is loaded with the cache enabled
+pub fn cache_files(path: &Path) -> Vec<std::path::PathBuf> {
+    let (Some(parent), Some(filename)) = (path.parent(), path.file_name()) else {
+        return vec![];
+    };
+    let filename_str = filename.to_string_lossy();
+    let Ok(entries) = std::fs::read_dir(parent) else {
+        return vec![];
+    };
+    let mut found: Vec<_> = entries
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|entry_path| {
+            entry_path.file_name().is_some_and(|entry_name| {
+                let entry_name_str = entry_name.to_string_lossy();
+                // Check if this is a cache file for our HDT
+                entry_name_str.starts_with(&*filename_str)
+                    && (entry_name_str.contains(".index.") || entry_name_str.ends_with(".cache"))
+            })
+        })
+        .collect();
+    found.sort();
+    found
+}
+
+/// Delete the index cache files built next to an HDT file
+pub fn remove_cache_files(path: &Path) {
+    for entry_path in cache_files(path) {
+        if let Err(e) = std::fs::remove_file(&entry_path) {
+            eprintln!(
+                "Warning: Failed to delete cache file {:?}: {}",
+                entry_path, e
+            );
+        } else {
+            eprintln!("Deleted cache file: {:?}", entry_path);
+        }
+    }
+}
+
+/// Adds the graph of a HDT file, failing rather than replacing another file with the same graph name
+fn insert_graph(
+    file_paths: &mut HashMap<String, std::path::PathBuf>,
+    graph_name: String,
+    path: &Path,
+) -> anyhow::Result<()> {
+    if let Some(existing) = file_paths.get(&graph_name) {
+        if existing != path {
+            return Err(anyhow::anyhow!(
+                "{} and {} would both be the graph {graph_name}, rename one of the files",
+                existing.display(),
+                path.display()
+            ));
         }
-        result
     }
+    file_paths.insert(graph_name, path.to_path_buf());
+    Ok(())
+}
+
+/// Graph name of a HDT file, `<graph_base><file stem>` or `file:///<file name>` without a base.
+/// An empty base gives the bare file stem.
+fn file_graph_name(graph_base: Option<&str>, path: &Path) -> anyhow::Result<String> {
+    let name = match graph_base {
+        Some(_) => path.file_stem(),
+        None => path.file_name(),
+    }
+    .ok_or_else(|| anyhow::anyhow!("Invalid file path: {:?}", path))?
+    .to_str()
+    .ok_or_else(|| anyhow::anyhow!("Invalid filename encoding: {:?}", path))?;
+    Ok(format!("{}{name}", graph_base.unwrap_or("file:///")))
 }
 
 #[cfg(feature = "server")]
 pub fn graph_to_file(name: oxrdf::NamedOrBlankNodeRef) -> Option<String> {
     if let oxrdf::NamedOrBlankNodeRef::NamedNode(n) = name {
-        let res = n.to_string().parse::<http::Uri>();
+        // short graph names are the file stem already
+        if !n.as_str().contains(':') {
+            return Some(n.as_str().to_string());
+        }
+        let res = n.as_str().parse::<http::Uri>();
         let Ok(uri) = res else {
             return None;
         };
@@ -366,43 +867,81 @@ pub fn graph_to_file(name: oxrdf::NamedOrBlankNodeRef) -> Option<String> {
     None
 }
 
-/// Create the correct term for a given resource string.
-/// Slow, use the appropriate method if you know which type (Literal, URI, or blank node) the string has.
+/// Convert a triple of HDT strings into an RDF triple
+pub fn hdt_triple_to_triple(t: &[Arc<str>; 3]) -> Result<Triple, Error> {
+    Ok(Triple {
+        subject: parse_subject(&t[0])?,
+        predicate: parse_predicate(&t[1])?,
+        object: parse_object(&t[2])?,
+    })
+}
+
+/// Create the correct term for a given resource string whose position in the triple is unknown.
+/// Use [parse_subject], [parse_predicate] or [parse_object] when the position is known.
 // Based on https://github.com/KonradHoeffner/hdt/blob/871db777db3220dc4874af022287975b31d72d3a/src/hdt_graph.rs#L64
 pub fn hdt_bgp_str_to_term(s: &str) -> Result<Term, Error> {
+    parse_object(s)
+}
+
+/// Parse the HDT string of a subject, which is an IRI or a blank node
+pub fn parse_subject(s: &str) -> Result<NamedOrBlankNode, Error> {
+    match s.chars().next() {
+        None => Err(Error::new(ErrorKind::InvalidData, "empty input")),
+        // Underscore prefix indicating a Blank Node.
+        Some('_') => parse_blank_node(s).map(Into::into),
+        Some('"') => Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("literal {s} can't be a subject"),
+        )),
+        _ => parse_iri(s).map(Into::into),
+    }
+}
+
+/// Parse the HDT string of a predicate, which is always an IRI
+pub fn parse_predicate(s: &str) -> Result<NamedNode, Error> {
     match s.chars().next() {
         None => Err(Error::new(ErrorKind::InvalidData, "empty input")),
+        Some('_') | Some('"') => Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("{s} can't be a predicate, predicates are IRIs"),
+        )),
+        _ => parse_iri(s),
+    }
+}
+
+/// Parse the HDT string of an object, which is an IRI, a blank node or a literal
+pub fn parse_object(s: &str) -> Result<Term, Error> {
+    match s.chars().next() {
         // Double-quote delimiters are used around the string.
-        Some('"') => match Term::from_str(s) {
-            Ok(s) => Ok(s),
-            Err(e) => Err(Error::new(
+        Some('"') => oxrdf::Literal::from_str(s).map(Into::into).map_err(|e| {
+            Error::new(
                 ErrorKind::InvalidData,
                 format!("literal parse error {e} for {s}"),
-            )),
-        },
-        // Underscore prefix indicating a Blank Node.
-        Some('_') => match BlankNode::from_str(s) {
-            Ok(n) => Ok(n.into()),
-            Err(e) => Err(Error::new(
-                ErrorKind::InvalidData,
-                format!("blanknode parse error {e} for {s}"),
-            )),
-        },
-        // Double-quote delimiters not present. Underscore prefix
-        // not present. Assuming a URI.
-        _ => {
-            // Note that Term::from_str() will not work for URIs (NamedNode) when the string is not within "<" and ">" delimiters.
-            match NamedNode::new(s) {
-                Ok(n) => Ok(n.into()),
-                Err(e) => Err(Error::new(
-                    ErrorKind::InvalidData,
-                    format!("iri parse error {e} for {s}"),
-                )),
-            }
-        }
+            )
+        }),
+        _ => parse_subject(s).map(Into::into),
     }
 }
 
+fn parse_blank_node(s: &str) -> Result<BlankNode, Error> {
+    BlankNode::from_str(s).map_err(|e| {
+        Error::new(
+            ErrorKind::InvalidData,
+            format!("blanknode parse error {e} for {s}"),
+        )
+    })
+}
+
+/// IRIs are stored without the "<" and ">" delimiters Term::from_str() expects
+fn parse_iri(s: &str) -> Result<NamedNode, Error> {
+    NamedNode::new(s).map_err(|e| {
+        Error::new(
+            ErrorKind::InvalidData,
+            format!("iri parse error {e} for {s}"),
+        )
+    })
+}
+
 /// Convert triple string formats from OxRDF to HDT.
 pub fn term_to_hdt_bgp_str(term: Term) -> String {
     match term {
@@ -412,112 +951,1266 @@ pub fn term_to_hdt_bgp_str(term: Term) -> String {
     }
 }
 
-impl<'a> QueryableDataset<'a> for &'a AggregateHdtSnapshot {
-    type InternalTerm = Arc<str>;
-    type Error = Error;
-
-    fn internal_quads_for_pattern(
-        &self,
-        subject: Option<&Arc<str>>,
-        predicate: Option<&Arc<str>>,
-        object: Option<&Arc<str>>,
-        graph_name: Option<Option<&Arc<str>>>,
-    ) -> impl Iterator<Item = Result<InternalQuad<Self::InternalTerm>, Error>> + use<'a> {
-        let subject_pattern = subject.cloned();
-        let predicate_pattern = predicate.cloned();
-        let object_pattern = object.cloned();
-
-        let graph_name_owned = graph_name.map(|inner| inner.cloned());
-
-        // Optimization: Pre-filter graphs to reduce unnecessary work
-        // Note: get_snapshot() already filtered graphs at load time,
-        // so self.hdts contains only the required graphs. This filter
-        // handles additional runtime graph name matching from the query.
-        let graphs_to_query: Vec<(&String, &hdt::hdt::HdtHybrid)> = self
-            .hdts
-            .iter()
-            .filter(|(g, _h)| {
-                match &graph_name_owned {
-                    // Query for default graph: Some(None)
-                    // Default graph is always union of all loaded graphs
-                    Some(None) => true,
-                    // Query for specific named graph: Some(Some(graph))
-                    Some(Some(target_graph)) => {
-                        let g_arc: Arc<str> = Arc::from(g.as_str());
-                        &g_arc == target_graph
-                    }
-                    // Query across all graphs: None
-                    None => true,
-                }
-            })
-            .collect();
-
-        // Optimization: Collect iterators into a Vec first, then flatten
-        // allows lazy evaluation of triples
-        let iters: Vec<_> = graphs_to_query
-            .iter()
-            .map(|(graph_name, hdt)| {
-                let ps = subject_pattern.as_ref().map(|s| s.as_ref());
-                let pp = predicate_pattern.as_ref().map(|p| p.as_ref());
-                let po = object_pattern.as_ref().map(|o| o.as_ref());
-                let graph_arc: Arc<str> = Arc::from(graph_name.as_str());
-
-                // Get iterator and immediately convert to owned triples with graph name
-                // Due to HDT's API design (returns Box<dyn Iterator + '_>), must collect here
-                let triples: Vec<_> = hdt
-                    .triples_with_pattern(ps, pp, po)
-                    .map(|[subject, predicate, object]| {
-                        (subject, predicate, object, graph_arc.clone())
-                    })
-                    .collect();
-                triples
-            })
-            .collect();
+/// Triple pattern matching shared by HDTs loaded from files and from memory
+trait TriplesWithPattern {
+    fn triples_matching<'a>(
+        &'a self,
+        subject: Option<&'a str>,
+        predicate: Option<&'a str>,
+        object: Option<&'a str>,
+    ) -> Box<dyn Iterator<Item = [Arc<str>; 3]> + 'a>;
+
+    /// Distinct predicates of the graph, read from its dictionary rather than its triples
+    fn predicates(&self) -> Vec<String>;
+}
 
-        // Optimization: Flatten collected results without additional copying
-        iters
-            .into_iter()
-            .flatten()
-            .map(|(subject, predicate, object, graph_arc)| {
-                Ok(InternalQuad {
-                    subject,
-                    predicate,
-                    object,
-                    graph_name: Some(graph_arc),
-                })
-            })
+impl TriplesWithPattern for hdt::hdt::HdtHybrid {
+    fn triples_matching<'a>(
+        &'a self,
+        subject: Option<&'a str>,
+        predicate: Option<&'a str>,
+        object: Option<&'a str>,
+    ) -> Box<dyn Iterator<Item = [Arc<str>; 3]> + 'a> {
+        Box::new(self.triples_with_pattern(subject, predicate, object))
     }
 
-    fn internalize_term(&self, term: Term) -> Result<Arc<str>, Error> {
-        Ok(Arc::from(term_to_hdt_bgp_str(term)))
+    fn predicates(&self) -> Vec<String> {
+        section_strings(&self.dict.predicates)
     }
+}
 
-    fn externalize_term(&self, term: Arc<str>) -> Result<Term, Error> {
-        hdt_bgp_str_to_term(&term)
+impl TriplesWithPattern for hdt::Hdt {
+    fn triples_matching<'a>(
+        &'a self,
+        subject: Option<&'a str>,
+        predicate: Option<&'a str>,
+        object: Option<&'a str>,
+    ) -> Box<dyn Iterator<Item = [Arc<str>; 3]> + 'a> {
+        Box::new(self.triples_with_pattern(subject, predicate, object))
     }
 
-    fn internal_named_graphs(
-        &self,
-    ) -> impl Iterator<Item = Result<Self::InternalTerm, Self::Error>> + use<'a> {
-        let keys: Vec<Arc<str>> = self.hdts.keys().map(|k| Arc::from(k.as_str())).collect();
-        keys.into_iter().map(Ok)
+    fn predicates(&self) -> Vec<String> {
+        section_strings(&self.dict.predicates)
     }
+}
 
-    fn contains_internal_graph_name(&self, graph_name: &Arc<str>) -> Result<bool, Self::Error> {
-        Ok(self.hdts.contains_key(graph_name.as_ref()))
-    }
+/// Strings of a HDT dictionary section, whose ids start at 1
+fn section_strings(section: &hdt::dict_sect_pfc::DictSectPFC) -> Vec<String> {
+    (1..=section.num_strings)
+        .filter_map(|id| match section.extract(id) {
+            Ok(s) => Some(s),
+            Err(e) => {
+                log::warn!("error reading dictionary entry {id}: {e}");
+                None
+            }
+        })
+        .collect()
 }
 
-pub fn query<'a>(
-    q: &str,
+/// Number of triples read by the first lookup of [ChunkedMatches]
+const FIRST_CHUNK_SIZE: usize = 64;
+
+/// Triples of a graph matching a pattern, read on demand. The HDT iterator borrows the pattern, so
+/// it can't be kept between calls. Triples are read in chunks instead, each as large as all the
+/// previous ones, and the lookup is repeated for each chunk, skipping the triples already read.
+/// Evaluations stopping early, e.g. for an ASK or a LIMIT, only read the first chunks, while
+/// reading all matches costs about twice a single lookup.
+struct ChunkedMatches<'a> {
+    hdt: &'a dyn TriplesWithPattern,
+    pattern: [Option<Arc<str>>; 3],
+    read: usize,
+    chunk: std::vec::IntoIter<[Arc<str>; 3]>,
+    exhausted: bool,
+}
+
+impl<'a> ChunkedMatches<'a> {
+    fn new(hdt: &'a dyn TriplesWithPattern, pattern: [Option<Arc<str>>; 3]) -> Self {
+        Self {
+            hdt,
+            pattern,
+            read: 0,
+            chunk: Vec::new().into_iter(),
+            exhausted: false,
+        }
+    }
+}
+
+impl Iterator for ChunkedMatches<'_> {
+    type Item = [Arc<str>; 3];
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(triple) = self.chunk.next() {
+            return Some(triple);
+        }
+        if self.exhausted {
+            return None;
+        }
+        let size = self.read.max(FIRST_CHUNK_SIZE);
+        let [subject, predicate, object] = &self.pattern;
+        let chunk: Vec<_> = self
+            .hdt
+            .triples_matching(subject.as_deref(), predicate.as_deref(), object.as_deref())
+            .skip(self.read)
+            .take(size)
+            .collect();
+        self.exhausted = chunk.len() < size;
+        self.read += chunk.len();
+        self.chunk = chunk.into_iter();
+        self.chunk.next()
+    }
+}
+
+impl AggregateHdtSnapshot {
+    /// Build a snapshot from serialized HDTs already in memory (or any other reader), keyed by
+    /// graph name. Nothing is read from or written to disk, e.g. for embedded or WASM use.
+    pub fn from_readers<R: Read>(named: Vec<(String, R)>) -> anyhow::Result<Self> {
+        let mut memory_hdts = HashMap::new();
+        for (graph_name, reader) in named {
+            let hdt = hdt::Hdt::read(BufReader::new(reader))
+                .map_err(|e| anyhow::anyhow!("Failed to read HDT of graph {graph_name}: {e}"))?;
+            memory_hdts.insert(graph_name, hdt);
+        }
+        Ok(Self {
+            hdts: HashMap::new(),
+            memory_hdts,
+            union_default_graph: true,
+            default_graph: None,
+        })
+    }
+
+    /// All graphs of the snapshot, whether loaded from a file or from memory
+    fn graphs(&self) -> impl Iterator<Item = (&String, &dyn TriplesWithPattern)> {
+        self.hdts
+            .iter()
+            .map(|(g, h)| (g, h as &dyn TriplesWithPattern))
+            .chain(
+                self.memory_hdts
+                    .iter()
+                    .map(|(g, h)| (g, h as &dyn TriplesWithPattern)),
+            )
+    }
+
+    fn contains_graph(&self, graph_name: &str) -> bool {
+        self.hdts.contains_key(graph_name) || self.memory_hdts.contains_key(graph_name)
+    }
+
+    /// Graph names aren't always absolute IRIs, see [AggregateHdt::with_short_graph_names]
+    fn externalize(&self, term: Arc<str>) -> Result<Term, Error> {
+        match hdt_bgp_str_to_term(&term) {
+            Err(_) if self.contains_graph(&term) => Ok(NamedNode::new_unchecked(&*term).into()),
+            result => result,
+        }
+    }
+
+    /// Names of the graphs of the snapshot containing `triple`, sorted. Empty for triples only built
+    /// by a query, e.g. from a CONSTRUCT template with blank nodes.
+    pub fn graphs_containing(&self, triple: &Triple) -> Vec<String> {
+        let subject = term_to_hdt_bgp_str(triple.subject.clone().into());
+        let object = term_to_hdt_bgp_str(triple.object.clone());
+        let mut graphs: Vec<_> = self
+            .graphs()
+            .filter(|(_, hdt)| {
+                hdt.triples_matching(
+                    Some(&subject),
+                    Some(triple.predicate.as_str()),
+                    Some(&object),
+                )
+                .next()
+                .is_some()
+            })
+            .map(|(g, _)| g.clone())
+            .collect();
+        graphs.sort();
+        graphs
+    }
+
+    /// Choose between the default graph being the union of all graphs (the default) or an
+    /// empty graph, in which case only patterns in a GRAPH clause match
+    pub fn with_union_default_graph(mut self, union_default_graph: bool) -> Self {
+        self.union_default_graph = union_default_graph;
+        self
+    }
+
+    /// Use the graph named `default_graph` as the default graph, whatever
+    /// [AggregateHdtSnapshot::with_union_default_graph] says, so patterns outside a GRAPH clause
+    /// only match its triples. The default graph is empty if the snapshot has no such graph.
+    pub fn with_default_graph(mut self, default_graph: Option<String>) -> Self {
+        self.default_graph = default_graph;
+        self
+    }
+
+    /// Distinct predicates used in the graphs of the snapshot, sorted. They are read from the
+    /// dictionaries, without going through the triples.
+    pub fn predicates(&self) -> Vec<String> {
+        let predicates: BTreeSet<_> = self
+            .graphs()
+            .flat_map(|(_, hdt)| hdt.predicates())
+            .collect();
+        predicates.into_iter().collect()
+    }
+
+    /// Distinct classes, i.e. IRIs that are the object of a `rdf:type` triple, sorted. Only the
+    /// `rdf:type` triples are read, looked up by predicate.
+    pub fn classes(&self) -> Vec<String> {
+        let rdf_type = oxrdf::vocab::rdf::TYPE.as_str();
+        let classes: BTreeSet<_> = self
+            .graphs()
+            .flat_map(|(_, hdt)| {
+                hdt.triples_matching(None, Some(rdf_type), None)
+                    .map(|[_, _, o]| o)
+            })
+            // literals start with a quote and blank nodes with an underscore
+            .filter(|o| !o.starts_with(['"', '_']))
+            .collect();
+        classes.into_iter().map(|c| c.to_string()).collect()
+    }
+
+    /// Quads matching a pattern, read lazily from the graphs as they are consumed
+    fn quads_for_pattern<'s>(
+        &'s self,
+        subject: Option<&Arc<str>>,
+        predicate: Option<&Arc<str>>,
+        object: Option<&Arc<str>>,
+        graph_name: Option<Option<&Arc<str>>>,
+    ) -> impl Iterator<Item = InternalQuad<Arc<str>>> + use<'s> {
+        let pattern = [subject.cloned(), predicate.cloned(), object.cloned()];
+
+        let graph_name_owned = graph_name.map(|inner| inner.cloned());
+
+        // Optimization: Pre-filter graphs to reduce unnecessary work
+        // Note: get_snapshot() already filtered graphs at load time,
+        // so self.hdts contains only the required graphs. This filter
+        // handles additional runtime graph name matching from the query.
+        let graphs_to_query: Vec<(&String, &dyn TriplesWithPattern)> = self
+            .graphs()
+            .filter(|(g, _h)| {
+                match &graph_name_owned {
+                    // Query for default graph: Some(None)
+                    // Default graph is the designated graph, or else the union of all loaded
+                    // graphs, unless disabled
+                    Some(None) => match &self.default_graph {
+                        Some(default_graph) => *g == default_graph,
+                        None => self.union_default_graph,
+                    },
+                    // Query for specific named graph: Some(Some(graph))
+                    Some(Some(target_graph)) => {
+                        let g_arc: Arc<str> = Arc::from(g.as_str());
+                        &g_arc == target_graph
+                    }
+                    // Query across all graphs: None
+                    None => true,
+                }
+            })
+            .collect();
+
+        // The default graph is the set union of all graphs, a triple found in several
+        // files (e.g. a HDT and the one converted from RDF inputs) is only returned once
+        let deduplicate = matches!(graph_name_owned, Some(None)) && graphs_to_query.len() > 1;
+        let mut seen = std::collections::HashSet::new();
+        graphs_to_query
+            .into_iter()
+            .flat_map(move |(graph_name, hdt)| {
+                let graph_arc: Arc<str> = Arc::from(graph_name.as_str());
+                ChunkedMatches::new(hdt, pattern.clone()).map(move |[subject, predicate, object]| {
+                    InternalQuad {
+                        subject,
+                        predicate,
+                        object,
+                        graph_name: Some(graph_arc.clone()),
+                    }
+                })
+            })
+            .filter(move |q| {
+                !deduplicate
+                    || seen.insert([q.subject.clone(), q.predicate.clone(), q.object.clone()])
+            })
+    }
+
+    fn named_graphs(&self) -> Vec<Arc<str>> {
+        self.graphs().map(|(k, _)| Arc::from(k.as_str())).collect()
+    }
+}
+
+impl<'a> QueryableDataset<'a> for &'a AggregateHdtSnapshot {
+    type InternalTerm = Arc<str>;
+    type Error = Error;
+
+    fn internal_quads_for_pattern(
+        &self,
+        subject: Option<&Arc<str>>,
+        predicate: Option<&Arc<str>>,
+        object: Option<&Arc<str>>,
+        graph_name: Option<Option<&Arc<str>>>,
+    ) -> impl Iterator<Item = Result<InternalQuad<Self::InternalTerm>, Error>> + use<'a> {
+        let snapshot: &'a AggregateHdtSnapshot = *self;
+        snapshot
+            .quads_for_pattern(subject, predicate, object, graph_name)
+            .map(Ok)
+    }
+
+    fn internalize_term(&self, term: Term) -> Result<Arc<str>, Error> {
+        Ok(Arc::from(term_to_hdt_bgp_str(term)))
+    }
+
+    fn externalize_term(&self, term: Arc<str>) -> Result<Term, Error> {
+        self.externalize(term)
+    }
+
+    fn internal_named_graphs(
+        &self,
+    ) -> impl Iterator<Item = Result<Self::InternalTerm, Self::Error>> + use<'a> {
+        self.named_graphs().into_iter().map(Ok)
+    }
+
+    fn contains_internal_graph_name(&self, graph_name: &Arc<str>) -> Result<bool, Self::Error> {
+        Ok(self.contains_graph(graph_name))
+    }
+}
+
+/// A snapshot seen through the `FROM` and `FROM NAMED` clauses of a query: the default graph is
+/// the union of the `FROM` graphs and only the `FROM NAMED` graphs can be matched by name.
+/// Without `FROM NAMED` there are no named graphs, and without `FROM` the default graph is empty.
+struct ScopedSnapshot<'a> {
+    snapshot: &'a AggregateHdtSnapshot,
+    default: Vec<Arc<str>>,
+    named: Vec<Arc<str>>,
+}
+
+impl<'a> ScopedSnapshot<'a> {
+    fn new(snapshot: &'a AggregateHdtSnapshot, dataset: &QueryDataset) -> Self {
+        let names = |graphs: &[NamedNode]| -> Vec<Arc<str>> {
+            graphs.iter().map(|g| Arc::from(g.as_str())).collect()
+        };
+        Self {
+            snapshot,
+            default: names(&dataset.default),
+            named: dataset.named.as_deref().map(names).unwrap_or_default(),
+        }
+    }
+
+    fn is_named(&self, graph_name: &Arc<str>) -> bool {
+        self.named.contains(graph_name) && self.snapshot.contains_graph(graph_name)
+    }
+}
+
+impl<'a> QueryableDataset<'a> for ScopedSnapshot<'a> {
+    type InternalTerm = Arc<str>;
+    type Error = Error;
+
+    fn internal_quads_for_pattern(
+        &self,
+        subject: Option<&Arc<str>>,
+        predicate: Option<&Arc<str>>,
+        object: Option<&Arc<str>>,
+        graph_name: Option<Option<&Arc<str>>>,
+    ) -> impl Iterator<Item = Result<InternalQuad<Self::InternalTerm>, Error>> + use<'a> {
+        let graphs: Vec<Arc<str>> = match graph_name {
+            Some(None) => self.default.clone(),
+            Some(Some(graph_name)) if self.is_named(graph_name) => vec![graph_name.clone()],
+            Some(Some(_)) => vec![],
+            None => self
+                .named
+                .iter()
+                .filter(|g| self.is_named(g))
+                .cloned()
+                .collect(),
+        };
+        // the default graph is the set union of the FROM graphs, as for the whole snapshot
+        let deduplicate = graph_name == Some(None) && graphs.len() > 1;
+        let mut seen = std::collections::HashSet::new();
+        let pattern = [subject.cloned(), predicate.cloned(), object.cloned()];
+        let snapshot = self.snapshot;
+        graphs
+            .into_iter()
+            .flat_map(move |g| {
+                let [subject, predicate, object] = &pattern;
+                snapshot.quads_for_pattern(
+                    subject.as_ref(),
+                    predicate.as_ref(),
+                    object.as_ref(),
+                    Some(Some(&g)),
+                )
+            })
+            .filter(move |q| {
+                !deduplicate
+                    || seen.insert([q.subject.clone(), q.predicate.clone(), q.object.clone()])
+            })
+            .map(Ok)
+    }
+
+    fn internalize_term(&self, term: Term) -> Result<Arc<str>, Error> {
+        Ok(Arc::from(term_to_hdt_bgp_str(term)))
+    }
+
+    fn externalize_term(&self, term: Arc<str>) -> Result<Term, Error> {
+        self.snapshot.externalize(term)
+    }
+
+    fn internal_named_graphs(
+        &self,
+    ) -> impl Iterator<Item = Result<Self::InternalTerm, Self::Error>> + use<'a> {
+        self.named
+            .iter()
+            .filter(|g| self.is_named(g))
+            .cloned()
+            .collect::<Vec<_>>()
+            .into_iter()
+            .map(Ok)
+    }
+
+    fn contains_internal_graph_name(&self, graph_name: &Arc<str>) -> Result<bool, Self::Error> {
+        Ok(self.is_named(graph_name))
+    }
+}
+
+pub fn query<'a>(
+    q: &str,
     hdt: &'a AggregateHdtSnapshot,
     base_iri: Option<String>,
 ) -> Result<spareval::QueryResults<'a>, QueryEvaluationError> {
-    let query = SparqlParser::new()
+    let query = parse_query(q, base_iri)?;
+    evaluate(&query, hdt)
+}
+
+/// Parse a SPARQL query string, resolving relative IRIs against `base_iri` (defaults to `http://example.com/`)
+pub fn parse_query(q: &str, base_iri: Option<String>) -> Result<Query, QueryEvaluationError> {
+    Ok(SparqlParser::new()
         .with_base_iri(base_iri.unwrap_or("http://example.com/".to_string()))
         .unwrap()
-        .parse_query(q)?;
-    QueryEvaluator::new().prepare(&query).execute(hdt)
+        .parse_query(q)?)
+}
+
+/// Prepends `PREFIX` declarations for `prefixes` to a SPARQL query, except for the prefix names
+/// the query declares itself, so its own declarations win.
+pub fn prepend_prefixes(q: &str, prefixes: &[(String, String)]) -> String {
+    let declared = query_prefixes(q);
+    let mut prepended = String::new();
+    for (name, namespace) in prefixes {
+        if !declared.iter().any(|(n, _)| n == name) {
+            prepended.push_str(&format!("PREFIX {name}: <{namespace}>\n"));
+        }
+    }
+    prepended.push_str(q);
+    prepended
+}
+
+/// Prefix declarations (`PREFIX name: <namespace>`) of a SPARQL query, in declaration order.
+/// spargebra resolves prefixed names while parsing and doesn't keep them, so they are read from the query text.
+pub fn query_prefixes(q: &str) -> Vec<(String, String)> {
+    prefix_declarations(q)
+        .into_iter()
+        .map(|(_, name, namespace)| (name, namespace))
+        .collect()
+}
+
+/// Names of the prefixes a SPARQL query declares without using them, in declaration order. A
+/// prefix is unused when the query still parses without its declaration, with `shared` prefixes
+/// of other names available as in [prepend_prefixes]. Only meaningful for a query that parses.
+pub fn unused_prefixes(q: &str, shared: &[(String, String)]) -> Vec<String> {
+    prefix_declarations(q)
+        .into_iter()
+        .filter(|(span, name, _)| {
+            let without = format!("{}{}", &q[..span.start], &q[span.end..]);
+            let shared: Vec<_> = shared.iter().filter(|(n, _)| n != name).cloned().collect();
+            parse_query(&prepend_prefixes(&without, &shared), None).is_ok()
+        })
+        .map(|(_, name, _)| name)
+        .collect()
+}
+
+/// [query_prefixes] along with the span of each declaration in the query text. Comments, strings
+/// and IRIs are skipped, a `PREFIX` inside them is not a declaration.
+fn prefix_declarations(q: &str) -> Vec<(std::ops::Range<usize>, String, String)> {
+    let mut prefixes = vec![];
+    let mut start = 0;
+    while let Some(rest) = q.get(start..).filter(|rest| !rest.is_empty()) {
+        if let Some(len) = skipped_token(rest) {
+            start += len;
+            continue;
+        }
+        let is_keyword = rest
+            .get(.."prefix".len())
+            .is_some_and(|word| word.eq_ignore_ascii_case("prefix"))
+            && !q[..start]
+                .chars()
+                .next_back()
+                .is_some_and(|c| c.is_alphanumeric() || c == '_' || c == ':' || c == '?');
+        if is_keyword {
+            let keyword = start;
+            start += "prefix".len();
+            if let Some((len, name, namespace)) = prefix_declaration(&q[start..]) {
+                start += len;
+                prefixes.push((keyword..start, name, namespace));
+            }
+            continue;
+        }
+        start += rest.chars().next().map_or(1, char::len_utf8);
+    }
+    prefixes
+}
+
+/// The `name: <namespace>` following a `PREFIX` keyword, with its length in the query text
+fn prefix_declaration(rest: &str) -> Option<(usize, String, String)> {
+    let (name, after) = rest.split_once(':')?;
+    let name = name.trim();
+    if !name
+        .chars()
+        .all(|c| c.is_alphanumeric() || c == '_' || c == '-' || c == '.')
+    {
+        return None;
+    }
+    let (namespace, after) = after.trim_start().strip_prefix('<')?.split_once('>')?;
+    Some((
+        rest.len() - after.len(),
+        name.to_string(),
+        namespace.to_string(),
+    ))
+}
+
+/// Length of the comment, string or IRI the query text starts with, if any. A `<` not closed
+/// before a character IRIs can't contain is a comparison, not an IRI.
+fn skipped_token(rest: &str) -> Option<usize> {
+    let mut chars = rest.char_indices();
+    match chars.next()?.1 {
+        '#' => Some(rest.find('\n').unwrap_or(rest.len())),
+        quote @ ('"' | '\'') => {
+            let long = [quote; 3].iter().collect::<String>();
+            let is_long = rest.starts_with(&long);
+            if is_long {
+                chars.nth(1);
+            }
+            while let Some((i, c)) = chars.next() {
+                if c == '\\' {
+                    chars.next();
+                } else if is_long && rest[i..].starts_with(&long) {
+                    return Some(i + long.len());
+                } else if !is_long && (c == quote || c == '\n') {
+                    return Some(i + c.len_utf8());
+                }
+            }
+            Some(rest.len())
+        }
+        '<' => chars
+            .take_while(|(_, c)| !c.is_whitespace() && !"<\"{}|^`\\".contains(*c))
+            .find(|(_, c)| *c == '>')
+            .map(|(i, _)| i + 1),
+        _ => None,
+    }
+}
+
+/// Evaluate an already parsed SPARQL query over a snapshot. The `FROM` and `FROM NAMED` clauses of
+/// the query, if any, select the graphs of the snapshot the query sees, by graph name.
+pub fn evaluate<'a>(
+    query: &Query,
+    hdt: &'a AggregateHdtSnapshot,
+) -> Result<spareval::QueryResults<'a>, QueryEvaluationError> {
+    let (query, dataset) = take_dataset(query);
+    match dataset {
+        Some(dataset) => evaluate_over(&query, ScopedSnapshot::new(hdt, &dataset)),
+        None => evaluate_over(&query, hdt),
+    }
+}
+
+fn evaluate_over<'a>(
+    query: &Query,
+    dataset: impl QueryableDataset<'a>,
+) -> Result<spareval::QueryResults<'a>, QueryEvaluationError> {
+    let query = inline_values(query);
+    QueryEvaluator::new().prepare(&query).execute(dataset)
+}
+
+/// Splits the `FROM` and `FROM NAMED` clauses off a query
+fn take_dataset(query: &Query) -> (Query, Option<QueryDataset>) {
+    let mut query = query.clone();
+    let dataset = match &mut query {
+        Query::Select { dataset, .. }
+        | Query::Construct { dataset, .. }
+        | Query::Describe { dataset, .. }
+        | Query::Ask { dataset, .. } => dataset.take(),
+    };
+    (query, dataset)
+}
+
+/// Names of the graphs `query` can match: those of its `FROM` and `FROM NAMED` clauses, or else of
+/// the `GRAPH <name>` clauses holding all of its patterns. None when any graph can match, e.g. with
+/// a pattern over the default graph, a `GRAPH ?g` clause or a DESCRIBE query.
+pub fn query_graphs(query: &Query) -> Option<Vec<String>> {
+    // EXISTS patterns inside expressions aren't walked, the query is then treated as unrestricted
+    fn may_use_exists(expression: &Expression) -> bool {
+        expression.to_string().to_lowercase().contains("exists")
+    }
+    fn collect(pattern: &GraphPattern, in_graph: bool, found: &mut Vec<String>) -> bool {
+        match pattern {
+            GraphPattern::Bgp { patterns } => in_graph || patterns.is_empty(),
+            GraphPattern::Path { .. } => in_graph,
+            GraphPattern::Graph { name, inner } => match name {
+                NamedNodePattern::NamedNode(n) => {
+                    found.push(n.as_str().to_string());
+                    collect(inner, true, found)
+                }
+                NamedNodePattern::Variable(_) => false,
+            },
+            GraphPattern::Join { left, right }
+            | GraphPattern::Union { left, right }
+            | GraphPattern::Minus { left, right } => {
+                collect(left, in_graph, found) && collect(right, in_graph, found)
+            }
+            GraphPattern::LeftJoin {
+                left,
+                right,
+                expression,
+            } => {
+                !expression.as_ref().is_some_and(may_use_exists)
+                    && collect(left, in_graph, found)
+                    && collect(right, in_graph, found)
+            }
+            GraphPattern::Filter { expr, inner } => {
+                !may_use_exists(expr) && collect(inner, in_graph, found)
+            }
+            GraphPattern::Extend {
+                inner, expression, ..
+            } => !may_use_exists(expression) && collect(inner, in_graph, found),
+            GraphPattern::Values { .. } => true,
+            GraphPattern::OrderBy { inner, .. }
+            | GraphPattern::Project { inner, .. }
+            | GraphPattern::Distinct { inner }
+            | GraphPattern::Reduced { inner }
+            | GraphPattern::Slice { inner, .. }
+            | GraphPattern::Group { inner, .. } => collect(inner, in_graph, found),
+            _ => false,
+        }
+    }
+
+    let (Query::Select {
+        dataset, pattern, ..
+    }
+    | Query::Construct {
+        dataset, pattern, ..
+    }
+    | Query::Describe {
+        dataset, pattern, ..
+    }
+    | Query::Ask {
+        dataset, pattern, ..
+    }) = query;
+    if let Some(dataset) = dataset {
+        let named = dataset.named.iter().flatten();
+        return Some(
+            dataset
+                .default
+                .iter()
+                .chain(named)
+                .map(|g| g.as_str().to_string())
+                .collect(),
+        );
+    }
+    // described resources are looked up in the default graph
+    if matches!(query, Query::Describe { .. }) {
+        return None;
+    }
+    let mut found = vec![];
+    collect(pattern, false, &mut found).then_some(found)
+}
+
+/// Joins the WHERE clause of `query` with a single row of values for `bindings`, as a trailing
+/// `VALUES` clause would. Returns the rewritten query and the bound variables the WHERE clause
+/// doesn't use, which have no effect.
+pub fn bind_variables(
+    query: &Query,
+    bindings: &[(Variable, GroundTerm)],
+) -> (Query, Vec<Variable>) {
+    fn bind(
+        pattern: &GraphPattern,
+        values: &GraphPattern,
+        used: &mut Vec<Variable>,
+    ) -> GraphPattern {
+        // solution modifiers, aggregates and SELECT expressions apply to the WHERE clause results
+        match pattern {
+            GraphPattern::Project { inner, variables } => GraphPattern::Project {
+                inner: Box::new(bind(inner, values, used)),
+                variables: variables.clone(),
+            },
+            GraphPattern::Distinct { inner } => GraphPattern::Distinct {
+                inner: Box::new(bind(inner, values, used)),
+            },
+            GraphPattern::Reduced { inner } => GraphPattern::Reduced {
+                inner: Box::new(bind(inner, values, used)),
+            },
+            GraphPattern::Slice {
+                inner,
+                start,
+                length,
+            } => GraphPattern::Slice {
+                inner: Box::new(bind(inner, values, used)),
+                start: *start,
+                length: *length,
+            },
+            GraphPattern::OrderBy { inner, expression } => GraphPattern::OrderBy {
+                inner: Box::new(bind(inner, values, used)),
+                expression: expression.clone(),
+            },
+            GraphPattern::Filter { expr, inner } => GraphPattern::Filter {
+                expr: expr.clone(),
+                inner: Box::new(bind(inner, values, used)),
+            },
+            GraphPattern::Extend {
+                inner,
+                variable,
+                expression,
+            } => GraphPattern::Extend {
+                inner: Box::new(bind(inner, values, used)),
+                variable: variable.clone(),
+                expression: expression.clone(),
+            },
+            GraphPattern::Group {
+                inner,
+                variables,
+                aggregates,
+            } => GraphPattern::Group {
+                inner: Box::new(bind(inner, values, used)),
+                variables: variables.clone(),
+                aggregates: aggregates.clone(),
+            },
+            pattern => {
+                pattern.on_in_scope_variable(|v| used.push(v.clone()));
+                GraphPattern::Join {
+                    left: Box::new(pattern.clone()),
+                    right: Box::new(values.clone()),
+                }
+            }
+        }
+    }
+
+    let values = GraphPattern::Values {
+        variables: bindings.iter().map(|(v, _)| v.clone()).collect(),
+        bindings: vec![bindings.iter().map(|(_, t)| Some(t.clone())).collect()],
+    };
+    let mut used = vec![];
+    let query = match query {
+        Query::Select {
+            dataset,
+            pattern,
+            base_iri,
+        } => Query::Select {
+            dataset: dataset.clone(),
+            pattern: bind(pattern, &values, &mut used),
+            base_iri: base_iri.clone(),
+        },
+        Query::Construct {
+            template,
+            dataset,
+            pattern,
+            base_iri,
+        } => Query::Construct {
+            template: template.clone(),
+            dataset: dataset.clone(),
+            pattern: bind(pattern, &values, &mut used),
+            base_iri: base_iri.clone(),
+        },
+        Query::Describe {
+            dataset,
+            pattern,
+            base_iri,
+        } => Query::Describe {
+            dataset: dataset.clone(),
+            pattern: bind(pattern, &values, &mut used),
+            base_iri: base_iri.clone(),
+        },
+        Query::Ask {
+            dataset,
+            pattern,
+            base_iri,
+        } => Query::Ask {
+            dataset: dataset.clone(),
+            pattern: bind(pattern, &values, &mut used),
+            base_iri: base_iri.clone(),
+        },
+    };
+    let unused = bindings
+        .iter()
+        .map(|(v, _)| v.clone())
+        .filter(|v| !used.contains(v))
+        .collect();
+    (query, unused)
+}
+
+/// Rewrites the joins of a `VALUES` block with a basic graph pattern, possibly in a `GRAPH` clause,
+/// as a union with a branch per row, the values of the row substituted into the triple patterns.
+/// Each branch is then a selective HDT lookup, instead of the pattern being matched on its own and
+/// joined with the rows. Joins are kept when a row binds none of the variables of the pattern,
+/// which would then be matched once per row.
+pub fn inline_values(query: &Query) -> Query {
+    fn inline(pattern: &GraphPattern) -> GraphPattern {
+        let inline_box = |p: &GraphPattern| Box::new(inline(p));
+        match pattern {
+            GraphPattern::Join { left, right } => {
+                let (left, right) = (inline(left), inline(right));
+                if let (
+                    GraphPattern::Values {
+                        variables,
+                        bindings,
+                    },
+                    inner,
+                )
+                | (
+                    inner,
+                    GraphPattern::Values {
+                        variables,
+                        bindings,
+                    },
+                ) = (&left, &right)
+                {
+                    if let Some(union) = values_union(variables, bindings, inner) {
+                        return union;
+                    }
+                }
+                GraphPattern::Join {
+                    left: Box::new(left),
+                    right: Box::new(right),
+                }
+            }
+            GraphPattern::LeftJoin {
+                left,
+                right,
+                expression,
+            } => GraphPattern::LeftJoin {
+                left: inline_box(left),
+                right: inline_box(right),
+                expression: expression.clone(),
+            },
+            GraphPattern::Union { left, right } => GraphPattern::Union {
+                left: inline_box(left),
+                right: inline_box(right),
+            },
+            GraphPattern::Minus { left, right } => GraphPattern::Minus {
+                left: inline_box(left),
+                right: inline_box(right),
+            },
+            GraphPattern::Filter { expr, inner } => GraphPattern::Filter {
+                expr: expr.clone(),
+                inner: inline_box(inner),
+            },
+            GraphPattern::Graph { name, inner } => GraphPattern::Graph {
+                name: name.clone(),
+                inner: inline_box(inner),
+            },
+            GraphPattern::Extend {
+                inner,
+                variable,
+                expression,
+            } => GraphPattern::Extend {
+                inner: inline_box(inner),
+                variable: variable.clone(),
+                expression: expression.clone(),
+            },
+            GraphPattern::OrderBy { inner, expression } => GraphPattern::OrderBy {
+                inner: inline_box(inner),
+                expression: expression.clone(),
+            },
+            GraphPattern::Project { inner, variables } => GraphPattern::Project {
+                inner: inline_box(inner),
+                variables: variables.clone(),
+            },
+            GraphPattern::Distinct { inner } => GraphPattern::Distinct {
+                inner: inline_box(inner),
+            },
+            GraphPattern::Reduced { inner } => GraphPattern::Reduced {
+                inner: inline_box(inner),
+            },
+            GraphPattern::Slice {
+                inner,
+                start,
+                length,
+            } => GraphPattern::Slice {
+                inner: inline_box(inner),
+                start: *start,
+                length: *length,
+            },
+            GraphPattern::Group {
+                inner,
+                variables,
+                aggregates,
+            } => GraphPattern::Group {
+                inner: inline_box(inner),
+                variables: variables.clone(),
+                aggregates: aggregates.clone(),
+            },
+            // SERVICE patterns are sent elsewhere as they are
+            pattern => pattern.clone(),
+        }
+    }
+
+    fn values_union(
+        variables: &[Variable],
+        bindings: &[Vec<Option<GroundTerm>>],
+        inner: &GraphPattern,
+    ) -> Option<GraphPattern> {
+        let bgp = match inner {
+            GraphPattern::Graph { inner, .. } => &**inner,
+            inner => inner,
+        };
+        if !matches!(bgp, GraphPattern::Bgp { .. }) || bindings.is_empty() {
+            return None;
+        }
+        let mut used = vec![];
+        inner.on_in_scope_variable(|v| used.push(v.clone()));
+        let mut branches = vec![];
+        for row in bindings {
+            let bound: Vec<_> = variables
+                .iter()
+                .zip(row)
+                .filter_map(|(v, t)| Some((v, t.as_ref()?)))
+                .filter(|(v, _)| used.contains(*v))
+                .collect();
+            if bound.is_empty() {
+                return None;
+            }
+            // None when the row can't match, e.g. a literal bound to a predicate
+            if let Some(substituted) = substitute(inner, &bound) {
+                branches.push(GraphPattern::Join {
+                    left: Box::new(GraphPattern::Values {
+                        variables: variables.to_vec(),
+                        bindings: vec![row.clone()],
+                    }),
+                    right: Box::new(substituted),
+                });
+            }
+        }
+        if branches.is_empty() {
+            return Some(GraphPattern::Values {
+                variables: variables.to_vec(),
+                bindings: vec![],
+            });
+        }
+        Some(union_all(branches))
+    }
+
+    fn substitute(
+        pattern: &GraphPattern,
+        row: &[(&Variable, &GroundTerm)],
+    ) -> Option<GraphPattern> {
+        let value = |v: &Variable| row.iter().find(|(b, _)| *b == v).map(|(_, t)| *t);
+        let term = |t: &TermPattern| match t {
+            TermPattern::Variable(v) => value(v).map_or_else(|| t.clone(), |t| t.clone().into()),
+            t => t.clone(),
+        };
+        let named_node = |n: &NamedNodePattern| match n {
+            NamedNodePattern::Variable(v) => match value(v) {
+                Some(GroundTerm::NamedNode(node)) => Some(node.clone().into()),
+                Some(_) => None,
+                None => Some(n.clone()),
+            },
+            n => Some(n.clone()),
+        };
+        match pattern {
+            GraphPattern::Bgp { patterns } => Some(GraphPattern::Bgp {
+                patterns: patterns
+                    .iter()
+                    .map(|p| {
+                        Some(TriplePattern {
+                            subject: term(&p.subject),
+                            predicate: named_node(&p.predicate)?,
+                            object: term(&p.object),
+                        })
+                    })
+                    .collect::<Option<_>>()?,
+            }),
+            GraphPattern::Graph { name, inner } => Some(GraphPattern::Graph {
+                name: named_node(name)?,
+                inner: Box::new(substitute(inner, row)?),
+            }),
+            pattern => Some(pattern.clone()),
+        }
+    }
+
+    // balanced, so thousands of rows don't make a deeply nested pattern
+    fn union_all(mut branches: Vec<GraphPattern>) -> GraphPattern {
+        if branches.len() == 1 {
+            return branches.remove(0);
+        }
+        let right = branches.split_off(branches.len() / 2);
+        GraphPattern::Union {
+            left: Box::new(union_all(branches)),
+            right: Box::new(union_all(right)),
+        }
+    }
+
+    let mut query = query.clone();
+    let (Query::Select { pattern, .. }
+    | Query::Construct { pattern, .. }
+    | Query::Describe { pattern, .. }
+    | Query::Ask { pattern, .. }) = &mut query;
+    *pattern = inline(pattern);
+    query
+}
+
+/// Triple patterns of the WHERE clause of `query`, in query order, each with the number of triples
+/// of the snapshot it matches on its own. Patterns are looked up in the HDT indexes without
+/// evaluating the query, so joins and filters are ignored and a triple found in several graphs is
+/// counted once per graph. Property paths aren't estimated.
+pub fn pattern_estimates(
+    query: &Query,
+    snapshot: &AggregateHdtSnapshot,
+) -> Vec<(TriplePattern, usize)> {
+    fn collect(pattern: &GraphPattern, found: &mut Vec<TriplePattern>) {
+        match pattern {
+            GraphPattern::Bgp { patterns } => found.extend(patterns.iter().cloned()),
+            GraphPattern::Join { left, right }
+            | GraphPattern::LeftJoin { left, right, .. }
+            | GraphPattern::Union { left, right }
+            | GraphPattern::Minus { left, right } => {
+                collect(left, found);
+                collect(right, found);
+            }
+            GraphPattern::Filter { inner, .. }
+            | GraphPattern::Graph { inner, .. }
+            | GraphPattern::Extend { inner, .. }
+            | GraphPattern::OrderBy { inner, .. }
+            | GraphPattern::Project { inner, .. }
+            | GraphPattern::Distinct { inner }
+            | GraphPattern::Reduced { inner }
+            | GraphPattern::Slice { inner, .. }
+            | GraphPattern::Group { inner, .. }
+            | GraphPattern::Service { inner, .. } => collect(inner, found),
+            _ => {}
+        }
+    }
+    // variables and blank nodes match anything
+    fn constant(term: &TermPattern) -> Option<String> {
+        match term {
+            TermPattern::NamedNode(n) => Some(n.as_str().to_string()),
+            TermPattern::Literal(l) => Some(l.to_string()),
+            _ => None,
+        }
+    }
+
+    let (Query::Select { pattern, .. }
+    | Query::Construct { pattern, .. }
+    | Query::Describe { pattern, .. }
+    | Query::Ask { pattern, .. }) = query;
+    let mut patterns = vec![];
+    collect(pattern, &mut patterns);
+    patterns
+        .into_iter()
+        .map(|p| {
+            let subject = constant(&p.subject);
+            let predicate = match &p.predicate {
+                NamedNodePattern::NamedNode(n) => Some(n.as_str().to_string()),
+                NamedNodePattern::Variable(_) => None,
+            };
+            let object = constant(&p.object);
+            let estimate = snapshot
+                .graphs()
+                .map(|(_, hdt)| {
+                    hdt.triples_matching(
+                        subject.as_deref(),
+                        predicate.as_deref(),
+                        object.as_deref(),
+                    )
+                    .count()
+                })
+                .sum();
+            (p, estimate)
+        })
+        .collect()
+}
+
+/// The algebra of `query` as a JSON object, for tools rather than people: its `form` (`select`,
+/// `construct`, `describe` or `ask`), its `pattern` tree and, when present, its `dataset` and
+/// CONSTRUCT `template`. Each node of the tree has a `type`, the snake case name of the operator,
+/// and the fields of that operator, child patterns under `inner`, or `left` and `right`. Terms and
+/// expressions are written in SPARQL syntax.
+pub fn query_plan_json(query: &Query) -> serde_json::Value {
+    use serde_json::{json, Value};
+
+    fn triple(pattern: &TriplePattern) -> Value {
+        json!({
+            "subject": pattern.subject.to_string(),
+            "predicate": pattern.predicate.to_string(),
+            "object": pattern.object.to_string(),
+        })
+    }
+    fn strings<T: std::fmt::Display>(items: &[T]) -> Vec<String> {
+        items.iter().map(|i| i.to_string()).collect()
+    }
+    fn node(pattern: &GraphPattern) -> Value {
+        match pattern {
+            GraphPattern::Bgp { patterns } => json!({
+                "type": "bgp",
+                "patterns": patterns.iter().map(triple).collect::<Vec<_>>(),
+            }),
+            GraphPattern::Path {
+                subject,
+                path,
+                object,
+            } => json!({
+                "type": "path",
+                "subject": subject.to_string(),
+                "path": path.to_string(),
+                "object": object.to_string(),
+            }),
+            GraphPattern::Join { left, right } => json!({
+                "type": "join",
+                "left": node(left),
+                "right": node(right),
+            }),
+            GraphPattern::LeftJoin {
+                left,
+                right,
+                expression,
+            } => json!({
+                "type": "left_join",
+                "left": node(left),
+                "right": node(right),
+                "expression": expression.as_ref().map(|e| e.to_string()),
+            }),
+            GraphPattern::Filter { expr, inner } => json!({
+                "type": "filter",
+                "expression": expr.to_string(),
+                "inner": node(inner),
+            }),
+            GraphPattern::Union { left, right } => json!({
+                "type": "union",
+                "left": node(left),
+                "right": node(right),
+            }),
+            GraphPattern::Graph { name, inner } => json!({
+                "type": "graph",
+                "name": name.to_string(),
+                "inner": node(inner),
+            }),
+            GraphPattern::Extend {
+                inner,
+                variable,
+                expression,
+            } => json!({
+                "type": "extend",
+                "variable": variable.to_string(),
+                "expression": expression.to_string(),
+                "inner": node(inner),
+            }),
+            GraphPattern::Minus { left, right } => json!({
+                "type": "minus",
+                "left": node(left),
+                "right": node(right),
+            }),
+            GraphPattern::Values {
+                variables,
+                bindings,
+            } => json!({
+                "type": "values",
+                "variables": strings(variables),
+                // UNDEF values are null
+                "rows": bindings
+                    .iter()
+                    .map(|row| row.iter().map(|t| t.as_ref().map(|t| t.to_string())).collect())
+                    .collect::<Vec<Vec<_>>>(),
+            }),
+            GraphPattern::OrderBy { inner, expression } => json!({
+                "type": "order_by",
+                "expressions": strings(expression),
+                "inner": node(inner),
+            }),
+            GraphPattern::Project { inner, variables } => json!({
+                "type": "project",
+                "variables": strings(variables),
+                "inner": node(inner),
+            }),
+            GraphPattern::Distinct { inner } => json!({
+                "type": "distinct",
+                "inner": node(inner),
+            }),
+            GraphPattern::Reduced { inner } => json!({
+                "type": "reduced",
+                "inner": node(inner),
+            }),
+            GraphPattern::Slice {
+                inner,
+                start,
+                length,
+            } => json!({
+                "type": "slice",
+                "start": start,
+                "length": length,
+                "inner": node(inner),
+            }),
+            GraphPattern::Group {
+                inner,
+                variables,
+                aggregates,
+            } => json!({
+                "type": "group",
+                "variables": strings(variables),
+                "aggregates": aggregates
+                    .iter()
+                    .map(|(v, a)| json!({"variable": v.to_string(), "aggregate": a.to_string()}))
+                    .collect::<Vec<_>>(),
+                "inner": node(inner),
+            }),
+            GraphPattern::Service {
+                name,
+                inner,
+                silent,
+            } => json!({
+                "type": "service",
+                "name": name.to_string(),
+                "silent": silent,
+                "inner": node(inner),
+            }),
+            // operators of SPARQL extensions, kept readable if not structured
+            #[allow(unreachable_patterns)]
+            pattern => json!({
+                "type": "other",
+                "sse": pattern.to_sse(),
+            }),
+        }
+    }
+
+    let (form, dataset, pattern) = match query {
+        Query::Select {
+            dataset, pattern, ..
+        } => ("select", dataset, pattern),
+        Query::Construct {
+            dataset, pattern, ..
+        } => ("construct", dataset, pattern),
+        Query::Describe {
+            dataset, pattern, ..
+        } => ("describe", dataset, pattern),
+        Query::Ask {
+            dataset, pattern, ..
+        } => ("ask", dataset, pattern),
+    };
+    let mut plan = json!({ "form": form, "pattern": node(pattern) });
+    if let Some(dataset) = dataset {
+        plan["dataset"] = json!({
+            "default": strings(&dataset.default),
+            "named": dataset.named.as_deref().map(strings),
+        });
+    }
+    if let Query::Construct { template, .. } = query {
+        plan["template"] = template.iter().map(triple).collect();
+    }
+    plan
+}
+
+/// Advances `solutions` past its first `count` solutions one at a time, without keeping them, e.g.
+/// for the offset of a page of results. Returns how many were skipped, fewer than `count` when
+/// `solutions` runs out first.
+pub fn skip_solutions<S, E>(
+    solutions: &mut impl Iterator<Item = Result<S, E>>,
+    count: usize,
+) -> Result<usize, E> {
+    let mut skipped = 0;
+    while skipped < count {
+        match solutions.next() {
+            Some(solution) => {
+                solution?;
+                skipped += 1;
+            }
+            None => break,
+        }
+    }
+    Ok(skipped)
 }
 
 #[cfg(test)]
@@ -525,6 +2218,67 @@ mod tests {
     #[cfg(feature = "server")]
     use super::*;
 
+    #[test]
+    fn test_parse_term_positions() {
+        let iri = "http://example.org/Apple";
+        assert!(super::parse_subject(iri).is_ok());
+        assert!(super::parse_subject("_:b0").is_ok());
+        assert!(super::parse_subject("\"red\"").is_err());
+
+        assert!(super::parse_predicate(iri).is_ok());
+        assert!(super::parse_predicate("\"red\"").is_err());
+        assert!(super::parse_predicate("_:b0").is_err());
+        assert!(super::parse_predicate("").is_err());
+
+        assert!(super::parse_object("\"red\"@en").unwrap().is_literal());
+        assert!(super::parse_object("_:b0").unwrap().is_blank_node());
+        assert!(super::parse_object(iri).unwrap().is_named_node());
+        assert!(super::parse_object("\"unterminated").is_err());
+    }
+
+    #[test]
+    fn test_query_prefixes() {
+        let prefixes = super::query_prefixes(
+            "PREFIX ex: <http://example.org/>\nprefix rdf:<http://www.w3.org/1999/02/22-rdf-syntax-ns#>\nPREFIX : <http://default/>\nSELECT ?prefix WHERE { ?prefix rdf:type ex:Fruit }",
+        );
+        assert_eq!(
+            prefixes,
+            vec![
+                ("ex".to_string(), "http://example.org/".to_string()),
+                (
+                    "rdf".to_string(),
+                    "http://www.w3.org/1999/02/22-rdf-syntax-ns#".to_string()
+                ),
+                ("".to_string(), "http://default/".to_string()),
+            ]
+        );
+
+        // PREFIX in comments, strings and IRIs is not a declaration
+        let prefixes = super::query_prefixes(
+            "# PREFIX old: <http://old/>\nPREFIX ex: <http://example.org/prefix/>\nSELECT ?s WHERE { ?s ex:note \"PREFIX str: <http://str/>\" ; ex:long '''it's\nprefix long: <http://long/>''' . FILTER(?s < <http://example.org/prefix:x>) }",
+        );
+        assert_eq!(
+            prefixes,
+            vec![("ex".to_string(), "http://example.org/prefix/".to_string())]
+        );
+        let prefixes = super::query_prefixes(
+            "PREFIX ex: <http://example.org/> SELECT ?n WHERE { ?s ex:n ?n FILTER(?n < 3) } # prefix\nPREFIX late: <http://late/>",
+        );
+        assert_eq!(prefixes.len(), 2);
+    }
+
+    #[test]
+    fn test_unused_prefixes() {
+        let q = "PREFIX ex: <http://example.org/>\nPREFIX rdfs: <http://www.w3.org/2000/01/rdf-schema#>\nPREFIX : <http://default/>\nSELECT ?s WHERE { ?s a ex:Fruit ; :p \"rdfs:label\" }";
+        // a prefixed name in a string is not a use
+        assert_eq!(super::unused_prefixes(q, &[]), vec!["rdfs".to_string()]);
+        assert!(super::unused_prefixes("SELECT * WHERE { ?s ?p ?o }", &[]).is_empty());
+
+        // a shared prefix of the same name doesn't stand in for the query's declaration
+        let shared = [("ex".to_string(), "http://other/".to_string())];
+        assert_eq!(super::unused_prefixes(q, &shared), vec!["rdfs".to_string()]);
+    }
+
     /// Helper function to get the path to a test HDT file
     #[cfg(feature = "server")]
     fn get_test_hdt_path(filename: &str) -> String {
@@ -653,4 +2407,718 @@ mod tests {
             "New graph should exist after insertion"
         );
     }
+
+    #[test]
+    #[cfg(feature = "server")]
+    fn test_get_all_graphs() {
+        let tmp_dir = tempfile::tempdir().expect("temp dir");
+        let before = std::time::SystemTime::now() - std::time::Duration::from_secs(60);
+        let graphs = fruit_store(tmp_dir.path())
+            .get_all_graphs()
+            .expect("graph info");
+        let names: Vec<_> = graphs.iter().map(|g| g.name.as_str()).collect();
+        assert_eq!(names, vec!["file:///banana.hdt", "file:///pineapple.hdt"]);
+        for graph in graphs {
+            assert_eq!(
+                graph.file_size,
+                std::fs::metadata(&graph.path).unwrap().len()
+            );
+            assert!(graph.file_size > 0);
+            assert!(graph.mtime > before && graph.mtime <= std::time::SystemTime::now());
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "server")]
+    fn test_insert_named_graph_from_turtle() {
+        let tmp_dir = tempfile::tempdir().expect("temp dir");
+        let turtle = tmp_dir.path().join("banana.ttl");
+        std::fs::copy("tests/resources/banana.ttl", &turtle).expect("copy");
+        let store = AggregateHdt::new(&[get_test_hdt_path("apple.hdt")])
+            .expect("Failed to create AggregateHDT");
+
+        let graph = NamedNode::new("http://example.org/bananas").unwrap();
+        store
+            .insert_named_graph(&graph, &turtle)
+            .expect("Failed to insert named graph");
+        assert_eq!(
+            store.graph_path(graph.as_str()),
+            Some(tmp_dir.path().join("banana.hdt"))
+        );
+
+        let snapshot = store.get_snapshot(None).expect("snapshot");
+        let results = super::query(
+            "SELECT ?fruit WHERE { GRAPH <http://example.org/bananas> { ?fruit a <http://example.org/Fruit> } }",
+            &snapshot,
+            None,
+        )
+        .expect("query evaluation");
+        assert_eq!(
+            solution_values(results, "fruit"),
+            vec!["<http://example.org/Banana>".to_string()]
+        );
+
+        let unsupported = tmp_dir.path().join("banana.txt");
+        std::fs::write(&unsupported, "not RDF").expect("write");
+        assert!(store.insert_named_graph(&graph, &unsupported).is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "server")]
+    fn test_append_to_named_graph() {
+        let tmp_dir = tempfile::tempdir().expect("temp dir");
+        let banana = tmp_dir.path().join("banana.hdt");
+        crate::create::do_create(
+            banana.to_str().unwrap(),
+            &["tests/resources/banana.ttl".to_string()],
+        )
+        .expect("test HDT");
+        let store = super::AggregateHdt::new(&[banana.to_string_lossy().into_owned()])
+            .expect("Failed to create AggregateHDT")
+            .with_mutations(true);
+
+        store
+            .append_to_named_graph(
+                &NamedNode::new("file:///banana.hdt").unwrap(),
+                Path::new("tests/resources/pineapple.ttl"),
+            )
+            .expect("append");
+
+        let snapshot = store.get_snapshot(None).expect("snapshot");
+        let results = super::query(
+            "SELECT ?fruit WHERE { GRAPH <file:///banana.hdt> { ?fruit a <http://example.org/Fruit> } }",
+            &snapshot,
+            None,
+        )
+        .expect("query evaluation");
+        assert_eq!(
+            solution_values(results, "fruit"),
+            vec![
+                "<http://example.org/Banana>".to_string(),
+                "<http://example.org/Pineapple>".to_string()
+            ]
+        );
+        // the Fruit class is described by both files and kept once
+        let results = super::query(
+            "SELECT ?label WHERE { <http://example.org/Fruit> <http://www.w3.org/2000/01/rdf-schema#label> ?label }",
+            &snapshot,
+            None,
+        )
+        .expect("query evaluation");
+        assert_eq!(
+            solution_values(results, "label"),
+            vec!["\"Fruit\"".to_string()]
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "server")]
+    fn test_concurrent_updates_of_named_graph() {
+        let tmp_dir = tempfile::tempdir().expect("temp dir");
+        let banana = tmp_dir.path().join("banana.hdt");
+        crate::create::do_create(
+            banana.to_str().unwrap(),
+            &["tests/resources/banana.ttl".to_string()],
+        )
+        .expect("test HDT");
+        let store = super::AggregateHdt::new(&[banana.to_string_lossy().into_owned()])
+            .expect("Failed to create AggregateHDT")
+            .with_mutations(true);
+        let graph = NamedNode::new("file:///banana.hdt").unwrap();
+
+        // each update reads the graph and writes it back, none of them may be lost
+        std::thread::scope(|scope| {
+            for i in 0..8 {
+                let (store, graph) = (&store, &graph);
+                scope.spawn(move || {
+                    let triple = Triple::new(
+                        NamedNode::new(format!("http://example.org/Banana{i}")).unwrap(),
+                        NamedNode::new("http://www.w3.org/1999/02/22-rdf-syntax-ns#type").unwrap(),
+                        NamedNode::new("http://example.org/Fruit").unwrap(),
+                    );
+                    store
+                        .update_named_graph(graph, &Default::default(), &[triple])
+                        .expect("update");
+                });
+            }
+        });
+
+        let snapshot = store.get_snapshot(None).expect("snapshot");
+        let results = super::query(
+            "SELECT ?fruit WHERE { GRAPH <file:///banana.hdt> { ?fruit a <http://example.org/Fruit> } }",
+            &snapshot,
+            None,
+        )
+        .expect("query evaluation");
+        assert_eq!(solution_values(results, "fruit").len(), 9);
+    }
+
+    #[test]
+    fn test_predicates_and_classes() {
+        let store = super::AggregateHdt::new(&["tests/resources/apple.hdt".to_string()])
+            .expect("Failed to create AggregateHDT")
+            .get_snapshot(None)
+            .expect("msg");
+
+        let predicates = store.predicates();
+        for p in [
+            "http://www.w3.org/1999/02/22-rdf-syntax-ns#type",
+            "http://www.w3.org/2000/01/rdf-schema#label",
+            "http://example.org/hasColor",
+        ] {
+            assert_eq!(
+                predicates.iter().filter(|x| x.as_str() == p).count(),
+                1,
+                "{p} should be listed once"
+            );
+        }
+        let mut sorted = predicates.clone();
+        sorted.sort();
+        assert_eq!(predicates, sorted);
+
+        assert_eq!(
+            store.classes(),
+            vec![
+                "http://example.org/Fruit".to_string(),
+                "http://www.w3.org/2000/01/rdf-schema#Class".to_string()
+            ]
+        );
+    }
+
+    #[test]
+    fn test_graph_base() {
+        use spareval::QueryableDataset;
+
+        let store = super::AggregateHdt::new(&["tests/resources/apple.hdt".to_string()])
+            .expect("Failed to create AggregateHDT")
+            .with_graph_base("http://my.org/graphs/")
+            .expect("valid graph base")
+            .get_snapshot(None)
+            .expect("msg");
+        let graphs = store
+            .internal_named_graphs()
+            .map(|g| g.map(|g| g.to_string()))
+            .collect::<Result<Vec<_>, _>>()
+            .expect("graph names");
+        assert_eq!(graphs, vec!["http://my.org/graphs/apple".to_string()]);
+
+        assert!(super::AggregateHdt::empty()
+            .with_graph_base("not an iri")
+            .is_err());
+    }
+
+    /// Store of banana.hdt and pineapple.hdt, created in `dir` from the test resources
+    fn fruit_store(dir: &std::path::Path) -> super::AggregateHdt {
+        let mut paths = vec![];
+        for fruit in ["banana", "pineapple"] {
+            let path = dir.join(format!("{fruit}.hdt"));
+            crate::create::do_create(
+                path.to_str().unwrap(),
+                &[format!("tests/resources/{fruit}.ttl")],
+            )
+            .expect("test HDT");
+            paths.push(path.to_string_lossy().into_owned());
+        }
+        super::AggregateHdt::new(&paths).expect("Failed to create AggregateHDT")
+    }
+
+    fn solution_values(results: spareval::QueryResults<'_>, variable: &str) -> Vec<String> {
+        let spareval::QueryResults::Solutions(solutions) = results else {
+            panic!("SELECT should return solutions");
+        };
+        let mut values = solutions
+            .map(|s| s.map(|s| s.get(variable).map(|t| t.to_string()).unwrap_or_default()))
+            .collect::<Result<Vec<_>, _>>()
+            .expect("solutions");
+        values.sort();
+        values
+    }
+
+    #[test]
+    #[cfg(feature = "server")]
+    fn test_iter_all_triples() {
+        let tmp_dir = tempfile::tempdir().expect("temp dir");
+        let store = fruit_store(tmp_dir.path());
+
+        let mut streamed: Vec<_> = store.iter_all_triples().collect();
+        let mut collected = store.collect_all_triples();
+        assert!(!streamed.is_empty());
+        streamed.sort();
+        collected.sort();
+        assert_eq!(streamed, collected);
+
+        // one graph after the other
+        let graphs: Vec<_> = store.iter_all_triples().map(|(g, _)| g).collect();
+        let banana = graphs.iter().filter(|g| *g == "file:///banana.hdt").count();
+        assert!(banana > 0);
+        assert!(graphs[..banana].iter().all(|g| g == "file:///banana.hdt"));
+        assert!(graphs[banana..]
+            .iter()
+            .all(|g| g == "file:///pineapple.hdt"));
+    }
+
+    #[test]
+    fn test_skip_solutions() {
+        let tmp_dir = tempfile::tempdir().expect("temp dir");
+        let snapshot = fruit_store(tmp_dir.path())
+            .get_snapshot(None)
+            .expect("snapshot");
+        let solutions = |query: &str| {
+            let spareval::QueryResults::Solutions(solutions) =
+                super::query(query, &snapshot, None).expect("query evaluation")
+            else {
+                panic!("SELECT should return solutions");
+            };
+            solutions
+        };
+        let all = "SELECT * WHERE { ?s ?p ?o }";
+        let total = solutions(all).count();
+
+        let mut rest = solutions(all);
+        assert_eq!(super::skip_solutions(&mut rest, 3).expect("skip"), 3);
+        assert_eq!(rest.count(), total - 3);
+
+        let mut rest = solutions(all);
+        assert_eq!(
+            super::skip_solutions(&mut rest, total + 10).expect("skip"),
+            total
+        );
+        assert!(rest.next().is_none());
+    }
+
+    #[test]
+    fn test_load_timeout() {
+        use std::time::Duration;
+
+        let tmp_dir = tempfile::tempdir().expect("temp dir");
+        let err = match fruit_store(tmp_dir.path())
+            .with_load_timeout(Duration::from_nanos(1))
+            .get_snapshot(None)
+        {
+            Ok(_) => panic!("loading should not finish within a nanosecond"),
+            Err(e) => e.to_string(),
+        };
+        assert!(err.contains("timed out"), "{err}");
+        assert!(
+            err.contains("banana.hdt") || err.contains("pineapple.hdt"),
+            "{err}"
+        );
+
+        let snapshot = fruit_store(tmp_dir.path())
+            .with_load_timeout(Duration::from_secs(60))
+            .get_snapshot(None)
+            .expect("HDTs load within the timeout");
+        assert_eq!(snapshot.hdts.len(), 2);
+    }
+
+    #[test]
+    fn test_query_graphs() {
+        let graphs =
+            |q: &str| super::query_graphs(&super::parse_query(q, None).expect("valid query"));
+        assert_eq!(
+            graphs("SELECT * WHERE { GRAPH <file:///a.hdt> { ?s ?p ?o } }"),
+            Some(vec!["file:///a.hdt".to_string()])
+        );
+        assert_eq!(
+            graphs("SELECT * FROM <file:///a.hdt> FROM NAMED <file:///b.hdt> WHERE { ?s ?p ?o }"),
+            Some(vec![
+                "file:///a.hdt".to_string(),
+                "file:///b.hdt".to_string()
+            ])
+        );
+        assert_eq!(graphs("SELECT * WHERE { ?s ?p ?o }"), None);
+        assert_eq!(graphs("SELECT * WHERE { GRAPH ?g { ?s ?p ?o } }"), None);
+        assert_eq!(
+            graphs("SELECT * WHERE { GRAPH <file:///a.hdt> { ?s ?p ?o } ?s ?p ?o }"),
+            None
+        );
+        assert_eq!(
+            graphs("ASK { GRAPH <file:///a.hdt> { ?s ?p ?o } FILTER NOT EXISTS { ?s a ?t } }"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_from_clauses() {
+        let tmp_dir = tempfile::tempdir().expect("temp dir");
+        let store = fruit_store(tmp_dir.path())
+            .get_snapshot(None)
+            .expect("snapshot");
+        let fruits = "PREFIX ex: <http://example.org/> SELECT ?fruit";
+        let graph_names = "SELECT ?g";
+
+        let results = super::query(
+            &format!("{fruits} WHERE {{ ?fruit a ex:Fruit }}"),
+            &store,
+            None,
+        )
+        .expect("query evaluation");
+        assert_eq!(
+            solution_values(results, "fruit"),
+            vec![
+                "<http://example.org/Banana>".to_string(),
+                "<http://example.org/Pineapple>".to_string()
+            ]
+        );
+
+        let results = super::query(
+            &format!("{fruits} FROM <file:///banana.hdt> WHERE {{ ?fruit a ex:Fruit }}"),
+            &store,
+            None,
+        )
+        .expect("query evaluation");
+        assert_eq!(
+            solution_values(results, "fruit"),
+            vec!["<http://example.org/Banana>".to_string()]
+        );
+
+        // FROM alone leaves no named graphs, FROM NAMED alone an empty default graph
+        let results = super::query(
+            &format!("{graph_names} FROM <file:///banana.hdt> WHERE {{ GRAPH ?g {{ }} }}"),
+            &store,
+            None,
+        )
+        .expect("query evaluation");
+        assert!(solution_values(results, "g").is_empty());
+        let results = super::query(
+            &format!("{graph_names} FROM NAMED <file:///pineapple.hdt> WHERE {{ GRAPH ?g {{ }} }}"),
+            &store,
+            None,
+        )
+        .expect("query evaluation");
+        assert_eq!(
+            solution_values(results, "g"),
+            vec!["<file:///pineapple.hdt>".to_string()]
+        );
+        let results = super::query(
+            &format!("{fruits} FROM NAMED <file:///pineapple.hdt> WHERE {{ ?fruit a ex:Fruit }}"),
+            &store,
+            None,
+        )
+        .expect("query evaluation");
+        assert!(solution_values(results, "fruit").is_empty());
+    }
+
+    #[test]
+    fn test_short_graph_names() {
+        use spareval::QueryableDataset;
+
+        let tmp_dir = tempfile::tempdir().expect("temp dir");
+        let store = fruit_store(tmp_dir.path())
+            .with_short_graph_names()
+            .expect("short graph names")
+            .get_snapshot(None)
+            .expect("msg");
+        let mut graphs = store
+            .internal_named_graphs()
+            .map(|g| g.map(|g| g.to_string()))
+            .collect::<Result<Vec<_>, _>>()
+            .expect("graph names");
+        graphs.sort();
+        assert_eq!(graphs, vec!["banana".to_string(), "pineapple".to_string()]);
+
+        // the labels are still returned as graph names by queries
+        let results = super::query("SELECT ?g WHERE { GRAPH ?g { } }", &store, None)
+            .expect("query evaluation");
+        let spareval::QueryResults::Solutions(solutions) = results else {
+            panic!("SELECT should return solutions");
+        };
+        let mut names = solutions
+            .map(|s| s.map(|s| s.get("g").map(|t| t.to_string())))
+            .collect::<Result<Vec<_>, _>>()
+            .expect("solutions");
+        names.sort();
+        assert_eq!(
+            names,
+            vec![
+                Some("<banana>".to_string()),
+                Some("<pineapple>".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn test_same_file_name_in_different_dirs() {
+        let first = tempfile::tempdir().expect("temp dir");
+        let second = tempfile::tempdir().expect("temp dir");
+        let mut paths = vec![];
+        for (dir, fruit) in [(&first, "banana"), (&second, "pineapple")] {
+            let path = dir.path().join("fruit.hdt");
+            crate::create::do_create(
+                path.to_str().unwrap(),
+                &[format!("tests/resources/{fruit}.ttl")],
+            )
+            .expect("test HDT");
+            paths.push(path.to_string_lossy().into_owned());
+        }
+
+        let err = match super::AggregateHdt::new(&paths) {
+            Ok(_) => panic!("the second file should not replace the first"),
+            Err(e) => e.to_string(),
+        };
+        assert!(err.contains(&paths[0]), "{err}");
+        assert!(err.contains(&paths[1]), "{err}");
+    }
+
+    #[test]
+    fn test_snapshot_from_readers() {
+        let bytes = std::fs::read("tests/resources/apple.hdt").expect("test HDT");
+        let snapshot = super::AggregateHdtSnapshot::from_readers(vec![(
+            "file:///apple.hdt".to_string(),
+            std::io::Cursor::new(bytes),
+        )])
+        .expect("HDT read from memory");
+
+        let results = super::query(
+            "SELECT ?color WHERE { GRAPH <file:///apple.hdt> { <http://example.org/Apple> <http://example.org/hasColor> ?color } }",
+            &snapshot,
+            None,
+        )
+        .expect("query evaluation");
+        let spareval::QueryResults::Solutions(solutions) = results else {
+            panic!("SELECT should return solutions");
+        };
+        let colors = solutions
+            .map(|s| s.map(|s| s.get("color").map(|t| t.to_string())))
+            .collect::<Result<Vec<_>, _>>()
+            .expect("solutions");
+        assert_eq!(colors, vec![Some("\"Red\"".to_string())]);
+    }
+
+    /// Snapshot counting the quads its pattern lookups return
+    struct CountingSnapshot<'a> {
+        snapshot: &'a super::AggregateHdtSnapshot,
+        quads: &'a std::sync::atomic::AtomicUsize,
+    }
+
+    impl<'a> spareval::QueryableDataset<'a> for CountingSnapshot<'a> {
+        type InternalTerm = std::sync::Arc<str>;
+        type Error = std::io::Error;
+
+        fn internal_quads_for_pattern(
+            &self,
+            subject: Option<&std::sync::Arc<str>>,
+            predicate: Option<&std::sync::Arc<str>>,
+            object: Option<&std::sync::Arc<str>>,
+            graph_name: Option<Option<&std::sync::Arc<str>>>,
+        ) -> impl Iterator<Item = Result<spareval::InternalQuad<Self::InternalTerm>, Self::Error>>
+               + use<'a> {
+            let quads = self.quads;
+            self.snapshot
+                .quads_for_pattern(subject, predicate, object, graph_name)
+                .inspect(move |_| {
+                    quads.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                })
+                .map(Ok)
+        }
+
+        fn internalize_term(
+            &self,
+            term: spargebra::term::Term,
+        ) -> Result<std::sync::Arc<str>, Self::Error> {
+            Ok(std::sync::Arc::from(super::term_to_hdt_bgp_str(term)))
+        }
+
+        fn externalize_term(
+            &self,
+            term: std::sync::Arc<str>,
+        ) -> Result<spargebra::term::Term, Self::Error> {
+            self.snapshot.externalize(term)
+        }
+
+        fn internal_named_graphs(
+            &self,
+        ) -> impl Iterator<Item = Result<Self::InternalTerm, Self::Error>> + use<'a> {
+            self.snapshot.named_graphs().into_iter().map(Ok)
+        }
+
+        fn contains_internal_graph_name(
+            &self,
+            graph_name: &std::sync::Arc<str>,
+        ) -> Result<bool, Self::Error> {
+            Ok(self.snapshot.contains_graph(graph_name))
+        }
+    }
+
+    #[test]
+    fn test_values_inlined() {
+        use std::io::Write as _;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let tmp_dir = tempfile::tempdir().expect("temp dir");
+        let nt = tmp_dir.path().join("items.nt");
+        let mut file = std::fs::File::create(&nt).expect("NT file");
+        for i in 0..2000 {
+            writeln!(
+                file,
+                "<http://example.org/item/{i}> <http://example.org/value> \"{i}\" ."
+            )
+            .expect("NT triple");
+        }
+        drop(file);
+        let hdt = tmp_dir.path().join("items.hdt");
+        crate::create::do_create(hdt.to_str().unwrap(), &[nt.to_string_lossy().into_owned()])
+            .expect("test HDT");
+        let snapshot = super::AggregateHdt::new(&[hdt.to_string_lossy().into_owned()])
+            .expect("Failed to create AggregateHDT")
+            .get_snapshot(None)
+            .expect("snapshot");
+
+        let rows: String = (0..500)
+            .step_by(5)
+            .map(|i| format!("<http://example.org/item/{i}> "))
+            .collect();
+        let query = super::parse_query(
+            &format!("SELECT ?v WHERE {{ VALUES ?s {{ {rows} UNDEF }} ?s <http://example.org/value> ?v . ?s ?p ?v }}"),
+            None,
+        )
+        .expect("query");
+        let quads = AtomicUsize::new(0);
+        let results = super::evaluate_over(
+            &query,
+            CountingSnapshot {
+                snapshot: &snapshot,
+                quads: &quads,
+            },
+        )
+        .expect("query evaluation");
+        // the UNDEF row keeps the join, the pattern is then matched on its own
+        assert_eq!(solution_values(results, "v").len(), 2100);
+
+        let query = super::parse_query(
+            &format!("SELECT ?v WHERE {{ VALUES ?s {{ {rows} }} ?s <http://example.org/value> ?v . ?s ?p ?v }}"),
+            None,
+        )
+        .expect("query");
+        quads.store(0, Ordering::Relaxed);
+        let results = super::evaluate_over(
+            &query,
+            CountingSnapshot {
+                snapshot: &snapshot,
+                quads: &quads,
+            },
+        )
+        .expect("query evaluation");
+        let mut expected: Vec<_> = (0..500).step_by(5).map(|i| format!("\"{i}\"")).collect();
+        expected.sort();
+        assert_eq!(solution_values(results, "v"), expected);
+        // a lookup per row and triple pattern instead of scanning the 2000 triples
+        let looked_up = quads.load(Ordering::Relaxed);
+        assert!(looked_up <= 2 * 100, "{looked_up} quads looked up");
+    }
+
+    /// HDT counting the triples its lookups read
+    struct CountingTriples {
+        hdt: hdt::Hdt,
+        read: std::sync::atomic::AtomicUsize,
+    }
+
+    impl super::TriplesWithPattern for CountingTriples {
+        fn triples_matching<'a>(
+            &'a self,
+            subject: Option<&'a str>,
+            predicate: Option<&'a str>,
+            object: Option<&'a str>,
+        ) -> Box<dyn Iterator<Item = [std::sync::Arc<str>; 3]> + 'a> {
+            Box::new(
+                self.hdt
+                    .triples_matching(subject, predicate, object)
+                    .inspect(|_| {
+                        self.read.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                    }),
+            )
+        }
+
+        fn predicates(&self) -> Vec<String> {
+            super::TriplesWithPattern::predicates(&self.hdt)
+        }
+    }
+
+    #[test]
+    fn test_matches_read_lazily() {
+        use std::io::Write as _;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let tmp_dir = tempfile::tempdir().expect("temp dir");
+        let nt = tmp_dir.path().join("items.nt");
+        let mut file = std::fs::File::create(&nt).expect("NT file");
+        for i in 0..2000 {
+            writeln!(
+                file,
+                "<http://example.org/item/{i}> <http://example.org/value> \"{i}\" ."
+            )
+            .expect("NT triple");
+        }
+        drop(file);
+        let triples = CountingTriples {
+            hdt: hdt::Hdt::read_nt(&nt).expect("test HDT"),
+            read: AtomicUsize::new(0),
+        };
+
+        // the first few matches only read the first chunk
+        let mut matches = super::ChunkedMatches::new(&triples, [None, None, None]);
+        assert_eq!(matches.by_ref().take(10).count(), 10);
+        assert_eq!(
+            triples.read.load(Ordering::Relaxed),
+            super::FIRST_CHUNK_SIZE
+        );
+        // reading all of them repeats the lookup for each chunk
+        assert_eq!(matches.count(), 1990);
+        let read = triples.read.load(Ordering::Relaxed);
+        assert!(read <= 2 * 2000, "{read} triples read");
+
+        // predicates come from the dictionary, no triple is read
+        assert_eq!(super::TriplesWithPattern::predicates(&triples).len(), 1);
+        assert_eq!(triples.read.load(Ordering::Relaxed), read);
+
+        // evaluations stopping early only consume the quads they need
+        let hdt = tmp_dir.path().join("items.hdt");
+        crate::create::do_create(hdt.to_str().unwrap(), &[nt.to_string_lossy().into_owned()])
+            .expect("test HDT");
+        let snapshot = super::AggregateHdt::new(&[hdt.to_string_lossy().into_owned()])
+            .expect("Failed to create AggregateHDT")
+            .get_snapshot(None)
+            .expect("snapshot");
+        for query in [
+            "SELECT * WHERE { ?s ?p ?o } LIMIT 5",
+            "CONSTRUCT WHERE { ?s ?p ?o } LIMIT 5",
+        ] {
+            let query = super::parse_query(query, None).expect("query");
+            let quads = AtomicUsize::new(0);
+            let results = super::evaluate_over(
+                &query,
+                CountingSnapshot {
+                    snapshot: &snapshot,
+                    quads: &quads,
+                },
+            )
+            .expect("query evaluation");
+            let count = match results {
+                spareval::QueryResults::Solutions(solutions) => solutions.count(),
+                spareval::QueryResults::Graph(triples) => triples.count(),
+                spareval::QueryResults::Boolean(_) => panic!("no boolean result expected"),
+            };
+            assert_eq!(count, 5);
+            let consumed = quads.load(Ordering::Relaxed);
+            assert!(consumed < 2000, "{consumed} quads consumed");
+        }
+
+        // ASK stops at the first solution
+        let query = super::parse_query("ASK { ?s ?p ?o }", None).expect("query");
+        let quads = AtomicUsize::new(0);
+        let results = super::evaluate_over(
+            &query,
+            CountingSnapshot {
+                snapshot: &snapshot,
+                quads: &quads,
+            },
+        )
+        .expect("query evaluation");
+        assert!(matches!(results, spareval::QueryResults::Boolean(true)));
+        let consumed = quads.load(Ordering::Relaxed);
+        assert!(
+            consumed <= super::FIRST_CHUNK_SIZE,
+            "{consumed} quads consumed"
+        );
+    }
 }
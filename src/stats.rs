@@ -0,0 +1,40 @@
+// Copyright (c) 2025, Decisym, LLC
+// Licensed under the BSD 3-Clause License (see LICENSE file in the project root).
+
+use crate::view::header_triples;
+use log::*;
+use std::io::{BufWriter, Write};
+
+/// Triple counts of HDT files read from their headers, without loading the dictionaries and
+/// triples. Writes a line per file, largest first, as `<triples>\t<file>`, followed by the total
+/// as `<triples>\ttotal`, or the same as a JSON object with `json`.
+pub fn do_stats<W: Write>(
+    hdt_files: &[String],
+    json: bool,
+    writer: &mut BufWriter<W>,
+) -> anyhow::Result<(), anyhow::Error> {
+    let mut counts = vec![];
+    for f in hdt_files {
+        let triples = header_triples(f)?;
+        debug!("{f}: {triples} triples");
+        counts.push((f.as_str(), triples));
+    }
+    counts.sort_by(|(f1, t1), (f2, t2)| t2.cmp(t1).then(f1.cmp(f2)));
+    let total: u64 = counts.iter().map(|(_, t)| t).sum();
+
+    if json {
+        let files: Vec<_> = counts
+            .iter()
+            .map(|(f, t)| serde_json::json!({ "file": f, "triples": t }))
+            .collect();
+        let stats = serde_json::json!({ "files": files, "total": total });
+        writeln!(writer, "{}", serde_json::to_string_pretty(&stats)?)?;
+    } else {
+        for (f, triples) in &counts {
+            writeln!(writer, "{triples}\t{f}")?;
+        }
+        writeln!(writer, "{total}\ttotal")?;
+    }
+    writer.flush()?;
+    Ok(())
+}
@@ -0,0 +1,37 @@
+// Copyright (c) 2025, Decisym, LLC
+// Licensed under the BSD 3-Clause License (see LICENSE file in the project root).
+
+//! Versions `de` was built with, to include in bug reports
+
+/// `de` version followed by the versions of the RDF dependencies it was built against, one per
+/// line. Dependencies are `unknown` when no Cargo.lock was available at build time.
+pub fn version_info() -> String {
+    let mut info = format!("de {}\n", env!("CARGO_PKG_VERSION"));
+    let versions: Vec<(&str, &str)> = env!("DE_DEPENDENCY_VERSIONS")
+        .split(';')
+        .filter_map(|v| v.split_once('='))
+        .collect();
+    for dependency in [
+        "hdt",
+        "oxrdf",
+        "oxrdfio",
+        "sparesults",
+        "spareval",
+        "spargebra",
+    ] {
+        let mut found: Vec<&str> = versions
+            .iter()
+            .filter(|(name, _)| *name == dependency)
+            .map(|(_, version)| *version)
+            .collect();
+        if found.is_empty() {
+            found.push("unknown");
+        }
+        info.push_str(&format!("{dependency} {}\n", found.join(", ")));
+    }
+    #[cfg(feature = "server")]
+    if let Some((_, version)) = versions.iter().find(|(name, _)| *name == "oxhttp") {
+        info.push_str(&format!("oxhttp {version}\n"));
+    }
+    info
+}
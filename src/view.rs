@@ -1,7 +1,10 @@
 // Copyright (c) 2025, Decisym, LLC
 // Licensed under the BSD 3-Clause License (see LICENSE file in the project root).
 
+use crate::create::HdtCompression;
+use crate::sparql::AggregateHdt;
 use anyhow::anyhow;
+use hdt::containers::rdf::Term;
 use hdt::containers::ControlInfo;
 use hdt::header::Header;
 use log::{debug, error};
@@ -10,48 +13,76 @@ use std::{
     path::Path,
 };
 
+/// Well known header predicates summarized by `view`, with the label they are displayed with
+const SUMMARY_PREDICATES: &[(&str, &str)] = &[
+    (TRIPLES_PREDICATE, "triples"),
+    (
+        "http://rdfs.org/ns/void#distinctSubjects",
+        "distinct subjects",
+    ),
+    ("http://rdfs.org/ns/void#properties", "properties"),
+    (
+        "http://rdfs.org/ns/void#distinctObjects",
+        "distinct objects",
+    ),
+    ("http://purl.org/HDT/hdt#originalSize", "original size"),
+    ("http://purl.org/HDT/hdt#hdtSize", "HDT size"),
+    ("http://purl.org/HDT/hdt#triplesOrder", "triples order"),
+    ("http://purl.org/dc/terms/issued", "issued"),
+];
+
+/// Options controlling what `view` displays
+#[derive(Clone, Default, Debug)]
+pub struct ViewOptions {
+    /// Also print every header triple after the summary
+    pub raw: bool,
+    /// Also print the distinct predicates used in the file
+    pub predicates: bool,
+}
+
 /// display some HDT file statistics
 pub fn show_content<W: Write>(
     hdt_files: &[String],
     indent: String,
     writer: &mut BufWriter<W>,
+) -> anyhow::Result<(), anyhow::Error> {
+    show_content_with_options(hdt_files, indent, &ViewOptions::default(), writer)
+}
+
+/// Same as [show_content], with additional control over the output through [ViewOptions]
+pub fn show_content_with_options<W: Write>(
+    hdt_files: &[String],
+    indent: String,
+    options: &ViewOptions,
+    writer: &mut BufWriter<W>,
 ) -> anyhow::Result<(), anyhow::Error> {
     debug!("Getting HDT info ...");
 
     for f in hdt_files {
-        let path = Path::new(f);
-        if !path.exists() {
-            error!("file {path:?} could not be found on local machine");
-            return Err(anyhow!(
-                "file {:?} could not be found on local machine",
-                path
-            ));
-        }
-        let file = match std::fs::File::open(path) {
-            Ok(f) => f,
-            Err(e) => {
-                return Err(anyhow!("error opening HDT file {path:?}: {e}"));
+        let (control_info, h) = read_header(f)?;
+        writeln!(writer, "{indent}{f}:")?;
+        writeln!(writer, "{indent}\tformat: {}", control_info.format)?;
+        for (predicate, label) in SUMMARY_PREDICATES {
+            if let Some(value) = header_value(&h, predicate) {
+                writeln!(writer, "{indent}\t{label}: {value}")?;
             }
-        };
-        let mut reader = std::io::BufReader::new(file);
-        // seek past the start of the file, nothing in here worth displaying
-        match ControlInfo::read(&mut reader) {
-            Ok(_) => {}
-            Err(e) => {
-                error!("failed to read HDT control info for file {f}: {e}");
-                return Err(anyhow!("error reading control info for HDT file {f}: {e}"));
+        }
+        if options.raw {
+            writeln!(writer, "{indent}\theader:")?;
+            for t in h.body {
+                writeln!(writer, "{indent}\t\t{}: {:?}", t.predicate, t.object)?
             }
-        };
-        let h = match Header::read(&mut reader) {
-            Ok(v) => v,
-            Err(e) => {
-                error!("failed to read HDT header for file {f}: {e}");
-                return Err(anyhow!("error reading header for HDT file {f}: {e}"));
+        }
+        if options.predicates {
+            // the header only counts predicates, listing them requires loading the triples
+            let snapshot = AggregateHdt::new(std::slice::from_ref(f))?
+                .with_cache(false)
+                .get_snapshot(None)
+                .map_err(|e| anyhow!("error loading HDT file {f}: {e}"))?;
+            writeln!(writer, "{indent}\tpredicates:")?;
+            for p in snapshot.predicates() {
+                writeln!(writer, "{indent}\t\t{p}")?;
             }
-        };
-        writeln!(writer, "{indent}{f}:")?;
-        for t in h.body {
-            writeln!(writer, "{indent}\t{}: {:?}", t.predicate, t.object)?
         }
     }
 
@@ -59,8 +90,84 @@ pub fn show_content<W: Write>(
     Ok(())
 }
 
+/// Header predicate of the number of triples of a HDT
+pub(crate) const TRIPLES_PREDICATE: &str = "http://rdfs.org/ns/void#triples";
+
+/// Read the control info and header of a HDT file, without loading the dictionary and triples.
+/// HDTs compressed with [crate::create::CreateOptions::compress_output] are decompressed while read.
+pub(crate) fn read_header(f: &str) -> anyhow::Result<(ControlInfo, Header)> {
+    let path = Path::new(f);
+    if !path.exists() {
+        error!("file {path:?} could not be found on local machine");
+        return Err(anyhow!(
+            "file {:?} could not be found on local machine",
+            path
+        ));
+    }
+    let file = match std::fs::File::open(path) {
+        Ok(f) => f,
+        Err(e) => {
+            return Err(anyhow!("error opening HDT file {path:?}: {e}"));
+        }
+    };
+    let mut reader: Box<dyn std::io::BufRead> = match HdtCompression::from_file_name(f) {
+        None => Box::new(std::io::BufReader::new(file)),
+        Some(HdtCompression::Gzip) => {
+            Box::new(std::io::BufReader::new(flate2::read::GzDecoder::new(file)))
+        }
+        Some(HdtCompression::Zstd) => Box::new(std::io::BufReader::new(
+            zstd::stream::read::Decoder::new(file)
+                .map_err(|e| anyhow!("error opening HDT file {path:?}: {e}"))?,
+        )),
+    };
+    let control_info = match ControlInfo::read(&mut reader) {
+        Ok(c) => c,
+        Err(e) => {
+            error!("failed to read HDT control info for file {f}: {e}");
+            return Err(anyhow!("error reading control info for HDT file {f}: {e}"));
+        }
+    };
+    let h = match Header::read(&mut reader) {
+        Ok(v) => v,
+        Err(e) => {
+            error!("failed to read HDT header for file {f}: {e}");
+            return Err(anyhow!("error reading header for HDT file {f}: {e}"));
+        }
+    };
+    Ok((control_info, h))
+}
+
+/// Literal value of the header triple with `predicate`, e.g. `http://rdfs.org/ns/void#triples`
+pub(crate) fn header_value(h: &Header, predicate: &str) -> Option<String> {
+    h.body.iter().find_map(|t| match &t.object {
+        Term::Literal(l) if t.predicate.trim_matches(['<', '>']) == predicate => {
+            Some(l.form().to_string())
+        }
+        _ => None,
+    })
+}
+
+/// Number of triples of a HDT file, as recorded in its header
+pub(crate) fn header_triples(f: &str) -> anyhow::Result<u64> {
+    let (_, h) = read_header(f)?;
+    let triples = header_value(&h, TRIPLES_PREDICATE)
+        .ok_or_else(|| anyhow!("header of HDT file {f} has no triple count"))?;
+    triples
+        .parse()
+        .map_err(|e| anyhow!("invalid triple count {triples} in HDT file {f}: {e}"))
+}
+
 pub fn view_hdt<W: Write>(hdt_files: &[String], writer: &mut BufWriter<W>) -> anyhow::Result<()> {
-    match show_content(hdt_files, String::new(), writer) {
+    view_hdt_with_options(hdt_files, &ViewOptions::default(), writer)
+}
+
+/// Same as [view_hdt], with additional control over the output through [ViewOptions]
+pub fn view_hdt_with_options<W: Write>(
+    hdt_files: &[String],
+    options: &ViewOptions,
+    writer: &mut BufWriter<W>,
+) -> anyhow::Result<()> {
+    match show_content_with_options(hdt_files, String::new(), options, writer) {
         Ok(_) => {}
         Err(e) => return Err(e),
     };
@@ -83,4 +190,42 @@ mod tests {
         .expect("failed to load hdt file");
         Ok(())
     }
+
+    #[test]
+    fn test_view_summary() -> anyhow::Result<()> {
+        let mut writer = BufWriter::new(Vec::new());
+        view::view_hdt(&["tests/resources/apple.hdt".to_string()], &mut writer)?;
+        let output = String::from_utf8(writer.into_inner()?)?;
+        assert!(output.contains("\ttriples: 9"));
+        assert!(output.contains("HDTv1"));
+        assert!(!output.contains("header:"));
+
+        let mut writer = BufWriter::new(Vec::new());
+        view::view_hdt_with_options(
+            &["tests/resources/apple.hdt".to_string()],
+            &view::ViewOptions {
+                raw: true,
+                ..Default::default()
+            },
+            &mut writer,
+        )?;
+        let output = String::from_utf8(writer.into_inner()?)?;
+        assert!(output.contains("\ttriples: 9"));
+        assert!(output.contains("header:"));
+        assert!(!output.contains("predicates:"));
+
+        let mut writer = BufWriter::new(Vec::new());
+        view::view_hdt_with_options(
+            &["tests/resources/apple.hdt".to_string()],
+            &view::ViewOptions {
+                predicates: true,
+                ..Default::default()
+            },
+            &mut writer,
+        )?;
+        let output = String::from_utf8(writer.into_inner()?)?;
+        assert!(output.contains("\tpredicates:\n"));
+        assert!(output.contains("\t\thttp://example.org/hasColor\n"));
+        Ok(())
+    }
 }
@@ -0,0 +1,36 @@
+// Copyright (c) 2025, Decisym, LLC
+// Licensed under the BSD 3-Clause License (see LICENSE file in the project root).
+
+// Kept apart from the other integration tests as it points the process wide temp dir elsewhere
+mod cleanup {
+    use de::*;
+    use std::io::BufWriter;
+    use tempfile::tempdir;
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_query_error_removes_temp_dirs() -> anyhow::Result<()> {
+        let tmp_dir = tempdir()?;
+        std::env::set_var("TMPDIR", tmp_dir.path());
+
+        // the TTL file is converted in a working dir before the output format is rejected
+        let options = query::QueryOptions {
+            output: Some(query::DeOutput::TURTLE),
+            ..Default::default()
+        };
+        let mut writer = BufWriter::new(Vec::new());
+        let res = query::do_query_with_options(
+            &["tests/resources/apple.ttl".to_string()],
+            &["tests/resources/query-color.rq".to_string()],
+            &options,
+            &mut writer,
+        )
+        .await;
+        std::env::remove_var("TMPDIR");
+        assert!(res.is_err());
+
+        assert_eq!(std::fs::read_dir(tmp_dir.path())?.count(), 0);
+        tmp_dir.close()?;
+        Ok(())
+    }
+}
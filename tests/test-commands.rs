@@ -95,7 +95,7 @@ mod integration {
         let res = query::do_query(
             &data_files,
             &query_files,
-            &query::DeOutput::CSV,
+            Some(&query::DeOutput::CSV),
             &mut writer,
         )
         .await;
@@ -136,7 +136,7 @@ http://example.org/Banana"#
         let res = query::do_query(
             &data_files,
             &query_files,
-            &query::DeOutput::CSV,
+            Some(&query::DeOutput::CSV),
             &mut writer,
         )
         .await;
@@ -176,7 +176,7 @@ http://example.org/Banana"#
         let res = query::do_query(
             &data_files,
             &query_files,
-            &query::DeOutput::CSV,
+            Some(&query::DeOutput::CSV),
             &mut writer,
         )
         .await;
@@ -193,7 +193,7 @@ http://example.org/Pineapple,yellow"#
         let res = query::do_query(
             &data_files,
             &query_files,
-            &query::DeOutput::TSV,
+            Some(&query::DeOutput::TSV),
             &mut writer2,
         )
         .await;
@@ -205,11 +205,23 @@ http://example.org/Pineapple,yellow"#
             "?fruit\t?color\n<http://example.org/Pineapple>\t\"yellow\""
         );
 
+        let options = query::QueryOptions {
+            output: Some(query::DeOutput::TSV),
+            raw_iris: true,
+            ..Default::default()
+        };
+        let mut writer_raw = create_test_writer();
+        query::do_query_with_options(&data_files, &query_files, &options, &mut writer_raw).await?;
+        assert_eq!(
+            get_output_from_writer(writer_raw)?.trim(),
+            "?fruit\t?color\nhttp://example.org/Pineapple\t\"yellow\""
+        );
+
         let mut writer3 = create_test_writer();
         let res = query::do_query(
             &data_files,
             &query_files,
-            &query::DeOutput::JSON,
+            Some(&query::DeOutput::JSON),
             &mut writer3,
         )
         .await;
@@ -225,7 +237,7 @@ http://example.org/Pineapple,yellow"#
         let res = query::do_query(
             &data_files,
             &query_files,
-            &query::DeOutput::XML,
+            Some(&query::DeOutput::XML),
             &mut writer4,
         )
         .await;
@@ -242,7 +254,7 @@ http://example.org/Pineapple,yellow"#
         let res = query::do_query(
             &data_files,
             &query_files,
-            &query::DeOutput::NTRIPLE,
+            Some(&query::DeOutput::NTRIPLE),
             &mut writer5,
         )
         .await;
@@ -280,7 +292,7 @@ http://example.org/Pineapple,yellow"#
         let res = query::do_query(
             &data_files,
             &query_files,
-            &query::DeOutput::CSV,
+            Some(&query::DeOutput::CSV),
             &mut writer,
         )
         .await;
@@ -309,7 +321,7 @@ http://example.org/Banana"#
         let res = query::do_query(
             &data_files,
             &query_files,
-            &query::DeOutput::CSV,
+            Some(&query::DeOutput::CSV),
             &mut writer,
         )
         .await;
@@ -353,8 +365,221 @@ http://example.org/Banana"#
 
         let query_files = vec!["tests/resources/query-color.rq".to_string()];
         let mut writer = create_test_writer();
-        let res = query::do_query(&pkgs, &query_files, &query::DeOutput::CSV, &mut writer).await;
+        let res = query::do_query(
+            &pkgs,
+            &query_files,
+            Some(&query::DeOutput::CSV),
+            &mut writer,
+        )
+        .await;
+        assert!(res.is_ok());
+
+        let output = get_output_from_writer(writer)?;
+        assert_eq!(
+            output.replace("\r", "").trim(),
+            r#"fruit
+http://example.org/Pineapple
+http://example.org/Banana"#
+        );
+        tmp_dir.close()?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_query_describe() -> anyhow::Result<()> {
+        let data_files = vec!["tests/resources/apple.hdt".to_string()];
+        let query_files = vec!["tests/resources/describe-apple.rq".to_string()];
+
+        // DESCRIBE defaults to Turtle, which groups the description by subject
+        let mut writer = create_test_writer();
+        let res = query::do_query(&data_files, &query_files, None, &mut writer).await;
+        assert!(res.is_ok());
+
+        let output = get_output_from_writer(writer)?;
+        assert!(output.contains("<http://example.org/Apple>"));
+        assert!(output.contains(" ;"));
+        assert!(output.contains("\"Red Delicious\""));
+
+        // graph results can not be written as SPARQL result sets
+        let mut writer2 = create_test_writer();
+        let res = query::do_query(
+            &data_files,
+            &query_files,
+            Some(&query::DeOutput::CSV),
+            &mut writer2,
+        )
+        .await;
+        assert!(res.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_query_pretty_json() -> anyhow::Result<()> {
+        let data_files = vec!["tests/resources/pineapple.ttl".to_string()];
+        let query_files = vec!["tests/resources/query-fruit-color.rq".to_string()];
+
+        let mut compact_writer = create_test_writer();
+        let res = query::do_query(
+            &data_files,
+            &query_files,
+            Some(&query::DeOutput::JSON),
+            &mut compact_writer,
+        )
+        .await;
         assert!(res.is_ok());
+        let compact = get_output_from_writer(compact_writer)?;
+
+        let mut pretty_writer = create_test_writer();
+        let options = query::QueryOptions {
+            output: Some(query::DeOutput::JSON),
+            pretty: true,
+            ..Default::default()
+        };
+        let res =
+            query::do_query_with_options(&data_files, &query_files, &options, &mut pretty_writer)
+                .await;
+        assert!(res.is_ok());
+        let pretty = get_output_from_writer(pretty_writer)?;
+
+        assert!(pretty.trim().lines().count() > 1);
+        assert!(pretty.contains("\n  \"head\""));
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&compact)?,
+            serde_json::from_str::<serde_json::Value>(&pretty)?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_create_keep_intermediate() -> anyhow::Result<()> {
+        let tmp_dir = tempdir()?;
+        let new_hdt = format!("{}/combined.hdt", tmp_dir.as_ref().display());
+
+        let options = create::CreateOptions {
+            keep_intermediate: true,
+            ..Default::default()
+        };
+        let summary = create::do_create_with_options(
+            &new_hdt,
+            &[
+                "tests/resources/pineapple.ttl".to_string(),
+                "tests/resources/banana.ttl".to_string(),
+            ],
+            &options,
+        )?;
+        let nt = summary
+            .intermediate_nt
+            .expect("intermediate file should be reported");
+        assert!(Path::new(&nt).exists());
+        assert!(std::fs::read_to_string(&nt)?.contains("<http://example.org/Pineapple>"));
+        std::fs::remove_file(nt)?;
+
+        // default behavior still cleans up
+        let options = create::CreateOptions {
+            force: true,
+            ..Default::default()
+        };
+        let summary = create::do_create_with_options(
+            &new_hdt,
+            &["tests/resources/banana.ttl".to_string()],
+            &options,
+        )?;
+        assert!(summary.intermediate_nt.is_none());
+
+        // a single NTriples input is used as is and is not reported as intermediate
+        let options = create::CreateOptions {
+            force: true,
+            keep_intermediate: true,
+            ..Default::default()
+        };
+        let summary = create::do_create_with_options(
+            &new_hdt,
+            &["tests/resources/banana.nt".to_string()],
+            &options,
+        )?;
+        assert!(summary.intermediate_nt.is_none());
+        assert!(Path::new("tests/resources/banana.nt").exists());
+        tmp_dir.close()?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_create_temp_prefix() -> anyhow::Result<()> {
+        let tmp_dir = tempdir()?;
+        let new_hdt = format!("{}/fruit.hdt", tmp_dir.as_ref().display());
+        let temp_prefix = format!("{}/build", tmp_dir.as_ref().display());
+        let data = [
+            "tests/resources/pineapple.ttl".to_string(),
+            "tests/resources/banana.ttl".to_string(),
+        ];
+
+        let options = create::CreateOptions {
+            keep_intermediate: true,
+            temp_prefix: Some(temp_prefix.clone()),
+            force: true,
+            ..Default::default()
+        };
+        let summary = create::do_create_with_options(&new_hdt, &data, &options)?;
+        assert_eq!(summary.intermediate_nt, Some(format!("{temp_prefix}.nt")));
+
+        // the kept file is never overwritten by a re-run
+        let err = create::do_create_with_options(&new_hdt, &data, &options)
+            .err()
+            .expect("the intermediate file already exists");
+        assert!(err.to_string().contains("already exists"), "{err}");
+        tmp_dir.close()?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_create_rdf_star_unsupported() -> anyhow::Result<()> {
+        let tmp_dir = tempdir()?;
+        let new_hdt = format!("{}/quoted.hdt", tmp_dir.as_ref().display());
+
+        let res = create::do_create(&new_hdt, &["tests/resources/quoted.ttl".to_string()]);
+        let err = res.err().expect("quoted triples can not be stored in HDT");
+        assert!(err.to_string().contains("RDF-star"));
+        assert!(!Path::new(&new_hdt).exists());
+
+        // compressed inputs are checked on their decompressed text
+        let xz = tmp_dir.path().join("quoted.ttl.xz");
+        let mut encoder = xz2::write::XzEncoder::new(std::fs::File::create(&xz)?, 6);
+        std::io::Write::write_all(&mut encoder, &std::fs::read("tests/resources/quoted.ttl")?)?;
+        encoder.finish()?;
+        let res = create::do_create(&new_hdt, &[xz.to_str().unwrap().to_string()]);
+        let err = res.err().expect("quoted triples can not be stored in HDT");
+        assert!(err.to_string().contains("RDF-star"), "{err}");
+
+        // a literal with << on a line with an unrelated syntax error is not RDF-star
+        let ttl = tmp_dir.path().join("arrows.ttl");
+        std::fs::write(
+            &ttl,
+            "<http://example.org/a> <http://example.org/note> \"a << b\" <http://example.org/c> .\n",
+        )?;
+        let res = create::do_create(&new_hdt, &[ttl.to_str().unwrap().to_string()]);
+        let err = res.err().expect("invalid Turtle");
+        assert!(!err.to_string().contains("RDF-star"), "{err}");
+        tmp_dir.close()?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_query_load_concurrency() -> anyhow::Result<()> {
+        let tmp_dir = tempdir()?;
+        let mut pkgs = vec![];
+        for d in ["pineapple", "banana", "apple"] {
+            let new_hdt = format!("{}/{d}.hdt", tmp_dir.as_ref().display());
+            create::do_create(&new_hdt, &[format!("tests/resources/{d}.ttl")])?;
+            pkgs.push(new_hdt);
+        }
+
+        let query_files = vec!["tests/resources/query-color.rq".to_string()];
+        let options = query::QueryOptions {
+            load_concurrency: Some(1),
+            ..Default::default()
+        };
+        let mut writer = create_test_writer();
+        query::do_query_with_options(&pkgs, &query_files, &options, &mut writer).await?;
 
         let output = get_output_from_writer(writer)?;
         assert_eq!(
@@ -363,6 +588,1883 @@ http://example.org/Banana"#
 http://example.org/Pineapple
 http://example.org/Banana"#
         );
+
+        // rayon would pick its default number of threads for 0
+        let options = query::QueryOptions {
+            load_concurrency: Some(0),
+            ..Default::default()
+        };
+        let mut writer = create_test_writer();
+        assert!(
+            query::do_query_with_options(&pkgs, &query_files, &options, &mut writer)
+                .await
+                .is_err()
+        );
+        tmp_dir.close()?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_create_cache() -> anyhow::Result<()> {
+        let tmp_dir = tempdir()?;
+        let new_hdt = format!("{}/apple.hdt", tmp_dir.as_ref().display());
+
+        // the index cache is written next to the HDT by default
+        create::do_create(&new_hdt, &["tests/resources/apple.ttl".to_string()])?;
+        assert!(!sparql::cache_files(Path::new(&new_hdt)).is_empty());
+        for cache in sparql::cache_files(Path::new(&new_hdt)) {
+            std::fs::remove_file(cache)?;
+        }
+
+        let options = create::CreateOptions {
+            no_cache: true,
+            force: true,
+            ..Default::default()
+        };
+        create::do_create_with_options(
+            &new_hdt,
+            &["tests/resources/apple.ttl".to_string()],
+            &options,
+        )?;
+        assert!(Path::new(&new_hdt).exists());
+
+        let files: Vec<String> = std::fs::read_dir(tmp_dir.path())?
+            .map(|e| e.map(|e| e.file_name().to_string_lossy().into_owned()))
+            .collect::<Result<_, _>>()?;
+        assert_eq!(files, vec!["apple.hdt".to_string()]);
+        tmp_dir.close()?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_reindex() -> anyhow::Result<()> {
+        let tmp_dir = tempdir()?;
+        let new_hdt = format!("{}/apple.hdt", tmp_dir.as_ref().display());
+        create::do_create(&new_hdt, &["tests/resources/apple.ttl".to_string()])?;
+        let cache = sparql::cache_files(Path::new(&new_hdt));
+        assert!(!cache.is_empty());
+
+        // a corrupt cache file is replaced, a deleted one recreated
+        std::fs::write(&cache[0], b"corrupt")?;
+        let mut writer = create_test_writer();
+        reindex::do_reindex(std::slice::from_ref(&new_hdt), &mut writer)?;
+        let regenerated = std::fs::read(&cache[0])?;
+        assert!(!regenerated.is_empty() && regenerated != b"corrupt");
+        let output = get_output_from_writer(writer)?;
+        assert!(output.starts_with(&format!("{new_hdt}\t")), "{output}");
+
+        std::fs::remove_file(&cache[0])?;
+        reindex::do_reindex(std::slice::from_ref(&new_hdt), &mut create_test_writer())?;
+        assert_eq!(sparql::cache_files(Path::new(&new_hdt)), cache);
+
+        assert!(reindex::do_reindex(
+            &["tests/resources/apple.ttl".to_string()],
+            &mut create_test_writer()
+        )
+        .is_err());
+        tmp_dir.close()?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_stats() -> anyhow::Result<()> {
+        let tmp_dir = tempdir()?;
+        let banana = format!("{}/banana.hdt", tmp_dir.as_ref().display());
+        create::do_create(&banana, &["tests/resources/banana.ttl".to_string()])?;
+        let files = ["tests/resources/apple.hdt".to_string(), banana.clone()];
+
+        let mut writer = create_test_writer();
+        stats::do_stats(&files, false, &mut writer)?;
+        let output = get_output_from_writer(writer)?;
+        let lines: Vec<(u64, &str)> = output
+            .lines()
+            .map(|l| {
+                let (count, name) = l.split_once('\t').expect("tab separated line");
+                (count.parse().expect("triple count"), name)
+            })
+            .collect();
+        assert_eq!(lines.len(), 3, "{output}");
+        assert!(
+            lines.contains(&(9, "tests/resources/apple.hdt")),
+            "{output}"
+        );
+        assert_eq!(lines[2], (lines[0].0 + lines[1].0, "total"));
+
+        let mut writer = create_test_writer();
+        stats::do_stats(&files, true, &mut writer)?;
+        let json: serde_json::Value = serde_json::from_str(&get_output_from_writer(writer)?)?;
+        assert_eq!(json["total"], lines[2].0);
+        assert_eq!(json["files"].as_array().map(|f| f.len()), Some(2));
+        Ok(())
+    }
+
+    #[test]
+    fn test_version_info() {
+        let info = de::version::version_info();
+        assert!(
+            info.starts_with(&format!("de {}\n", env!("CARGO_PKG_VERSION"))),
+            "{info}"
+        );
+        assert!(info.contains("\nhdt "));
+        assert!(info.contains("\nspareval "));
+    }
+
+    #[test]
+    fn test_create_force() -> anyhow::Result<()> {
+        let tmp_dir = tempdir()?;
+        let new_hdt = format!("{}/apple.hdt", tmp_dir.as_ref().display());
+        let data = ["tests/resources/apple.ttl".to_string()];
+        std::fs::write(&new_hdt, "important")?;
+
+        let err =
+            create::do_create_with_options(&new_hdt, &data, &create::CreateOptions::default())
+                .err()
+                .unwrap();
+        assert!(err.to_string().contains("already exists"), "{err}");
+        assert_eq!(std::fs::read_to_string(&new_hdt)?, "important");
+
+        let options = create::CreateOptions {
+            force: true,
+            ..Default::default()
+        };
+        let summary = create::do_create_with_options(&new_hdt, &data, &options)?;
+        assert!(summary.triples > 0);
+        assert_ne!(std::fs::read(&new_hdt)?, b"important");
+        tmp_dir.close()?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_create_skip_unhandled() -> anyhow::Result<()> {
+        let tmp_dir = tempdir()?;
+        let new_hdt = format!("{}/apple.hdt", tmp_dir.as_ref().display());
+        let notes = tmp_dir.path().join("notes.txt");
+        std::fs::write(&notes, "not RDF")?;
+        let data = [
+            "tests/resources/apple.ttl".to_string(),
+            notes.to_string_lossy().into_owned(),
+        ];
+
+        let err = create::do_create(&new_hdt, &data).err().unwrap();
+        assert!(err.to_string().contains("unsupported files"), "{err}");
+        assert!(!Path::new(&new_hdt).exists());
+
+        let options = create::CreateOptions {
+            skip_unhandled: true,
+            ..Default::default()
+        };
+        let summary = create::do_create_with_options(&new_hdt, &data, &options)?;
+        assert_eq!(summary.conversion.unhandled, vec![data[1].clone()]);
+
+        let mut writer = create_test_writer();
+        dump::do_dump(&[new_hdt], &dump::DumpOptions::default(), &mut writer)?;
+        assert!(get_output_from_writer(writer)?.contains("<http://example.org/Apple>"));
+        tmp_dir.close()?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_create_without_triples() -> anyhow::Result<()> {
+        let tmp_dir = tempdir()?;
+        let new_hdt = format!("{}/empty.hdt", tmp_dir.as_ref().display());
+        let empty_ttl = tmp_dir.path().join("empty.ttl");
+        std::fs::write(
+            &empty_ttl,
+            "@prefix ex: <http://example.org/> .\n# nothing else\n",
+        )?;
+
+        let err = create::do_create(&new_hdt, &[empty_ttl.to_string_lossy().into_owned()])
+            .err()
+            .unwrap();
+        assert!(
+            err.to_string().contains("no triples to build HDT from"),
+            "{err}"
+        );
+        assert!(!Path::new(&new_hdt).exists());
+        tmp_dir.close()?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_create_max_triples() -> anyhow::Result<()> {
+        let tmp_dir = tempdir()?;
+        let new_hdt = format!("{}/fruit.hdt", tmp_dir.as_ref().display());
+
+        let options = create::CreateOptions {
+            max_triples: Some(3),
+            ..Default::default()
+        };
+        let err = create::do_create_with_options(
+            &new_hdt,
+            &["tests/resources/apple.ttl".to_string()],
+            &options,
+        )
+        .err()
+        .unwrap();
+        assert!(err.to_string().contains("maximum of 3 triples"), "{err}");
+        assert!(!Path::new(&new_hdt).exists());
+
+        // NTriples inputs are not converted, they count against the limit too
+        let err = create::do_create_with_options(
+            &new_hdt,
+            &["tests/resources/banana.nt".to_string()],
+            &options,
+        )
+        .err()
+        .unwrap();
+        assert!(err.to_string().contains("maximum of 3"), "{err}");
+        assert!(!Path::new(&new_hdt).exists());
+
+        // combined NTriples inputs stop being copied at the limit, the combined file is removed
+        let temp_prefix = tmp_dir.path().join("combined");
+        let other_nt = tmp_dir.path().join("other.nt");
+        std::fs::copy("tests/resources/banana.nt", &other_nt)?;
+        let options = create::CreateOptions {
+            max_triples: Some(3),
+            temp_prefix: Some(temp_prefix.to_string_lossy().into_owned()),
+            ..Default::default()
+        };
+        let err = create::do_create_with_options(
+            &new_hdt,
+            &[
+                "tests/resources/banana.nt".to_string(),
+                other_nt.to_string_lossy().into_owned(),
+            ],
+            &options,
+        )
+        .err()
+        .unwrap();
+        assert!(err.to_string().contains("maximum of 3"), "{err}");
+        assert!(err.to_string().contains("banana.nt"), "{err}");
+        assert!(!Path::new(&new_hdt).exists());
+        assert!(!tmp_dir.path().join("combined.nt").exists());
+
+        let options = create::CreateOptions {
+            max_triples: Some(100),
+            ..Default::default()
+        };
+        create::do_create_with_options(
+            &new_hdt,
+            &["tests/resources/apple.ttl".to_string()],
+            &options,
+        )?;
+        assert!(Path::new(&new_hdt).exists());
+        tmp_dir.close()?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_create_superhero_from_nt() -> anyhow::Result<()> {
+        // downloaded by 'make init'
+        let source_rdf = "tests/resources/superhero.ttl".to_string();
+        if !Path::new(&source_rdf).exists() {
+            eprintln!("skipped, {source_rdf} is missing, run 'make init'");
+            return Ok(());
+        }
+        let tmp_dir = tempdir()?;
+        let from_ttl = format!("{}/from-ttl.hdt", tmp_dir.as_ref().display());
+        let from_nt = format!("{}/from-nt.hdt", tmp_dir.as_ref().display());
+
+        // a single NTriples input is handed to the HDT builder as is
+        let mut nt_file = tempfile::Builder::new()
+            .suffix(".nt")
+            .tempfile_in(tmp_dir.path())?;
+        let (source_nt, _) = create::files_to_rdf(
+            std::slice::from_ref(&source_rdf),
+            &mut nt_file,
+            std::sync::Arc::new(rdf2nt::OxRdfConvert::default()),
+        )?;
+        create::do_create(&from_ttl, std::slice::from_ref(&source_rdf))?;
+        create::do_create(&from_nt, std::slice::from_ref(&source_nt))?;
+
+        let dump_lines = |hdt: &str| -> anyhow::Result<Vec<String>> {
+            let mut writer = create_test_writer();
+            dump::do_dump(
+                &[hdt.to_string()],
+                &dump::DumpOptions::default(),
+                &mut writer,
+            )?;
+            let mut lines: Vec<String> = get_output_from_writer(writer)?
+                .lines()
+                .map(str::to_string)
+                .collect();
+            lines.sort();
+            Ok(lines)
+        };
+        let triples = dump_lines(&from_ttl)?;
+        assert!(!triples.is_empty());
+        assert_eq!(triples, dump_lines(&from_nt)?);
+
+        let mut writer = create_test_writer();
+        query::do_query(
+            &[from_nt],
+            &["tests/resources/hero-height.rq".to_string()],
+            Some(&query::DeOutput::CSV),
+            &mut writer,
+        )
+        .await?;
+        assert_eq!(
+            get_output_from_writer(writer)?.replace("\r", ""),
+            std::fs::read_to_string("tests/goldens/superhero-query.csv")?
+        );
+        tmp_dir.close()?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_create_plan() -> anyhow::Result<()> {
+        let tmp_dir = tempdir()?;
+        let unsupported = tmp_dir.path().join("notes.txt");
+        std::fs::write(&unsupported, "not RDF")?;
+        let unsupported = unsupported.to_str().unwrap().to_string();
+        let data = vec![
+            "tests/resources/apple.ttl".to_string(),
+            "tests/resources/banana.nt".to_string(),
+            unsupported.clone(),
+            "tests/resources/missing.ttl".to_string(),
+        ];
+
+        // NTriples combined with other files are converted, their blank nodes are relabelled
+        let plan = create::plan_create(&data, &create::CreateOptions::default())?;
+        assert_eq!(
+            plan.convert,
+            vec![
+                "tests/resources/apple.ttl".to_string(),
+                "tests/resources/banana.nt".to_string()
+            ]
+        );
+        assert!(plan.copy.is_empty());
+        // a single one is used as is
+        let plan = create::plan_create(
+            &["tests/resources/banana.nt".to_string()],
+            &create::CreateOptions::default(),
+        )?;
+        assert_eq!(plan.copy, vec!["tests/resources/banana.nt".to_string()]);
+        assert_eq!(
+            plan.unrecognized,
+            vec![
+                "tests/resources/missing.ttl".to_string(),
+                unsupported.clone()
+            ]
+        );
+
+        let mut writer = create_test_writer();
+        create::write_plan(&plan, &mut writer)?;
+        assert_eq!(
+            get_output_from_writer(writer)?,
+            format!(
+                "convert\ttests/resources/apple.ttl\nconvert\ttests/resources/banana.nt\nunrecognized\ttests/resources/missing.ttl\nunrecognized\t{unsupported}\n"
+            )
+        );
+        tmp_dir.close()?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_rdf_to_hdt_bytes() -> anyhow::Result<()> {
+        let bytes = create::rdf_to_hdt_bytes(&["tests/resources/apple.ttl".to_string()])?;
+        let hdt = hdt::Hdt::read(std::io::Cursor::new(bytes.clone()))?;
+        assert!(hdt.triples_all().count() > 0);
+
+        let snapshot = sparql::AggregateHdtSnapshot::from_readers(vec![(
+            "file:///apple.hdt".to_string(),
+            std::io::Cursor::new(bytes),
+        )])?;
+        let results = sparql::query(
+            "SELECT ?color WHERE { <http://example.org/Apple> <http://example.org/hasColor> ?color }",
+            &snapshot,
+            None,
+        )?;
+        let spareval::QueryResults::Solutions(solutions) = results else {
+            panic!("SELECT should return solutions");
+        };
+        let colors = solutions
+            .map(|s| s.map(|s| s.get("color").map(|t| t.to_string())))
+            .collect::<Result<Vec<_>, _>>()?;
+        assert_eq!(colors, vec![Some("\"Red\"".to_string())]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_create_from_n3() -> anyhow::Result<()> {
+        let tmp_dir = tempdir()?;
+        let n3 = tmp_dir.path().join("cherry.n3");
+        std::fs::write(
+            &n3,
+            r#"@prefix ex: <http://example.org/>.
+ex:Cherry ex:hasColor "Red".
+{ ?x ex:hasColor "Red" } => { ?x a ex:RedThing }.
+?y ex:likes ex:Cherry.
+"Red" ex:colorOf ex:Cherry.
+"#,
+        )?;
+        let new_hdt = format!("{}/cherry.hdt", tmp_dir.as_ref().display());
+        create::do_create(&new_hdt, &[n3.to_string_lossy().into_owned()])?;
+
+        let query = tmp_dir.path().join("color.rq");
+        std::fs::write(
+            &query,
+            "SELECT ?s ?c WHERE { ?s <http://example.org/hasColor> ?c }",
+        )?;
+        let mut writer = create_test_writer();
+        query::do_query(
+            &[new_hdt],
+            &[query.to_string_lossy().into_owned()],
+            None,
+            &mut writer,
+        )
+        .await?;
+        // the statement quoted in the rule isn't asserted
+        assert_eq!(
+            get_output_from_writer(writer)?,
+            "s,c\r\nhttp://example.org/Cherry,Red\r\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_create_converter() -> anyhow::Result<()> {
+        assert_eq!(
+            create::CreateOptions::default().converter,
+            rdf2nt::Converter::Oxrdf
+        );
+        assert_eq!(
+            query::QueryOptions::default().converter,
+            rdf2nt::Converter::Oxrdf
+        );
+
+        let tmp_dir = tempdir()?;
+        let dump = |options: &create::CreateOptions, name: &str| -> anyhow::Result<String> {
+            let new_hdt = format!("{}/{name}.hdt", tmp_dir.as_ref().display());
+            create::do_create_with_options(
+                &new_hdt,
+                &["tests/resources/apple.ttl".to_string()],
+                options,
+            )?;
+            let mut writer = create_test_writer();
+            let dump_options = dump::DumpOptions {
+                sort: true,
+                ..Default::default()
+            };
+            dump::do_dump(&[new_hdt], &dump_options, &mut writer)?;
+            Ok(get_output_from_writer(writer)?)
+        };
+        let explicit = create::CreateOptions {
+            converter: rdf2nt::Converter::Oxrdf,
+            ..Default::default()
+        };
+        assert_eq!(
+            dump(&create::CreateOptions::default(), "default")?,
+            dump(&explicit, "explicit")?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_create_normalize_literals() -> anyhow::Result<()> {
+        let tmp_dir = tempdir()?;
+        let nt = tmp_dir.path().join("numbers.nt");
+        std::fs::write(
+            &nt,
+            r#"<http://example.org/a> <http://example.org/count> "01"^^<http://www.w3.org/2001/XMLSchema#integer> .
+<http://example.org/a> <http://example.org/valid> "1"^^<http://www.w3.org/2001/XMLSchema#boolean> .
+<http://example.org/a> <http://example.org/label> "01" .
+<http://example.org/a> <http://example.org/size> "big"^^<http://www.w3.org/2001/XMLSchema#integer> .
+"#,
+        )?;
+        let new_hdt = format!("{}/numbers.hdt", tmp_dir.as_ref().display());
+        let options = create::CreateOptions {
+            normalize_literals: true,
+            ..Default::default()
+        };
+        create::do_create_with_options(&new_hdt, &[nt.to_string_lossy().into_owned()], &options)?;
+
+        let mut writer = create_test_writer();
+        dump::do_dump(&[new_hdt], &dump::DumpOptions::default(), &mut writer)?;
+        let output = get_output_from_writer(writer)?;
+        assert!(
+            output.contains("\"1\"^^<http://www.w3.org/2001/XMLSchema#integer>"),
+            "{output}"
+        );
+        assert!(
+            output.contains("\"true\"^^<http://www.w3.org/2001/XMLSchema#boolean>"),
+            "{output}"
+        );
+        // plain literals and invalid values are kept as is
+        assert!(
+            output.contains("<http://example.org/label> \"01\""),
+            "{output}"
+        );
+        assert!(
+            output.contains("\"big\"^^<http://www.w3.org/2001/XMLSchema#integer>"),
+            "{output}"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_create_compress_output() -> anyhow::Result<()> {
+        let tmp_dir = tempdir()?;
+        for compression in [create::HdtCompression::Gzip, create::HdtCompression::Zstd] {
+            let options = create::CreateOptions {
+                compress_output: Some(compression),
+                ..Default::default()
+            };
+            let err = create::do_create_with_options(
+                &format!("{}/apple.hdt", tmp_dir.as_ref().display()),
+                &["tests/resources/apple.ttl".to_string()],
+                &options,
+            )
+            .err()
+            .unwrap();
+            assert!(err.to_string().contains("should end with"), "{err}");
+
+            let new_hdt = format!(
+                "{}/apple.hdt.{}",
+                tmp_dir.as_ref().display(),
+                compression.extension()
+            );
+            create::do_create_with_options(
+                &new_hdt,
+                &["tests/resources/apple.ttl".to_string()],
+                &options,
+            )?;
+            // no index cache is written next to the compressed file
+            for entry in std::fs::read_dir(tmp_dir.path())? {
+                let name = entry?.file_name().to_string_lossy().into_owned();
+                assert!(
+                    create::HdtCompression::from_file_name(&name).is_some(),
+                    "{name}"
+                );
+            }
+
+            let mut writer = create_test_writer();
+            query::do_query(
+                &[new_hdt],
+                &["tests/resources/query-fruit-color.rq".to_string()],
+                Some(&query::DeOutput::CSV),
+                &mut writer,
+            )
+            .await?;
+            let output = get_output_from_writer(writer)?;
+            assert!(output.contains("http://example.org/Apple,Red"), "{output}");
+        }
+        tmp_dir.close()?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_create_validate_iris() -> anyhow::Result<()> {
+        let tmp_dir = tempdir()?;
+        let data = tmp_dir.path().join("bad-iri.nt");
+        std::fs::write(
+            &data,
+            "<http://example.org/Apple> <http://example.org/hasColor> \"red\" .\n\
+             <http://example.org/Bad%zz> <http://example.org/hasColor> \"grey\" .\n",
+        )?;
+        let data = [data.to_str().unwrap().to_string()];
+        let new_hdt = format!("{}/bad-iri.hdt", tmp_dir.as_ref().display());
+
+        let options = create::CreateOptions {
+            validate_iris: Some(rdf2nt::IriValidation::Strict),
+            ..Default::default()
+        };
+        let err = create::do_create_with_options(&new_hdt, &data, &options)
+            .err()
+            .unwrap();
+        assert!(
+            err.to_string()
+                .contains("invalid IRI in triple <http://example.org/Bad%zz>"),
+            "{err}"
+        );
+        assert!(!Path::new(&new_hdt).exists());
+
+        assert_eq!(
+            rdf2nt::validate_iris(&data[0], rdf2nt::IriValidation::Warn)?,
+            1
+        );
+        tmp_dir.close()?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_create_stats() -> anyhow::Result<()> {
+        let tmp_dir = tempdir()?;
+        let new_hdt = format!("{}/fruit.hdt", tmp_dir.as_ref().display());
+
+        let summary = create::do_create_with_options(
+            &new_hdt,
+            &[
+                "tests/resources/pineapple.ttl".to_string(),
+                "tests/resources/banana.ttl".to_string(),
+            ],
+            &create::CreateOptions::default(),
+        )?;
+        assert_eq!(summary.conversion.converted, 2);
+        assert!(summary.conversion.unhandled.is_empty());
+        assert_eq!(summary.conversion.triples.len(), 2);
+        assert!(summary.triples > 0);
+        assert_eq!(summary.triples, summary.hdt.triples_all().count());
+
+        let mut writer = create_test_writer();
+        create::write_summary(&new_hdt, &summary, &mut writer)?;
+        let size = std::fs::metadata(&new_hdt)?.len();
+        assert_eq!(
+            get_output_from_writer(writer)?,
+            format!("{new_hdt}\t{size} bytes\t{} triples\n", summary.triples)
+        );
+        tmp_dir.close()?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_create_input_dir() -> anyhow::Result<()> {
+        let tmp_dir = tempdir()?;
+        let input_dir = tmp_dir.path().join("data");
+        std::fs::create_dir(&input_dir)?;
+        std::fs::copy("tests/resources/banana.ttl", input_dir.join("banana.ttl"))?;
+        std::fs::copy(
+            "tests/resources/pineapple.ttl",
+            input_dir.join("pineapple.ttl"),
+        )?;
+        std::fs::write(input_dir.join("README.txt"), "not RDF")?;
+        let new_hdt = format!("{}/fruit.hdt", tmp_dir.as_ref().display());
+
+        let options = create::CreateOptions {
+            input_dir: Some(input_dir.to_str().unwrap().to_string()),
+            ..Default::default()
+        };
+        let summary = create::do_create_with_options(&new_hdt, &[], &options)?;
+        assert_eq!(summary.conversion.converted, 2);
+
+        let mut writer = create_test_writer();
+        query::do_query(
+            &[new_hdt],
+            &["tests/resources/query-fruit-color.rq".to_string()],
+            Some(&query::DeOutput::CSV),
+            &mut writer,
+        )
+        .await?;
+        assert_eq!(
+            get_output_from_writer(writer)?.replace("\r", "").trim(),
+            "fruit,color\nhttp://example.org/Pineapple,yellow\nhttp://example.org/Banana,yellow"
+        );
+        tmp_dir.close()?;
+        Ok(())
+    }
+
+    #[cfg(feature = "profiling")]
+    #[tokio::test]
+    async fn test_query_profile() -> anyhow::Result<()> {
+        let tmp_dir = tempdir()?;
+        let profile = format!("{}/out.pb", tmp_dir.as_ref().display());
+        let options = query::QueryOptions {
+            profile: Some(profile.clone()),
+            ..Default::default()
+        };
+        let mut writer = create_test_writer();
+        query::do_query_with_options(
+            &["tests/resources/apple.ttl".to_string()],
+            &["tests/resources/query-fruit-color.rq".to_string()],
+            &options,
+            &mut writer,
+        )
+        .await?;
+        assert!(get_output_from_writer(writer)?.contains("http://example.org/Apple"));
+        assert!(std::fs::metadata(&profile)?.len() > 0);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_query_zip() -> anyhow::Result<()> {
+        use std::io::Write;
+
+        let tmp_dir = tempdir()?;
+        let zip_path = tmp_dir.path().join("fruit.zip");
+        let mut zip = zip::ZipWriter::new(std::fs::File::create(&zip_path)?);
+        for fruit in ["apple", "banana"] {
+            let hdt = format!("{}/{fruit}.hdt", tmp_dir.as_ref().display());
+            create::do_create(&hdt, &[format!("tests/resources/{fruit}.ttl")])?;
+            zip.start_file(
+                format!("fruit/{fruit}.hdt"),
+                zip::write::SimpleFileOptions::default(),
+            )?;
+            zip.write_all(&std::fs::read(&hdt)?)?;
+        }
+        zip.finish()?;
+
+        let store = sparql::AggregateHdt::from_zip(&zip_path)?;
+        assert!(store.contains_graph_name("file:///apple.hdt")?);
+        assert!(store.contains_graph_name("file:///banana.hdt")?);
+
+        let mut writer = create_test_writer();
+        query::do_query(
+            &[zip_path.to_string_lossy().into_owned()],
+            &["tests/resources/query-fruit-color.rq".to_string()],
+            None,
+            &mut writer,
+        )
+        .await?;
+        let output = get_output_from_writer(writer)?;
+        assert!(output.contains("http://example.org/Apple"), "{output}");
+        assert!(output.contains("http://example.org/Banana"), "{output}");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_construct_nquads_graphs() -> anyhow::Result<()> {
+        let tmp_dir = tempdir()?;
+        let mut data = vec![];
+        for fruit in ["apple", "banana"] {
+            let hdt = format!("{}/{fruit}.hdt", tmp_dir.as_ref().display());
+            create::do_create(&hdt, &[format!("tests/resources/{fruit}.ttl")])?;
+            data.push(hdt);
+        }
+        let query = tmp_dir.path().join("construct.rq");
+        std::fs::write(
+            &query,
+            "PREFIX ex: <http://example.org/>
+            CONSTRUCT { ?f ex:hasColor ?c . ?f ex:checked true } WHERE { ?f ex:hasColor ?c }",
+        )?;
+        let mut writer = create_test_writer();
+        query::do_query(
+            &data,
+            &[query.to_string_lossy().into_owned()],
+            Some(&query::DeOutput::NQUADS),
+            &mut writer,
+        )
+        .await?;
+        let output = get_output_from_writer(writer)?;
+        assert!(output.contains(
+            "<http://example.org/Apple> <http://example.org/hasColor> \"Red\" <file:///apple.hdt> ."
+        ), "{output}");
+        assert!(output.contains(
+            "<http://example.org/Banana> <http://example.org/hasColor> \"yellow\" <file:///banana.hdt> ."
+        ), "{output}");
+        // triples built by the query aren't in any graph
+        assert!(output.contains(
+            "<http://example.org/Apple> <http://example.org/checked> \"true\"^^<http://www.w3.org/2001/XMLSchema#boolean> ."
+        ), "{output}");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_construct_output_graph() -> anyhow::Result<()> {
+        let tmp_dir = tempdir()?;
+        let query = tmp_dir.path().join("construct.rq");
+        std::fs::write(
+            &query,
+            "PREFIX ex: <http://example.org/>
+            CONSTRUCT { ?f ex:hasColor ?c . ?f ex:checked true } WHERE { ?f ex:hasColor ?c }",
+        )?;
+        let options = query::QueryOptions {
+            output: Some(query::DeOutput::NQUADS),
+            output_graph: Some("http://ex.org/g".to_string()),
+            ..Default::default()
+        };
+        let mut writer = create_test_writer();
+        query::do_query_with_options(
+            &["tests/resources/apple.hdt".to_string()],
+            &[query.to_string_lossy().into_owned()],
+            &options,
+            &mut writer,
+        )
+        .await?;
+        let output = get_output_from_writer(writer)?;
+        let lines = output.lines().collect::<Vec<_>>();
+        assert!(!lines.is_empty());
+        for line in lines {
+            assert!(line.ends_with(" <http://ex.org/g> ."), "{output}");
+        }
+
+        let options = query::QueryOptions {
+            output_graph: Some("not an IRI".to_string()),
+            ..options
+        };
+        let err = query::do_query_with_options(
+            &["tests/resources/apple.hdt".to_string()],
+            &[query.to_string_lossy().into_owned()],
+            &options,
+            &mut create_test_writer(),
+        )
+        .await
+        .err()
+        .unwrap();
+        assert!(err.to_string().contains("invalid output graph"), "{err}");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_query_loads_referenced_graphs() -> anyhow::Result<()> {
+        let tmp_dir = tempdir()?;
+        let apple = format!("{}/apple.hdt", tmp_dir.as_ref().display());
+        std::fs::copy("tests/resources/apple.hdt", &apple)?;
+        // fails to load, so any query loading it fails
+        let broken = format!("{}/broken.hdt", tmp_dir.as_ref().display());
+        std::fs::write(&broken, "not a HDT")?;
+        let data = [apple, broken];
+
+        let query = tmp_dir.path().join("graph.rq");
+        std::fs::write(
+            &query,
+            "SELECT ?c WHERE { GRAPH <file:///apple.hdt> { ?f <http://example.org/hasColor> ?c } }",
+        )?;
+        let mut writer = create_test_writer();
+        query::do_query(
+            &data,
+            &[query.to_string_lossy().into_owned()],
+            None,
+            &mut writer,
+        )
+        .await?;
+        assert_eq!(get_output_from_writer(writer)?, "c\r\nRed\r\n");
+
+        std::fs::write(
+            &query,
+            "SELECT ?c WHERE { ?f <http://example.org/hasColor> ?c }",
+        )?;
+        assert!(query::do_query(
+            &data,
+            &[query.to_string_lossy().into_owned()],
+            None,
+            &mut create_test_writer(),
+        )
+        .await
+        .is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_query_plain_writer() -> anyhow::Result<()> {
+        let mut output: Vec<u8> = Vec::new();
+        query::do_query(
+            &["tests/resources/apple.hdt".to_string()],
+            &["tests/resources/query-fruit-color.rq".to_string()],
+            None,
+            &mut output,
+        )
+        .await?;
+        assert_eq!(
+            String::from_utf8(output)?,
+            "fruit,color\r\nhttp://example.org/Apple,Red\r\n"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_query_hash() -> anyhow::Result<()> {
+        let tmp_dir = tempdir()?;
+        let queries = [
+            "SELECT ?p ?o WHERE { <http://example.org/Apple> ?p ?o } ORDER BY ?p",
+            "SELECT ?p ?o WHERE { <http://example.org/Apple> ?p ?o } ORDER BY DESC(?p)",
+            "SELECT ?p WHERE { <http://example.org/Apple> ?p ?o }",
+        ];
+        let mut query_files = vec![];
+        for (i, q) in queries.iter().enumerate() {
+            let path = tmp_dir.path().join(format!("q{i}.rq"));
+            std::fs::write(&path, q)?;
+            query_files.push(path.to_string_lossy().into_owned());
+        }
+        let options = query::QueryOptions {
+            hash: true,
+            ..Default::default()
+        };
+        let mut runs = vec![];
+        for _ in 0..2 {
+            let mut writer = create_test_writer();
+            query::do_query_with_options(
+                &["tests/resources/apple.hdt".to_string()],
+                &query_files,
+                &options,
+                &mut writer,
+            )
+            .await?;
+            let output = get_output_from_writer(writer)?;
+            let hashes: Vec<String> = output
+                .lines()
+                .map(|l| l.split_once("  ").expect("hash and file").0.to_string())
+                .collect();
+            runs.push(hashes);
+        }
+        let first = &runs[0];
+        assert_eq!(first.len(), 3);
+        assert_eq!(first[0].len(), 64);
+        assert_eq!(first, &runs[1]);
+        // the order of the solutions doesn't matter, their content does
+        assert_eq!(first[0], first[1]);
+        assert_ne!(first[0], first[2]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_query_explain_unused_prefixes() -> anyhow::Result<()> {
+        let tmp_dir = tempdir()?;
+        let rq = tmp_dir.path().join("unused.rq");
+        std::fs::write(
+            &rq,
+            "PREFIX ex: <http://example.org/>\nPREFIX owl: <http://www.w3.org/2002/07/owl#>\nSELECT ?s WHERE { ?s a ex:Fruit }",
+        )?;
+        let rq = rq.to_str().unwrap().to_string();
+        let options = query::QueryOptions {
+            explain_unused_prefixes: true,
+            ..Default::default()
+        };
+        let mut writer = create_test_writer();
+        query::do_query_with_options(
+            &["tests/resources/apple.hdt".to_string()],
+            std::slice::from_ref(&rq),
+            &options,
+            &mut writer,
+        )
+        .await?;
+        assert_eq!(get_output_from_writer(writer)?, format!("{rq}\towl:\n"));
+        tmp_dir.close()?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_query_explain() -> anyhow::Result<()> {
+        fn count_triple_patterns(node: &serde_json::Value) -> usize {
+            match node {
+                serde_json::Value::Object(fields) => {
+                    let own = match fields.get("type").and_then(|t| t.as_str()) {
+                        Some("bgp") => fields["patterns"].as_array().map_or(0, |p| p.len()),
+                        _ => 0,
+                    };
+                    own + fields.values().map(count_triple_patterns).sum::<usize>()
+                }
+                serde_json::Value::Array(items) => items.iter().map(count_triple_patterns).sum(),
+                _ => 0,
+            }
+        }
+
+        let tmp_dir = tempdir()?;
+        let rq = tmp_dir.path().join("fruit.rq");
+        std::fs::write(
+            &rq,
+            "PREFIX ex: <http://example.org/>\nSELECT ?s ?c WHERE { ?s a ex:Fruit ; ex:hasColor ?c OPTIONAL { ?s ex:grows ?where } FILTER(?c != \"Green\") }",
+        )?;
+        let rq = rq.to_str().unwrap().to_string();
+        let explain = |format| {
+            let rq = rq.clone();
+            async move {
+                let options = query::QueryOptions {
+                    explain: Some(format),
+                    ..Default::default()
+                };
+                let mut writer = create_test_writer();
+                query::do_query_with_options(
+                    &["tests/resources/apple.hdt".to_string()],
+                    &[rq],
+                    &options,
+                    &mut writer,
+                )
+                .await?;
+                anyhow::Ok(get_output_from_writer(writer)?)
+            }
+        };
+
+        let text = explain(query::ExplainFormat::Text).await?;
+        assert!(text.starts_with(&format!("{rq}\t")), "{text}");
+        assert!(text.contains("bgp"), "{text}");
+
+        let json = explain(query::ExplainFormat::Json).await?;
+        let plan: serde_json::Value = serde_json::from_str(json.trim())?;
+        assert_eq!(plan["file"], rq.as_str());
+        assert_eq!(plan["form"], "select");
+        assert_eq!(plan["pattern"]["type"], "project");
+        assert_eq!(count_triple_patterns(&plan), 3);
+        tmp_dir.close()?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_query_prefix_file() -> anyhow::Result<()> {
+        let tmp_dir = tempdir()?;
+        let prefix_file = tmp_dir.path().join("prefixes.ttl");
+        std::fs::write(
+            &prefix_file,
+            "@prefix ex: <http://example.org/> .\nPREFIX color: <http://example.org/unused/>\n",
+        )?;
+        // the query's own declaration of color: wins over the prefix file
+        let query = tmp_dir.path().join("query.rq");
+        std::fs::write(
+            &query,
+            "PREFIX color: <http://example.org/>\nSELECT ?c WHERE { ex:Apple color:hasColor ?c }",
+        )?;
+        let options = query::QueryOptions {
+            prefix_file: Some(prefix_file.to_string_lossy().into_owned()),
+            ..Default::default()
+        };
+        let mut writer = create_test_writer();
+        query::do_query_with_options(
+            &["tests/resources/apple.hdt".to_string()],
+            &[query.to_string_lossy().into_owned()],
+            &options,
+            &mut writer,
+        )
+        .await?;
+        assert_eq!(get_output_from_writer(writer)?, "c\r\nRed\r\n");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_query_explain_cost() -> anyhow::Result<()> {
+        let tmp_dir = tempdir()?;
+        let query = tmp_dir.path().join("query.rq");
+        std::fs::write(
+            &query,
+            "SELECT * WHERE { ?s ?p ?o . ?s <http://example.org/hasColor> \"Red\" }",
+        )?;
+        let options = query::QueryOptions {
+            explain_cost: true,
+            ..Default::default()
+        };
+        let mut writer = create_test_writer();
+        query::do_query_with_options(
+            &["tests/resources/apple.hdt".to_string()],
+            &[query.to_string_lossy().into_owned()],
+            &options,
+            &mut writer,
+        )
+        .await?;
+        let output = get_output_from_writer(writer)?;
+        let lines: Vec<_> = output.lines().collect();
+        assert_eq!(lines.len(), 2, "{output}");
+        // every triple matches the first pattern, only the color of the apple the second
+        assert_eq!(lines[0], "9\t?s ?p ?o");
+        assert!(
+            lines[1].starts_with("1\t?s <http://example.org/hasColor>"),
+            "{output}"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_query_not_an_hdt_file() -> anyhow::Result<()> {
+        let tmp_dir = tempdir()?;
+        let fake = tmp_dir.path().join("apple.hdt");
+        std::fs::copy("tests/resources/apple.ttl", &fake)?;
+        let fake = fake.to_string_lossy().into_owned();
+
+        let mut writer = create_test_writer();
+        let err = query::do_query(
+            std::slice::from_ref(&fake),
+            &["tests/resources/query-color.rq".to_string()],
+            None,
+            &mut writer,
+        )
+        .await
+        .err()
+        .unwrap();
+        assert!(
+            err.to_string()
+                .contains(&format!("file {fake} is not a valid HDT file")),
+            "{err}"
+        );
+        tmp_dir.close()?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_query_trig_named_graphs() -> anyhow::Result<()> {
+        let tmp_dir = tempdir()?;
+        let trig = tmp_dir.path().join("fruit.trig");
+        std::fs::write(
+            &trig,
+            r#"@prefix ex: <http://example.org/> .
+ex:Fruit a ex:Class .
+ex:tropical { ex:Banana a ex:Fruit . ex:Pineapple a ex:Fruit . }
+ex:temperate { ex:Apple a ex:Fruit . }
+"#,
+        )?;
+        let rq = tmp_dir.path().join("tropical.rq");
+        std::fs::write(
+            &rq,
+            "SELECT ?fruit WHERE { GRAPH <http://example.org/tropical> { ?fruit a <http://example.org/Fruit> } } ORDER BY ?fruit",
+        )?;
+        let mut writer = create_test_writer();
+        query::do_query(
+            &[trig.to_str().unwrap().to_string()],
+            &[rq.to_str().unwrap().to_string()],
+            None,
+            &mut writer,
+        )
+        .await?;
+        assert_eq!(
+            get_output_from_writer(writer)?,
+            "fruit\r\nhttp://example.org/Banana\r\nhttp://example.org/Pineapple\r\n"
+        );
+
+        // the default graph is still the union of all graphs
+        let rq = tmp_dir.path().join("all.rq");
+        std::fs::write(&rq, "SELECT (COUNT(*) AS ?n) WHERE { ?s a ?o }")?;
+        let mut writer = create_test_writer();
+        query::do_query(
+            &[trig.to_str().unwrap().to_string()],
+            &[rq.to_str().unwrap().to_string()],
+            None,
+            &mut writer,
+        )
+        .await?;
+        assert!(get_output_from_writer(writer)?.ends_with("\r\n4\r\n"));
+        tmp_dir.close()?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_query_output_hdt() -> anyhow::Result<()> {
+        let tmp_dir = tempdir()?;
+        let new_hdt = format!("{}/apple.hdt", tmp_dir.as_ref().display());
+        create::do_create(&new_hdt, &["tests/resources/apple.ttl".to_string()])?;
+        let construct = tmp_dir.path().join("construct.rq");
+        std::fs::write(
+            &construct,
+            "CONSTRUCT { ?s <http://example.org/color> ?o } WHERE { ?s <http://example.org/hasColor> ?o }",
+        )?;
+        let construct = [construct.to_str().unwrap().to_string()];
+
+        let sub_hdt = format!("{}/sub.hdt", tmp_dir.as_ref().display());
+        let options = query::QueryOptions {
+            output: Some(query::DeOutput::HDT),
+            out_file: Some(sub_hdt.clone()),
+            ..Default::default()
+        };
+        let mut writer = create_test_writer();
+        query::do_query_with_options(&[new_hdt.clone()], &construct, &options, &mut writer).await?;
+        assert!(get_output_from_writer(writer)?.is_empty());
+
+        let select = tmp_dir.path().join("select.rq");
+        std::fs::write(
+            &select,
+            "SELECT ?o WHERE { ?s <http://example.org/color> ?o }",
+        )?;
+        let mut writer = create_test_writer();
+        query::do_query(
+            &[sub_hdt.clone()],
+            &[select.to_str().unwrap().to_string()],
+            None,
+            &mut writer,
+        )
+        .await?;
+        let output = get_output_from_writer(writer)?;
+        assert_eq!(output.lines().count(), 2, "{output}");
+
+        // SELECT results are not triples
+        let mut writer = create_test_writer();
+        let res = query::do_query_with_options(
+            &[new_hdt.clone()],
+            &["tests/resources/query-color.rq".to_string()],
+            &options,
+            &mut writer,
+        )
+        .await;
+        assert!(res.is_err());
+
+        // an HDT is never written to stdout
+        let options = query::QueryOptions {
+            output: Some(query::DeOutput::HDT),
+            ..Default::default()
+        };
+        let mut writer = create_test_writer();
+        let res = query::do_query_with_options(&[new_hdt], &construct, &options, &mut writer).await;
+        assert!(res.is_err());
+        tmp_dir.close()?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_query_output_auto() -> anyhow::Result<()> {
+        let tmp_dir = tempdir()?;
+        let new_hdt = format!("{}/apple.hdt", tmp_dir.as_ref().display());
+        create::do_create(&new_hdt, &["tests/resources/apple.ttl".to_string()])?;
+        let construct = tmp_dir.path().join("construct.rq");
+        std::fs::write(&construct, "CONSTRUCT { ?s ?p ?o } WHERE { ?s ?p ?o }")?;
+        let construct = [construct.to_str().unwrap().to_string()];
+
+        // the output file extension picks the format
+        let out_file = format!("{}/out.ttl", tmp_dir.as_ref().display());
+        let options = query::QueryOptions {
+            output: Some(query::DeOutput::AUTO),
+            out_file: Some(out_file.clone()),
+            ..Default::default()
+        };
+        let mut writer = create_test_writer();
+        query::do_query_with_options(&[new_hdt.clone()], &construct, &options, &mut writer).await?;
+        assert!(get_output_from_writer(writer)?.is_empty());
+        let turtle = std::fs::read_to_string(&out_file)?;
+        // Turtle groups the triples of a subject, NTriples repeats it on every line
+        assert!(turtle.contains(" ;\n"), "{turtle}");
+
+        // without a format the output file extension is used too
+        let options = query::QueryOptions {
+            out_file: Some(out_file.clone()),
+            ..Default::default()
+        };
+        std::fs::remove_file(&out_file)?;
+        let mut writer = create_test_writer();
+        query::do_query_with_options(&[new_hdt.clone()], &construct, &options, &mut writer).await?;
+        assert_eq!(std::fs::read_to_string(&out_file)?, turtle);
+
+        // otherwise the query form does
+        let options = query::QueryOptions {
+            output: Some(query::DeOutput::AUTO),
+            ..Default::default()
+        };
+        let mut writer = create_test_writer();
+        query::do_query_with_options(
+            &[new_hdt],
+            &["tests/resources/query-color.rq".to_string()],
+            &options,
+            &mut writer,
+        )
+        .await?;
+        assert!(get_output_from_writer(writer)?.starts_with("fruit"));
+        tmp_dir.close()?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_query_bind() -> anyhow::Result<()> {
+        let tmp_dir = tempdir()?;
+        let new_hdt = format!("{}/apple.hdt", tmp_dir.as_ref().display());
+        create::do_create(&new_hdt, &["tests/resources/apple.ttl".to_string()])?;
+        let rq = tmp_dir.path().join("typed.rq");
+        std::fs::write(&rq, "SELECT ?x WHERE { ?x a ?t } ORDER BY ?x")?;
+        let query_files = [rq.to_str().unwrap().to_string()];
+
+        let mut writer = create_test_writer();
+        query::do_query(&[new_hdt.clone()], &query_files, None, &mut writer).await?;
+        assert_eq!(
+            get_output_from_writer(writer)?.replace("\r", "").trim(),
+            "x\nhttp://example.org/Apple\nhttp://example.org/Fruit"
+        );
+
+        let options = query::QueryOptions {
+            bindings: vec![("t".to_string(), "http://example.org/Fruit".to_string())],
+            ..Default::default()
+        };
+        let mut writer = create_test_writer();
+        query::do_query_with_options(&[new_hdt.clone()], &query_files, &options, &mut writer)
+            .await?;
+        assert_eq!(
+            get_output_from_writer(writer)?.replace("\r", "").trim(),
+            "x\nhttp://example.org/Apple"
+        );
+
+        // unused variables are only warned about, blank nodes can't be bound
+        let options = query::QueryOptions {
+            bindings: vec![("color".to_string(), "\"red\"".to_string())],
+            ..Default::default()
+        };
+        let mut writer = create_test_writer();
+        query::do_query_with_options(&[new_hdt.clone()], &query_files, &options, &mut writer)
+            .await?;
+        assert_eq!(
+            get_output_from_writer(writer)?
+                .replace("\r", "")
+                .lines()
+                .count(),
+            3
+        );
+        let options = query::QueryOptions {
+            bindings: vec![("t".to_string(), "_:b0".to_string())],
+            ..Default::default()
+        };
+        let mut writer = create_test_writer();
+        assert!(
+            query::do_query_with_options(&[new_hdt], &query_files, &options, &mut writer)
+                .await
+                .is_err()
+        );
+        tmp_dir.close()?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_create_input_format_override() -> anyhow::Result<()> {
+        let tmp_dir = tempdir()?;
+        let data = format!("{}/pineapple", tmp_dir.as_ref().display());
+        std::fs::copy("tests/resources/pineapple.ttl", &data)?;
+        let new_hdt = format!("{}/pineapple.hdt", tmp_dir.as_ref().display());
+
+        let options = create::CreateOptions {
+            input_format: Some(rdf2nt::InputFormat::Ttl),
+            ..Default::default()
+        };
+        create::do_create_with_options(&new_hdt, &[data], &options)?;
+        assert!(Path::new(&new_hdt).exists());
+
+        let query_files = vec!["tests/resources/query-color.rq".to_string()];
+        let mut writer = create_test_writer();
+        query::do_query(
+            &[new_hdt],
+            &query_files,
+            Some(&query::DeOutput::CSV),
+            &mut writer,
+        )
+        .await?;
+
+        let output = get_output_from_writer(writer)?;
+        assert_eq!(
+            output.replace("\r", "").trim(),
+            r#"fruit
+http://example.org/Pineapple"#
+        );
+        tmp_dir.close()?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_convert_bom_encoded_input() -> anyhow::Result<()> {
+        use rdf2nt::Rdf2Nt;
+
+        let tmp_dir = tempdir()?;
+        let turtle = std::fs::read_to_string("tests/resources/banana.ttl")?;
+        let utf16 = |big_endian: bool| -> Vec<u8> {
+            let bom: u16 = 0xFEFF;
+            std::iter::once(bom)
+                .chain(turtle.encode_utf16())
+                .flat_map(|u| match big_endian {
+                    true => u.to_be_bytes(),
+                    false => u.to_le_bytes(),
+                })
+                .collect()
+        };
+        let inputs = [
+            (
+                "utf8-bom.ttl",
+                [b"\xEF\xBB\xBF".as_slice(), turtle.as_bytes()].concat(),
+            ),
+            ("utf16le.ttl", utf16(false)),
+            ("utf16be.ttl", utf16(true)),
+        ];
+        for (name, content) in inputs {
+            let input = tmp_dir.path().join(name);
+            std::fs::write(&input, content)?;
+            let output = tempfile::NamedTempFile::new()?;
+            let res = rdf2nt::OxRdfConvert::default()
+                .convert_to_nt(vec![input.to_str().unwrap().to_string()], output.as_file())?;
+            assert_eq!(res.converted, 1, "{name}");
+            let nt = std::fs::read_to_string(output.path())?;
+            assert_eq!(nt.lines().count(), 12, "{name}");
+            assert!(
+                nt.contains("<http://example.org/Banana> <http://example.org/hasColor> \"yellow\"")
+            );
+        }
+
+        let utf32 = tmp_dir.path().join("utf32.ttl");
+        std::fs::write(&utf32, [0xFF, 0xFE, 0x00, 0x00])?;
+        let output = tempfile::NamedTempFile::new()?;
+        let err = rdf2nt::OxRdfConvert::default()
+            .convert_to_nt(vec![utf32.to_str().unwrap().to_string()], output.as_file())
+            .unwrap_err();
+        assert!(err.to_string().contains("unsupported encoding UTF-32"));
+        tmp_dir.close()?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_create_compressed_input() -> anyhow::Result<()> {
+        use std::io::Write;
+
+        let tmp_dir = tempdir()?;
+        let turtle = std::fs::read("tests/resources/pineapple.ttl")?;
+        let xz = format!("{}/pineapple.ttl.xz", tmp_dir.as_ref().display());
+        let mut encoder = xz2::write::XzEncoder::new(std::fs::File::create(&xz)?, 6);
+        encoder.write_all(&turtle)?;
+        encoder.finish()?;
+        let bz2 = format!("{}/banana.nt.bz2", tmp_dir.as_ref().display());
+        let mut encoder = bzip2::write::BzEncoder::new(
+            std::fs::File::create(&bz2)?,
+            bzip2::Compression::default(),
+        );
+        encoder.write_all(&std::fs::read("tests/resources/banana.nt")?)?;
+        encoder.finish()?;
+
+        let new_hdt = format!("{}/compressed.hdt", tmp_dir.as_ref().display());
+        create::do_create(&new_hdt, &[xz, bz2])?;
+
+        let query_files = vec!["tests/resources/query-color.rq".to_string()];
+        let mut writer = create_test_writer();
+        query::do_query(
+            &[new_hdt],
+            &query_files,
+            Some(&query::DeOutput::CSV),
+            &mut writer,
+        )
+        .await?;
+        let output = get_output_from_writer(writer)?;
+        assert!(output.contains("http://example.org/Pineapple"));
+        assert!(output.contains("http://example.org/Banana"));
+        tmp_dir.close()?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_create_explain_errors() -> anyhow::Result<()> {
+        let tmp_dir = tempdir()?;
+        let data = format!("{}/bad.ttl", tmp_dir.as_ref().display());
+        std::fs::write(
+            &data,
+            "@prefix ex: <http://example.org/> .\nex:Apple ex:hasColor \"Red\" .\nex:Banana ex:hasColor \"Yellow\" \"Extra\" .\n",
+        )?;
+        let new_hdt = format!("{}/bad.hdt", tmp_dir.as_ref().display());
+
+        let err = create::do_create(&new_hdt, std::slice::from_ref(&data))
+            .err()
+            .unwrap();
+        assert!(!err.to_string().contains("ex:Banana"));
+
+        let options = create::CreateOptions {
+            explain_errors: true,
+            ..Default::default()
+        };
+        let err = create::do_create_with_options(&new_hdt, &[data], &options)
+            .err()
+            .unwrap();
+        let message = err.to_string();
+        assert!(message.contains("bad.ttl:3:"), "{message}");
+        assert!(
+            message.contains("ex:Banana ex:hasColor \"Yellow\" \"Extra\" ."),
+            "{message}"
+        );
+        tmp_dir.close()?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_convert_reports_triples_per_file() -> anyhow::Result<()> {
+        use rdf2nt::Rdf2Nt;
+
+        let inputs = vec![
+            "tests/resources/banana.ttl".to_string(),
+            "tests/resources/pineapple.ttl".to_string(),
+        ];
+        let output = tempfile::NamedTempFile::new()?;
+        let res =
+            rdf2nt::OxRdfConvert::default().convert_to_nt(inputs.clone(), output.as_file())?;
+
+        assert_eq!(res.converted, 2);
+        let files: Vec<_> = res.triples.iter().map(|(f, _)| f.clone()).collect();
+        assert_eq!(files, inputs);
+        assert_eq!(res.triples[0].1, 12);
+        let total: u64 = res.triples.iter().map(|(_, n)| n).sum();
+        let written = std::fs::read_to_string(output.path())?.lines().count();
+        assert_eq!(total, u64::try_from(written)?);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_create_keeps_blank_nodes_of_files_distinct() -> anyhow::Result<()> {
+        let tmp_dir = tempdir()?;
+        let query = tmp_dir.path().join("query.rq");
+        std::fs::write(
+            &query,
+            "PREFIX ex: <http://example.org/>
+            SELECT ?name ?age WHERE { ?p ex:name ?name ; ex:age ?age } ORDER BY ?name",
+        )?;
+        // NTriples inputs are relabelled too when combined, rather than copied as is
+        for extension in ["ttl", "nt"] {
+            let mut data = vec![];
+            for (name, age) in [("Alice", 30), ("Bob", 40)] {
+                let input = tmp_dir.path().join(format!("{name}.{extension}"));
+                let content = match extension {
+                    "ttl" => format!(
+                        "@prefix ex: <http://example.org/> .\n_:x ex:name \"{name}\" ; ex:age {age} .\n"
+                    ),
+                    _ => format!(
+                        "_:x <http://example.org/name> \"{name}\" .\n_:x <http://example.org/age> \"{age}\"^^<http://www.w3.org/2001/XMLSchema#integer> .\n"
+                    ),
+                };
+                std::fs::write(&input, content)?;
+                data.push(input.to_str().unwrap().to_string());
+            }
+            let people = format!("{}/people-{extension}.hdt", tmp_dir.as_ref().display());
+            create::do_create(&people, &data)?;
+
+            let mut writer = create_test_writer();
+            query::do_query(
+                &[people],
+                &[query.to_str().unwrap().to_string()],
+                Some(&query::DeOutput::CSV),
+                &mut writer,
+            )
+            .await?;
+
+            // both files label their node _:x, merged they would pair every name with every age
+            assert_eq!(
+                get_output_from_writer(writer)?.replace("\r", "").trim(),
+                "name,age\nAlice,30\nBob,40"
+            );
+        }
+        tmp_dir.close()?;
+        Ok(())
+    }
+
+    /// Converter recording which files it was asked to convert
+    struct SpyConvert {
+        converted: std::sync::Mutex<Vec<String>>,
+    }
+
+    impl rdf2nt::Rdf2Nt for SpyConvert {
+        fn convert_to_nt(
+            &self,
+            file_paths: Vec<String>,
+            output_file: &std::fs::File,
+        ) -> anyhow::Result<rdf2nt::ConvertResult> {
+            self.converted
+                .lock()
+                .unwrap()
+                .extend(file_paths.iter().cloned());
+            rdf2nt::OxRdfConvert::default().convert_to_nt(file_paths, output_file)
+        }
+    }
+
+    #[test]
+    fn test_create_manifest_skips_unchanged() -> anyhow::Result<()> {
+        let tmp_dir = tempdir()?;
+        let manifest = tmp_dir.path().join("manifest.json");
+        let mut data = vec![];
+        for d in ["pineapple", "apple"] {
+            let f = format!("{}/{d}.ttl", tmp_dir.as_ref().display());
+            std::fs::copy(format!("tests/resources/{d}.ttl"), &f)?;
+            data.push(f);
+        }
+
+        let run = || -> anyhow::Result<Vec<String>> {
+            let spy = std::sync::Arc::new(SpyConvert {
+                converted: std::sync::Mutex::new(vec![]),
+            });
+            let mut out = tempfile::NamedTempFile::new()?;
+            let (nt, stats) =
+                create::files_to_rdf_with_manifest(&data, &mut out, spy.clone(), &manifest)?;
+            assert!(stats.unhandled.is_empty());
+            let content = std::fs::read_to_string(nt)?;
+            assert!(content.contains("<http://example.org/Pineapple>"));
+            assert!(content.contains("<http://example.org/Apple>"));
+            let converted = spy.converted.lock().unwrap().clone();
+            Ok(converted)
+        };
+
+        assert_eq!(run()?, data);
+        assert!(manifest.exists());
+        assert!(run()?.is_empty());
+
+        // touching one input only reconverts that input
+        std::fs::File::options()
+            .write(true)
+            .open(&data[1])?
+            .set_modified(std::time::SystemTime::now() + std::time::Duration::from_secs(10))?;
+        assert_eq!(run()?, vec![data[1].clone()]);
+
+        tmp_dir.close()?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_query_count() -> anyhow::Result<()> {
+        let pkgs = vec![
+            "tests/resources/pineapple.ttl".to_string(),
+            "tests/resources/banana.ttl".to_string(),
+        ];
+
+        let mut writer = create_test_writer();
+        query::do_query(
+            &pkgs,
+            &["tests/resources/query-color.rq".to_string()],
+            Some(&query::DeOutput::COUNT),
+            &mut writer,
+        )
+        .await?;
+        assert_eq!(get_output_from_writer(writer)?.trim(), "2");
+
+        let mut writer = create_test_writer();
+        query::do_query(
+            &["tests/resources/apple.hdt".to_string()],
+            &["tests/resources/describe-apple.rq".to_string()],
+            Some(&query::DeOutput::COUNT),
+            &mut writer,
+        )
+        .await?;
+        assert_eq!(get_output_from_writer(writer)?.trim(), "7");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_query_jsonld() -> anyhow::Result<()> {
+        let mut writer = create_test_writer();
+        query::do_query(
+            &["tests/resources/apple.hdt".to_string()],
+            &["tests/resources/describe-apple.rq".to_string()],
+            Some(&query::DeOutput::JSONLD),
+            &mut writer,
+        )
+        .await?;
+        let output = get_output_from_writer(writer)?;
+        let json: serde_json::Value = serde_json::from_str(&output)?;
+        assert!(json.to_string().contains("http://example.org/Apple"));
+
+        // JSON-LD serializes graphs, not solutions
+        let mut writer = create_test_writer();
+        let res = query::do_query(
+            &["tests/resources/apple.hdt".to_string()],
+            &["tests/resources/query-color.rq".to_string()],
+            Some(&query::DeOutput::JSONLD),
+            &mut writer,
+        )
+        .await;
+        assert!(res.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_query_default_graph_union_of_hdt_and_rdf() -> anyhow::Result<()> {
+        let tmp_dir = tempdir()?;
+        let banana_hdt = format!("{}/banana.hdt", tmp_dir.as_ref().display());
+        create::do_create(&banana_hdt, &["tests/resources/banana.ttl".to_string()])?;
+        let data = vec![banana_hdt, "tests/resources/pineapple.ttl".to_string()];
+
+        let query = tmp_dir.path().join("query.rq");
+        std::fs::write(
+            &query,
+            "PREFIX ex: <http://example.org/>
+            PREFIX rdf: <http://www.w3.org/1999/02/22-rdf-syntax-ns#>
+            PREFIX rdfs: <http://www.w3.org/2000/01/rdf-schema#>
+            SELECT ?fruit ?label WHERE { ?fruit rdf:type ex:Fruit . ex:Fruit rdfs:label ?label } ORDER BY ?fruit",
+        )?;
+        let mut writer = create_test_writer();
+        query::do_query(
+            &data,
+            &[query.to_str().unwrap().to_string()],
+            Some(&query::DeOutput::CSV),
+            &mut writer,
+        )
+        .await?;
+
+        // ex:Fruit's label is in both files but the default graph is a set union
+        assert_eq!(
+            get_output_from_writer(writer)?.replace("\r", "").trim(),
+            "fruit,label\nhttp://example.org/Banana,Fruit\nhttp://example.org/Pineapple,Fruit"
+        );
+        tmp_dir.close()?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_query_graph_scope() -> anyhow::Result<()> {
+        let tmp_dir = tempdir()?;
+        let banana_hdt = format!("{}/banana.hdt", tmp_dir.as_ref().display());
+        create::do_create(&banana_hdt, &["tests/resources/banana.ttl".to_string()])?;
+        let data = vec![banana_hdt, "tests/resources/apple.hdt".to_string()];
+
+        let query = tmp_dir.path().join("query.rq");
+        std::fs::write(
+            &query,
+            "PREFIX ex: <http://example.org/>
+            PREFIX rdf: <http://www.w3.org/1999/02/22-rdf-syntax-ns#>
+            SELECT ?fruit WHERE { ?fruit rdf:type ex:Fruit } ORDER BY ?fruit",
+        )?;
+        let queries = [query.to_str().unwrap().to_string()];
+        let options = query::QueryOptions {
+            graphs: vec!["file:///apple.hdt".to_string()],
+            ..Default::default()
+        };
+        let mut writer = create_test_writer();
+        query::do_query_with_options(&data, &queries, &options, &mut writer).await?;
+        assert_eq!(
+            get_output_from_writer(writer)?.replace("\r", "").trim(),
+            "fruit\nhttp://example.org/Apple"
+        );
+
+        let options = query::QueryOptions {
+            graphs: vec!["file:///cherry.hdt".to_string()],
+            ..Default::default()
+        };
+        let mut writer = create_test_writer();
+        let err = query::do_query_with_options(&data, &queries, &options, &mut writer)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("unknown graph file:///cherry.hdt"));
+        tmp_dir.close()?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_dump_sorted() -> anyhow::Result<()> {
+        let tmp_dir = tempdir()?;
+        let combined = format!("{}/combined.hdt", tmp_dir.as_ref().display());
+        create::do_create(
+            &combined,
+            &[
+                "tests/resources/pineapple.ttl".to_string(),
+                "tests/resources/banana.ttl".to_string(),
+            ],
+        )?;
+        let data = vec![combined, "tests/resources/apple.hdt".to_string()];
+        let options = dump::DumpOptions {
+            sort: true,
+            ..Default::default()
+        };
+
+        let mut first = create_test_writer();
+        dump::do_dump(&data, &options, &mut first)?;
+        let first = get_output_from_writer(first)?;
+        let mut second = create_test_writer();
+        dump::do_dump(&data, &options, &mut second)?;
+        assert_eq!(first, get_output_from_writer(second)?);
+
+        assert!(first.contains("<http://example.org/Apple>"));
+        assert!(first.contains("<http://example.org/Pineapple>"));
+
+        tmp_dir.close()?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_dump_sample() -> anyhow::Result<()> {
+        let tmp_dir = tempdir()?;
+        let combined = format!("{}/combined.hdt", tmp_dir.as_ref().display());
+        create::do_create(
+            &combined,
+            &[
+                "tests/resources/pineapple.ttl".to_string(),
+                "tests/resources/banana.ttl".to_string(),
+            ],
+        )?;
+        let data = vec![combined, "tests/resources/apple.hdt".to_string()];
+
+        let mut all = create_test_writer();
+        dump::do_dump(&data, &dump::DumpOptions::default(), &mut all)?;
+        let all = get_output_from_writer(all)?;
+
+        let options = dump::DumpOptions {
+            sample: Some(5),
+            seed: Some(42),
+            ..Default::default()
+        };
+        let mut first = create_test_writer();
+        dump::do_dump(&data, &options, &mut first)?;
+        let first = get_output_from_writer(first)?;
+        let mut second = create_test_writer();
+        dump::do_dump(&data, &options, &mut second)?;
+        assert_eq!(first, get_output_from_writer(second)?);
+
+        assert_eq!(first.lines().count(), 5);
+        for line in first.lines() {
+            assert!(all.lines().any(|l| l == line), "{line} is not in the dump");
+        }
+
+        tmp_dir.close()?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_query_prefixes() -> anyhow::Result<()> {
+        let pkgs = vec![
+            "tests/resources/pineapple.ttl".to_string(),
+            "tests/resources/banana.ttl".to_string(),
+        ];
+        let query_files = vec!["tests/resources/query-color.rq".to_string()];
+
+        for (output, expected) in [
+            (query::DeOutput::CSV, "fruit\nex:Pineapple\nex:Banana"),
+            (query::DeOutput::TSV, "?fruit\nex:Pineapple\nex:Banana"),
+        ] {
+            let options = query::QueryOptions {
+                output: Some(output),
+                prefixes: true,
+                ..Default::default()
+            };
+            let mut writer = create_test_writer();
+            query::do_query_with_options(&pkgs, &query_files, &options, &mut writer).await?;
+            let output = get_output_from_writer(writer)?;
+            assert_eq!(output.replace("\r", "").trim(), expected);
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_query_csv_delimiter() -> anyhow::Result<()> {
+        let pkgs = vec![
+            "tests/resources/pineapple.ttl".to_string(),
+            "tests/resources/banana.ttl".to_string(),
+        ];
+        let options = query::QueryOptions {
+            delimiter: Some('|'),
+            ..Default::default()
+        };
+        let mut writer = create_test_writer();
+        query::do_query_with_options(
+            &pkgs,
+            &["tests/resources/query-fruit-color.rq".to_string()],
+            &options,
+            &mut writer,
+        )
+        .await?;
+        assert_eq!(
+            get_output_from_writer(writer)?,
+            "fruit|color\r\nhttp://example.org/Pineapple|yellow\r\nhttp://example.org/Banana|yellow\r\n"
+        );
+
+        // values containing the delimiter or quotes are quoted
+        let tmp_dir = tempdir()?;
+        let query = tmp_dir.path().join("query.rq");
+        std::fs::write(
+            &query,
+            r#"PREFIX ex: <http://example.org/>
+            SELECT ?color WHERE { ex:Banana ex:hasColor ?c BIND(CONCAT(?c, "|\"ripe\"") AS ?color) }"#,
+        )?;
+        let mut writer = create_test_writer();
+        query::do_query_with_options(
+            &pkgs,
+            &[query.to_str().unwrap().to_string()],
+            &options,
+            &mut writer,
+        )
+        .await?;
+        assert_eq!(
+            get_output_from_writer(writer)?,
+            "color\r\n\"yellow|\"\"ripe\"\"\"\r\n"
+        );
+        tmp_dir.close()?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_query_ask_large_graph() -> anyhow::Result<()> {
+        let tmp_dir = tempdir()?;
+        let nt = tmp_dir.path().join("large.nt");
+        let mut content = String::new();
+        for i in 0..50_000 {
+            content.push_str(&format!(
+                "<http://example.org/s{i}> <http://example.org/value> \"{i}\" .\n"
+            ));
+        }
+        std::fs::write(&nt, content)?;
+        let large_hdt = format!("{}/large.hdt", tmp_dir.as_ref().display());
+        create::do_create(&large_hdt, &[nt.to_str().unwrap().to_string()])?;
+
+        // the early exit itself is checked by counting the quads read in sparql::tests
+        for (pattern, expected) in [
+            ("?s <http://example.org/value> ?o", "true"),
+            ("?s <http://example.org/missing> ?o", "false"),
+        ] {
+            let query = tmp_dir.path().join("ask.rq");
+            std::fs::write(&query, format!("ASK {{ {pattern} }}"))?;
+            let mut writer = create_test_writer();
+            query::do_query(
+                std::slice::from_ref(&large_hdt),
+                &[query.to_str().unwrap().to_string()],
+                None,
+                &mut writer,
+            )
+            .await?;
+            assert_eq!(
+                get_output_from_writer(writer)?.replace("\r", "").trim(),
+                expected
+            );
+        }
         tmp_dir.close()?;
         Ok(())
     }
@@ -109,6 +109,162 @@ mod server_tests {
         Ok(())
     }
 
+    #[test]
+    fn test_sparql_query_without_union_default_graph() -> anyhow::Result<()> {
+        let (tmp_dir, store) = setup_test_store()?;
+
+        let select = |query: &str| -> anyhow::Result<String> {
+            let mut request = Request::builder()
+                .method(Method::POST)
+                .uri("http://localhost/query")
+                .header("Content-Type", "application/sparql-query")
+                .header("Accept", "text/csv")
+                .body(Body::from(query.to_string()))
+                .unwrap();
+            let response = handle_response(de::serve::handle_request(
+                &mut request,
+                &store,
+                false,
+                tmp_dir.path().to_str().unwrap().to_string(),
+            ))?;
+            assert_eq!(response.status(), StatusCode::OK);
+            Ok(read_body(response).replace("\r", ""))
+        };
+
+        // the default graph is empty, only GRAPH patterns match
+        assert_eq!(select("SELECT ?s WHERE { ?s ?p ?o }")?.trim(), "s");
+        let scoped = select(
+            "SELECT DISTINCT ?s WHERE { GRAPH <file:///banana.hdt> { ?s a <http://example.org/Fruit> } }",
+        )?;
+        assert_eq!(scoped.trim(), "s\nhttp://example.org/Banana");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_sparql_query_with_default_graph() -> anyhow::Result<()> {
+        let (tmp_dir, store) = setup_test_store()?;
+        let config = de::serve::ServeConfig {
+            locations: tmp_dir.path().to_str().unwrap().to_string(),
+            default_graph: Some("file:///banana.hdt".to_string()),
+            ..Default::default()
+        };
+
+        let select = |query: &str| -> anyhow::Result<String> {
+            let mut request = Request::builder()
+                .method(Method::POST)
+                .uri("http://localhost/query")
+                .header("Content-Type", "application/sparql-query")
+                .header("Accept", "text/csv")
+                .body(Body::from(query.to_string()))
+                .unwrap();
+            let response = handle_response(de::serve::handle_request_with_config(
+                &mut request,
+                &store,
+                &config,
+            ))?;
+            assert_eq!(response.status(), StatusCode::OK);
+            Ok(read_body(response).replace("\r", ""))
+        };
+
+        // patterns outside a GRAPH clause only match the designated graph
+        let fruits = select("SELECT DISTINCT ?s WHERE { ?s a <http://example.org/Fruit> }")?;
+        assert_eq!(fruits.trim(), "s\nhttp://example.org/Banana");
+        // the other graphs are still named graphs
+        let scoped = select(
+            "SELECT DISTINCT ?s WHERE { GRAPH <file:///pineapple.hdt> { ?s a <http://example.org/Fruit> } }",
+        )?;
+        assert_eq!(scoped.trim(), "s\nhttp://example.org/Pineapple");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_sparql_query_protocol_dataset() -> anyhow::Result<()> {
+        let (tmp_dir, store) = setup_test_store()?;
+        let config = de::serve::ServeConfig {
+            locations: tmp_dir.path().to_str().unwrap().to_string(),
+            default_graph: Some("file:///pineapple.hdt".to_string()),
+            ..Default::default()
+        };
+
+        let select = |query: &str, params: &str| -> anyhow::Result<String> {
+            let mut request = Request::builder()
+                .method(Method::GET)
+                .uri(format!(
+                    "http://localhost/query?query={}&{params}",
+                    urlencoding::encode(query)
+                ))
+                .header("Accept", "text/csv")
+                .body(Body::empty())
+                .unwrap();
+            let response = handle_response(de::serve::handle_request_with_config(
+                &mut request,
+                &store,
+                &config,
+            ))?;
+            assert_eq!(response.status(), StatusCode::OK);
+            Ok(read_body(response).replace("\r", ""))
+        };
+        let fruits = "SELECT DISTINCT ?s WHERE { ?s a <http://example.org/Fruit> }";
+
+        // default-graph-uri makes up the default graph, in place of the designated one
+        let listed = select(fruits, "default-graph-uri=file%3A%2F%2F%2Fbanana.hdt")?;
+        assert_eq!(listed.trim(), "s\nhttp://example.org/Banana");
+        // it also replaces the FROM clauses of the query
+        let listed = select(
+            "SELECT DISTINCT ?s FROM <file:///pineapple.hdt> WHERE { ?s a <http://example.org/Fruit> }",
+            "default-graph-uri=file%3A%2F%2F%2Fbanana.hdt",
+        )?;
+        assert_eq!(listed.trim(), "s\nhttp://example.org/Banana");
+        // with only named-graph-uri the default graph is empty
+        let empty = select(fruits, "named-graph-uri=file%3A%2F%2F%2Fbanana.hdt")?;
+        assert_eq!(empty.trim(), "s");
+        let named = select(
+            "SELECT DISTINCT ?g WHERE { GRAPH ?g { ?s ?p ?o } }",
+            "named-graph-uri=file%3A%2F%2F%2Fbanana.hdt",
+        )?;
+        assert_eq!(named.trim(), "g\nfile:///banana.hdt");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_sparql_query_from_clauses() -> anyhow::Result<()> {
+        let (tmp_dir, store) = setup_test_store()?;
+
+        let select = |query: &str| -> anyhow::Result<String> {
+            let mut request = Request::builder()
+                .method(Method::POST)
+                .uri("http://localhost/query")
+                .header("Content-Type", "application/sparql-query")
+                .header("Accept", "text/csv")
+                .body(Body::from(query.to_string()))
+                .unwrap();
+            let response = handle_response(de::serve::handle_request(
+                &mut request,
+                &store,
+                true,
+                tmp_dir.path().to_str().unwrap().to_string(),
+            ))?;
+            assert_eq!(response.status(), StatusCode::OK);
+            Ok(read_body(response).replace("\r", ""))
+        };
+
+        // FROM makes up the default graph instead of the union of all graphs
+        let fruits = select(
+            "SELECT DISTINCT ?s FROM <file:///banana.hdt> WHERE { ?s a <http://example.org/Fruit> }",
+        )?;
+        assert_eq!(fruits.trim(), "s\nhttp://example.org/Banana");
+        // only the FROM NAMED graphs can be matched by name
+        let graphs = select(
+            "SELECT DISTINCT ?g FROM NAMED <file:///pineapple.hdt> WHERE { GRAPH ?g { ?s ?p ?o } }",
+        )?;
+        assert_eq!(graphs.trim(), "g\nfile:///pineapple.hdt");
+
+        Ok(())
+    }
+
     #[test]
     fn test_sparql_query_service_description() -> anyhow::Result<()> {
         let (tmp_dir, store) = setup_test_store()?;
@@ -140,6 +296,85 @@ mod server_tests {
         Ok(())
     }
 
+    #[test]
+    fn test_sparql_query_service_description_jsonld() -> anyhow::Result<()> {
+        let (tmp_dir, store) = setup_test_store()?;
+
+        let mut request = Request::builder()
+            .method(Method::GET)
+            .uri("http://localhost/query")
+            .header("Accept", "application/ld+json")
+            .body(Body::empty())
+            .unwrap();
+
+        let config = de::serve::ServeConfig {
+            locations: tmp_dir.path().to_str().unwrap().to_string(),
+            read_only: true,
+            ..Default::default()
+        };
+        let response = handle_response(de::serve::handle_request_with_config(
+            &mut request,
+            &store,
+            &config,
+        ))?;
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["Content-Type"], "application/ld+json");
+        let body = read_body(response);
+        let triples = oxrdfio::RdfParser::from_format(oxrdfio::RdfFormat::JsonLd {
+            profile: oxrdfio::JsonLdProfileSet::empty(),
+        })
+        .with_base_iri("http://localhost/query")?
+        .for_reader(body.as_bytes())
+        .map(|q| q.map(|q| (q.predicate.into_string(), q.object.to_string())))
+        .collect::<Result<Vec<_>, _>>()?;
+
+        let sd = "http://www.w3.org/ns/sparql-service-description#";
+        let has = |p: &str, o: &str| triples.iter().any(|t| t.0 == p && t.1 == o);
+        assert!(has(&format!("{sd}endpoint"), "<http://localhost/query>"));
+        assert!(has(
+            &format!("{sd}resultFormat"),
+            "<http://www.w3.org/ns/formats/JSON-LD>"
+        ));
+        assert!(has(
+            &format!("{sd}feature"),
+            &format!("<{sd}UnionDefaultGraph>")
+        ));
+        assert!(has(
+            &format!("{sd}feature"),
+            "<https://github.com/DeciSym/de#ReadOnly>"
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_head_query_and_update() -> anyhow::Result<()> {
+        let (tmp_dir, store) = setup_test_store()?;
+
+        for path in ["/query", "/update"] {
+            let mut request = Request::builder()
+                .method(Method::HEAD)
+                .uri(format!("http://localhost{path}"))
+                .header("Accept", "text/turtle")
+                .body(Body::empty())
+                .unwrap();
+
+            let response = handle_response(de::serve::handle_request(
+                &mut request,
+                &store,
+                true,
+                tmp_dir.path().to_str().unwrap().to_string(),
+            ))?;
+
+            assert_eq!(response.status(), StatusCode::OK, "{path}");
+            assert_eq!(response.headers()["Content-Type"], "text/turtle", "{path}");
+            assert!(read_body(response).is_empty(), "{path}");
+        }
+
+        Ok(())
+    }
+
     #[test]
     fn test_update_create_graph() -> anyhow::Result<()> {
         let (tmp_dir, store) = setup_test_store()?;
@@ -235,6 +470,127 @@ mod server_tests {
         Ok(())
     }
 
+    #[test]
+    fn test_update_delete_data_with_mutations() -> anyhow::Result<()> {
+        let (tmp_dir, store) = setup_test_store()?;
+        let store = store.with_mutations(true);
+        let locations = tmp_dir.path().to_str().unwrap().to_string();
+
+        let send = |path: &str, content_type: &str, body: &str| {
+            let mut request = Request::builder()
+                .method(Method::POST)
+                .uri(format!("http://localhost/{path}"))
+                .header("Content-Type", content_type)
+                .header("Accept", "application/sparql-results+json")
+                .body(Body::from(body.to_string()))
+                .unwrap();
+            handle_response(de::serve::handle_request(
+                &mut request,
+                &store,
+                true,
+                locations.clone(),
+            ))
+        };
+        let ask = |pattern: &str| -> anyhow::Result<bool> {
+            let query = format!("PREFIX ex: <http://example.org/> ASK {{ GRAPH <file:///banana.hdt> {{ {pattern} }} }}");
+            let response = send("query", "application/sparql-query", &query)?;
+            assert_eq!(response.status(), StatusCode::OK);
+            Ok(read_body(response).contains("true"))
+        };
+
+        let response = send(
+            "update",
+            "application/sparql-update",
+            r#"PREFIX ex: <http://example.org/>
+            DELETE DATA { GRAPH <file:///banana.hdt> { ex:Banana ex:hasColor "yellow" . } }"#,
+        )?;
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        assert!(!ask(r#"ex:Banana ex:hasColor "yellow""#)?);
+        assert!(ask(r#"ex:Banana ex:hasTaste "sweet""#)?);
+
+        let response = send(
+            "update",
+            "application/sparql-update",
+            r#"PREFIX ex: <http://example.org/>
+            DELETE { GRAPH <file:///banana.hdt> { ?fruit ex:hasTaste ?taste } }
+            INSERT { GRAPH <file:///banana.hdt> { ?fruit ex:hasTaste "very sweet" } }
+            WHERE { GRAPH <file:///banana.hdt> { ?fruit ex:hasTaste ?taste } }"#,
+        )?;
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        assert!(!ask(r#"ex:Banana ex:hasTaste "sweet""#)?);
+        assert!(ask(r#"ex:Banana ex:hasTaste "very sweet""#)?);
+
+        // the default graph is the union of all graphs and can't be modified
+        let result = de::serve::handle_request(
+            &mut Request::builder()
+                .method(Method::POST)
+                .uri("http://localhost/update")
+                .header("Content-Type", "application/sparql-update")
+                .body(Body::from(
+                    r#"PREFIX ex: <http://example.org/> DELETE DATA { ex:Banana ex:hasShape "curved" . }"#,
+                ))
+                .unwrap(),
+            &store,
+            true,
+            locations.clone(),
+        );
+        assert_eq!(result.unwrap_err().0, StatusCode::FORBIDDEN);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_update_drop_graph() -> anyhow::Result<()> {
+        let (tmp_dir, store) = setup_test_store()?;
+        let locations = tmp_dir.path().to_str().unwrap().to_string();
+        let update = |store: &AggregateHdt, update: &str| {
+            let mut request = Request::builder()
+                .method(Method::POST)
+                .uri("http://localhost/update")
+                .header("Content-Type", "application/sparql-update")
+                .body(Body::from(update.to_string()))
+                .unwrap();
+            de::serve::handle_request(&mut request, store, true, locations.clone())
+        };
+        let insert = r#"PREFIX ex: <http://example.org/>
+            INSERT DATA { GRAPH <http://example.org/cherry> { ex:Cherry ex:hasColor "red" . } }"#;
+        let graph = "http://example.org/cherry".to_string();
+
+        // existing graphs can't be dropped unless mutations are allowed
+        handle_response(update(&store, insert))?;
+        let (status, _) = update(&store, "DROP GRAPH <http://example.org/cherry>").unwrap_err();
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert!(store.contains_graph_name(&graph)?);
+
+        let store = store.with_mutations(true);
+        let response = handle_response(update(&store, "DROP GRAPH <http://example.org/cherry>"))?;
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        assert!(!store.contains_graph_name(&graph)?);
+
+        // dropping it again fails, unless SILENT
+        assert!(update(&store, "DROP GRAPH <http://example.org/cherry>").is_err());
+        handle_response(update(
+            &store,
+            "DROP SILENT GRAPH <http://example.org/cherry>",
+        ))?;
+
+        handle_response(update(&store, insert))?;
+        handle_response(update(&store, "CLEAR GRAPH <http://example.org/cherry>"))?;
+        assert!(!store.contains_graph_name(&graph)?);
+        assert!(update(&store, "CLEAR DEFAULT").is_err());
+        // SILENT doesn't lift the target and mutation checks
+        assert!(update(&store, "CLEAR SILENT DEFAULT").is_err());
+        assert!(update(&store, "DROP SILENT ALL").is_err());
+
+        handle_response(update(&store, insert))?;
+        let store = store.with_mutations(false);
+        let (status, _) =
+            update(&store, "DROP SILENT GRAPH <http://example.org/cherry>").unwrap_err();
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert!(store.contains_graph_name(&graph)?);
+        Ok(())
+    }
+
     #[test]
     fn test_store_get_all() -> anyhow::Result<()> {
         let (tmp_dir, store) = setup_test_store()?;
@@ -349,6 +705,92 @@ ex:Orange ex:hasColor "orange" .
         Ok(())
     }
 
+    #[test]
+    fn test_store_default_graph_is_not_replaced() -> anyhow::Result<()> {
+        let (tmp_dir, store) = setup_test_store()?;
+        let config = de::serve::ServeConfig {
+            locations: tmp_dir.path().to_str().unwrap().to_string(),
+            allow_mutations: true,
+            ..Default::default()
+        };
+
+        let mut request = Request::builder()
+            .method(Method::DELETE)
+            .uri("http://localhost/store?default")
+            .body(Body::empty())
+            .unwrap();
+        let (status, _) =
+            de::serve::handle_request_with_config(&mut request, &store, &config).unwrap_err();
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        let mut request = Request::builder()
+            .method(Method::PUT)
+            .uri("http://localhost/store?default")
+            .header("Content-Type", "text/turtle")
+            .body(Body::from(
+                "<http://example.org/s> <http://example.org/p> 1 .",
+            ))
+            .unwrap();
+        let (status, _) =
+            de::serve::handle_request_with_config(&mut request, &store, &config).unwrap_err();
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        // both data files are still there
+        assert!(store.contains_graph_name("file:///banana.hdt")?);
+        assert!(store.contains_graph_name("file:///pineapple.hdt")?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_store_post_appends_to_existing_graph() -> anyhow::Result<()> {
+        let (tmp_dir, store) = setup_test_store()?;
+        let post = || {
+            Request::builder()
+                .method(Method::POST)
+                .uri("http://localhost/store?graph=file:///banana.hdt")
+                .header("Content-Type", "text/turtle")
+                .body(Body::from(
+                    "<http://example.org/Plantain> a <http://example.org/Fruit> .",
+                ))
+                .unwrap()
+        };
+        let mut config = de::serve::ServeConfig {
+            locations: tmp_dir.path().to_str().unwrap().to_string(),
+            ..Default::default()
+        };
+
+        let (status, _) =
+            de::serve::handle_request_with_config(&mut post(), &store, &config).unwrap_err();
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        let store = store.with_mutations(true);
+        config.allow_mutations = true;
+        let response = handle_response(de::serve::handle_request_with_config(
+            &mut post(),
+            &store,
+            &config,
+        ))?;
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+        let mut query = Request::builder()
+            .uri(format!(
+                "http://localhost/query?query={}",
+                urlencoding::encode(
+                    "SELECT ?fruit WHERE { GRAPH <file:///banana.hdt> { ?fruit a <http://example.org/Fruit> } }"
+                )
+            ))
+            .header("Accept", "text/csv")
+            .body(Body::empty())
+            .unwrap();
+        let response = handle_response(de::serve::handle_request_with_config(
+            &mut query, &store, &config,
+        ))?;
+        let body = read_body(response);
+        assert!(body.contains("http://example.org/Banana"), "{body}");
+        assert!(body.contains("http://example.org/Plantain"), "{body}");
+        Ok(())
+    }
+
     #[test]
     fn test_store_head_graph_exists() -> anyhow::Result<()> {
         let (tmp_dir, store) = setup_test_store()?;
@@ -457,4 +899,839 @@ ex:Orange ex:hasColor "orange" .
 
         Ok(())
     }
+
+    #[test]
+    fn test_shutdown_removes_temp_files() -> anyhow::Result<()> {
+        let (tmp_dir, store) = setup_test_store()?;
+
+        let update = r#"
+            PREFIX ex: <http://example.org/>
+            INSERT DATA {
+                GRAPH <http://example.org/shutdowngraph> {
+                    ex:Kiwi ex:hasColor "green" .
+                }
+            }
+        "#;
+        let mut request = Request::builder()
+            .method(Method::POST)
+            .uri("http://localhost/update")
+            .header("Content-Type", "application/sparql-update")
+            .body(Body::from(update))
+            .unwrap();
+        let response = handle_response(de::serve::handle_request(
+            &mut request,
+            &store,
+            true,
+            tmp_dir.path().to_str().unwrap().to_string(),
+        ))?;
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+        // both the uploaded NT file and the HDT built from it are tracked
+        let temp_files = store.temp_files();
+        assert_eq!(temp_files.len(), 2);
+        assert!(temp_files.iter().all(|p| p.exists()));
+
+        let shutdown = de::serve::ShutdownState::default();
+        let in_flight = shutdown.begin_request();
+        assert!(in_flight.is_some());
+        drop(in_flight);
+
+        assert!(shutdown.shutdown(&store, std::time::Duration::from_secs(1)));
+        assert!(temp_files.iter().all(|p| !p.exists()));
+        assert!(store.temp_files().is_empty());
+        // requests arriving after shutdown began are rejected
+        assert!(shutdown.begin_request().is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_sparql_construct_streams_results() -> anyhow::Result<()> {
+        use std::io::BufRead as _;
+
+        let (tmp_dir, store) = setup_test_store()?;
+
+        // cross product of every triple with every object, large enough to need many reads
+        let query = "CONSTRUCT { ?s ?p ?o2 } WHERE { ?s ?p ?o . ?s2 ?p2 ?o2 }";
+        let mut request = Request::builder()
+            .method(Method::POST)
+            .uri("http://localhost/query")
+            .header("Content-Type", "application/sparql-query")
+            .header("Accept", "application/n-triples")
+            .body(Body::from(query))
+            .unwrap();
+
+        let response = handle_response(de::serve::handle_request(
+            &mut request,
+            &store,
+            true,
+            tmp_dir.path().to_str().unwrap().to_string(),
+        ))?;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // the body is produced on demand, the first triple is available without draining the rest
+        let mut reader = std::io::BufReader::with_capacity(64, response.into_body());
+        let mut first = String::new();
+        reader.read_line(&mut first)?;
+        assert!(first.trim_end().ends_with(" ."));
+
+        let remaining = reader.lines().count();
+        assert!(remaining > 0);
+
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_unix_socket_proxy() -> anyhow::Result<()> {
+        use std::io::Write as _;
+        use std::os::unix::net::UnixStream;
+
+        let (tmp_dir, store) = setup_test_store()?;
+        let store = std::sync::Arc::new(store);
+        let locations = tmp_dir.path().to_str().unwrap().to_string();
+
+        let addr = std::net::TcpListener::bind("127.0.0.1:0")?.local_addr()?;
+        let _server = oxhttp::Server::new(move |request| {
+            if request.headers().get("x-de-unix-socket-token").unwrap() != "secret" {
+                return http::Response::builder()
+                    .status(http::StatusCode::FORBIDDEN)
+                    .body(Body::empty())
+                    .unwrap();
+            }
+            de::serve::handle_request(request, &store, true, locations.clone()).unwrap_or_else(
+                |(status, _)| {
+                    http::Response::builder()
+                        .status(status)
+                        .body(Body::empty())
+                        .unwrap()
+                },
+            )
+        })
+        .bind(addr)
+        .spawn()?;
+
+        let socket = tmp_dir.path().join("de.sock");
+        let proxy = de::serve::UnixSocketProxy::start(&socket, addr, "secret")?;
+
+        // the connection is closed after the response even though the client keeps it alive
+        let mut stream = UnixStream::connect(&socket)?;
+        stream.write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n")?;
+        let mut response = String::new();
+        stream.read_to_string(&mut response)?;
+        assert!(response.starts_with("HTTP/1.1 200"), "{response}");
+
+        drop(proxy);
+        assert!(!socket.exists());
+
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_serve_unix_socket() -> anyhow::Result<()> {
+        use std::io::Write as _;
+        use std::os::unix::net::UnixStream;
+
+        let (tmp_dir, _) = setup_test_store()?;
+        let socket = tmp_dir.path().join("de.sock");
+        let config = de::serve::ServeConfig {
+            bind: format!("unix:{}", socket.display()),
+            locations: tmp_dir.path().to_str().unwrap().to_string(),
+            ..Default::default()
+        };
+        let handle = de::serve::serve_with_config(config)?;
+        let request = "GET /query?query=ASK%20%7B%7D HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n";
+
+        let mut stream = UnixStream::connect(&socket)?;
+        stream.write_all(request.as_bytes())?;
+        let mut response = String::new();
+        stream.read_to_string(&mut response)?;
+        assert!(response.starts_with("HTTP/1.1 200"), "{response}");
+
+        // other local users can reach the loopback port but not the endpoint behind it
+        let mut stream = std::net::TcpStream::connect(handle.local_addrs()[0])?;
+        stream.write_all(request.as_bytes())?;
+        let mut response = String::new();
+        stream.read_to_string(&mut response)?;
+        assert!(response.starts_with("HTTP/1.1 403"), "{response}");
+
+        handle.shutdown(std::time::Duration::from_secs(1));
+        Ok(())
+    }
+
+    #[test]
+    fn test_store_get_graph_etag() -> anyhow::Result<()> {
+        let (tmp_dir, store) = setup_test_store()?;
+        let locations = tmp_dir.path().to_str().unwrap().to_string();
+
+        let get = |extra: Option<(&str, String)>| {
+            let mut builder = Request::builder()
+                .method(Method::GET)
+                .uri("http://localhost/store?graph=file:///banana.hdt")
+                .header("Accept", "application/n-triples");
+            if let Some((name, value)) = extra {
+                builder = builder.header(name, value);
+            }
+            let mut request = builder.body(Body::empty()).unwrap();
+            handle_response(de::serve::handle_request(
+                &mut request,
+                &store,
+                true,
+                locations.clone(),
+            ))
+        };
+
+        let response = get(None)?;
+        assert_eq!(response.status(), StatusCode::OK);
+        let etag = response.headers()["ETag"].to_str()?.to_string();
+        let last_modified = response.headers()["Last-Modified"].to_str()?.to_string();
+
+        let response = get(Some(("If-None-Match", etag.clone())))?;
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(response.headers()["ETag"].to_str()?, etag);
+
+        let response = get(Some(("If-Modified-Since", last_modified)))?;
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+
+        let response = get(Some(("If-None-Match", "\"stale\"".to_string())))?;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_serve_with_config() -> anyhow::Result<()> {
+        use std::io::Write as _;
+
+        let (tmp_dir, _) = setup_test_store()?;
+        let config = de::serve::ServeConfig {
+            bind: "127.0.0.1:0".to_string(),
+            locations: tmp_dir.path().to_str().unwrap().to_string(),
+            read_only: true,
+            ..Default::default()
+        };
+        let handle = de::serve::serve_with_config(config)?;
+        let addr = handle.local_addrs()[0];
+        assert_ne!(addr.port(), 0);
+
+        let send = |request: &str| -> anyhow::Result<String> {
+            let mut stream = std::net::TcpStream::connect(addr)?;
+            stream.write_all(request.as_bytes())?;
+            let mut response = String::new();
+            stream.read_to_string(&mut response)?;
+            Ok(response)
+        };
+        let ask = "GET /query?query=ASK%7B%3Fs%20%3Fp%20%3Fo%7D HTTP/1.1\r\nHost: localhost\r\nAccept: application/sparql-results+json\r\nConnection: close\r\n\r\n";
+
+        let response = send(ask)?;
+        assert!(response.starts_with("HTTP/1.1 200"));
+        assert!(response.contains("true"));
+
+        let update = "INSERT DATA { GRAPH <http://example.org/g> { <http://example.org/s> <http://example.org/p> 1 } }";
+        let response = send(&format!(
+            "POST /update HTTP/1.1\r\nHost: localhost\r\nContent-Type: application/sparql-update\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{update}",
+            update.len()
+        ))?;
+        assert!(response.starts_with("HTTP/1.1 403"));
+
+        assert!(handle.shutdown(std::time::Duration::from_secs(1)));
+        let response = send(ask)?;
+        assert!(response.starts_with("HTTP/1.1 503"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_multiple_bind_addresses() -> anyhow::Result<()> {
+        use std::io::Write as _;
+        use std::time::Duration;
+
+        let (tmp_dir, _) = setup_test_store()?;
+        let config = de::serve::ServeConfig {
+            bind: "127.0.0.1:0, 127.0.0.1:0".to_string(),
+            locations: tmp_dir.path().to_str().unwrap().to_string(),
+            ..Default::default()
+        };
+        let handle = de::serve::serve_with_config(config.clone())?;
+        assert_eq!(handle.local_addrs().len(), 2);
+        assert_ne!(handle.local_addrs()[0], handle.local_addrs()[1]);
+        for address in handle.local_addrs() {
+            let mut stream = std::net::TcpStream::connect(address)?;
+            stream.write_all(
+                b"GET /metrics HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+            )?;
+            let mut response = String::new();
+            stream.read_to_string(&mut response)?;
+            assert!(response.starts_with("HTTP/1.1 200"), "{response}");
+        }
+        assert!(handle.shutdown(Duration::from_secs(1)));
+
+        let err = de::serve::serve_with_config(de::serve::ServeConfig {
+            bind: "127.0.0.1:0,not an address".to_string(),
+            ..config
+        })
+        .err()
+        .unwrap();
+        assert!(err.to_string().contains("\"not an address\""), "{err}");
+        Ok(())
+    }
+
+    #[test]
+    fn test_query_abandoned_on_disconnect() -> anyhow::Result<()> {
+        use std::io::Write as _;
+        use std::time::{Duration, Instant};
+
+        let (tmp_dir, _) = setup_test_store()?;
+        let handle = de::serve::serve_with_config(de::serve::ServeConfig {
+            bind: "127.0.0.1:0".to_string(),
+            locations: tmp_dir.path().to_str().unwrap().to_string(),
+            ..Default::default()
+        })?;
+        let address = handle.local_addrs()[0];
+
+        // a cross product far larger than the socket buffers
+        let query = "SELECT * WHERE { ?a ?b ?c . ?d ?e ?f . ?g ?h ?i . ?j ?k ?l }"
+            .replace(' ', "%20")
+            .replace('?', "%3F")
+            .replace('{', "%7B")
+            .replace('}', "%7D");
+        let mut stream = std::net::TcpStream::connect(address)?;
+        stream.write_all(
+            format!(
+                "GET /query?query={query} HTTP/1.1\r\nHost: localhost\r\nAccept: text/csv\r\n\r\n"
+            )
+            .as_bytes(),
+        )?;
+        let mut start = [0; 1024];
+        stream.read_exact(&mut start)?;
+        assert!(start.starts_with(b"HTTP/1.1 200"));
+
+        let metrics = || -> anyhow::Result<String> {
+            let mut stream = std::net::TcpStream::connect(address)?;
+            stream.write_all(
+                b"GET /metrics HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+            )?;
+            let mut response = String::new();
+            stream.read_to_string(&mut response)?;
+            Ok(response)
+        };
+        // the evaluation waits for the client to read more of the results
+        let response = metrics()?;
+        assert!(
+            response.contains("de_query_evaluations_running 1\n"),
+            "{response}"
+        );
+
+        drop(stream);
+        let deadline = Instant::now() + Duration::from_secs(30);
+        let mut response = metrics()?;
+        while !response.contains("de_query_evaluations_running 0\n") && Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(50));
+            response = metrics()?;
+        }
+        assert!(
+            response.contains("de_query_evaluations_running 0\n"),
+            "{response}"
+        );
+        assert!(
+            response.contains("de_abandoned_responses_total 1\n"),
+            "{response}"
+        );
+        assert!(handle.shutdown(Duration::from_secs(1)));
+        Ok(())
+    }
+
+    #[test]
+    fn test_connection_limits() -> anyhow::Result<()> {
+        use std::io::Write as _;
+        use std::time::{Duration, Instant};
+
+        let (tmp_dir, _) = setup_test_store()?;
+        let config = de::serve::ServeConfig {
+            bind: "127.0.0.1:0".to_string(),
+            locations: tmp_dir.path().to_str().unwrap().to_string(),
+            max_connections: Some(4),
+            timeout: Duration::from_secs(1),
+            ..Default::default()
+        };
+        let handle = de::serve::serve_with_config(config.clone())?;
+
+        // the connection is kept alive after the response until it has been idle for the timeout
+        let mut stream = std::net::TcpStream::connect(handle.local_addrs()[0])?;
+        stream.set_read_timeout(Some(Duration::from_secs(30)))?;
+        let start = Instant::now();
+        stream.write_all(b"GET /query?query=ASK%7B%3Fs%20%3Fp%20%3Fo%7D HTTP/1.1\r\nHost: localhost\r\nAccept: application/sparql-results+json\r\n\r\n")?;
+        let mut response = String::new();
+        stream.read_to_string(&mut response)?;
+        assert!(response.starts_with("HTTP/1.1 200"), "{response}");
+        assert!(start.elapsed() >= Duration::from_secs(1));
+        assert!(handle.shutdown(Duration::from_secs(1)));
+
+        for invalid in [
+            de::serve::ServeConfig {
+                max_connections: Some(0),
+                ..config.clone()
+            },
+            de::serve::ServeConfig {
+                timeout: Duration::ZERO,
+                ..config
+            },
+        ] {
+            assert!(de::serve::serve_with_config(invalid).is_err());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_watch() -> anyhow::Result<()> {
+        use std::io::Write as _;
+        use std::time::{Duration, Instant};
+
+        let (tmp_dir, _) = setup_test_store()?;
+        let config = de::serve::ServeConfig {
+            bind: "127.0.0.1:0".to_string(),
+            locations: tmp_dir.path().to_str().unwrap().to_string(),
+            watch: true,
+            ..Default::default()
+        };
+        let handle = de::serve::serve_with_config(config)?;
+
+        // built elsewhere so the watcher only sees the complete file
+        let build_dir = tempdir()?;
+        let apple = build_dir.path().join("apple.hdt");
+        de::create::do_create(
+            apple.to_str().unwrap(),
+            &["tests/resources/apple.ttl".to_string()],
+        )?;
+        std::fs::copy(&apple, tmp_dir.path().join("apple.hdt"))?;
+
+        let deadline = Instant::now() + Duration::from_secs(10);
+        let apple_graph = "file:///apple.hdt".to_string();
+        while !handle.store().contains_graph_name(&apple_graph)? {
+            assert!(Instant::now() < deadline, "apple.hdt was not picked up");
+            std::thread::sleep(Duration::from_millis(50));
+        }
+
+        let mut stream = std::net::TcpStream::connect(handle.local_addrs()[0])?;
+        let query = urlencoding::encode("ASK { GRAPH <file:///apple.hdt> { ?s ?p ?o } }");
+        write!(
+            stream,
+            "GET /query?query={query} HTTP/1.1\r\nHost: localhost\r\nAccept: text/csv\r\nConnection: close\r\n\r\n"
+        )?;
+        let mut response = String::new();
+        stream.read_to_string(&mut response)?;
+        assert!(response.starts_with("HTTP/1.1 200"), "{response}");
+        assert!(response.contains("true"), "{response}");
+
+        std::fs::remove_file(tmp_dir.path().join("apple.hdt"))?;
+        while handle.store().contains_graph_name(&apple_graph)? {
+            assert!(Instant::now() < deadline, "apple.hdt was not removed");
+            std::thread::sleep(Duration::from_millis(50));
+        }
+        assert!(handle.shutdown(Duration::from_secs(1)));
+        Ok(())
+    }
+
+    #[test]
+    fn test_rate_limit() -> anyhow::Result<()> {
+        use std::io::Write as _;
+
+        let (tmp_dir, _) = setup_test_store()?;
+        let config = de::serve::ServeConfig {
+            bind: "127.0.0.1:0".to_string(),
+            locations: tmp_dir.path().to_str().unwrap().to_string(),
+            rate_limit: Some(3),
+            ..Default::default()
+        };
+        // without a proxy setting X-Forwarded-For clients could pick their own bucket
+        assert!(de::serve::serve_with_config(config.clone()).is_err());
+        let config = de::serve::ServeConfig {
+            trusted_proxy: true,
+            ..config
+        };
+        let handle = de::serve::serve_with_config(config)?;
+        let addr = handle.local_addrs()[0];
+
+        let send = |path: &str, client: &str| -> anyhow::Result<String> {
+            let mut stream = std::net::TcpStream::connect(addr)?;
+            write!(
+                stream,
+                "GET {path} HTTP/1.1\r\nHost: localhost\r\nX-Forwarded-For: {client}\r\nConnection: close\r\n\r\n"
+            )?;
+            let mut response = String::new();
+            stream.read_to_string(&mut response)?;
+            Ok(response)
+        };
+        let ask = "/query?query=ASK%7B%3Fs%20%3Fp%20%3Fo%7D";
+
+        for _ in 0..3 {
+            assert!(send(ask, "192.0.2.1")?.starts_with("HTTP/1.1 200"));
+        }
+        let response = send(ask, "192.0.2.1")?;
+        assert!(response.starts_with("HTTP/1.1 429"), "{response}");
+        assert!(response.to_lowercase().contains("retry-after: "));
+        // only the address appended by the proxy counts, not the ones sent by the client
+        let response = send(ask, "198.51.100.7, 192.0.2.1")?;
+        assert!(response.starts_with("HTTP/1.1 429"), "{response}");
+
+        // other clients and the UI pages are not limited
+        assert!(send(ask, "192.0.2.2")?.starts_with("HTTP/1.1 200"));
+        assert!(send("/", "192.0.2.1")?.starts_with("HTTP/1.1 200"));
+
+        handle.shutdown(std::time::Duration::from_secs(1));
+        Ok(())
+    }
+
+    #[test]
+    fn test_query_cache() -> anyhow::Result<()> {
+        use std::io::Write as _;
+
+        let (tmp_dir, _) = setup_test_store()?;
+        let config = de::serve::ServeConfig {
+            bind: "127.0.0.1:0".to_string(),
+            locations: tmp_dir.path().to_str().unwrap().to_string(),
+            query_cache_size: 2,
+            ..Default::default()
+        };
+        let handle = de::serve::serve_with_config(config)?;
+        let addr = handle.local_addrs()[0];
+
+        let send = |path: &str| -> anyhow::Result<String> {
+            let mut stream = std::net::TcpStream::connect(addr)?;
+            write!(
+                stream,
+                "GET {path} HTTP/1.1\r\nHost: localhost\r\nAccept: text/csv\r\nConnection: close\r\n\r\n"
+            )?;
+            let mut response = String::new();
+            stream.read_to_string(&mut response)?;
+            Ok(response.to_lowercase())
+        };
+        let count = "/query?query=SELECT%20(COUNT(*)%20AS%20%3Fc)%20%7B%3Fs%20%3Fp%20%3Fo%7D";
+
+        let first = send(count)?;
+        assert!(first.contains("x-cache: miss"), "{first}");
+        let second = send(count)?;
+        assert!(second.contains("x-cache: hit"), "{second}");
+        assert_eq!(
+            first.rsplit("\r\n\r\n").next(),
+            second.rsplit("\r\n\r\n").next()
+        );
+
+        // a new graph file changes the dataset, the cached result is stale
+        de::create::do_create(
+            tmp_dir.path().join("apple.hdt").to_str().unwrap(),
+            &["tests/resources/apple.ttl".to_string()],
+        )?;
+        let third = send(count)?;
+        assert!(third.contains("x-cache: miss"), "{third}");
+        assert_ne!(
+            first.rsplit("\r\n\r\n").next(),
+            third.rsplit("\r\n\r\n").next()
+        );
+
+        handle.shutdown(std::time::Duration::from_secs(1));
+        Ok(())
+    }
+
+    #[test]
+    fn test_query_cache_max_bytes() -> anyhow::Result<()> {
+        use std::io::Write as _;
+
+        let (tmp_dir, _) = setup_test_store()?;
+        let config = de::serve::ServeConfig {
+            bind: "127.0.0.1:0".to_string(),
+            locations: tmp_dir.path().to_str().unwrap().to_string(),
+            query_cache_size: 2,
+            query_cache_max_bytes: 1024,
+            ..Default::default()
+        };
+        let handle = de::serve::serve_with_config(config)?;
+        let addr = handle.local_addrs()[0];
+
+        let send = |path: &str| -> anyhow::Result<String> {
+            let mut stream = std::net::TcpStream::connect(addr)?;
+            write!(
+                stream,
+                "GET {path} HTTP/1.1\r\nHost: localhost\r\nAccept: text/csv\r\nConnection: close\r\n\r\n"
+            )?;
+            let mut response = String::new();
+            stream.read_to_string(&mut response)?;
+            Ok(response.to_lowercase())
+        };
+
+        // all the triples are larger than a quarter of the budget, they are streamed every time
+        let all = "/query?query=SELECT%20*%20%7B%3Fs%20%3Fp%20%3Fo%7D";
+        let first = send(all)?;
+        assert!(first.contains("x-cache: miss"), "{first}");
+        assert!(first.contains("banana"), "{first}");
+        let second = send(all)?;
+        assert!(second.contains("x-cache: miss"), "{second}");
+        assert_eq!(
+            first.rsplit("\r\n\r\n").next(),
+            second.rsplit("\r\n\r\n").next()
+        );
+
+        // a small result still fits
+        let count = "/query?query=SELECT%20(COUNT(*)%20AS%20%3Fc)%20%7B%3Fs%20%3Fp%20%3Fo%7D";
+        assert!(send(count)?.contains("x-cache: miss"));
+        assert!(send(count)?.contains("x-cache: hit"));
+
+        handle.shutdown(std::time::Duration::from_secs(1));
+        Ok(())
+    }
+
+    #[test]
+    fn test_metrics() -> anyhow::Result<()> {
+        use std::io::Write as _;
+
+        let (tmp_dir, _) = setup_test_store()?;
+        let config = de::serve::ServeConfig {
+            bind: "127.0.0.1:0".to_string(),
+            locations: tmp_dir.path().to_str().unwrap().to_string(),
+            rate_limit: Some(1),
+            trusted_proxy: true,
+            ..Default::default()
+        };
+        let handle = de::serve::serve_with_config(config)?;
+        let addr = handle.local_addrs()[0];
+
+        let send = |path: &str| -> anyhow::Result<String> {
+            let mut stream = std::net::TcpStream::connect(addr)?;
+            write!(
+                stream,
+                "GET {path} HTTP/1.1\r\nHost: localhost\r\nAccept: text/csv\r\nConnection: close\r\n\r\n"
+            )?;
+            let mut response = String::new();
+            stream.read_to_string(&mut response)?;
+            Ok(response)
+        };
+
+        let before = send("/metrics")?;
+        assert!(before.starts_with("HTTP/1.1 200"), "{before}");
+        assert!(before.contains("de_queries_total 0\n"), "{before}");
+        assert!(before.contains("de_graphs_loaded 2\n"), "{before}");
+
+        let query = send("/query?query=SELECT%20*%20%7B%3Fs%20%3Fp%20%3Fo%7D")?;
+        assert!(query.starts_with("HTTP/1.1 200"), "{query}");
+
+        // scrapes aren't rate limited even though the query used up the only request
+        let after = send("/metrics")?;
+        assert!(after.starts_with("HTTP/1.1 200"), "{after}");
+        assert!(after.contains("de_queries_total 1\n"), "{after}");
+        assert!(after.contains("de_query_errors_total 0\n"), "{after}");
+        assert!(
+            after.contains("de_query_duration_seconds_count 1\n"),
+            "{after}"
+        );
+        assert!(!after.contains("de_served_bytes_total 0\n"), "{after}");
+
+        handle.shutdown(std::time::Duration::from_secs(1));
+        Ok(())
+    }
+
+    #[test]
+    fn test_update_requires_auth() -> anyhow::Result<()> {
+        let (tmp_dir, store) = setup_test_store()?;
+        let config = de::serve::ServeConfig {
+            locations: tmp_dir.path().to_str().unwrap().to_string(),
+            auth: Some(de::serve::ServeAuth::Bearer("secret".to_string())),
+            ..Default::default()
+        };
+        let update = r#"
+            PREFIX ex: <http://example.org/>
+            INSERT DATA {
+                GRAPH <http://example.org/newgraph> {
+                    ex:Apple ex:hasColor "red" .
+                }
+            }
+        "#;
+        let request = |token: Option<&str>| {
+            let mut request = Request::builder()
+                .method(Method::POST)
+                .uri("http://localhost/update")
+                .header("Content-Type", "application/sparql-update");
+            if let Some(token) = token {
+                request = request.header("Authorization", format!("Bearer {token}"));
+            }
+            request.body(Body::from(update)).unwrap()
+        };
+
+        for token in [None, Some("wrong")] {
+            let response = handle_response(de::serve::handle_request_with_config(
+                &mut request(token),
+                &store,
+                &config,
+            ))?;
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+            assert_eq!(
+                response.headers().get("WWW-Authenticate").unwrap(),
+                "Bearer realm=\"de\""
+            );
+        }
+
+        let response = handle_response(de::serve::handle_request_with_config(
+            &mut request(Some("secret")),
+            &store,
+            &config,
+        ))?;
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+        // queries stay open without --auth-reads
+        let mut query = Request::builder()
+            .uri("http://localhost/query?query=ASK%20%7B%7D")
+            .header("Accept", "application/sparql-results+json")
+            .body(Body::empty())
+            .unwrap();
+        let response = handle_response(de::serve::handle_request_with_config(
+            &mut query, &store, &config,
+        ))?;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_sparql_query_pagination() -> anyhow::Result<()> {
+        let (tmp_dir, store) = setup_test_store()?;
+        let query = "PREFIX ex: <http://example.org/> PREFIX rdf: <http://www.w3.org/1999/02/22-rdf-syntax-ns#> SELECT ?fruit WHERE { ?fruit rdf:type ex:Fruit } ORDER BY ?fruit";
+        let get = |params: &str| {
+            let mut request = Request::builder()
+                .uri(format!(
+                    "http://localhost/query?query={}{params}",
+                    urlencoding::encode(query)
+                ))
+                .header("Accept", "text/csv")
+                .body(Body::empty())
+                .unwrap();
+            handle_response(de::serve::handle_request(
+                &mut request,
+                &store,
+                true,
+                tmp_dir.path().to_str().unwrap().to_string(),
+            ))
+        };
+
+        let response = get("&limit=1")?;
+        assert_eq!(response.status(), StatusCode::OK);
+        let link = response
+            .headers()
+            .get("Link")
+            .expect("next page link")
+            .to_str()?
+            .to_string();
+        assert!(link.ends_with("; rel=\"next\""), "{link}");
+        assert!(link.contains("limit=1&offset=1"), "{link}");
+        let body_text = read_body(response);
+        assert!(body_text.contains("Banana"), "{body_text}");
+        assert!(!body_text.contains("Pineapple"), "{body_text}");
+
+        // the last page has no next link
+        let response = get("&limit=1&offset=1")?;
+        assert!(response.headers().get("Link").is_none());
+        let body_text = read_body(response);
+        assert!(body_text.contains("Pineapple"), "{body_text}");
+
+        // an offset alone skips the first results and returns all the others
+        let response = get("&offset=1")?;
+        assert!(response.headers().get("Link").is_none());
+        let body_text = read_body(response);
+        assert!(!body_text.contains("Banana"), "{body_text}");
+        assert!(body_text.contains("Pineapple"), "{body_text}");
+        let response = get("&offset=1000")?;
+        assert_eq!(read_body(response).trim(), "fruit");
+
+        // without the parameters all results are returned
+        let response = get("")?;
+        assert!(response.headers().get("Link").is_none());
+        let body_text = read_body(response);
+        assert!(body_text.contains("Banana") && body_text.contains("Pineapple"));
+
+        assert!(get("&limit=0").is_err());
+        assert!(get("&limit=-1").is_err());
+
+        // parameters of a form body are carried over to the next page like those of the URL
+        let mut request = Request::builder()
+            .method(Method::POST)
+            .uri("http://localhost/query")
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .header("Accept", "text/csv")
+            .body(Body::from(format!(
+                "query={}&limit=1&union-default-graph",
+                urlencoding::encode(query)
+            )))
+            .unwrap();
+        let response = handle_response(de::serve::handle_request(
+            &mut request,
+            &store,
+            true,
+            tmp_dir.path().to_str().unwrap().to_string(),
+        ))?;
+        let link = response
+            .headers()
+            .get("Link")
+            .expect("next page link")
+            .to_str()?
+            .to_string();
+        assert!(link.contains("union-default-graph="), "{link}");
+        assert!(link.contains("limit=1&offset=1"), "{link}");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_prepared_query_cache() -> anyhow::Result<()> {
+        let (tmp_dir, store) = setup_test_store()?;
+        let config = de::serve::ServeConfig {
+            locations: tmp_dir.path().to_str().unwrap().to_string(),
+            ..Default::default()
+        };
+        let request = |uri: &str| {
+            Request::builder()
+                .uri(uri)
+                .header("Accept", "application/sparql-results+json")
+                .body(Body::empty())
+                .unwrap()
+        };
+        let query = "http://localhost/query?query=SELECT%20%3Fs%20WHERE%20%7B%3Fs%20%3Fp%20%3Fo%7D";
+        for _ in 0..2 {
+            let response = handle_response(de::serve::handle_request_with_config(
+                &mut request(query),
+                &store,
+                &config,
+            ))?;
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+        assert_eq!(config.prepared_queries.parses(), 1);
+
+        // a different base IRI is parsed again
+        let response = handle_response(de::serve::handle_request_with_config(
+            &mut request(&query.replace("localhost", "example.org")),
+            &store,
+            &config,
+        ))?;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(config.prepared_queries.parses(), 2);
+
+        // long queries are parsed every time rather than kept
+        let long_query = format!("SELECT ?s WHERE {{?s ?p ?o}} #{}", "x".repeat(70_000));
+        for parses in [3, 4] {
+            let mut request = Request::builder()
+                .method(Method::POST)
+                .uri("http://localhost/query")
+                .header("Content-Type", "application/sparql-query")
+                .header("Accept", "application/sparql-results+json")
+                .body(Body::from(long_query.clone()))
+                .unwrap();
+            let response = handle_response(de::serve::handle_request_with_config(
+                &mut request,
+                &store,
+                &config,
+            ))?;
+            assert_eq!(response.status(), StatusCode::OK);
+            assert_eq!(config.prepared_queries.parses(), parses);
+        }
+
+        Ok(())
+    }
 }